@@ -16,10 +16,67 @@ fn commit_info_from_git() -> Option<String> {
         .and_then(|output| String::from_utf8(output.stdout).ok())
 }
 
+/// Write the syntax definitions in `syntax.d` out as a list of `(file name, content)`
+/// for `Syntax::builtin`.
+fn embed_syntax_definitions() {
+    let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("syntax.d");
+    let mut paths: Vec<_> = std::fs::read_dir(&dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .collect()
+        })
+        .unwrap_or_default();
+    paths.retain(|path| path.extension().is_some_and(|ext| ext == "ini"));
+    paths.sort();
+    let mut list = String::from("&[\n");
+    for path in paths {
+        let name = path.file_name().unwrap().to_string_lossy();
+        list += &format!(
+            "    ({name:?}, include_str!({:?})),\n",
+            path.display().to_string()
+        );
+    }
+    list += "]\n";
+    let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("syntax_d.rs");
+    std::fs::write(out, list).unwrap();
+}
+
+/// The enabled features and the target triple, e.g. `debug, tui; x86_64-unknown-linux-gnu`.
+fn build_info() -> String {
+    let mut features: Vec<_> = std::env::vars()
+        .filter_map(|(key, _)| {
+            let feature = key.strip_prefix("CARGO_FEATURE_")?;
+            Some(feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    format!(
+        "{}; {}",
+        features.join(", "),
+        std::env::var("TARGET").unwrap_or_default()
+    )
+}
+
+/// Seconds since the Unix epoch, or `$SOURCE_DATE_EPOCH` for reproducible builds.
+fn build_timestamp() -> u64 {
+    std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs())
+        })
+}
+
 fn main() {
+    embed_syntax_definitions();
     println!(
         "cargo:rustc-env=VERSION_INFO={}{}",
         env!("CARGO_PKG_VERSION"),
         commit_info_from_git().unwrap_or_default()
     );
+    println!("cargo:rustc-env=BUILD_INFO={}", build_info());
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", build_timestamp());
 }
@@ -0,0 +1,42 @@
+//! Highlight a piece of Rust and print its tokens as spans of graphemes. It only
+//! uses the buffer and the built-in syntax definitions, so it also builds without the
+//! terminal editor, for example for the web:
+//!
+//! ```sh
+//! cargo run --example highlight --no-default-features
+//! cargo build --example highlight --no-default-features --target wasm32-unknown-unknown
+//! ```
+
+use arcaea::{Buffer, Syntax, TokenType};
+
+const SOURCE: &str = r#"/* Greet */
+fn main() {
+    let answer = 42; // 答案
+    println!("{answer}");
+}"#;
+
+fn main() {
+    let mut buffer: Buffer = SOURCE.parse().unwrap();
+    buffer.syntax = Syntax::builtin("rs").expect("Rust is built in");
+    buffer.update_syntax();
+
+    for (y, row) in buffer.rows.iter().enumerate() {
+        let mut start = 0;
+        for x in 1..=row.len() {
+            if x < row.len() && row.syntax[x] == row.syntax[start] {
+                continue;
+            }
+            if row.syntax[start] != TokenType::Normal {
+                let text: String = row.rope[start..x].iter().map(|(g, _)| g.as_str()).collect();
+                println!(
+                    "{}:{}..{} {:?} {text:?}",
+                    y + 1,
+                    start,
+                    x,
+                    row.syntax[start]
+                );
+            }
+            start = x;
+        }
+    }
+}
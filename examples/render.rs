@@ -0,0 +1,31 @@
+//! Time how long the editor takes to draw a frame of highlighted text into its screen
+//! buffer, on a wide terminal, without a real terminal:
+//!
+//! ```sh
+//! cargo run --release --example render
+//! ```
+
+use std::time::Instant;
+
+use arcaea::{Editor, Syntax};
+
+const FRAMES: u32 = 2000;
+
+fn main() {
+    let line = r#"    let answer = vec![42, 0x2a]; // 答案 "string" /* comment */ ok"#;
+    let text = vec![line; 200].join("\n");
+    let mut editor = Editor::open_str(&text, 400, 100);
+    editor.buffer.syntax = Syntax::builtin("rs").expect("Rust is built in");
+    editor.buffer.update_syntax();
+
+    editor.render_to_buffer();
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        editor.render_to_buffer();
+    }
+    let frame = start.elapsed() / FRAMES;
+    println!(
+        "{frame:?} per frame, {}x{}",
+        editor.terminal.width, editor.terminal.height
+    );
+}
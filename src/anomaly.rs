@@ -0,0 +1,112 @@
+//! Characters that are hard or impossible to see but change what text means: spaces
+//! that do not break, zero-width characters and the controls of bidirectional text,
+//! which can make source code read differently from how it runs.
+
+use std::{borrow::Cow, ops::RangeInclusive, sync::RwLock};
+
+/// The characters looked for unless the config says otherwise.
+pub const DEFAULT: &[RangeInclusive<char>] = &[
+    '\u{a0}'..='\u{a0}',
+    '\u{61c}'..='\u{61c}',
+    '\u{200b}'..='\u{200b}',
+    '\u{200e}'..='\u{200f}',
+    '\u{202a}'..='\u{202f}',
+    '\u{2060}'..='\u{2060}',
+    '\u{2066}'..='\u{2069}',
+    '\u{feff}'..='\u{feff}',
+];
+
+static CHARS: RwLock<Cow<'static, [RangeInclusive<char>]>> = RwLock::new(Cow::Borrowed(DEFAULT));
+
+/// Look for the characters in `chars` from now on.
+pub fn set(chars: Vec<RangeInclusive<char>>) {
+    *CHARS.write().unwrap() = Cow::Owned(chars);
+}
+
+/// Whether `c` is one of the characters looked for.
+pub fn is_anomaly(c: char) -> bool {
+    CHARS.read().unwrap().iter().any(|range| range.contains(&c))
+}
+
+/// Parse a list of code points and ranges of them, like `U+00A0, U+202A-U+202E`. An
+/// empty list turns the scan off.
+pub fn parse(list: &str) -> Option<Vec<RangeInclusive<char>>> {
+    let code_point = |text: &str| {
+        let hex = text.strip_prefix("U+").or(text.strip_prefix("u+"))?;
+        char::from_u32(u32::from_str_radix(hex, 16).ok()?)
+    };
+    list.split([',', ' ', '\t'])
+        .filter(|item| !item.is_empty())
+        .map(|item| match item.split_once('-') {
+            Some((start, end)) => Some(code_point(start)?..=code_point(end)?),
+            None => code_point(item).map(|c| c..=c),
+        })
+        .collect()
+}
+
+/// The Unicode name of `c` after its code point, like `U+200B ZERO WIDTH SPACE`. Only
+/// the usual suspects are named.
+pub fn describe(c: char) -> String {
+    let name = match c {
+        '\u{a0}' => "NO-BREAK SPACE",
+        '\u{ad}' => "SOFT HYPHEN",
+        '\u{61c}' => "ARABIC LETTER MARK",
+        '\u{200b}' => "ZERO WIDTH SPACE",
+        '\u{200c}' => "ZERO WIDTH NON-JOINER",
+        '\u{200d}' => "ZERO WIDTH JOINER",
+        '\u{200e}' => "LEFT-TO-RIGHT MARK",
+        '\u{200f}' => "RIGHT-TO-LEFT MARK",
+        '\u{202a}' => "LEFT-TO-RIGHT EMBEDDING",
+        '\u{202b}' => "RIGHT-TO-LEFT EMBEDDING",
+        '\u{202c}' => "POP DIRECTIONAL FORMATTING",
+        '\u{202d}' => "LEFT-TO-RIGHT OVERRIDE",
+        '\u{202e}' => "RIGHT-TO-LEFT OVERRIDE",
+        '\u{202f}' => "NARROW NO-BREAK SPACE",
+        '\u{2060}' => "WORD JOINER",
+        '\u{2066}' => "LEFT-TO-RIGHT ISOLATE",
+        '\u{2067}' => "RIGHT-TO-LEFT ISOLATE",
+        '\u{2068}' => "FIRST STRONG ISOLATE",
+        '\u{2069}' => "POP DIRECTIONAL ISOLATE",
+        '\u{feff}' => "ZERO WIDTH NO-BREAK SPACE (BOM)",
+        _ => return format!("U+{:04X}", c as u32),
+    };
+    format!("U+{:04X} {name}", c as u32)
+}
+
+/// What `c` is replaced with when cleaning up: a plain space for the spaces that do
+/// not break, nothing for the zero-width characters. `None` for the rest, like the
+/// bidirectional controls, which right-to-left text can need.
+pub fn replacement(c: char) -> Option<&'static str> {
+    match c {
+        '\u{a0}' | '\u{202f}' => Some(" "),
+        '\u{ad}' | '\u{200b}' | '\u{2060}' | '\u{feff}' => Some(""),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("U+00A0, u+202a-U+202E\tU+FEFF"),
+            Some(vec![
+                '\u{a0}'..='\u{a0}',
+                '\u{202a}'..='\u{202e}',
+                '\u{feff}'..='\u{feff}'
+            ])
+        );
+        assert_eq!(parse(""), Some(vec![]));
+        assert_eq!(parse("00A0"), None);
+        assert_eq!(parse("U+D800"), None);
+        assert_eq!(parse("U+200B-"), None);
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(describe('\u{202e}'), "U+202E RIGHT-TO-LEFT OVERRIDE");
+        assert_eq!(describe('\u{1f}'), "U+001F");
+    }
+}
@@ -0,0 +1,1292 @@
+use std::{
+    convert::Infallible,
+    fs::File,
+    io::{self, Read},
+    ops::{Range, RangeInclusive},
+    path::Path,
+    str::FromStr,
+    time::Duration,
+};
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    row::{nfc, LineEnding},
+    syntax::{TokenState, TokenType},
+    History, Position, Row, Syntax,
+};
+
+/// Edits arriving faster than this (e.g. key repeat) are merged into one undo step,
+/// unless the editor is configured otherwise.
+const HISTORY_COALESCE_WINDOW: Duration = Duration::from_millis(50);
+
+/// A text document: its lines, the file it belongs to, and its edit history.
+pub struct Buffer {
+    pub rows: Vec<Row>,
+    pub filename: Option<String>,
+    /// Whether there are unsaved changes.
+    pub dirty: bool,
+    /// The history version the file was last loaded or saved at, where undo and redo
+    /// leave the buffer clean.
+    saved_version: Option<usize>,
+    /// The number in the name of a scratch buffer, one without a file, as in
+    /// `Untitled-2`.
+    pub scratch: usize,
+    pub syntax: Syntax,
+    /// Whether `syntax` was chosen by name rather than by the file extension, so it is
+    /// kept when the file is saved under another name.
+    pub syntax_forced: bool,
+    /// How many bytes were loaded, if the buffer is a read-only preview of the start of
+    /// a bigger file.
+    pub preview: Option<u64>,
+    pub history: History<Row>,
+    /// While the buffer is highlighted a chunk at a time, how many lines from the top
+    /// are done. The lines below show as plain text until `highlight_more` gets there.
+    highlighted: Option<usize>,
+    /// How many lines `update_syntax_lines` has highlighted, for tests to see that an
+    /// edit is highlighted where it was made.
+    #[cfg(test)]
+    pub lines_highlighted: usize,
+
+    /// The counts for the history version they were taken at.
+    stats: Option<(usize, Stats)>,
+    /// The byte offset of each line's start and of the end of the buffer, for the
+    /// history version they were taken at.
+    line_starts: Option<(usize, Vec<usize>)>,
+    /// The number of lines wider than a limit, with the history version and limit.
+    long_lines: Option<(usize, usize, usize)>,
+    /// The number of hard to see characters, with the history version.
+    anomalies: Option<(usize, usize)>,
+    /// The lines listed in the outline, for the history version they were found at.
+    outline: Option<(usize, Vec<usize>)>,
+}
+
+/// Character and word counts, as shown in the status bar.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Grapheme clusters, not counting line breaks.
+    pub chars: usize,
+    pub words: usize,
+}
+
+impl Stats {
+    fn of<'a>(rows: impl IntoIterator<Item = &'a Row>) -> Self {
+        rows.into_iter().fold(Stats::default(), |stats, row| Stats {
+            chars: stats.chars + row.len(),
+            words: stats.words + row.to_string().unicode_words().count(),
+        })
+    }
+}
+
+impl Default for Buffer {
+    fn default() -> Self {
+        let mut history = History::default();
+        history.coalesce_window = HISTORY_COALESCE_WINDOW;
+        Buffer {
+            rows: vec![Row::from("")],
+            filename: None,
+            dirty: false,
+            saved_version: None,
+            scratch: 0,
+            syntax: Syntax::default(),
+            syntax_forced: false,
+            preview: None,
+            history,
+            stats: None,
+            line_starts: None,
+            long_lines: None,
+            anomalies: None,
+            outline: None,
+            highlighted: None,
+            #[cfg(test)]
+            lines_highlighted: 0,
+        }
+    }
+}
+
+impl FromStr for Buffer {
+    type Err = Infallible;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut buffer = Buffer::default();
+        let mut lines = text.split('\n').peekable();
+        buffer.rows.clear();
+        while let Some(line) = lines.next() {
+            // Every line but the last ends with a line break
+            let (line, ending) = match line.strip_suffix('\r') {
+                _ if lines.peek().is_none() => (line, None),
+                Some(line) => (line, Some(LineEnding::Crlf)),
+                None => (line, Some(LineEnding::Lf)),
+            };
+            let mut row = Row::from(line);
+            row.ending = ending;
+            buffer.rows.push(row);
+        }
+        Ok(buffer)
+    }
+}
+
+impl Buffer {
+    /// Read a file into a new buffer.
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let mut buffer: Buffer = text.parse().unwrap_or_default();
+        buffer.filename = Some(path.to_string_lossy().into_owned());
+        Ok(buffer)
+    }
+
+    /// Read the first `limit` bytes of a file into a new read-only preview, leaving out
+    /// a character or line break cut short at the end.
+    pub fn preview_file(path: &Path, limit: u64) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        File::open(path)?.take(limit).read_to_end(&mut bytes)?;
+        let text = match std::str::from_utf8(&bytes) {
+            Ok(text) => text,
+            Err(err) if err.error_len().is_none() => {
+                std::str::from_utf8(&bytes[..err.valid_up_to()]).unwrap()
+            }
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        };
+        let text = text.strip_suffix('\r').unwrap_or(text);
+        let mut buffer: Buffer = text.parse().unwrap_or_default();
+        buffer.filename = Some(path.to_string_lossy().into_owned());
+        buffer.preview = Some(text.len() as u64);
+        Ok(buffer)
+    }
+
+    /// Write the buffer to a file, keeping the line ending of every line.
+    pub fn write_to(&self, path: &Path) -> io::Result<()> {
+        std::fs::write(path, self.to_string())
+    }
+
+    /// Mark the buffer as matching its file as of the current history version.
+    pub fn mark_saved(&mut self) {
+        self.dirty = false;
+        self.saved_version = self.history.version();
+    }
+
+    /// Mark the buffer as having no file to match.
+    pub fn mark_unsaved(&mut self) {
+        self.dirty = true;
+        self.saved_version = None;
+    }
+
+    /// Whether the current history version differs from the one last saved.
+    pub fn differs_from_saved(&self) -> bool {
+        self.history.version() != self.saved_version
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// The line ending most lines use, which new line breaks get when saving.
+    pub fn line_ending(&self) -> LineEnding {
+        let (lf, crlf) = self.count_line_endings();
+        if crlf > lf {
+            LineEnding::Crlf
+        } else {
+            LineEnding::Lf
+        }
+    }
+
+    /// Whether some lines end with `\n` and others with `\r\n`.
+    pub fn has_mixed_line_endings(&self) -> bool {
+        let (lf, crlf) = self.count_line_endings();
+        lf > 0 && crlf > 0
+    }
+
+    fn count_line_endings(&self) -> (usize, usize) {
+        self.rows
+            .iter()
+            .fold((0, 0), |(lf, crlf), row| match row.ending {
+                Some(LineEnding::Lf) => (lf + 1, crlf),
+                Some(LineEnding::Crlf) => (lf, crlf + 1),
+                None => (lf, crlf),
+            })
+    }
+
+    /// Give every line break the same ending. Returns whether anything changed.
+    pub fn set_line_ending(&mut self, ending: LineEnding) -> bool {
+        let last = self.rows.len() - 1;
+        let mut changed = false;
+        for (i, row) in self.rows.iter_mut().enumerate() {
+            let ending = (i != last).then_some(ending);
+            changed |= row.ending != ending;
+            row.ending = ending;
+        }
+        self.dirty |= changed;
+        changed
+    }
+
+    #[allow(clippy::inherent_to_string)]
+    pub fn to_string(&self) -> String {
+        let default = self.line_ending();
+        let mut text = String::new();
+        for (i, row) in self.rows.iter().enumerate() {
+            if i > 0 {
+                let ending = self.rows[i - 1].ending.unwrap_or(default);
+                text.push_str(ending.as_str());
+            }
+            text.extend(row.rope.iter().map(|(g, _)| g.as_str()));
+        }
+        text
+    }
+
+    /// Where `pos` is as the end of a range: past the end of a row is past its line
+    /// break, at the start of the next row. Otherwise it is clamped to the buffer.
+    pub fn range_bound(&self, pos: Position) -> Position {
+        match self.rows.get(pos.y) {
+            Some(row) if pos.x > row.len() && pos.y + 1 < self.rows.len() => {
+                Position { x: 0, y: pos.y + 1 }
+            }
+            _ => self.clamp(pos),
+        }
+    }
+
+    /// Clamp a position to the end of its line. Positions past the last line go to the
+    /// end of the buffer, so that clamping keeps positions in order.
+    pub fn clamp(&self, pos: Position) -> Position {
+        let last = self.rows.len() - 1;
+        if pos.y > last {
+            return Position {
+                x: self.rows[last].len(),
+                y: last,
+            };
+        }
+        Position {
+            x: pos.x.min(self.rows[pos.y].len()),
+            y: pos.y,
+        }
+    }
+
+    /// The lines between two positions, each with the range of its cells that lies
+    /// between them.
+    pub fn row_ranges(
+        &self,
+        begin: Position,
+        end: Position,
+    ) -> impl Iterator<Item = (&Row, Range<usize>)> {
+        let (begin, end) = (
+            self.range_bound(begin.min(end)),
+            self.range_bound(begin.max(end)),
+        );
+        (begin.y..=end.y).map(move |y| {
+            let row = &self.rows[y];
+            let l = if y == begin.y { begin.x } else { 0 };
+            let r = if y == end.y { end.x } else { row.len() };
+            (row, l..r)
+        })
+    }
+
+    /// The text between two positions, with lines joined by `\n`.
+    pub fn text_range(&self, begin: Position, end: Position) -> String {
+        let mut text = String::new();
+        for (i, (row, range)) in self.row_ranges(begin, end).enumerate() {
+            if i > 0 {
+                text.push('\n');
+            }
+            text.extend(row.rope[range].iter().map(|(g, _)| g.as_str()));
+        }
+        text
+    }
+
+    /// Insert text at `pos`, which may span several lines (`\n` or `\r\n`).
+    /// Returns the position right after the inserted text.
+    pub fn insert(&mut self, pos: Position, text: &str) -> Position {
+        let pos = self.clamp(pos);
+        self.dirty = true;
+
+        let mut lines = text
+            .split('\n')
+            .map(|line| Row::from(line.strip_suffix('\r').unwrap_or(line)));
+        let first = lines.next().unwrap_or_default();
+
+        let right = self.rows[pos.y].split_off(pos.x);
+        self.rows[pos.y].append(first);
+
+        let mut end = Position {
+            x: self.rows[pos.y].len(),
+            y: pos.y,
+        };
+        for line in lines {
+            end = Position {
+                x: line.len(),
+                y: end.y + 1,
+            };
+            self.rows.insert(end.y, line);
+        }
+        self.rows[end.y].append(right);
+        end
+    }
+
+    /// Add text at the end without touching the lines before the last one, which is
+    /// continued. Only the changed lines are highlighted again. The buffer isn't marked
+    /// as modified.
+    pub fn append(&mut self, text: &str) {
+        let last = self.line_count() - 1;
+        let end = Position {
+            x: self.rows[last].len(),
+            y: last,
+        };
+        let dirty = self.dirty;
+        self.insert(end, text);
+        self.dirty = dirty;
+        self.stats = None;
+        self.line_starts = None;
+        self.long_lines = None;
+        self.anomalies = None;
+        self.outline = None;
+
+        let mut state = match last.checked_sub(1) {
+            Some(y) => self.rows[y].final_state.clone(),
+            None => TokenState::default(),
+        };
+        for row in &mut self.rows[last..] {
+            row.update_syntax(&self.syntax, &mut state);
+        }
+    }
+
+    /// Delete the text between two positions. Returns the position where the text was.
+    pub fn delete_range(&mut self, begin: Position, end: Position) -> Position {
+        let (begin, end) = (
+            self.range_bound(begin.min(end)),
+            self.range_bound(begin.max(end)),
+        );
+        if begin == end {
+            return begin;
+        }
+        self.dirty = true;
+
+        let right = self.rows[end.y].split_off(end.x);
+        self.rows[begin.y].split_off(begin.x);
+        self.rows[begin.y].append(right);
+        self.rows.drain(begin.y + 1..=end.y);
+        begin
+    }
+
+    /// Count the characters and words. Every edit records a history version, so the
+    /// counts are only taken again once the version has changed.
+    pub fn stats(&mut self) -> Stats {
+        match (self.history.version(), self.stats) {
+            (Some(version), Some((cached, stats))) if version == cached => stats,
+            (version, _) => {
+                let stats = Stats::of(&self.rows);
+                self.stats = version.map(|version| (version, stats));
+                stats
+            }
+        }
+    }
+
+    /// How many lines are wider than `limit` columns. Kept like `stats` until the
+    /// history version changes.
+    pub fn long_lines(&mut self, limit: usize) -> usize {
+        match (self.history.version(), self.long_lines) {
+            (Some(version), Some((v, l, count))) if (v, l) == (version, limit) => count,
+            (version, _) => {
+                let count = (self.rows.iter())
+                    .filter(|row| row.visual_width() > limit)
+                    .count();
+                self.long_lines = version.map(|version| (version, limit, count));
+                count
+            }
+        }
+    }
+
+    /// The cells on line `y` with hard to see characters in them. A byte order mark
+    /// at the very start of the file is where it belongs, and not counted.
+    pub fn anomalies_on(&self, y: usize) -> &[usize] {
+        let row = &self.rows[y];
+        match row.anomalies() {
+            [0, rest @ ..] if y == 0 && row.rope[0].0.starts_with('\u{feff}') => rest,
+            cells => cells,
+        }
+    }
+
+    /// How many cells have hard to see characters in them. Kept like `stats` until
+    /// the history version changes; the rows keep theirs until they are edited.
+    pub fn anomaly_count(&mut self) -> usize {
+        match (self.history.version(), self.anomalies) {
+            (Some(version), Some((cached, count))) if version == cached => count,
+            (version, _) => {
+                let count = (0..self.rows.len())
+                    .map(|y| self.anomalies_on(y).len())
+                    .sum();
+                self.anomalies = version.map(|version| (version, count));
+                count
+            }
+        }
+    }
+
+    /// The lines that start a definition: those whose first word, after any keywords
+    /// like `pub`, is one of the syntax's `structure_keywords`, outside multi-line
+    /// comments and strings. Kept like `stats` until the history version changes.
+    pub fn outline(&mut self) -> &[usize] {
+        let version = self.history.version();
+        if version.is_none() || self.outline.as_ref().map(|(v, _)| *v) != version {
+            let lines = (0..self.rows.len())
+                .filter(|&y| self.is_structure(&self.rows[y]))
+                .collect();
+            self.outline = Some((version.unwrap_or_default(), lines));
+        }
+        &self.outline.as_ref().unwrap().1
+    }
+
+    fn is_structure(&self, row: &Row) -> bool {
+        let blank = |i: usize| row.rope[i].0.chars().all(char::is_whitespace);
+        let mut start = 0;
+        while start < row.len() {
+            if blank(start) {
+                start += 1;
+                continue;
+            }
+            let end = (start..row.len()).find(|&i| blank(i)).unwrap_or(row.len());
+            let word: String = row.rope[start..end]
+                .iter()
+                .map(|(g, _)| g.as_str())
+                .collect();
+            match row.syntax.get(start) {
+                Some(TokenType::MlComment | TokenType::MlString) => return false,
+                _ if self.syntax.structure_keywords.contains(&word) => return true,
+                Some(TokenType::Keyword1 | TokenType::Keyword2 | TokenType::Keyword3) => {}
+                _ => return false,
+            }
+            start = end;
+        }
+        false
+    }
+
+    /// How many bytes of the saved file come before `pos`, counting the line breaks as
+    /// they will be written. Like `stats`, the line offsets are kept until the history
+    /// version changes, so only the cursor's own line is measured each time.
+    pub fn byte_offset(&mut self, pos: Position) -> usize {
+        let pos = self.clamp(pos);
+        let start = self.line_starts()[pos.y];
+        let row = &self.rows[pos.y].rope[..pos.x];
+        start + row.iter().map(|(g, _)| g.len()).sum::<usize>()
+    }
+
+    /// How many bytes of its line come before `pos`, as compilers count columns.
+    pub fn byte_column(&mut self, pos: Position) -> usize {
+        let pos = self.clamp(pos);
+        self.byte_offset(pos) - self.line_starts()[pos.y]
+    }
+
+    /// The size of the saved file in bytes.
+    pub fn byte_len(&mut self) -> usize {
+        *self.line_starts().last().unwrap()
+    }
+
+    fn line_starts(&mut self) -> &[usize] {
+        let version = self.history.version();
+        if version.is_none() || self.line_starts.as_ref().map(|(v, _)| *v) != version {
+            let default = self.line_ending();
+            let mut starts = vec![0];
+            for (i, row) in self.rows.iter().enumerate() {
+                let ending = match i + 1 < self.rows.len() {
+                    true => row.ending.unwrap_or(default).as_str().len(),
+                    false => 0,
+                };
+                let len = row.rope.iter().map(|(g, _)| g.len()).sum::<usize>();
+                starts.push(starts[i] + len + ending);
+            }
+            self.line_starts = Some((version.unwrap_or_default(), starts));
+        }
+        &self.line_starts.as_ref().unwrap().1
+    }
+
+    /// Whether most indented lines start with a tab rather than a space.
+    pub fn indents_with_tabs(&self) -> bool {
+        let (mut tabs, mut spaces) = (0, 0);
+        for row in &self.rows {
+            match row.rope.first().and_then(|(g, _)| g.chars().next()) {
+                Some('\t') => tabs += 1,
+                Some(' ') => spaces += 1,
+                _ => {}
+            }
+        }
+        tabs > spaces
+    }
+
+    /// `text` with the leading whitespace of its lines written the way the buffer
+    /// indents, with tabs or spaces. With `indent`, the whitespace its non-blank lines
+    /// have in common is replaced by `indent`, and blank lines are emptied.
+    ///
+    /// Unless `whole_lines`, the first line goes after other text and is left alone,
+    /// except that `indent` drops its leading whitespace.
+    pub fn fit_indent(
+        &self,
+        text: &str,
+        tab_width: usize,
+        whole_lines: bool,
+        indent: Option<&str>,
+    ) -> String {
+        let tabs = self.indents_with_tabs();
+        let lines: Vec<_> = text.split('\n').collect();
+        let skip = usize::from(!whole_lines);
+        let common = match indent {
+            Some(_) => (lines.iter().skip(skip))
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| indent_width(line, tab_width).0)
+                .min()
+                .unwrap_or(0),
+            None => 0,
+        };
+
+        let fitted: Vec<_> = (lines.iter().enumerate())
+            .map(|(i, line)| {
+                let (width, start) = indent_width(line, tab_width);
+                let (rest, prefix) = (&line[start..], indent.unwrap_or_default());
+                match indent {
+                    None if i < skip => line.to_string(),
+                    Some(_) if i < skip => rest.to_string(),
+                    Some(_) if line.trim().is_empty() => rest.to_string(),
+                    _ => {
+                        let width = width.saturating_sub(common);
+                        let whitespace = match tabs {
+                            true => "\t".repeat(width / tab_width) + &" ".repeat(width % tab_width),
+                            false => " ".repeat(width),
+                        };
+                        format!("{prefix}{whitespace}{rest}")
+                    }
+                }
+            })
+            .collect();
+        fitted.join("\n")
+    }
+
+    /// Delete the blank lines, or if `squeeze`, all but the first of each run of them.
+    /// The last line is kept, as it holds what comes after the final line break.
+    /// Returns the lines that were deleted, in order.
+    pub fn remove_blank_lines(&mut self, squeeze: bool) -> Vec<usize> {
+        let last = self.rows.len() - 1;
+        let removed: Vec<usize> = (0..last)
+            .filter(|&y| self.rows[y].is_blank())
+            .filter(|&y| !squeeze || (y > 0 && self.rows[y - 1].is_blank()))
+            .collect();
+        let mut y = 0;
+        self.rows.retain(|_| {
+            y += 1;
+            removed.binary_search(&(y - 1)).is_err()
+        });
+        self.dirty |= !removed.is_empty();
+        removed
+    }
+
+    /// Delete the ANSI escape sequences, such as the colors in a pasted log. Returns
+    /// how many there were.
+    pub fn strip_ansi(&mut self) -> usize {
+        let mut count = 0;
+        for row in &mut self.rows {
+            let (text, found) = strip_ansi(row.rope.iter().flat_map(|(g, _)| g.chars()));
+            if found > 0 {
+                let ending = row.ending;
+                *row = Row::from(text.as_str());
+                row.ending = ending;
+                count += found;
+            }
+        }
+        self.dirty |= count > 0;
+        count
+    }
+
+    /// Measure every line again, including the copy kept by the history, after the
+    /// width of ambiguous characters has changed.
+    pub fn remeasure(&mut self) {
+        for row in self.rows.iter_mut().chain(&mut self.history.current) {
+            row.remeasure();
+        }
+    }
+
+    /// Rewrite the text in Unicode normalization form C, so that accents pasted from
+    /// macOS as separate combining characters look the same as typed ones. Returns how
+    /// many lines changed.
+    pub fn normalize(&mut self) -> usize {
+        let mut count = 0;
+        for row in &mut self.rows {
+            let text = row.to_string();
+            let normalized = nfc(&text);
+            if normalized != text {
+                let ending = row.ending;
+                *row = Row::from(&*normalized);
+                row.ending = ending;
+                count += 1;
+            }
+        }
+        self.dirty |= count > 0;
+        count
+    }
+
+    /// Rewrite the leading whitespace of `lines` as whole levels of `tab_width` columns,
+    /// with tabs or spaces as the buffer mostly does. Lines keep their nearest level,
+    /// unless the syntax has `indent_brackets`, in which case it is the number of
+    /// brackets left open before them, not counting those in strings and comments.
+    /// Blank lines are emptied. Returns `false` if nothing changed.
+    pub fn reindent(&mut self, lines: RangeInclusive<usize>, tab_width: usize) -> bool {
+        let tabs = self.indents_with_tabs();
+        let levels: Vec<_> = if self.syntax.indent_brackets {
+            self.bracket_depths(*lines.end())[*lines.start()..].to_vec()
+        } else {
+            self.rows[lines.clone()]
+                .iter()
+                .map(|row| {
+                    let mut column = 0;
+                    for (g, _) in &row.rope[..row.first_non_blank()] {
+                        for c in g.chars() {
+                            column = match c {
+                                '\t' => (column / tab_width + 1) * tab_width,
+                                _ => column + 1,
+                            };
+                        }
+                    }
+                    (column + tab_width / 2) / tab_width
+                })
+                .collect()
+        };
+
+        let mut changed = false;
+        for (y, level) in lines.zip(levels) {
+            let row = &self.rows[y];
+            let indent = match level {
+                _ if row.is_blank() => String::new(),
+                _ if tabs => "\t".repeat(level),
+                _ => " ".repeat(level * tab_width),
+            };
+            let rest = &row.rope[row.first_non_blank()..];
+            let text = indent + &rest.iter().map(|(g, _)| g.as_str()).collect::<String>();
+            if text != row.to_string() {
+                let ending = row.ending;
+                self.rows[y] = Row::from(text.as_str());
+                self.rows[y].ending = ending;
+                changed = true;
+            }
+        }
+        self.dirty |= changed;
+        changed
+    }
+
+    /// The bracket nesting depth at the start of each row up to `last`, where closing
+    /// brackets at the start of a row already count.
+    fn bracket_depths(&self, last: usize) -> Vec<usize> {
+        let mut depth = 0usize;
+        let mut depths = Vec::new();
+        for row in &self.rows[..=last] {
+            let first = row.first_non_blank();
+            let closing = (row.rope[first..].iter())
+                .enumerate()
+                .take_while(|&(i, (g, _))| {
+                    matches!(g.as_str(), ")" | "]" | "}") && row.is_code(first + i)
+                })
+                .count();
+            depths.push(depth.saturating_sub(closing));
+            for (i, (g, _)) in row.rope.iter().enumerate() {
+                match g.as_str() {
+                    "(" | "[" | "{" if row.is_code(i) => depth += 1,
+                    ")" | "]" | "}" if row.is_code(i) => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+            }
+        }
+        depths
+    }
+
+    /// Whether `at` is between the two brackets of a pair like `{}`, both of them code
+    /// rather than part of a string or comment.
+    pub fn in_bracket_pair(&self, at: Position) -> bool {
+        let row = &self.rows[at.y];
+        let (Some(x), Some((close, _))) = (at.x.checked_sub(1), row.rope.get(at.x)) else {
+            return false;
+        };
+        let pair = (row.rope[x].0.as_str(), close.as_str());
+        matches!(pair, ("(", ")") | ("[", "]") | ("{", "}")) && row.is_code(x) && row.is_code(at.x)
+    }
+
+    /// The whitespace `y` starts with, and the same with one more level of indentation
+    /// after it, in tabs or `tab_width` spaces as the buffer mostly indents.
+    pub fn indents(&self, y: usize, tab_width: usize) -> (String, String) {
+        let row = &self.rows[y];
+        let indent: String = row.rope[..row.first_non_blank()]
+            .iter()
+            .map(|(g, _)| g.as_str())
+            .collect();
+        let level = match self.indents_with_tabs() {
+            true => "\t".to_string(),
+            false => " ".repeat(tab_width),
+        };
+        let deeper = indent.clone() + &level;
+        (indent, deeper)
+    }
+
+    /// Re-run syntax highlighting on every line, or only on the lines done so far while
+    /// highlighting a chunk at a time.
+    pub fn update_syntax(&mut self) {
+        let end = self.highlighted.unwrap_or(usize::MAX).min(self.rows.len());
+        let mut state = TokenState::default();
+        for line in &mut self.rows[..end] {
+            line.update_syntax(&self.syntax, &mut state);
+        }
+    }
+
+    /// Re-run syntax highlighting on `lines` after they were changed or moved, and on the
+    /// lines below them as far as the state they end in differs from before. The lines
+    /// after the range are expected to be the ones that were there before, in order.
+    pub fn update_syntax_lines(&mut self, lines: Range<usize>) {
+        let end = self.highlighted.unwrap_or(usize::MAX).min(self.rows.len());
+        let mut state = match lines.start.checked_sub(1) {
+            Some(y) => self.rows[y].final_state.clone(),
+            None => TokenState::default(),
+        };
+        for y in lines.start..end {
+            let before = self.rows[y].final_state.clone();
+            self.rows[y].update_syntax(&self.syntax, &mut state);
+            #[cfg(test)]
+            {
+                self.lines_highlighted += 1;
+            }
+            // An untouched line ending as it did leaves the rest as they were
+            if y >= lines.end && self.rows[y].final_state == before {
+                break;
+            }
+        }
+    }
+
+    /// Highlight from the top again, the first `lines` lines now and the rest in later
+    /// calls to `highlight_more`, so that a big file can be shown before it is all done.
+    pub fn update_syntax_lazily(&mut self, lines: usize) {
+        self.highlighted = Some(0);
+        self.highlight_more(lines);
+    }
+
+    /// Highlight up to `lines` more lines after the ones done by `update_syntax_lazily`
+    /// and earlier calls. Returns `false` if there was nothing left to do.
+    pub fn highlight_more(&mut self, lines: usize) -> bool {
+        let Some(start) = self.highlighted else {
+            return false;
+        };
+        let start = start.min(self.rows.len());
+        let end = (start + lines).min(self.rows.len());
+        let mut state = match start.checked_sub(1) {
+            Some(y) => self.rows[y].final_state.clone(),
+            None => TokenState::default(),
+        };
+        for row in &mut self.rows[start..end] {
+            row.update_syntax(&self.syntax, &mut state);
+        }
+        self.highlighted = (end < self.rows.len()).then_some(end);
+        true
+    }
+
+    /// While highlighting a chunk at a time, highlight the lines before `end` now if
+    /// they aren't done yet, so that lines scrolled to don't show as plain text.
+    pub fn highlight_to(&mut self, end: usize) {
+        if let Some(start) = self.highlighted.filter(|&start| start < end) {
+            self.highlight_more(end - start);
+        }
+    }
+}
+
+/// How many columns the leading spaces and tabs of `line` take, with tabs reaching to
+/// the next multiple of `tab_width`, and the byte index after them.
+fn indent_width(line: &str, tab_width: usize) -> (usize, usize) {
+    let mut width = 0;
+    for (i, c) in line.char_indices() {
+        width = match c {
+            '\t' => (width / tab_width + 1) * tab_width,
+            ' ' => width + 1,
+            _ => return (width, i),
+        };
+    }
+    (width, line.len())
+}
+
+/// Where in an escape sequence `strip_ansi` is.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    /// Outside of any.
+    None,
+    /// Right after ESC.
+    Start,
+    /// In the intermediate bytes of a two-part escape, like `ESC ( B`.
+    Intermediate,
+    /// In a control sequence, `ESC [` up to its final byte.
+    Csi,
+    /// In an operating system command or another string, `ESC ]` up to BEL or ST.
+    String,
+    /// After an ESC in a string, which starts the ST `ESC \` ending it.
+    StringEnd,
+}
+
+/// `chars` without the escape sequences in them, and how many there were. Sequences
+/// are followed char by char, whatever graphemes they were split into; one left
+/// unfinished at the end is dropped as well.
+fn strip_ansi(chars: impl Iterator<Item = char>) -> (String, usize) {
+    let (mut text, mut count) = (String::new(), 0);
+    let mut state = Escape::None;
+    for c in chars {
+        let next = match (state, c) {
+            (Escape::None, '\x1b') => Escape::Start,
+            (Escape::None, '\u{9b}') => Escape::Csi,
+            (Escape::None, '\u{9d}') => Escape::String,
+            (Escape::None, c) => {
+                text.push(c);
+                Escape::None
+            }
+            (Escape::Start, '[') => Escape::Csi,
+            (Escape::Start, ']' | 'P' | 'X' | '^' | '_') => Escape::String,
+            (Escape::Start | Escape::Intermediate, ' '..='/') => Escape::Intermediate,
+            (Escape::Start | Escape::Intermediate, _) => Escape::None,
+            (Escape::Csi, '@'..='~') => Escape::None,
+            // Parameters and intermediate bytes, or something that has no business
+            // in a sequence, which ends it as a terminal would
+            (Escape::Csi, ' '..='?') => Escape::Csi,
+            (Escape::Csi, _) => Escape::None,
+            (Escape::String, '\x07' | '\u{9c}') => Escape::None,
+            (Escape::String, '\x1b') => Escape::StringEnd,
+            (Escape::String, _) => Escape::String,
+            (Escape::StringEnd, '\\') => Escape::None,
+            (Escape::StringEnd, _) => Escape::String,
+        };
+        count += usize::from(state == Escape::None && next != Escape::None);
+        state = next;
+    }
+    (text, count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(text: &str) -> Buffer {
+        text.parse().unwrap()
+    }
+
+    fn pos(x: usize, y: usize) -> Position {
+        Position { x, y }
+    }
+
+    #[test]
+    fn test_from_str() {
+        let b = buffer("hello\nworld\n");
+        assert_eq!(b.line_count(), 3);
+        assert_eq!(b.line_ending(), LineEnding::Lf);
+        assert_eq!(b.to_string(), "hello\nworld\n");
+
+        let b = buffer("hello\r\nworld");
+        assert_eq!(b.line_count(), 2);
+        assert_eq!(b.line_ending(), LineEnding::Crlf);
+        assert_eq!(b.rows[0].to_string(), "hello");
+        assert_eq!(b.to_string(), "hello\r\nworld");
+
+        assert_eq!(buffer("").line_count(), 1);
+    }
+
+    #[test]
+    fn test_line_endings_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        for text in [
+            "one\ntwo\n",
+            "one\r\ntwo\r\n",
+            "one\r\ntwo\nthree\r\n\nfour",
+            "no line break",
+            "lone \r in the middle\r\n",
+        ] {
+            std::fs::write(&path, text).unwrap();
+            Buffer::from_file(&path).unwrap().write_to(&path).unwrap();
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn test_preview_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("big.txt");
+        std::fs::write(&path, "ab\r\n中文").unwrap();
+        let preview = |limit| {
+            let b = Buffer::preview_file(&path, limit).unwrap();
+            (b.to_string(), b.preview)
+        };
+        // "中" is three bytes, cut after the first
+        assert_eq!(preview(6), ("ab\r\n".to_string(), Some(4)));
+        assert_eq!(preview(3), ("ab".to_string(), Some(2)));
+        assert_eq!(preview(100), ("ab\r\n中文".to_string(), Some(10)));
+
+        std::fs::write(&path, b"ab\xff\xfecd").unwrap();
+        assert!(Buffer::preview_file(&path, 5).is_err());
+    }
+
+    #[test]
+    fn test_mixed_line_endings() {
+        let mut b = buffer("a\r\nb\nc\r\nd");
+        assert!(b.has_mixed_line_endings());
+        assert_eq!(b.line_ending(), LineEnding::Crlf);
+
+        // New line breaks use the usual ending, existing ones stay as they are
+        b.insert(pos(1, 3), "\n");
+        assert_eq!(b.to_string(), "a\r\nb\nc\r\nd\r\n");
+        b.insert(pos(1, 1), "\n");
+        assert_eq!(b.to_string(), "a\r\nb\r\n\nc\r\nd\r\n");
+        b.delete_range(pos(1, 0), pos(0, 2));
+        assert_eq!(b.to_string(), "a\nc\r\nd\n");
+
+        assert!(b.set_line_ending(LineEnding::Crlf));
+        assert!(!b.has_mixed_line_endings());
+        assert_eq!(b.to_string(), "a\r\nc\r\nd\r\n");
+        assert!(b.set_line_ending(LineEnding::Lf));
+        assert_eq!(b.to_string(), "a\nc\nd\n");
+        assert!(!b.set_line_ending(LineEnding::Lf));
+    }
+
+    #[test]
+    fn test_remove_blank_lines() {
+        let text = "a\n\n  \n\nb\n\t\nc\n\n";
+        let mut b = buffer(text);
+        assert_eq!(b.remove_blank_lines(true), vec![2, 3]);
+        assert_eq!(b.to_string(), "a\n\nb\n\t\nc\n\n");
+        assert!(b.remove_blank_lines(true).is_empty());
+
+        // The line after the final line break stays, so the file still ends with one
+        let mut b = buffer(text);
+        assert_eq!(b.remove_blank_lines(false), vec![1, 2, 3, 5, 7]);
+        assert_eq!(b.to_string(), "a\nb\nc\n");
+        assert!(b.dirty);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut b = buffer("cafe\u{301}\r\nplain\nA\u{30a}ngstro\u{308}m");
+        assert_eq!(b.normalize(), 2);
+        assert_eq!(b.to_string(), "café\r\nplain\nÅngström");
+        assert_eq!(b.rows[0].len(), 4);
+        assert!(b.dirty);
+        assert_eq!(b.normalize(), 0);
+    }
+
+    #[test]
+    fn test_strip_ansi() {
+        let mut b =
+            buffer("\x1b[1;31mred\x1b[0m plain\n\x1b]8;;http://a.io\x1b\\link\x1b]8;;\x07\nok");
+        assert_eq!(b.strip_ansi(), 4);
+        assert_eq!(b.to_string(), "red plain\nlink\nok");
+        assert_eq!(b.strip_ansi(), 0);
+
+        // Parts of a sequence joined to other characters in one grapheme, and 8-bit
+        // and two-part escapes
+        let (text, count) = strip_ansi("x\x1b[3m\u{301}y\u{9b}2Kz\x1b(Bw\x1b7".chars());
+        assert_eq!((text.as_str(), count), ("x\u{301}yzw", 4));
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut b = buffer("hello world");
+        assert_eq!(b.insert(pos(5, 0), ","), pos(6, 0));
+        assert_eq!(b.to_string(), "hello, world");
+        assert!(b.dirty);
+
+        // Multi-line insert splits the line around the inserted text
+        assert_eq!(b.insert(pos(6, 0), "\none\ntwo"), pos(3, 2));
+        assert_eq!(b.to_string(), "hello,\none\ntwo world");
+
+        assert_eq!(b.insert(pos(3, 2), "\n"), pos(0, 3));
+        assert_eq!(b.to_string(), "hello,\none\ntwo\n world");
+
+        // Out of range positions are clamped to the end of the line
+        assert_eq!(b.insert(pos(99, 1), "!"), pos(4, 1));
+        assert_eq!(b.rows[1].to_string(), "one!");
+    }
+
+    #[test]
+    fn test_insert_crlf_and_wide() {
+        let mut b = buffer("a\r\nb");
+        assert_eq!(b.insert(pos(1, 0), "你好\r\n🤗"), pos(1, 1));
+        assert_eq!(b.to_string(), "a你好\r\n🤗\r\nb");
+        assert_eq!(b.rows[0].len(), 3);
+        assert_eq!(b.rows[0].rope[1], ("你".to_string(), 2));
+        assert_eq!(b.rows[1].rope[0], ("🤗".to_string(), 2));
+    }
+
+    #[test]
+    fn test_text_range() {
+        let b = buffer("hello,\r\n你好🤗\r\nworld");
+        assert_eq!(b.text_range(pos(1, 0), pos(4, 0)), "ell");
+        assert_eq!(b.text_range(pos(4, 0), pos(2, 1)), "o,\n你好");
+        assert_eq!(b.text_range(pos(3, 1), pos(5, 2)), "\nworld");
+        assert_eq!(
+            b.text_range(pos(0, 0), pos(99, 99)),
+            "hello,\n你好🤗\nworld"
+        );
+        assert_eq!(b.text_range(pos(2, 1), pos(2, 1)), "");
+    }
+
+    #[test]
+    fn test_delete_range() {
+        let mut b = buffer("hello,\none\ntwo world");
+        assert_eq!(b.delete_range(pos(6, 0), pos(3, 2)), pos(6, 0));
+        assert_eq!(b.to_string(), "hello, world");
+
+        // Reversed positions are accepted
+        assert_eq!(b.delete_range(pos(6, 0), pos(5, 0)), pos(5, 0));
+        assert_eq!(b.to_string(), "hello world");
+
+        // Deleting a line break joins the lines
+        let mut b = buffer("ab\ncd");
+        assert_eq!(b.delete_range(pos(2, 0), pos(0, 1)), pos(2, 0));
+        assert_eq!(b.to_string(), "abcd");
+
+        // Past the end of a row is past its line break, except on the last row
+        let mut b = buffer("ab\ncd\nef");
+        assert_eq!(b.text_range(pos(1, 0), pos(3, 0)), "b\n");
+        assert_eq!(b.delete_range(pos(1, 0), pos(3, 0)), pos(1, 0));
+        assert_eq!(b.to_string(), "acd\nef");
+        assert_eq!(b.delete_range(pos(1, 1), pos(5, 1)), pos(1, 1));
+        assert_eq!(b.to_string(), "acd\ne");
+
+        // Past the last line is the end of the buffer
+        let mut b = buffer("ab\ncd");
+        assert_eq!(b.delete_range(pos(1, 1), pos(0, 2)), pos(1, 1));
+        assert_eq!(b.to_string(), "ab\nc");
+
+        let mut b = buffer("x");
+        assert_eq!(b.delete_range(pos(0, 0), pos(0, 0)), pos(0, 0));
+        assert!(!b.dirty);
+    }
+
+    #[test]
+    fn test_append() {
+        let mut b = buffer("one\ntw");
+        b.append("o\r\nthree\nfo");
+        assert_eq!(b.to_string(), "one\ntwo\nthree\nfo");
+        assert_eq!(b.line_count(), 4);
+        assert!(!b.dirty);
+        assert_eq!(b.stats().chars, 13);
+        b.append("ur");
+        assert_eq!(b.stats().chars, 15);
+    }
+
+    #[test]
+    fn test_reindent() {
+        let mut b = buffer("a\n   b\n\t  c\n  \nd");
+        assert!(b.reindent(0..=4, 4));
+        assert_eq!(b.to_string(), "a\n    b\n        c\n\nd");
+        assert!(!b.reindent(0..=4, 4));
+
+        // Mostly tab-indented buffers keep using tabs
+        let mut b = buffer("\ta\n\tb\n      c");
+        assert!(b.reindent(2..=2, 4));
+        assert_eq!(b.to_string(), "\ta\n\tb\n\t\tc");
+    }
+
+    #[test]
+    fn test_reindent_brackets() {
+        let mut b =
+            buffer("fn f() {\nlet s = \"{\"; // (\n  if x {\n    y(\n1,\n     2)\n}\n        }");
+        b.syntax = Syntax {
+            indent_brackets: true,
+            sl_string_quotes: vec!["\"".to_string()],
+            sl_comment_start: vec!["//".to_string()],
+            ..Default::default()
+        };
+        b.update_syntax();
+        assert!(b.reindent(1..=7, 2));
+        assert_eq!(
+            b.to_string(),
+            "fn f() {\n  let s = \"{\"; // (\n  if x {\n    y(\n      1,\n      2)\n  }\n}"
+        );
+    }
+
+    #[test]
+    fn test_fit_indent() {
+        // Leading tabs become spaces where the buffer indents with spaces, and back
+        let b = buffer("a\n    b");
+        assert_eq!(
+            b.fit_indent("x\n\ty\n\t  z", 4, true, None),
+            "x\n    y\n      z"
+        );
+        let b = buffer("\ta\n\tb");
+        assert_eq!(b.fit_indent("x\n      y", 4, true, None), "x\n\t  y");
+        // Text pasted after other text starts with a part of a line
+        assert_eq!(b.fit_indent("\tx\n    y", 4, false, None), "\tx\n\ty");
+
+        // The indentation the lines share is swapped for the one given
+        let b = buffer("a\n    b");
+        assert_eq!(
+            b.fit_indent("    x\n  \n      y\n", 2, true, Some("\t")),
+            "\tx\n\n\t  y\n"
+        );
+        assert_eq!(
+            b.fit_indent("  x\n    y\n  z", 4, false, Some("  ")),
+            "x\n    y\n  z"
+        );
+    }
+
+    #[test]
+    fn test_indents() {
+        let b = buffer("\tfn f() {}\n\t\tx\n    y");
+        assert_eq!(b.indents(0, 4), ("\t".to_string(), "\t\t".to_string()));
+        let b = buffer("  a\n    b");
+        assert_eq!(b.indents(1, 2), ("    ".to_string(), "      ".to_string()));
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut b = buffer("Hello, world!\n\n你好世界 🤗 foo_bar");
+        let stats = b.stats();
+        assert_eq!((stats.chars, stats.words), (27, 7));
+
+        // Counts are cached until the history moves to another version
+        let state = Position::default();
+        b.history.push_state(&b.rows, state, state, None);
+        assert_eq!(b.stats().chars, 27);
+        b.insert(pos(0, 1), "one more");
+        assert_eq!(b.stats().chars, 27);
+        b.history.push_state(&b.rows, state, state, None);
+        let stats = b.stats();
+        assert_eq!((stats.chars, stats.words), (35, 9));
+    }
+
+    #[test]
+    fn test_anomaly_count() {
+        let mut b = buffer("\u{feff}a\u{feff}\nb\u{202e}\u{a0}\n");
+        assert_eq!(b.anomalies_on(0), [2]);
+        assert_eq!(b.anomaly_count(), 3);
+
+        let state = Position::default();
+        b.history.push_state(&b.rows, state, state, None);
+        assert_eq!(b.anomaly_count(), 3);
+        b.delete_range(pos(1, 0), pos(2, 1));
+        assert_eq!(b.anomaly_count(), 3);
+        b.history.push_state(&b.rows, state, state, None);
+        assert_eq!(b.anomaly_count(), 1);
+    }
+
+    #[test]
+    fn test_long_lines() {
+        let mut b = buffer("12345\n中文中\n123\n");
+        assert_eq!(b.long_lines(4), 2);
+        assert_eq!(b.long_lines(5), 1);
+
+        let state = Position::default();
+        b.history.push_state(&b.rows, state, state, None);
+        assert_eq!(b.long_lines(3), 2);
+        b.insert(pos(0, 2), "4");
+        assert_eq!(b.long_lines(3), 2);
+        b.history.push_state(&b.rows, state, state, None);
+        assert_eq!(b.long_lines(3), 3);
+    }
+
+    #[test]
+    fn test_lazy_syntax() {
+        let text = "a /* b\nc\nd */ a\na\n/* e\nf";
+        let syntax = Syntax {
+            ml_comment_delims: Some(("/*".to_string(), "*/".to_string())),
+            keywords: vec![(TokenType::Keyword1, vec!["a".to_string()])],
+            ..Default::default()
+        };
+        let mut full = buffer(text);
+        full.syntax = syntax.clone();
+        full.update_syntax();
+
+        let mut b = buffer(text);
+        b.syntax = syntax;
+        b.update_syntax_lazily(2);
+        assert_eq!(b.highlighted, Some(2));
+        assert!(b.rows[2].syntax.is_empty());
+        // Edits redo only the lines already done
+        b.update_syntax();
+        assert!(b.rows[2].syntax.is_empty());
+
+        // Comments carry on across the chunks
+        assert!(b.highlight_more(3));
+        assert_eq!(b.highlighted, Some(5));
+        assert!(b.highlight_more(3));
+        assert_eq!(b.highlighted, None);
+        assert!(!b.highlight_more(3));
+        assert!(b.rows == full.rows);
+    }
+
+    #[test]
+    fn test_update_syntax_lines() {
+        let syntax = Syntax {
+            ml_comment_delims: Some(("/*".to_string(), "*/".to_string())),
+            keywords: vec![(TokenType::Keyword1, vec!["a".to_string()])],
+            ..Default::default()
+        };
+        let highlighted = |text: &str| {
+            let mut b = buffer(text);
+            b.syntax = syntax.clone();
+            b.update_syntax();
+            b
+        };
+
+        // Moving the opening of a comment down takes the lines it passes out of it
+        let mut b = highlighted("/* b\na\nc */ a\na");
+        b.rows[0..=1].rotate_right(1);
+        b.update_syntax_lines(0..2);
+        assert!(b.rows == highlighted("a\n/* b\nc */ a\na").rows);
+
+        // A copy of a line starts in the state the line ends in
+        let mut b = highlighted("a\n*/ a /*\nc */\na");
+        let copy = b.rows[1].clone();
+        b.rows.insert(2, copy);
+        b.update_syntax_lines(2..3);
+        assert!(b.rows == highlighted("a\n*/ a /*\n*/ a /*\nc */\na").rows);
+    }
+
+    #[test]
+    fn test_outline() {
+        let mut b = buffer(
+            "use std;\npub(crate) fn a() {}\n/*\nfn b\n*/\n  impl X {\n    fn c()\n// fn d\nlet f = fn",
+        );
+        b.syntax = Syntax {
+            ml_comment_delims: Some(("/*".to_string(), "*/".to_string())),
+            sl_comment_start: vec!["//".to_string()],
+            keywords: vec![(
+                TokenType::Keyword1,
+                ["pub", "crate", "fn", "impl", "let", "use"]
+                    .map(String::from)
+                    .to_vec(),
+            )],
+            structure_keywords: vec!["fn".to_string(), "impl".to_string()],
+            ..Default::default()
+        };
+        b.update_syntax();
+        assert_eq!(b.outline(), [1, 5, 6]);
+
+        let mut b = buffer("# Title\ntext # not\n## Part\n#tag\n```\n# code\n```");
+        b.syntax = Syntax {
+            ml_string_delim: Some("```".to_string()),
+            structure_keywords: vec!["#".to_string(), "##".to_string()],
+            ..Default::default()
+        };
+        b.update_syntax();
+        assert_eq!(b.outline(), [0, 2]);
+    }
+
+    #[test]
+    fn test_byte_offset() {
+        let mut b = buffer("a中\r\n🤗\nb");
+        assert_eq!(b.byte_offset(pos(1, 0)), 1);
+        assert_eq!(b.byte_offset(pos(0, 1)), 6);
+        assert_eq!(b.byte_offset(pos(1, 1)), 10);
+        assert_eq!(b.byte_offset(pos(9, 2)), 12);
+        assert_eq!(b.byte_len(), b.to_string().len());
+
+        // Offsets are cached until the history moves to another version
+        let state = Position::default();
+        b.history.push_state(&b.rows, state, state, None);
+        assert_eq!(b.byte_offset(pos(0, 2)), 11);
+        b.insert(pos(0, 0), "xyz\n");
+        assert_eq!(b.byte_offset(pos(0, 2)), 11);
+        b.history.push_state(&b.rows, state, state, None);
+        assert_eq!(b.byte_offset(pos(0, 2)), 10);
+        assert_eq!(b.byte_len(), b.to_string().len());
+    }
+
+    #[test]
+    fn test_delete_wide() {
+        let mut b = buffer("中文🤗ok\n第二行");
+        assert_eq!(b.delete_range(pos(1, 0), pos(2, 1)), pos(1, 0));
+        assert_eq!(b.to_string(), "中行");
+        assert_eq!(b.rows[0].rope.iter().map(|g| g.1).sum::<usize>(), 4);
+    }
+}
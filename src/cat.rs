@@ -0,0 +1,246 @@
+//! Non-interactive syntax highlighting, used by `arcaea --cat` and for copying the
+//! selection as rich text.
+
+use std::{
+    fmt::Write as _,
+    io::{self, BufWriter, Write},
+    ops::Range,
+    path::Path,
+};
+
+use crossterm::{
+    queue,
+    style::{Color, Print, ResetColor, SetForegroundColor},
+};
+
+use crate::{
+    style,
+    syntax::{TokenState, TokenType},
+    Error, Row, Syntax,
+};
+
+/// Print a file to stdout with syntax highlighting, using the definitions in
+/// `syntax_dir`: the one called `language` if given, or else the one for the file's
+/// extension. Colors are left out when the `NO_COLOR` environment variable is set.
+pub fn cat(path: &Path, syntax_dir: &Path, language: Option<&str>) -> Result<(), Error> {
+    let text = std::fs::read_to_string(path)?;
+
+    let syntax = match (language, path.extension().and_then(|ext| ext.to_str())) {
+        (Some(name), _) => Syntax::by_name(syntax_dir, name).unwrap_or_else(|err| {
+            eprintln!("arcaea: {:?}", err);
+            None
+        }),
+        (None, Some(ext)) => Syntax::get(syntax_dir, ext).unwrap_or_else(|err| {
+            eprintln!("arcaea: {:?}", err);
+            None
+        }),
+        (None, None) => None,
+    }
+    .unwrap_or_default();
+    let color = !std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    write_highlighted(&mut out, &text, &syntax, color)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Write `text` highlighted with `syntax`, switching the foreground color whenever
+/// the token type changes. Line endings are normalized to `\n`.
+pub fn write_highlighted(
+    out: &mut impl Write,
+    text: &str,
+    syntax: &Syntax,
+    color: bool,
+) -> io::Result<()> {
+    let mut state = TokenState::default();
+    let mut lines = text.split('\n').peekable();
+    while let Some(line) = lines.next() {
+        let mut row = Row::from(line.strip_suffix('\r').unwrap_or(line));
+        row.update_syntax(syntax, &mut state);
+
+        if color {
+            write_ansi_row(out, &row, 0..row.len())?;
+        } else {
+            out.write_all(row.to_string().as_bytes())?;
+        }
+
+        if lines.peek().is_some() {
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the cells of `row` in `range`, switching the foreground color whenever the
+/// token type changes and resetting it at the end.
+fn write_ansi_row(out: &mut impl Write, row: &Row, range: Range<usize>) -> io::Result<()> {
+    let mut spans = spans(row, range).peekable();
+    if spans.peek().is_none() {
+        return Ok(());
+    }
+    for (token, text) in spans {
+        queue!(
+            out,
+            SetForegroundColor(style::token_color(token)),
+            Print(text)
+        )?;
+    }
+    queue!(out, ResetColor)
+}
+
+/// Split the cells of `row` in `range` into runs of the same token type.
+fn spans(row: &Row, range: Range<usize>) -> impl Iterator<Item = (TokenType, String)> + '_ {
+    let mut cells = range
+        .map(|i| {
+            let token = row.syntax.get(i).copied().unwrap_or(TokenType::Normal);
+            (token, row.rope[i].0.as_str())
+        })
+        .peekable();
+    std::iter::from_fn(move || {
+        let (token, g) = cells.next()?;
+        let mut text = g.to_string();
+        while let Some((_, g)) = cells.next_if(|(next, _)| *next == token) {
+            text.push_str(g);
+        }
+        Some((token, text))
+    })
+}
+
+/// Highlight parts of rows with ANSI color escapes, one line per row.
+pub fn to_ansi<'a>(rows: impl IntoIterator<Item = (&'a Row, Range<usize>)>) -> String {
+    let mut out = Vec::new();
+    for (i, (row, range)) in rows.into_iter().enumerate() {
+        if i > 0 {
+            out.push(b'\n');
+        }
+        // Writing to a Vec can't fail
+        let _ = write_ansi_row(&mut out, row, range);
+    }
+    String::from_utf8(out).unwrap()
+}
+
+/// Highlight parts of rows as a self-contained HTML `<pre>` block, with the theme
+/// colors as inline styles.
+pub fn to_html<'a>(rows: impl IntoIterator<Item = (&'a Row, Range<usize>)>) -> String {
+    let mut html = format!(
+        "<pre style=\"background-color: {}; color: {}\">",
+        css_color(style::background()),
+        css_color(style::token_normal())
+    );
+    for (i, (row, range)) in rows.into_iter().enumerate() {
+        if i > 0 {
+            html.push('\n');
+        }
+        for (token, text) in spans(row, range) {
+            let text = escape_html(&text);
+            match token {
+                TokenType::Normal => html.push_str(&text),
+                _ => {
+                    let color = css_color(style::token_color(token));
+                    let _ = write!(html, "<span style=\"color: {color}\">{text}</span>");
+                }
+            }
+        }
+    }
+    html.push_str("</pre>");
+    html
+}
+
+fn css_color(color: Color) -> String {
+    match color {
+        Color::Rgb { r, g, b } => format!("#{r:02x}{g:02x}{b:02x}"),
+        _ => "inherit".to_string(),
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn highlight(text: &str, color: bool) -> String {
+        let syntax = Syntax {
+            keywords: vec![(TokenType::Keyword1, vec!["fn".to_string()])],
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write_highlighted(&mut out, text, &syntax, color).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_plain() {
+        assert_eq!(highlight("fn main\r\n{}\n", false), "fn main\n{}\n");
+        assert_eq!(highlight("no newline", false), "no newline");
+    }
+
+    fn ansi(command: impl crossterm::Command) -> String {
+        let mut result = String::new();
+        command.write_ansi(&mut result).unwrap();
+        result
+    }
+
+    #[test]
+    fn test_color() {
+        let output = highlight("fn main\n\n", true);
+        let keyword = ansi(SetForegroundColor(style::token_keyword1()));
+        let normal = ansi(SetForegroundColor(style::token_normal()));
+        let reset = ansi(ResetColor);
+        assert_eq!(output, format!("{keyword}fn{normal} main{reset}\n\n"));
+    }
+
+    fn highlighted_row(text: &str) -> Row {
+        let syntax = Syntax {
+            keywords: vec![(TokenType::Keyword1, vec!["fn".to_string()])],
+            ..Default::default()
+        };
+        let mut row = Row::from(text);
+        row.update_syntax(&syntax, &mut TokenState::default());
+        row
+    }
+
+    #[test]
+    fn test_to_ansi() {
+        let (first, second) = (highlighted_row("fn main"), highlighted_row("fn 中文"));
+        let keyword = ansi(SetForegroundColor(style::token_keyword1()));
+        let normal = ansi(SetForegroundColor(style::token_normal()));
+        let reset = ansi(ResetColor);
+        assert_eq!(
+            to_ansi([(&first, 1..4), (&second, 0..0), (&second, 0..4)]),
+            format!("{keyword}n{normal} m{reset}\n\n{keyword}fn{normal} 中{reset}")
+        );
+    }
+
+    #[test]
+    fn test_to_html() {
+        let row = highlighted_row("fn a<b && \"c\"");
+        let html = to_html([(&row, 0..row.len())]);
+        let keyword = css_color(style::token_keyword1());
+        assert!(html.starts_with("<pre style=\"background-color: #"));
+        assert!(html.ends_with(&format!(
+            "\"><span style=\"color: {keyword}\">fn</span> a&lt;b &amp;&amp; &quot;c&quot;</pre>"
+        )));
+        assert_eq!(
+            css_color(Color::Rgb {
+                r: 1,
+                g: 171,
+                b: 255
+            }),
+            "#01abff"
+        );
+    }
+}
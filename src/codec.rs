@@ -0,0 +1,197 @@
+//! Conversions of the selected text to and from Base64, URL percent-encoding and
+//! hexadecimal bytes.
+
+use crate::t;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+/// How many bytes each line of a hex dump holds.
+const HEX_BYTES_PER_LINE: usize = 16;
+
+/// A conversion offered by `Command::TransformSelection`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transform {
+    Base64Encode,
+    Base64Decode,
+    UrlEncode,
+    UrlDecode,
+    /// The UTF-8 bytes of the text as a hex dump, 16 bytes to a line.
+    HexEncode,
+    /// Hexadecimal bytes, whitespace ignored, read as UTF-8 text.
+    HexDecode,
+}
+
+impl Transform {
+    /// The transforms in the order they are offered.
+    pub const ALL: [Transform; 6] = [
+        Transform::Base64Encode,
+        Transform::Base64Decode,
+        Transform::UrlEncode,
+        Transform::UrlDecode,
+        Transform::HexEncode,
+        Transform::HexDecode,
+    ];
+
+    /// The name shown in the menu.
+    pub fn label(self) -> &'static str {
+        match self {
+            Transform::Base64Encode => t!("transform.base64_encode"),
+            Transform::Base64Decode => t!("transform.base64_decode"),
+            Transform::UrlEncode => t!("transform.url_encode"),
+            Transform::UrlDecode => t!("transform.url_decode"),
+            Transform::HexEncode => t!("transform.hex_encode"),
+            Transform::HexDecode => t!("transform.hex_decode"),
+        }
+    }
+
+    /// Convert `text`, or say why it can't be decoded.
+    pub fn apply(self, text: &str) -> Result<String, String> {
+        let utf8 = |bytes| String::from_utf8(bytes).map_err(|_| t!("transform.not_utf8"));
+        match self {
+            Transform::Base64Encode => Ok(base64_encode(text.as_bytes())),
+            Transform::Base64Decode => utf8(base64_decode(text)?),
+            Transform::UrlEncode => Ok(url_encode(text)),
+            Transform::UrlDecode => utf8(url_decode(text)?),
+            Transform::HexEncode => Ok(hex_encode(text.as_bytes())),
+            Transform::HexDecode => utf8(hex_decode(text)?),
+        }
+        .map_err(str::to_string)
+    }
+}
+
+/// Standard Base64 with padding.
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = (chunk.iter().enumerate()).fold(0, |n, (i, &b)| n | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            text.push(match i <= chunk.len() {
+                true => BASE64[(n >> (18 - 6 * i) & 63) as usize] as char,
+                false => '=',
+            });
+        }
+    }
+    text
+}
+
+/// Base64 with or without padding, ignoring whitespace such as line breaks.
+fn base64_decode(text: &str) -> Result<Vec<u8>, &'static str> {
+    let invalid = || t!("transform.invalid_base64");
+    let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    let digits = (text.trim_end_matches('=').bytes())
+        .map(|b| BASE64.iter().position(|&d| d == b).ok_or_else(invalid))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut bytes = Vec::with_capacity(digits.len() * 3 / 4);
+    for chunk in digits.chunks(4) {
+        if chunk.len() == 1 {
+            return Err(invalid());
+        }
+        let n = (chunk.iter().enumerate()).fold(0, |n, (i, &d)| n | (d as u32) << (18 - 6 * i));
+        bytes.extend((0..chunk.len() - 1).map(|i| (n >> (16 - 8 * i)) as u8));
+    }
+    Ok(bytes)
+}
+
+/// Percent-encode everything but the unreserved characters of RFC 3986.
+fn url_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for b in text.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{b:02X}")),
+        }
+    }
+    encoded
+}
+
+/// Decode `%XX` escapes, leaving everything else as it is.
+fn url_decode(text: &str) -> Result<Vec<u8>, &'static str> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let byte = (tail.get(..2))
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| t!("transform.invalid_url"))?;
+            bytes.push(byte);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    Ok(bytes)
+}
+
+/// Lowercase hex bytes separated by spaces, `HEX_BYTES_PER_LINE` to a line.
+fn hex_encode(bytes: &[u8]) -> String {
+    (bytes.chunks(HEX_BYTES_PER_LINE))
+        .map(|line| {
+            let hex: Vec<_> = line.iter().map(|b| format!("{b:02x}")).collect();
+            hex.join(" ")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Pairs of hex digits, with any whitespace between them.
+fn hex_decode(text: &str) -> Result<Vec<u8>, &'static str> {
+    let digits: Vec<_> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.len() % 2 != 0 {
+        return Err(t!("transform.invalid_hex"));
+    }
+    (digits.chunks(2))
+        .map(|pair| {
+            let hex: String = pair.iter().collect();
+            u8::from_str_radix(&hex, 16).map_err(|_| t!("transform.invalid_hex"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips() {
+        let pairs = [
+            (Transform::Base64Encode, Transform::Base64Decode),
+            (Transform::UrlEncode, Transform::UrlDecode),
+            (Transform::HexEncode, Transform::HexDecode),
+        ];
+        for text in ["", "a", "ab", "abc", "你好, world!\n🤗", &"x~/".repeat(20)] {
+            for (encode, decode) in pairs {
+                let encoded = encode.apply(text).unwrap();
+                assert_eq!(decode.apply(&encoded).unwrap(), text, "{encode:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_encodings() {
+        assert_eq!(base64_encode(b"Man"), "TWFu");
+        assert_eq!(base64_encode("中".as_bytes()), "5Lit");
+        assert_eq!(base64_encode(b"ab"), "YWI=");
+        assert_eq!(base64_decode("YW\nI").unwrap(), b"ab");
+        assert_eq!(url_encode("a b/中~"), "a%20b%2F%E4%B8%AD~");
+        assert_eq!(url_decode("a+b%2f").unwrap(), b"a+b/");
+        assert_eq!(hex_encode(&[0, 255, 16]), "00 ff 10");
+        assert_eq!(hex_encode(&[7; 17]).lines().count(), 2);
+        assert_eq!(hex_decode("0A ff\n10").unwrap(), [10, 255, 16]);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Transform::Base64Decode.apply("a").is_err());
+        assert!(Transform::Base64Decode.apply("ab$c").is_err());
+        // Valid Base64, but not of text
+        assert!(Transform::Base64Decode.apply("/w==").is_err());
+        assert!(Transform::UrlDecode.apply("100%").is_err());
+        assert!(Transform::UrlDecode.apply("%zz").is_err());
+        assert!(Transform::HexDecode.apply("abc").is_err());
+        assert!(Transform::HexDecode.apply("0g").is_err());
+    }
+}
@@ -0,0 +1,457 @@
+use std::{
+    collections::HashMap,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    anomaly, i18n,
+    keymap::{Keymap, WheelSpeed},
+    syntax::{process_ini_file, pv, SYNTAX_DIR},
+    t, Error,
+};
+
+/// User settings, read from `config.ini`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// Number of spaces inserted by the Tab key.
+    pub tab_width: usize,
+    /// Minimum number of lines and columns kept visible around the cursor.
+    pub scrolloff: usize,
+    /// Save the file automatically after it has been modified for this long.
+    pub autosave: Option<Duration>,
+    /// Edits made within this long of each other are undone together.
+    pub history_coalesce: Duration,
+    /// Lines wider than this many columns are marked and counted; `None` if unlimited.
+    pub max_line_length: Option<usize>,
+    /// Files bigger than this many megabytes are only loaded whole after asking, and
+    /// can be previewed instead; `None` if unlimited.
+    pub large_file_mb: Option<u64>,
+    /// The hard to see characters that are counted in the status bar and can be gone
+    /// through one by one.
+    pub anomaly_chars: Vec<RangeInclusive<char>>,
+    /// Render spaces as visible dots.
+    pub show_whitespace: bool,
+    /// Name the keys that do nothing in a toast when pressed, as they would be written
+    /// in the `[keys]` section.
+    pub show_unbound_keys: bool,
+    /// Show the cursor's byte offset in the file and how far through it it is in the
+    /// status bar.
+    pub show_offset: bool,
+    /// How far the mouse wheel scrolls.
+    pub wheel: WheelSpeed,
+    /// Let the view scroll down until the last line is at the top of the screen.
+    pub scroll_past_end: bool,
+    /// Whether the editor captures mouse events (disable to use the terminal's own selection).
+    pub mouse_capture: bool,
+    /// Interface language, overriding the locale environment variables.
+    pub language: Option<String>,
+    /// Count East Asian ambiguous-width characters as two columns; `None` decides by
+    /// the locale.
+    pub cjk_width: Option<bool>,
+    /// Path of a theme file.
+    pub theme: Option<PathBuf>,
+    /// Directory the syntax definitions are loaded from.
+    pub syntax_dir: PathBuf,
+    /// Key bindings, from the `[keys]` section.
+    pub keymap: Keymap,
+    /// External command the buffer is piped through by the format command.
+    pub format_command: Option<String>,
+    /// Format commands for particular file extensions, from the `[format]` section.
+    pub formatters: HashMap<String, String>,
+    /// Run the formatter before saving.
+    pub format_on_save: bool,
+    /// Whether search and counting occurrences ignore case.
+    pub search_ignore_case: bool,
+    /// Keep the text of scratch buffers closed without saving, to be restored later.
+    pub keep_discarded: bool,
+    /// Convert opened files, pasted text and accents typed with dead keys to Unicode
+    /// normalization form C. Search ignores the difference either way.
+    pub normalize_unicode: bool,
+    /// Write the indentation of pasted text with tabs or spaces as the buffer does.
+    pub paste_convert_indent: bool,
+    /// Vim-like modal editing, where Esc switches to normal mode instead of quitting.
+    pub vim_mode: bool,
+    /// How the insert-date command writes the date, in `strftime` notation.
+    pub date_format: String,
+    /// Snippets for every language, from the `[snippet]` section. Those of the syntax
+    /// definition take precedence.
+    pub snippets: HashMap<String, String>,
+    /// Show the file read-only and add what is written to it, like `tail -f`. Only set
+    /// by `--follow`.
+    pub follow: bool,
+    /// The name of the syntax definition to highlight the file with, whatever its
+    /// extension. Only set by `--language`.
+    pub syntax: Option<String>,
+
+    /// Problems found while loading, e.g. unknown keys. They are reported but never fatal.
+    pub warnings: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            tab_width: 4,
+            scrolloff: 2,
+            autosave: None,
+            history_coalesce: Duration::from_millis(50),
+            max_line_length: None,
+            large_file_mb: Some(50),
+            anomaly_chars: anomaly::DEFAULT.to_vec(),
+            show_whitespace: false,
+            show_unbound_keys: cfg!(feature = "debug"),
+            show_offset: false,
+            scroll_past_end: false,
+            wheel: WheelSpeed::default(),
+            mouse_capture: true,
+            language: None,
+            cjk_width: None,
+            theme: None,
+            syntax_dir: PathBuf::from(SYNTAX_DIR),
+            keymap: Keymap::default(),
+            format_command: None,
+            formatters: HashMap::new(),
+            format_on_save: false,
+            search_ignore_case: false,
+            keep_discarded: true,
+            normalize_unicode: false,
+            paste_convert_indent: false,
+            vim_mode: false,
+            date_format: "%Y-%m-%d".to_string(),
+            snippets: HashMap::new(),
+            follow: false,
+            syntax: None,
+            warnings: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// The location of the configuration file.
+    ///
+    /// `$ARCAEA_CONFIG` takes precedence, then `$XDG_CONFIG_HOME/arcaea/config.ini`,
+    /// then the platform's usual configuration directory.
+    pub fn path() -> Option<PathBuf> {
+        let var = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+        if let Some(path) = var("ARCAEA_CONFIG") {
+            return Some(PathBuf::from(path));
+        }
+        let dir = if let Some(dir) = var("XDG_CONFIG_HOME") {
+            PathBuf::from(dir)
+        } else if cfg!(windows) {
+            PathBuf::from(var("APPDATA")?)
+        } else if cfg!(target_os = "macos") {
+            PathBuf::from(var("HOME")?).join("Library/Application Support")
+        } else {
+            PathBuf::from(var("HOME")?).join(".config")
+        };
+        Some(dir.join("arcaea").join("config.ini"))
+    }
+
+    /// Load the configuration file, falling back to the defaults if there is none.
+    pub fn load() -> Self {
+        match Self::path() {
+            Some(path) if path.is_file() => Self::from_file(&path),
+            _ => Self::default(),
+        }
+    }
+
+    /// Load a configuration file. A file that cannot be parsed leaves the defaults in
+    /// place and is reported in `warnings`.
+    pub fn from_file(path: &Path) -> Self {
+        let mut config = Self::default();
+        let mut warnings = Vec::new();
+        let result = process_ini_file(path, &mut |key, value, line| {
+            // A binding with a typo is left out, and the command keeps its defaults
+            if let Some(command) = key.strip_prefix("keys.") {
+                match config.keymap.set(command, value) {
+                    Ok(conflicts) => warnings.extend(conflicts),
+                    Err(message) => {
+                        let err = Error::FileError(path.into(), line, message);
+                        warnings.push(format!("{:?}", err));
+                    }
+                }
+                return Ok(());
+            }
+            if let Err(message) = config.set(key, value) {
+                warnings.push(message);
+            }
+            Ok(())
+        });
+        if let Err(err) = result {
+            config = Self::default();
+            warnings.push(format!("{:?}", err));
+        }
+        config.warnings = warnings;
+        config
+    }
+
+    /// Apply a single `key=value` setting. Command line flags go through here too, after
+    /// the file has been loaded, so they take precedence over it.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        let invalid = || t!("config.invalid_value", key, value);
+        match key {
+            "tab_width" => match pv(value) {
+                Ok(0) | Err(_) => return Err(invalid()),
+                Ok(width) => self.tab_width = width,
+            },
+            "scrolloff" => self.scrolloff = pv(value).map_err(|_| invalid())?,
+            "autosave" => {
+                let seconds: u64 = pv(value).map_err(|_| invalid())?;
+                self.autosave = (seconds > 0).then(|| Duration::from_secs(seconds));
+            }
+            "history_coalesce_ms" => {
+                self.history_coalesce = Duration::from_millis(pv(value).map_err(|_| invalid())?);
+            }
+            "max_line_length" => {
+                let columns: usize = pv(value).map_err(|_| invalid())?;
+                self.max_line_length = (columns > 0).then_some(columns);
+            }
+            "large_file_mb" => {
+                let megabytes: u64 = pv(value).map_err(|_| invalid())?;
+                self.large_file_mb = (megabytes > 0).then_some(megabytes);
+            }
+            "anomaly_chars" => self.anomaly_chars = anomaly::parse(value).ok_or_else(invalid)?,
+            "show_whitespace" => self.show_whitespace = pv(value).map_err(|_| invalid())?,
+            "show_offset" => self.show_offset = pv(value).map_err(|_| invalid())?,
+            "show_unbound_keys" => self.show_unbound_keys = pv(value).map_err(|_| invalid())?,
+            "scroll_lines" | "scroll_lines_fast" | "scroll_columns" => {
+                let amount = match pv(value) {
+                    Ok(0) | Err(_) => return Err(invalid()),
+                    Ok(amount) => amount,
+                };
+                *match key {
+                    "scroll_lines" => &mut self.wheel.lines,
+                    "scroll_lines_fast" => &mut self.wheel.fast_lines,
+                    _ => &mut self.wheel.columns,
+                } = amount;
+            }
+            "scroll_past_end" => self.scroll_past_end = pv(value).map_err(|_| invalid())?,
+            "mouse_capture" => self.mouse_capture = pv(value).map_err(|_| invalid())?,
+            "language" => {
+                if !i18n::is_supported(value) {
+                    return Err(invalid());
+                }
+                self.language = Some(value.to_string());
+            }
+            "cjk_width" => {
+                self.cjk_width = match value {
+                    "" | "auto" => None,
+                    _ => Some(pv(value).map_err(|_| invalid())?),
+                }
+            }
+            "theme" => self.theme = (!value.is_empty()).then(|| PathBuf::from(value)),
+            "syntax_dir" => {
+                if value.is_empty() {
+                    return Err(invalid());
+                }
+                self.syntax_dir = PathBuf::from(value);
+            }
+            "format_command" => {
+                self.format_command = (!value.is_empty()).then(|| value.to_string())
+            }
+            "format_on_save" => self.format_on_save = pv(value).map_err(|_| invalid())?,
+            "search_ignore_case" => self.search_ignore_case = pv(value).map_err(|_| invalid())?,
+            "keep_discarded" => self.keep_discarded = pv(value).map_err(|_| invalid())?,
+            "normalize_unicode" => self.normalize_unicode = pv(value).map_err(|_| invalid())?,
+            "date_format" => {
+                if value.is_empty() {
+                    return Err(invalid());
+                }
+                self.date_format = value.to_string();
+            }
+            "vim_mode" => self.vim_mode = pv(value).map_err(|_| invalid())?,
+            "paste_convert_indent" => {
+                self.paste_convert_indent = pv(value).map_err(|_| invalid())?
+            }
+            _ if key.starts_with("format.") => {
+                let ext = &key["format.".len()..];
+                if value.is_empty() {
+                    self.formatters.remove(ext);
+                } else {
+                    self.formatters.insert(ext.to_string(), value.to_string());
+                }
+            }
+            _ if key.starts_with("snippet.") => {
+                let trigger = &key["snippet.".len()..];
+                if value.is_empty() {
+                    self.snippets.remove(trigger);
+                } else {
+                    self.snippets.insert(trigger.to_string(), value.to_string());
+                }
+            }
+            _ => return Err(t!("config.unknown_key", key)),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn config_file(content: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = Config::default();
+        assert_eq!(config.tab_width, 4);
+        assert_eq!(config.scrolloff, 2);
+        assert_eq!(config.autosave, None);
+        assert!(!config.show_whitespace);
+        assert!(config.mouse_capture);
+
+        let file = config_file("; nothing here\n\n");
+        assert_eq!(Config::from_file(file.path()), config);
+    }
+
+    #[test]
+    fn test_parse() {
+        let file = config_file(
+            "tab_width = 2\nscrolloff=5\nautosave=30\nshow_whitespace=true\nmouse_capture=false\nlanguage=en\ntheme=dark.ini\nvim_mode=true\nsearch_ignore_case=true\nkeep_discarded=false\nnormalize_unicode=true\npaste_convert_indent=true\nshow_offset=true\nshow_unbound_keys=true\nscroll_past_end=true\nmax_line_length=100\nlarge_file_mb=0\ncjk_width=true\ndate_format=%d/%m/%Y\nscroll_lines=3\nscroll_columns=8\nanomaly_chars=U+00A0 U+200B-U+200D\nhistory_coalesce_ms=0\n",
+        );
+        let config = Config::from_file(file.path());
+        assert_eq!(config.tab_width, 2);
+        assert_eq!(config.scrolloff, 5);
+        assert_eq!(config.autosave, Some(Duration::from_secs(30)));
+        assert_eq!(config.history_coalesce, Duration::ZERO);
+        assert!(config.show_whitespace);
+        assert!(!config.mouse_capture);
+        assert_eq!(config.language.as_deref(), Some("en"));
+        assert_eq!(config.theme, Some(PathBuf::from("dark.ini")));
+        assert!(config.vim_mode);
+        assert!(config.search_ignore_case);
+        assert!(config.normalize_unicode);
+        assert!(config.paste_convert_indent);
+        assert!(!config.keep_discarded);
+        assert!(config.show_offset);
+        assert!(config.show_unbound_keys);
+        assert!(config.scroll_past_end);
+        assert_eq!(config.max_line_length, Some(100));
+        assert_eq!(config.large_file_mb, None);
+        assert_eq!(config.cjk_width, Some(true));
+        assert_eq!(config.date_format, "%d/%m/%Y");
+        assert_eq!((config.wheel.lines, config.wheel.fast_lines), (3, 5));
+        assert_eq!(config.wheel.columns, 8);
+        assert_eq!(
+            config.anomaly_chars,
+            ['\u{a0}'..='\u{a0}', '\u{200b}'..='\u{200d}']
+        );
+        assert!(config.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_warnings() {
+        let file = config_file("tab_width=0\ncolour=red\nscrolloff=3\n");
+        let config = Config::from_file(file.path());
+        assert_eq!(config.tab_width, 4);
+        assert_eq!(config.scrolloff, 3);
+        assert_eq!(config.warnings.len(), 2);
+
+        // A malformed file is reported and ignored as a whole
+        let file = config_file("scrolloff=3\nnot a setting\n");
+        let config = Config::from_file(file.path());
+        assert_eq!(config.scrolloff, 2);
+        assert_eq!(config.warnings.len(), 1);
+        assert!(config.warnings[0].contains("line 2"));
+
+        // An empty value is invalid rather than an unknown key
+        let mut config = Config::default();
+        let invalid =
+            |key: &str| -> Result<(), String> { Err(t!("config.invalid_value", key, "")) };
+        assert_eq!(config.set("syntax_dir", " "), invalid("syntax_dir"));
+        assert_eq!(config.syntax_dir, PathBuf::from(SYNTAX_DIR));
+        assert_eq!(config.set("date_format", ""), invalid("date_format"));
+        assert_eq!(config.date_format, "%Y-%m-%d");
+    }
+
+    #[test]
+    fn test_keys() {
+        use crate::keymap::Command;
+        use crossterm::event::{KeyCode, KeyModifiers};
+
+        let file = config_file("tab_width=2\n\n[keys]\nquit = ctrl+q\nfind=ctrl+s\n");
+        let config = Config::from_file(file.path());
+        let keymap = &config.keymap;
+        assert_eq!(config.tab_width, 2);
+        assert_eq!(
+            keymap.get(KeyModifiers::CONTROL, KeyCode::Char('q')),
+            Some(Command::Quit)
+        );
+        assert_eq!(
+            keymap.get(KeyModifiers::CONTROL, KeyCode::Char('s')),
+            Some(Command::Find)
+        );
+        assert_eq!(
+            keymap.get(KeyModifiers::CONTROL, KeyCode::Char('z')),
+            Some(Command::Undo)
+        );
+        assert_eq!(config.warnings.len(), 1);
+
+        // An invalid binding is reported and left out, and the rest of the file applies
+        let file = config_file("[keys]\nquit=ctrl+shift+nope\nfind=ctrl+s\n");
+        let config = Config::from_file(file.path());
+        assert_eq!(
+            config.keymap.bindings(Command::Quit),
+            Keymap::default().bindings(Command::Quit)
+        );
+        assert_eq!(
+            config.keymap.get(KeyModifiers::CONTROL, KeyCode::Char('s')),
+            Some(Command::Find)
+        );
+        assert_eq!(config.warnings.len(), 2);
+        assert!(config.warnings[0].contains("ctrl+shift+nope"));
+        assert!(config.warnings[0].contains("line 2"));
+    }
+
+    #[test]
+    fn test_format() {
+        let file = config_file(
+            "format_command=prettier --stdin-filepath x\nformat_on_save=true\n[format]\nrs=rustfmt --emit stdout\n",
+        );
+        let config = Config::from_file(file.path());
+        assert_eq!(
+            config.format_command.as_deref(),
+            Some("prettier --stdin-filepath x")
+        );
+        assert_eq!(
+            config.formatters.get("rs").map(String::as_str),
+            Some("rustfmt --emit stdout")
+        );
+        assert!(config.format_on_save);
+        assert!(config.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_snippets() {
+        let file = config_file("[snippet]\nfor=for ${1:i} in $2 {\\n    $0\\n}\nx=\n");
+        let config = Config::from_file(file.path());
+        assert_eq!(
+            config.snippets.get("for").map(String::as_str),
+            Some(r"for ${1:i} in $2 {\n    $0\n}")
+        );
+        assert!(!config.snippets.contains_key("x"));
+        assert!(config.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_precedence() {
+        let file = config_file("language=zh\ntab_width=8\n");
+        let mut config = Config::from_file(file.path());
+        assert_eq!(config.language.as_deref(), Some("zh"));
+
+        // Command line flags are applied after the file
+        config.set("language", "en").unwrap();
+        assert_eq!(config.language.as_deref(), Some("en"));
+        assert_eq!(config.tab_width, 8);
+        assert!(config.set("language", "xx").is_err());
+        assert_eq!(config.language.as_deref(), Some("en"));
+    }
+}
@@ -0,0 +1,218 @@
+//! Calendar dates from the system clock, formatted with `strftime`-like patterns for
+//! the insert-date command.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// A moment as shown on a wall clock in some time zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    /// Days since 1970-01-01.
+    days: i64,
+    pub year: i64,
+    /// 1 to 12.
+    pub month: u32,
+    /// 1 to 31.
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    /// Seconds east of UTC.
+    pub offset: i64,
+}
+
+impl DateTime {
+    /// The current time in the local time zone, or in UTC where it isn't known.
+    pub fn now() -> Self {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() as i64);
+        Self::from_timestamp(secs, local_offset(secs))
+    }
+
+    /// The time `secs` seconds after the Unix epoch, `offset` seconds east of UTC.
+    pub fn from_timestamp(secs: i64, offset: i64) -> Self {
+        let local = secs + offset;
+        let (days, secs) = (local.div_euclid(86400), local.rem_euclid(86400) as u32);
+        let (year, month, day) = civil_from_days(days);
+        DateTime {
+            days,
+            year,
+            month,
+            day,
+            hour: secs / 3600,
+            minute: secs / 60 % 60,
+            second: secs % 60,
+            offset,
+        }
+    }
+
+    /// The day of the week, from 1 for Monday to 7 for Sunday.
+    pub fn weekday(&self) -> u32 {
+        // 1970-01-01 was a Thursday
+        (self.days + 3).rem_euclid(7) as u32 + 1
+    }
+
+    /// The day of the year, from 1.
+    pub fn ordinal(&self) -> u32 {
+        (self.days - days_from_civil(self.year, 1, 1)) as u32 + 1
+    }
+
+    /// The ISO 8601 week-numbering year and week, from 1 to 53. Weeks start on Monday
+    /// and belong to the year their Thursday is in.
+    pub fn iso_week(&self) -> (i64, u32) {
+        let thursday = Self::from_timestamp((self.days - self.weekday() as i64 + 4) * 86400, 0);
+        (thursday.year, (thursday.ordinal() - 1) / 7 + 1)
+    }
+
+    /// Fill in `format` like `strftime`: `%Y` `%y` `%m` `%d` `%e` `%H` `%M` `%S` `%j`
+    /// `%a` `%A` `%b` `%B` `%u` `%G` `%V` `%z`, `%F` for `%Y-%m-%d`, `%T` for
+    /// `%H:%M:%S` and `%%`. Anything else is kept as written.
+    pub fn format(&self, format: &str) -> String {
+        let mut out = String::new();
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            let weekday = WEEKDAYS[self.weekday() as usize - 1];
+            let month = MONTHS[self.month as usize - 1];
+            match chars.next() {
+                Some('Y') => out += &self.year.to_string(),
+                Some('y') => out += &format!("{:02}", self.year.rem_euclid(100)),
+                Some('m') => out += &format!("{:02}", self.month),
+                Some('d') => out += &format!("{:02}", self.day),
+                Some('e') => out += &format!("{:2}", self.day),
+                Some('H') => out += &format!("{:02}", self.hour),
+                Some('M') => out += &format!("{:02}", self.minute),
+                Some('S') => out += &format!("{:02}", self.second),
+                Some('j') => out += &format!("{:03}", self.ordinal()),
+                Some('a') => out += &weekday[..3],
+                Some('A') => out += weekday,
+                Some('b') => out += &month[..3],
+                Some('B') => out += month,
+                Some('u') => out += &self.weekday().to_string(),
+                Some('G') => out += &self.iso_week().0.to_string(),
+                Some('V') => out += &format!("{:02}", self.iso_week().1),
+                Some('z') => {
+                    let sign = if self.offset < 0 { '-' } else { '+' };
+                    let minutes = self.offset.abs() / 60;
+                    out += &format!("{sign}{:02}{:02}", minutes / 60, minutes % 60);
+                }
+                Some('F') => out += &self.format("%Y-%m-%d"),
+                Some('T') => out += &self.format("%H:%M:%S"),
+                Some('%') => out.push('%'),
+                Some(c) => {
+                    out.push('%');
+                    out.push(c);
+                }
+                None => out.push('%'),
+            }
+        }
+        out
+    }
+}
+
+/// The year, month and day of the date `days` days after 1970-01-01, in the proleptic
+/// Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    (yoe + era * 400 + (month <= 2) as i64, month, day)
+}
+
+/// The inverse of `civil_from_days`.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * ((month as i64 + 9) % 12) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// How far east of UTC the local time zone is at `secs` after the epoch.
+#[cfg(unix)]
+fn local_offset(secs: i64) -> i64 {
+    let time = secs as libc::time_t;
+    // SAFETY: `localtime_r` only writes to the `tm` it is given
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return 0;
+    }
+    tm.tm_gmtoff as i64
+}
+
+#[cfg(not(unix))]
+fn local_offset(_secs: i64) -> i64 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_date() {
+        // 2024-02-29 23:30:05 UTC, a Thursday
+        let date = DateTime::from_timestamp(1709249405, 0);
+        assert_eq!((date.year, date.month, date.day), (2024, 2, 29));
+        assert_eq!((date.hour, date.minute, date.second), (23, 30, 5));
+        assert_eq!(date.weekday(), 4);
+        assert_eq!(date.ordinal(), 60);
+        assert_eq!(
+            date.format("%F %T %a %B %j %y %% %q"),
+            "2024-02-29 23:30:05 Thu February 060 24 % %q"
+        );
+        // Half an hour later it's March, and morning in UTC+8
+        let date = DateTime::from_timestamp(1709249405 + 1800, 8 * 3600);
+        assert_eq!(date.format("%Y-%m-%d %H:%M %z"), "2024-03-01 08:00 +0800");
+        assert_eq!(
+            DateTime::from_timestamp(0, -5400).format("%F %T %z"),
+            "1969-12-31 22:30:00 -0130"
+        );
+        assert_eq!(days_from_civil(2024, 2, 29), date.days - 1);
+    }
+
+    #[test]
+    fn test_iso_week() {
+        let week =
+            |y, m, d| DateTime::from_timestamp(days_from_civil(y, m, d) * 86400, 0).iso_week();
+        assert_eq!(week(2024, 1, 1), (2024, 1));
+        assert_eq!(week(2021, 1, 3), (2020, 53));
+        assert_eq!(week(2024, 12, 30), (2025, 1));
+        assert_eq!(week(2026, 10, 16), (2026, 42));
+        let date = DateTime::from_timestamp(days_from_civil(2021, 1, 3) * 86400, 0);
+        assert_eq!(date.format("%G-W%V-%u"), "2020-W53-7");
+    }
+}
@@ -0,0 +1,98 @@
+//! A safety net for scratch buffers thrown away without saving. Their text is kept in
+//! a directory of its own for a while, to be restored from the recent files dialog.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{date::DateTime, Buffer};
+
+/// How many discarded buffers are kept. The oldest go first.
+const MAX_FILES: usize = 20;
+
+/// Where discarded buffers are kept: `$XDG_DATA_HOME/arcaea/discarded`, or the
+/// platform's usual place for such data.
+pub fn dir() -> Option<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+    let dir = if let Some(dir) = var("XDG_DATA_HOME") {
+        PathBuf::from(dir)
+    } else if cfg!(windows) {
+        PathBuf::from(var("APPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from(var("HOME")?).join("Library/Application Support")
+    } else {
+        PathBuf::from(var("HOME")?).join(".local/share")
+    };
+    Some(dir.join("arcaea").join("discarded"))
+}
+
+/// Write `buffer` to `dir`, named after the current time and the buffer, and delete
+/// the oldest files beyond the limit. Returns the new file.
+pub fn keep(dir: &Path, buffer: &Buffer) -> Option<PathBuf> {
+    fs::create_dir_all(dir).ok()?;
+    let time = DateTime::now().format("%Y%m%d-%H%M%S");
+    let path = dir.join(format!("{time}-Untitled-{}.txt", buffer.scratch));
+    buffer.write_to(&path).ok()?;
+    for old in list(dir).into_iter().skip(MAX_FILES) {
+        let _ = fs::remove_file(old);
+    }
+    Some(path)
+}
+
+/// The discarded buffers in `dir`, newest first.
+pub fn list(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<_> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .filter(|path| path.is_file())
+                .collect()
+        })
+        .unwrap_or_default();
+    // The names start with the time
+    files.sort_by(|a, b| b.cmp(a));
+    files
+}
+
+/// The name of a discarded buffer's file followed by its first line with text, to
+/// tell them apart in a list.
+pub fn label(file: &Path) -> String {
+    let name = file.file_stem().unwrap_or_default().to_string_lossy();
+    let text = fs::read_to_string(file).unwrap_or_default();
+    let line = text.lines().map(str::trim).find(|line| !line.is_empty());
+    let preview: String = line.unwrap_or_default().chars().take(40).collect();
+    format!("{name}  {preview}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keep() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut buffer: Buffer = "\n  first line\nsecond".parse().unwrap();
+        buffer.scratch = 2;
+        let path = keep(dir.path(), &buffer).unwrap();
+        assert!(path.starts_with(dir.path()));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "\n  first line\nsecond");
+        assert!(label(&path).ends_with("-Untitled-2  first line"));
+
+        // Only the newest are kept
+        for i in 0..MAX_FILES {
+            fs::write(
+                dir.path()
+                    .join(format!("20000101-0000{i:02}-Untitled-1.txt")),
+                "",
+            )
+            .unwrap();
+        }
+        assert_eq!(list(dir.path()).len(), MAX_FILES + 1);
+        keep(dir.path(), &buffer).unwrap();
+        let files = list(dir.path());
+        assert_eq!(files.len(), MAX_FILES);
+        assert!(files[0].to_string_lossy().ends_with("-Untitled-2.txt"));
+        assert!(!dir.path().join("20000101-000000-Untitled-1.txt").exists());
+    }
+}
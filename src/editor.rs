@@ -1,67 +1,147 @@
 use crossterm::{
-    cursor,
     event::{
         self, Event, KeyCode, KeyEventKind, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
     },
-    queue,
-    style::Stylize,
+    style::{ContentStyle, Stylize},
 };
 use std::{
-    io::{self, Write},
-    path::Path,
+    collections::HashMap,
+    fs,
+    io::{self, IsTerminal, Read},
+    ops::Range,
+    path::{Component, Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
 };
 use unicode_segmentation::UnicodeSegmentation;
-use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 use crate::{
-    style,
-    syntax::{TokenState, TokenType},
+    anomaly, cat,
+    codec::Transform,
+    date::DateTime,
+    discarded,
+    follow::{Follow, Update},
+    format, json,
+    keymap::{self, Command, Direction, Motion},
+    modal::{Modal, Mode},
+    recent::Recent,
+    row, signal,
+    snippet::Snippet,
+    style::{self, Theme},
+    t, trash,
     tui::Input,
-    Error, History, Row, Syntax, Terminal, Tui,
+    width::{self, Width},
+    Buffer, Config, Error, LineEnding, Position, Row, Syntax, Terminal, Tui,
 };
 
 const EXTRA_GAP: usize = 2;
-
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
-pub struct Position {
-    pub x: usize,
-    pub y: usize,
+const TOAST_DURATION: Duration = Duration::from_secs(3);
+/// The smallest window the editor can be drawn in.
+const MIN_WIDTH: usize = 40;
+const MIN_HEIGHT: usize = 9;
+/// How often the syntax definition and theme files are checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+/// How often a followed file is checked for new content.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(200);
+/// How long counting occurrences may take before it settles for a lower bound.
+const COUNT_TIME_LIMIT: Duration = Duration::from_millis(200);
+/// How many lines are highlighted at a time while waiting for input.
+const SYNTAX_CHUNK: usize = 5000;
+/// How long Esc does nothing after it closes the search box, so that a key held down
+/// long enough to repeat doesn't go on to clear the selection or quit.
+const ESCAPE_COOLDOWN: Duration = Duration::from_millis(500);
+
+/// A cleanup of the whole buffer offered by `Command::LineOperations`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineOperation {
+    RemoveBlankLines,
+    SqueezeBlankLines,
+    StripAnsi,
 }
 
-impl PartialOrd for Position {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
+impl LineOperation {
+    const ALL: [LineOperation; 3] = [
+        LineOperation::RemoveBlankLines,
+        LineOperation::SqueezeBlankLines,
+        LineOperation::StripAnsi,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            LineOperation::RemoveBlankLines => t!("lines.remove_blank"),
+            LineOperation::SqueezeBlankLines => t!("lines.squeeze_blank"),
+            LineOperation::StripAnsi => t!("lines.strip_ansi"),
+        }
     }
 }
 
-impl Ord for Position {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        match self.y.cmp(&other.y) {
-            std::cmp::Ordering::Equal => self.x.cmp(&other.x),
-            ord => ord,
-        }
-    }
+/// Where a mouse drag started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MouseArea {
+    /// The line numbers, for selecting whole lines.
+    Sidebar,
+    Text,
+    /// The selection, grabbed with Alt to move its lines elsewhere.
+    Lines,
 }
 
-impl<T: Into<usize>> From<(T, T)> for Position {
-    fn from(value: (T, T)) -> Self {
-        Position {
-            x: value.0.into(),
-            y: value.1.into(),
-        }
-    }
+/// The kinds of transient state `Command::Cancel` clears, one per press, before it
+/// quits. Listed in the order they are cleared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transient {
+    /// The placeholders of a snippet.
+    Snippet,
+    Selection,
+    /// The matches of the last search.
+    SearchResults,
+}
+
+/// A question or problem that came up before the editor had the terminal, brought up
+/// once it runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Prompt {
+    /// A problem to tell about, with a title.
+    Alert(String, String),
+    /// Whether to load the file, which is over `large_file_mb`, whole or only preview it.
+    LargeFile(String),
+    /// Pick a file to open, as no file was named.
+    OpenRecent,
+}
+
+/// A buffer that is open but not shown, with the view it was left with.
+struct Background {
+    buffer: Buffer,
+    viewbox: Position,
+    cursor: Position,
+    anchor: Option<Position>,
+    folds: Vec<Range<usize>>,
+    follow: Option<Follow>,
+}
+
+/// The pane of a split view that doesn't have the focus, with its own view of the
+/// buffer.
+struct Pane {
+    viewbox: Position,
+    cursor: Position,
+    anchor: Option<Position>,
+    /// Whether it is the lower pane.
+    below: bool,
 }
 
 #[derive(Default)]
 pub struct Editor {
-    pub filename: Option<String>,
-    is_crlf: bool,
-
-    buffer: Vec<Row>,
+    pub buffer: Buffer,
     status_string: String,
+    /// A short notification shown on the bottom line, and when it was posted.
+    toast: Option<(String, Instant)>,
     pub terminal: Terminal,
 
     sidebar_width: usize,
+    /// Whether the line below the status bar, for toasts and debug info, is hidden.
+    hide_message_line: bool,
+    /// Whether that line describes the text under the mouse instead of the cursor.
+    hover_info: bool,
+    /// The screen cell the mouse was last moved to, while `hover_info` is on.
+    hover: Option<Position>,
 
     viewbox: Position,
     cursor: Position,
@@ -70,1218 +150,6556 @@ pub struct Editor {
     /// None if not selected, Some if selected a range.
     anchor: Option<Position>,
 
-    pub dirty: bool,
-
-    history: History<Row>,
-    syntax: Syntax,
+    /// The other pane while the view is split. The fields above are the view of the
+    /// one with the focus.
+    split: Option<Pane>,
 
     search: Input,
-    search_result: Vec<Position>,
+    /// Where each match of the search query begins and ends, in buffer order, and
+    /// which one is current.
+    search_result: Vec<(Position, Position)>,
+    search_index: usize,
+    /// Whether Enter in the search box goes to the match before the current one.
+    search_reverse: bool,
+    /// The anchor and the cursor when the search box was opened. The first match is
+    /// looked for from there, and Esc puts them back.
+    search_origin: (Option<Position>, Position),
     is_searching: bool,
+    /// When Esc last closed the search box, or was ignored for following too soon after
+    /// that.
+    search_closed: Option<Instant>,
+
+    config: Config,
+    /// When the buffer was first found modified since the last autosave.
+    autosave_pending: Option<Instant>,
+
+    /// The last copied text, used when the system clipboard is unavailable.
+    clipboard: Option<String>,
+    /// Whether the last copy also reached the system clipboard.
+    clipboard_synced: bool,
+    /// Whether the last copy was of whole lines.
+    clipboard_linewise: bool,
+    /// The named registers, which keep what was copied into them for the session.
+    registers: HashMap<char, String>,
+    /// The register the next copy, cut or paste uses instead of the clipboard.
+    register: Option<char>,
+    /// Whether the next key names a register.
+    awaiting_register: bool,
+
+    /// Whether the window was too small the last time it was drawn.
+    window_too_small: bool,
+    /// The last left button event of a drag, and where the drag started.
+    drag: Option<(MouseEvent, MouseArea)>,
+    /// The number of frames drawn, shown in the debug line.
+    frame: usize,
+
+    /// The selections `GrowSelection` went through, the current one last. Forgotten
+    /// once the selection changes in any other way.
+    selection_stack: Vec<(Option<Position>, Position)>,
+    /// The placeholders of the snippet being filled in, the current one first. Empty
+    /// outside snippet mode.
+    tabstops: Vec<(Position, Position)>,
+
+    /// When the syntax definition and theme files were modified as of their last load,
+    /// and when that was last checked.
+    syntax_modified: Option<SystemTime>,
+    theme_modified: Option<SystemTime>,
+    files_checked: Option<Instant>,
+
+    /// The state of vim-like editing, if it is enabled.
+    modal: Option<Modal>,
+
+    /// The folded regions, sorted and disjoint: the lines hidden under each fold's
+    /// first line, which is the line before the range. The buffer keeps all of them.
+    folds: Vec<Range<usize>>,
+
+    /// The file being followed with `--follow`, which makes the buffer read-only, and
+    /// when it was last checked.
+    follow: Option<Follow>,
+    follow_checked: Option<Instant>,
+
+    /// The other open buffers, in the order the buffer switcher lists them with the
+    /// current one at `buffer_index`.
+    buffers: Vec<Background>,
+    buffer_index: usize,
+    /// How many scratch buffers were created, for numbering the next one.
+    scratch_count: usize,
+
+    /// The files opened most recently.
+    recent: Recent,
+    /// Where scratch buffers closed without saving are kept; `None` doesn't keep them.
+    discarded_dir: Option<PathBuf>,
+    /// What to ask or tell as soon as the terminal is set up, in order.
+    prompts: Vec<Prompt>,
 }
 
 impl Editor {
-    pub fn new() -> Self {
-        Self::default()
+    pub fn new(config: Config) -> Self {
+        let mut editor = Self {
+            modal: config.vim_mode.then(Modal::default),
+            config,
+            terminal: Terminal::new(),
+            recent: Recent::load(),
+            discarded_dir: discarded::dir(),
+            hover_info: cfg!(feature = "debug"),
+            ..Default::default()
+        };
+        editor.configure_buffer();
+        editor
+    }
+
+    /// An editor for `content` drawing to a headless terminal of the given size, to be
+    /// driven with `handle_event` without a real terminal.
+    pub fn open_str(content: &str, width: usize, height: usize) -> Self {
+        let mut editor = Editor {
+            buffer: content.parse().unwrap_or_default(),
+            terminal: Terminal::headless(width, height),
+            scratch_count: 1,
+            ..Default::default()
+        };
+        editor.buffer.scratch = 1;
+        editor.update_sidebar_width();
+        editor.create_history();
+        editor.buffer.mark_saved();
+        editor
+    }
+
+    /// Insert `text`, which may span lines, at `at` as an undo step of its own, and
+    /// return where the inserted text ends. The cursor and selection move along with
+    /// the text after `at`.
+    ///
+    /// ```
+    /// use arcaea::{Editor, Position};
+    ///
+    /// let mut editor = Editor::open_str("hello", 40, 10);
+    /// let end = editor.insert_text(Position { x: 5, y: 0 }, ", world\n!");
+    /// assert_eq!(end, Position { x: 1, y: 1 });
+    /// assert_eq!(editor.buffer.to_string(), "hello, world\n!");
+    /// ```
+    pub fn insert_text(&mut self, at: Position, text: &str) -> Position {
+        self.update_last_history_state();
+        self.buffer.history.mark_boundary();
+        let end = self.insert_at(at, text);
+        self.create_history();
+        self.buffer.history.mark_boundary();
+        end
+    }
+
+    /// Delete the text between two positions, in either order, as an undo step of its
+    /// own and return it. The cursor and selection move along with the text after it.
+    ///
+    /// ```
+    /// use arcaea::{Editor, Position};
+    ///
+    /// let mut editor = Editor::open_str("hello,\nworld", 40, 10);
+    /// let text = editor.delete_range(Position { x: 5, y: 0 }, Position { x: 0, y: 1 });
+    /// assert_eq!(text, ",\n");
+    /// assert_eq!(editor.buffer.to_string(), "helloworld");
+    /// ```
+    pub fn delete_range(&mut self, begin: Position, end: Position) -> String {
+        self.update_last_history_state();
+        self.buffer.history.mark_boundary();
+        let text = self.buffer.text_range(begin.min(end), begin.max(end));
+        self.delete_at(begin, end);
+        self.create_history();
+        self.buffer.history.mark_boundary();
+        text
+    }
+
+    /// Insert `text` at `at` without recording history, moving the cursor and anchor
+    /// if they are at or after `at`. Returns where the inserted text ends.
+    fn insert_at(&mut self, at: Position, text: &str) -> Position {
+        let at = self.buffer.clamp(at);
+        let end = self.buffer.insert(at, text);
+        self.cursor = shift_after_insert(self.cursor, at, end);
+        self.anchor = (self.anchor).map(|anchor| shift_after_insert(anchor, at, end));
+        if end.y != at.y {
+            self.folds.clear();
+        }
+        end
+    }
+
+    /// Delete the text between two positions without recording history, pulling the
+    /// cursor and anchor back with the text after it. Returns where the text was.
+    fn delete_at(&mut self, begin: Position, end: Position) -> Position {
+        let (begin, end) = (
+            self.buffer.range_bound(begin.min(end)),
+            self.buffer.range_bound(begin.max(end)),
+        );
+        let at = self.buffer.delete_range(begin, end);
+        self.cursor = shift_after_delete(self.cursor, begin, end);
+        self.anchor = (self.anchor).map(|anchor| shift_after_delete(anchor, begin, end));
+        if end.y != begin.y {
+            self.folds.clear();
+        }
+        at
+    }
+
+    /// Put the cursor within its row, or if there is a selection, move an end of it
+    /// past the last cell of its row past the line break, as edits expect.
+    fn settle_selection(&mut self) {
+        match self.anchor {
+            Some(anchor) => {
+                self.anchor = Some(self.buffer.range_bound(anchor));
+                self.cursor = self.buffer.range_bound(self.cursor);
+            }
+            None => self.cursor.x = self.cursor.x.min(self.get_width()),
+        }
     }
 
     fn get_width(&self) -> usize {
-        self.buffer[self.cursor.y].len()
+        self.buffer.rows[self.cursor.y].len()
     }
 
+    /// Open `filename`, or a scratch buffer, and run the editor until it quits: `open`
+    /// followed by `run`.
     pub fn init(&mut self, filename: &Option<String>) -> Result<(), Error> {
-        self.filename = filename.clone();
+        self.open(filename)?;
+        self.run()
+    }
 
-        self.terminal.init()?;
+    /// Load the theme and `filename`, or `-` for what is piped in, or else start a
+    /// scratch buffer, ready to be changed or shown. The terminal is left alone:
+    /// problems and questions that come up, like whether to load a large file whole,
+    /// wait for `run`, and such a file stays unloaded until then.
+    pub fn open(&mut self, filename: &Option<String>) -> Result<(), Error> {
+        self.apply_width_mode();
+        if let Some(path) = self.config.theme.clone() {
+            self.theme_modified = modified(&path);
+            match Theme::from_file(&path) {
+                Ok(theme) => style::set_theme(theme),
+                Err(err) => {
+                    let message = t!("error.message", format!("{:?}", err));
+                    self.alert_later(t!("error.theme_failed").to_string(), message);
+                }
+            }
+        }
 
-        self.buffer = vec![Row::from("")];
-        if let Some(name) = filename {
-            self.buffer = std::fs::read_to_string(name)
-                .unwrap_or_else(|err| {
-                    self.filename = None;
-                    let _ = Tui::alert(
-                        self,
-                        "文件读取失败".to_string(),
-                        Error::get_error_message(&err).to_string(),
-                    );
-                    String::new()
-                })
-                .split('\n')
-                .map(|line| {
-                    if line.ends_with('\r') {
-                        self.is_crlf = true;
-                    }
-                    line.strip_suffix('\r').unwrap_or(line)
-                })
-                .map(Row::from)
-                .collect();
-
-            let ext = Path::new(&name)
-                .extension()
-                .and_then(std::ffi::OsStr::to_str);
-            if let Some(s) = ext.and_then(|e| Syntax::get(e).transpose()) {
-                self.syntax = s.unwrap_or_else(|err| {
-                    let message = format!("错误: {:?}", err);
-                    let _ = Tui::alert(self, "语法高亮加载失败".to_string(), message);
-                    Syntax::default()
-                });
-                self.update_syntax();
-            } else {
-                self.syntax = Syntax::default();
+        // `-` is what is piped in, unless that is the terminal itself
+        let piped = filename.as_deref() == Some("-") && !io::stdin().is_terminal();
+        // A large file is loaded and settled once `run` has asked about it
+        let deferred =
+            !piped && (filename.as_deref()).is_some_and(|name| self.large_file(name).is_some());
+        match filename {
+            _ if piped => self.load_stdin(),
+            Some(name) if deferred => self.prompts.push(Prompt::LargeFile(name.clone())),
+            Some(name) => self.load_file(name),
+            None => {
+                self.scratch_count = 1;
+                self.buffer.scratch = 1;
             }
         }
+        if !deferred {
+            self.settle_loaded(filename.as_deref().filter(|_| !piped));
+        }
+        // Nothing could read it again
+        if piped {
+            self.buffer.mark_unsaved();
+        }
 
-        self.history
-            .push_state(&self.buffer, self.viewbox, self.cursor, self.anchor);
+        if !self.config.warnings.is_empty() {
+            self.toast(t!("config.warning", self.config.warnings.join("; ")));
+        }
 
-        if self.check_minimum_window_size() {
-            self.render()?;
+        if filename.is_none() && !self.recent.files.is_empty() {
+            self.prompts.push(Prompt::OpenRecent);
         }
+        Ok(())
+    }
 
-        self.event_loop()?;
+    /// Get a buffer just loaded from `filename` ready to edit: highlight it as the
+    /// command line asks, follow the file if it asks to, and start the history from it.
+    fn settle_loaded(&mut self, filename: Option<&str>) {
+        if let Some(name) = self.config.syntax.clone() {
+            self.force_syntax(&name);
+        }
 
-        self.on_exit()?;
+        if let Some(name) = filename.filter(|_| self.config.follow) {
+            self.follow = Some(Follow::new(name, &self.buffer));
+            self.cursor = self.buffer.clamp((0, usize::MAX).into());
+        }
 
-        Ok(())
+        self.buffer
+            .history
+            .push_state(&self.buffer.rows, self.viewbox, self.cursor, self.anchor);
+        self.buffer.mark_saved();
     }
 
-    fn event_loop(&mut self) -> Result<(), Error> {
-        let mut cnt = 0;
-        let mut mouse: Option<MouseEvent> = None;
-        let mut dragging_sidebar = false;
-        loop {
-            let mut should_update_viewbox = true;
-            if event::poll(std::time::Duration::from_millis(25))? {
-                match event::read()? {
-                    // Keyboard Event
-                    Event::Key(event) if event.kind != KeyEventKind::Release => {
-                        match (event.modifiers, event.code) {
-                            (KeyModifiers::CONTROL, KeyCode::Char('s' | 'S'))
-                            | (KeyModifiers::SHIFT, KeyCode::F(12)) => {
-                                self.try_save_file(event.code == KeyCode::F(12))?;
-                            }
+    /// Set up the terminal and handle input until the editor quits, then give the
+    /// terminal back.
+    pub fn run(&mut self) -> Result<(), Error> {
+        self.terminal.init(self.config.mouse_capture)?;
+        signal::install();
+        self.show_prompts()?;
 
-                            (_, KeyCode::Esc)
-                            | (KeyModifiers::CONTROL, KeyCode::Char('w' | 'W')) => {
-                                match Tui::confirm_exit(self)? {
-                                    Some(true) => {
-                                        if self.try_save_file(false)? {
-                                            break;
-                                        }
-                                    }
-                                    Some(false) => {
-                                        break;
-                                    }
-                                    None => {}
-                                };
-                            }
+        if self.check_minimum_window_size() {
+            self.render()?;
+        }
 
-                            // Select ALL
-                            (KeyModifiers::CONTROL, KeyCode::Char('a' | 'A')) => {
-                                self.anchor = Some(Position { x: 0, y: 0 });
-                                self.cursor.y = self.buffer.len() - 1;
-                                self.cursor.x = self.get_width();
-                                should_update_viewbox = false;
-                            }
+        self.event_loop()?;
 
-                            // Undo
-                            (KeyModifiers::CONTROL, KeyCode::Char('z' | 'Z')) => {
-                                if self.history.undo() {
-                                    self.buffer = self.history.current.clone();
-                                    self.viewbox = self.history.current_state.viewbox;
-                                    self.cursor = self.history.current_state.cursor;
-                                    self.anchor = self.history.current_state.anchor;
-
-                                    // TODO: set dirty flag by really checking if the buffer is changed
-                                    self.dirty = true;
-                                }
-                            }
+        self.on_exit()?;
 
-                            // Redo
-                            (KeyModifiers::CONTROL, KeyCode::Char('y' | 'Y')) => {
-                                if self.history.redo() {
-                                    self.buffer = self.history.current.clone();
-                                    self.viewbox = self.history.current_state.viewbox;
-                                    self.cursor = self.history.current_state.cursor;
-                                    self.anchor = self.history.current_state.anchor;
-
-                                    // TODO: set dirty flag by really checking if the buffer is changed
-                                    self.dirty = true;
-                                }
-                            }
+        Ok(())
+    }
 
-                            // Copy & Cut
-                            (KeyModifiers::CONTROL, KeyCode::Char('c' | 'C' | 'x' | 'X')) => {
-                                self.trigger_copy()?;
-
-                                if event.code == KeyCode::Char('x')
-                                    || event.code == KeyCode::Char('X')
-                                {
-                                    self.update_last_history_state();
-                                    self.dirty = true;
-                                    if let Some((begin, end)) = self.get_selection() {
-                                        self.delete_selection_range(begin, end);
-                                    } else {
-                                        // Just delete the current line
-                                        self.delete_selection_range(
-                                            (0, self.cursor.y).into(),
-                                            (self.get_width(), self.cursor.y).into(),
-                                        );
-                                    }
-                                    self.create_history();
-                                }
-                            }
+    /// Tell about a problem once the editor runs, or before the next input if it does.
+    fn alert_later(&mut self, title: String, message: String) {
+        self.prompts.push(Prompt::Alert(title, message));
+    }
 
-                            // Paste
-                            (KeyModifiers::CONTROL, KeyCode::Char('v' | 'V')) => {
-                                self.trigger_paste();
-                            }
+    /// Bring up the questions and problems put off until the terminal was set up.
+    fn show_prompts(&mut self) -> Result<(), Error> {
+        while !self.prompts.is_empty() {
+            match self.prompts.remove(0) {
+                Prompt::Alert(title, message) => Tui::alert(self, title, message)?,
+                Prompt::LargeFile(name) => {
+                    self.load_file(&name);
+                    self.settle_loaded(Some(&name));
+                }
+                Prompt::OpenRecent => self.open_recent()?,
+            }
+        }
+        Ok(())
+    }
 
-                            // Search
-                            (KeyModifiers::CONTROL, KeyCode::Char('f' | 'F')) => {
-                                self.toggle_search_mode()?;
-                            }
+    /// Read a file into the buffer and pick the syntax definition for its extension.
+    /// Problems are shown in alerts; a file that cannot be read leaves an empty buffer.
+    /// A file over `large_file_mb` is only loaded whole after asking, and otherwise
+    /// previewed, or not loaded at all.
+    fn load_file(&mut self, name: &str) {
+        let result = match self.large_file(name) {
+            Some((size, mb)) => match Tui::confirm_large_file(self, name, size >> 20, mb) {
+                Ok(Some(true)) => Buffer::from_file(Path::new(name)),
+                Ok(Some(false)) => Buffer::preview_file(Path::new(name), mb << 20),
+                _ => Ok(Buffer::default()),
+            },
+            None => Buffer::from_file(Path::new(name)),
+        };
+        self.buffer = match result {
+            Ok(buffer) if buffer.filename.is_none() => buffer,
+            Ok(mut buffer) => {
+                self.recent.add(Path::new(name));
+                if self.config.normalize_unicode && buffer.preview.is_none() {
+                    let lines = buffer.normalize();
+                    if lines > 0 {
+                        self.toast(t!("normalize.loaded", lines));
+                    }
+                }
+                buffer
+            }
+            Err(err) => {
+                self.alert_later(
+                    t!("error.read_failed").to_string(),
+                    Error::get_error_message(&err).to_string(),
+                );
+                Buffer::default()
+            }
+        };
+        self.configure_buffer();
+        self.detect_syntax(name);
+    }
 
-                            // Regular character input
-                            (KeyModifiers::NONE | KeyModifiers::SHIFT, KeyCode::Char(char)) => {
-                                self.update_last_history_state();
-                                self.dirty = true;
+    /// The size of the file `name` and the `large_file_mb` limit, if it is over it.
+    fn large_file(&self, name: &str) -> Option<(u64, u64)> {
+        let size = fs::metadata(name).map_or(0, |metadata| metadata.len());
+        let limit = (self.config.large_file_mb).filter(|&mb| size > mb << 20)?;
+        Some((size, limit))
+    }
 
-                                self.cursor.x = self.cursor.x.min(self.get_width());
+    /// Read what is piped in, as with `ls | arcaea -`, into a scratch buffer. The keys
+    /// still come from the terminal.
+    fn load_stdin(&mut self) {
+        let mut bytes = Vec::new();
+        if let Err(err) = io::stdin().read_to_end(&mut bytes) {
+            self.alert_later(
+                t!("error.read_failed").to_string(),
+                Error::get_error_message(&err).to_string(),
+            );
+        }
+        self.buffer = String::from_utf8_lossy(&bytes).parse().unwrap_or_default();
+        self.configure_buffer();
+        self.scratch_count = 1;
+        self.buffer.scratch = 1;
+    }
 
-                                if let Some((begin, end)) = self.get_selection() {
-                                    self.delete_selection_range(begin, end);
-                                }
+    /// Highlight the buffer with the syntax definition for the extension of `name`, or
+    /// none if there isn't one.
+    fn detect_syntax(&mut self, name: &str) {
+        let ext = Path::new(&name)
+            .extension()
+            .and_then(std::ffi::OsStr::to_str);
+        if let Some(s) = ext.and_then(|e| Syntax::get(&self.config.syntax_dir, e).transpose()) {
+            self.buffer.syntax = s.unwrap_or_else(|err| {
+                let message = t!("error.message", format!("{:?}", err));
+                self.alert_later(t!("error.syntax_failed").to_string(), message);
+                Syntax::default()
+            });
+            // Only what the first frame shows; the rest is done while waiting for input
+            self.buffer.update_syntax_lazily(self.terminal.height * 2);
+        } else {
+            self.buffer.syntax = Syntax::default();
+        }
 
-                                self.buffer[self.cursor.y].rope.insert(
-                                    self.cursor.x,
-                                    (char.to_string(), char.width().unwrap_or(0)),
-                                );
-                                self.cursor.x += 1;
+        self.syntax_modified = self.buffer.syntax.path.as_deref().and_then(modified);
+    }
 
-                                self.create_history();
-                            }
+    /// Highlight the buffer with the syntax definition called `name`, whatever its
+    /// file's extension, and keep it when the file is saved under another name.
+    fn force_syntax(&mut self, name: &str) {
+        match Syntax::by_name(&self.config.syntax_dir, name) {
+            Ok(Some(syntax)) => {
+                self.buffer.syntax = syntax;
+                self.buffer.syntax_forced = true;
+                self.buffer.update_syntax_lazily(self.terminal.height * 2);
+                self.syntax_modified = self.buffer.syntax.path.as_deref().and_then(modified);
+            }
+            Ok(None) => {}
+            Err(err) => {
+                let message = t!("error.message", format!("{:?}", err));
+                self.alert_later(t!("error.syntax_failed").to_string(), message);
+            }
+        }
+    }
 
-                            (_, KeyCode::Tab) => {
-                                self.update_last_history_state();
-                                self.dirty = true;
+    /// Open the file named under the cursor in place of the current one, relative to
+    /// the current file's directory or else the working directory, after asking to
+    /// save changes. A `:line` after the path moves the cursor there.
+    fn open_path_under_cursor(&mut self) -> Result<(), Error> {
+        if self.open_url_under_cursor() {
+            return Ok(());
+        }
+        let cursor = self.buffer.clamp(self.cursor);
+        let Some((name, line)) = path_at(&self.buffer.rows[cursor.y], cursor.x) else {
+            return Ok(());
+        };
+        let dir = (self.buffer.filename.as_deref()).and_then(|file| Path::new(file).parent());
+        let path = [dir.map(|dir| dir.join(&name)), Some(PathBuf::from(&name))]
+            .into_iter()
+            .flatten()
+            .find(|path| path.is_file());
+        let Some(path) = path else {
+            self.toast(t!("open.not_found", name));
+            return Ok(());
+        };
+        self.open_in_place(&path, line)
+    }
 
-                                self.cursor.x = self.cursor.x.min(self.get_width());
+    /// Pick a recently opened file and open it in place of the current one. Files that
+    /// no longer exist are dropped from the list when picked.
+    fn open_recent(&mut self) -> Result<(), Error> {
+        let discarded = (self.discarded_dir.as_deref())
+            .map(discarded::list)
+            .unwrap_or_default();
+        if self.recent.files.is_empty() && discarded.is_empty() {
+            self.toast(t!("recent.empty"));
+            return Ok(());
+        }
+        let files = self.recent.files.clone();
+        let Some(index) = Tui::select_recent(self, &files, discarded.len())? else {
+            return Ok(());
+        };
+        if index == files.len() {
+            return self.restore_discarded(&discarded);
+        }
+        if !files[index].is_file() {
+            self.recent.remove(index);
+            self.toast(t!("recent.missing", files[index].display()));
+            return Ok(());
+        }
+        self.open_in_place(&files[index], None)
+    }
 
-                                if let Some((begin, end)) = self.get_selection() {
-                                    self.delete_selection_range(begin, end);
-                                }
+    /// Keep the text of `buffer` if it is a scratch buffer with unsaved text about to be
+    /// thrown away, unless `keep_discarded` is off.
+    fn keep_discarded(&self, buffer: &Buffer) {
+        let empty = buffer.line_count() == 1 && buffer.rows[0].is_empty();
+        if !self.config.keep_discarded || buffer.filename.is_some() || !buffer.dirty || empty {
+            return;
+        }
+        if let Some(dir) = &self.discarded_dir {
+            discarded::keep(dir, buffer);
+        }
+    }
 
-                                self.buffer[self.cursor.y]
-                                    .rope
-                                    .insert(self.cursor.x, ("    ".to_string(), 4));
-                                self.cursor.x += 1;
+    /// Pick one of the kept scratch buffers and open it in a new scratch buffer, taking
+    /// it off the list.
+    fn restore_discarded(&mut self, files: &[PathBuf]) -> Result<(), Error> {
+        let items = files.iter().map(|file| discarded::label(file)).collect();
+        let title = t!("dialog.discarded").to_string();
+        let Some(index) = Tui::select(self, title, items)? else {
+            return Ok(());
+        };
+        match Buffer::from_file(&files[index]) {
+            Ok(buffer) => {
+                self.new_scratch_buffer();
+                self.buffer.rows = buffer.rows;
+                self.buffer.dirty = true;
+                self.create_history();
+                let _ = fs::remove_file(&files[index]);
+            }
+            Err(err) => {
+                let message = Error::get_error_message(&err).to_string();
+                Tui::alert(self, t!("error.read_failed").to_string(), message)?;
+            }
+        }
+        Ok(())
+    }
 
-                                self.create_history();
-                            }
+    /// Open `path` in place of the current file after asking to save changes, with the
+    /// cursor at the start of `line` (counting from 1) if there is one.
+    fn open_in_place(&mut self, path: &Path, line: Option<usize>) -> Result<(), Error> {
+        let unsaved = self.buffer.dirty.then(|| buffer_name(&self.buffer));
+        match Tui::confirm_exit(self, unsaved.into_iter().collect())? {
+            Some(true) if !self.try_save_file(false)? => return Ok(()),
+            Some(false) => self.keep_discarded(&self.buffer),
+            None => return Ok(()),
+            _ => {}
+        }
+        self.load_file(&path.to_string_lossy());
+        self.follow = None;
+        self.folds.clear();
+        self.tabstops.clear();
+        self.anchor = None;
+        self.set_viewbox(Position::default());
+        self.cursor = self.buffer.clamp(Position {
+            x: 0,
+            y: line.map_or(0, |line| line.saturating_sub(1)),
+        });
+        self.create_history();
+        self.buffer.mark_saved();
+        Ok(())
+    }
 
-                            // Control character input
-                            // viewbox or cursor's movement; delete; enter; etc.
-                            (modifiers, code) => {
-                                match code {
-                                    // TODO: Move cursor by visual offset, not logical offset
-                                    KeyCode::Up => {
-                                        if modifiers == KeyModifiers::ALT | KeyModifiers::SHIFT {
-                                            let (begin, end) = self
-                                                .get_selection()
-                                                .unwrap_or((self.cursor, self.cursor));
-                                            self.update_last_history_state();
-                                            self.dirty = true;
-
-                                            for i in (begin.y..=end.y).rev() {
-                                                self.buffer
-                                                    .insert(end.y + 1, self.buffer[i].clone());
-                                            }
-
-                                            self.create_history();
-                                        } else {
-                                            if modifiers == KeyModifiers::ALT {
-                                                let (begin, end) = self
-                                                    .get_selection()
-                                                    .unwrap_or((self.cursor, self.cursor));
-                                                if begin.y > 0 {
-                                                    self.update_last_history_state();
-                                                    self.dirty = true;
-
-                                                    for i in begin.y..=end.y {
-                                                        self.buffer.swap(i - 1, i);
-                                                    }
-                                                    if let Some(anchor) = &mut self.anchor {
-                                                        anchor.y -= 1;
-                                                    }
-
-                                                    self.create_history();
-                                                }
-                                            } else {
-                                                self.update_selection(modifiers);
-                                            }
-
-                                            if modifiers.contains(KeyModifiers::CONTROL) {
-                                                should_update_viewbox = false;
-
-                                                self.viewbox.y = self.viewbox.y.saturating_sub(1);
-                                            } else if self.cursor.y > 0 {
-                                                self.cursor.y -= 1;
-                                            } else {
-                                                self.cursor.x = 0;
-                                            }
-                                        }
-                                    }
-                                    KeyCode::Down => {
-                                        if modifiers == KeyModifiers::ALT | KeyModifiers::SHIFT {
-                                            let (begin, end) = self
-                                                .get_selection()
-                                                .unwrap_or((self.cursor, self.cursor));
-                                            self.update_last_history_state();
-                                            self.dirty = true;
-
-                                            for i in (begin.y..=end.y).rev() {
-                                                self.buffer
-                                                    .insert(end.y + 1, self.buffer[i].clone());
-                                            }
-
-                                            self.cursor.y += end.y - begin.y + 1;
-                                            if let Some(anchor) = &mut self.anchor {
-                                                anchor.y += end.y - begin.y + 1;
-                                            }
-
-                                            self.create_history();
-                                        } else {
-                                            if modifiers == KeyModifiers::ALT {
-                                                let (begin, end) = self
-                                                    .get_selection()
-                                                    .unwrap_or((self.cursor, self.cursor));
-                                                if end.y < self.buffer.len() - 1 {
-                                                    self.update_last_history_state();
-                                                    self.dirty = true;
-
-                                                    for i in (begin.y..=end.y).rev() {
-                                                        self.buffer.swap(i, i + 1);
-                                                    }
-                                                    if let Some(anchor) = &mut self.anchor {
-                                                        anchor.y += 1;
-                                                    }
-
-                                                    self.create_history();
-                                                }
-                                            } else {
-                                                self.update_selection(modifiers);
-                                            }
-
-                                            if modifiers.contains(KeyModifiers::CONTROL) {
-                                                should_update_viewbox = false;
-
-                                                self.viewbox.y = (self.viewbox.y + 1).min(
-                                                    (self.buffer.len() + EXTRA_GAP)
-                                                        .saturating_sub(self.terminal.height - 2),
-                                                );
-                                            } else if self.cursor.y < self.buffer.len() - 1 {
-                                                self.cursor.y += 1;
-                                            } else {
-                                                self.cursor.x = self.get_width();
-                                            }
-                                        }
-                                    }
-                                    KeyCode::Left => {
-                                        self.cursor.x = self.cursor.x.min(self.get_width());
-
-                                        // Fix wrong deletion when selection is empty
-                                        if let Some((begin, end)) = self.get_selection() {
-                                            if begin == end {
-                                                self.anchor = None;
-                                            }
-                                        }
-
-                                        let mut flag = false;
-                                        if let Some((begin, _)) = self.get_selection() {
-                                            self.cursor.x = begin.x;
-                                            flag = true;
-                                        }
-                                        self.update_selection(modifiers);
-
-                                        if modifiers.contains(KeyModifiers::CONTROL) {
-                                            // Move to the beginning of the word
-                                            if self.cursor.x == 0 && self.cursor.y > 0 {
-                                                self.cursor.y -= 1;
-                                                self.cursor.x = self.get_width();
-                                            }
-                                            while self.cursor.x > 0
-                                                && self.buffer[self.cursor.y].rope
-                                                    [self.cursor.x - 1]
-                                                    .0
-                                                    == " "
-                                            {
-                                                self.cursor.x -= 1;
-                                            }
-                                            while self.cursor.x > 0
-                                                && self.buffer[self.cursor.y].rope
-                                                    [self.cursor.x - 1]
-                                                    .0
-                                                    != " "
-                                            {
-                                                self.cursor.x -= 1;
-                                            }
-                                        } else if !flag && self.cursor.x > 0 {
-                                            self.cursor.x -= 1;
-                                        } else if !flag && self.cursor.y > 0 {
-                                            self.cursor.y -= 1;
-                                            self.cursor.x = self.get_width();
-                                        }
-                                    }
-                                    KeyCode::Right => {
-                                        self.cursor.x = self.cursor.x.min(self.get_width());
-
-                                        // Fix wrong deletion when selection is empty
-                                        if let Some((begin, end)) = self.get_selection() {
-                                            if begin == end {
-                                                self.anchor = None;
-                                            }
-                                        }
-
-                                        let mut flag = false;
-                                        if let Some((_, end)) = self.get_selection() {
-                                            self.cursor.x = end.x;
-                                            flag = true;
-                                        }
-                                        self.update_selection(modifiers);
-
-                                        if modifiers.contains(KeyModifiers::CONTROL) {
-                                            // Move to the end of the word
-                                            if self.cursor.x == self.get_width()
-                                                && self.cursor.y < self.buffer.len() - 1
-                                            {
-                                                self.cursor.y += 1;
-                                                self.cursor.x = 0;
-                                            }
-                                            while self.cursor.x
-                                                < self.buffer[self.cursor.y].rope.len()
-                                                && self.buffer[self.cursor.y].rope[self.cursor.x].0
-                                                    == " "
-                                            {
-                                                self.cursor.x += 1;
-                                            }
-                                            while self.cursor.x
-                                                < self.buffer[self.cursor.y].rope.len()
-                                                && self.buffer[self.cursor.y].rope[self.cursor.x].0
-                                                    != " "
-                                            {
-                                                self.cursor.x += 1;
-                                            }
-                                        } else if !flag && self.cursor.x < self.get_width() {
-                                            self.cursor.x += 1;
-                                        } else if !flag && self.cursor.y < self.buffer.len() - 1 {
-                                            self.cursor.y += 1;
-                                            self.cursor.x = 0;
-                                        }
-                                    }
-
-                                    KeyCode::PageUp => {
-                                        self.update_selection(modifiers);
-                                        self.cursor.y =
-                                            self.cursor.y.saturating_sub(self.terminal.height - 2);
-                                    }
-                                    KeyCode::PageDown => {
-                                        self.update_selection(modifiers);
-                                        self.cursor.y = (self.cursor.y + self.terminal.height - 2)
-                                            .min(self.buffer.len() - 1);
-                                    }
-                                    KeyCode::Home => {
-                                        self.update_selection(modifiers);
-                                        self.cursor.x = 0;
-                                    }
-                                    KeyCode::End => {
-                                        self.update_selection(modifiers);
-                                        self.cursor.x = self.get_width();
-                                    }
-
-                                    KeyCode::Enter => {
-                                        self.update_last_history_state();
-                                        self.dirty = true;
-
-                                        self.cursor.x = self.cursor.x.min(self.get_width());
-
-                                        if let Some((begin, end)) = self.get_selection() {
-                                            self.delete_selection_range(begin, end);
-                                        }
-
-                                        let new_line = Row::from(
-                                            self.buffer[self.cursor.y].rope[self.cursor.x..]
-                                                .to_vec(),
-                                        );
-                                        self.buffer.insert(self.cursor.y + 1, new_line);
-                                        self.buffer[self.cursor.y] = Row::from(
-                                            self.buffer[self.cursor.y].rope[..self.cursor.x]
-                                                .to_vec(),
-                                        );
-                                        self.cursor.y += 1;
-                                        self.cursor.x = 0;
-
-                                        self.create_history();
-                                    }
-
-                                    KeyCode::Backspace => {
-                                        self.update_last_history_state();
-                                        self.dirty = true;
-
-                                        self.cursor.x = self.cursor.x.min(self.get_width());
-
-                                        // Fix wrong deletion when selection is empty
-                                        if let Some((begin, end)) = self.get_selection() {
-                                            if begin == end {
-                                                self.anchor = None;
-                                            }
-                                        }
-
-                                        if let Some((begin, end)) = self.get_selection() {
-                                            self.delete_selection_range(begin, end);
-                                        } else if self.cursor.x > 0 {
-                                            // The cursor is in the middle, just delete the char
-                                            self.cursor.x -= 1;
-                                            self.buffer[self.cursor.y].rope.remove(self.cursor.x);
-                                        } else if self.cursor.y > 0 {
-                                            // The cursor is in the beginning, and not at the first line
-                                            // Merge the current line with the previous line
-                                            self.cursor.y -= 1;
-                                            self.cursor.x = self.get_width();
-                                            let mut row = self.buffer[self.cursor.y].rope.clone();
-                                            row.extend(self.buffer.remove(self.cursor.y + 1).rope);
-                                            self.buffer[self.cursor.y] = Row::from(row);
-                                        }
-
-                                        self.create_history();
-                                    }
-                                    KeyCode::Delete => {
-                                        self.update_last_history_state();
-                                        self.dirty = true;
-
-                                        self.cursor.x = self.cursor.x.min(self.get_width());
-
-                                        // Fix wrong deletion when selection is empty
-                                        if let Some((begin, end)) = self.get_selection() {
-                                            if begin == end {
-                                                self.anchor = None;
-                                            }
-                                        }
-
-                                        if let Some((begin, end)) = self.get_selection() {
-                                            self.delete_selection_range(begin, end);
-                                        } else if self.cursor.x < self.get_width() {
-                                            // The cursor is in the middle, just delete the char
-                                            self.buffer[self.cursor.y].rope.remove(self.cursor.x);
-                                        } else if self.cursor.y < self.buffer.len() - 1 {
-                                            // The cursor is in the end, and not at the last line
-                                            // Merge the current line with the next line
-                                            let mut row = self.buffer[self.cursor.y].rope.clone();
-                                            row.extend(self.buffer.remove(self.cursor.y + 1).rope);
-                                            self.buffer[self.cursor.y] = Row::from(row);
-                                        }
-
-                                        self.create_history();
-                                    }
-
-                                    _ => {}
-                                }
-                            }
-                        }
-                    }
+    /// The transient state the next `Command::Cancel` clears, if there is any.
+    fn transient(&self) -> Option<Transient> {
+        if !self.tabstops.is_empty() {
+            Some(Transient::Snippet)
+        } else if self.anchor.is_some() {
+            Some(Transient::Selection)
+        } else if !self.search_result.is_empty() {
+            Some(Transient::SearchResults)
+        } else {
+            None
+        }
+    }
 
-                    // Mouse Event
-                    Event::Mouse(event) => match event.kind {
-                        MouseEventKind::ScrollUp => {
-                            should_update_viewbox = false;
+    /// Clear the innermost transient state.
+    fn cancel_transient(&mut self) {
+        match self.transient() {
+            Some(Transient::Snippet) => self.tabstops.clear(),
+            Some(Transient::Selection) => {
+                self.anchor = None;
+                self.selection_stack.clear();
+            }
+            Some(Transient::SearchResults) => self.search_result.clear(),
+            None => {}
+        }
+    }
 
-                            let dt = if event.modifiers.contains(KeyModifiers::ALT) {
-                                5
-                            } else {
-                                2
-                            };
-                            self.viewbox.y = self.viewbox.y.saturating_sub(dt);
-                        }
-                        MouseEventKind::ScrollDown => {
-                            should_update_viewbox = false;
+    /// The open buffers in the order the buffer switcher lists them, the current one
+    /// among them.
+    fn all_buffers(&self) -> impl Iterator<Item = &Buffer> {
+        let (before, after) = self.buffers.split_at(self.buffer_index);
+        (before.iter().map(|background| &background.buffer))
+            .chain([&self.buffer])
+            .chain(after.iter().map(|background| &background.buffer))
+    }
 
-                            let dt = if event.modifiers.contains(KeyModifiers::ALT) {
-                                5
-                            } else {
-                                2
-                            };
-                            self.viewbox.y = (self.viewbox.y + dt).min(
-                                (self.buffer.len() + EXTRA_GAP)
-                                    .saturating_sub(self.terminal.height - 2),
-                            );
-                        }
-                        MouseEventKind::ScrollLeft => {
-                            should_update_viewbox = false;
+    /// Put the current buffer and its view in the background.
+    fn stash_buffer(&mut self) -> Background {
+        self.update_last_history_state();
+        // Both panes show the current buffer
+        self.split = None;
+        let background = Background {
+            buffer: std::mem::take(&mut self.buffer),
+            viewbox: std::mem::take(&mut self.viewbox),
+            cursor: std::mem::take(&mut self.cursor),
+            anchor: self.anchor.take(),
+            folds: std::mem::take(&mut self.folds),
+            follow: self.follow.take(),
+        };
+        self.configure_buffer();
+        background
+    }
 
-                            self.viewbox.x = self.viewbox.x.saturating_sub(3);
-                        }
-                        MouseEventKind::ScrollRight => {
-                            should_update_viewbox = false;
+    /// Apply the settings kept in each buffer to a buffer just made current.
+    fn configure_buffer(&mut self) {
+        self.buffer.history.coalesce_window = self.config.history_coalesce;
+    }
 
-                            self.viewbox.x =
-                                (self.viewbox.x + 3).min(self.get_width() + EXTRA_GAP + 1);
-                        }
+    /// Show a buffer from the background with the view it was left with.
+    fn show_buffer(&mut self, background: Background) {
+        self.buffer = background.buffer;
+        self.cursor = background.cursor;
+        self.anchor = background.anchor;
+        self.folds = background.folds;
+        self.set_viewbox(background.viewbox);
+        self.follow = background.follow;
+        self.follow_checked = None;
+        self.autosave_pending = None;
+        self.tabstops.clear();
+        self.selection_stack.clear();
+        self.search_result.clear();
+    }
 
-                        MouseEventKind::Down(MouseButton::Left)
-                        | MouseEventKind::Drag(MouseButton::Left) => {
-                            mouse = Some(event);
-                        }
+    /// Move the current file to the trash after asking, or delete it after asking
+    /// again if there is no trash. The text stays as an unsaved scratch buffer.
+    fn delete_file(&mut self) -> Result<(), Error> {
+        let Some(name) = self.buffer.filename.clone() else {
+            self.toast(t!("delete.no_file"));
+            return Ok(());
+        };
+        // The rest of a preview would be lost
+        if self.refuse_edit() {
+            return Ok(());
+        }
+        if Tui::confirm_delete(self, &name, false)? != Some(true) {
+            return Ok(());
+        }
+        let path = Path::new(&name);
+        let trashed = trash::dir().map(|trash| trash::move_to(&trash, path));
+        let result = match trashed {
+            Some(Ok(())) => Ok(t!("delete.trashed", name)),
+            _ => match Tui::confirm_delete(self, &name, true)? {
+                Some(true) => fs::remove_file(path).map(|()| t!("delete.deleted", name)),
+                _ => return Ok(()),
+            },
+        };
+        match result {
+            Ok(message) => {
+                self.buffer.filename = None;
+                self.buffer.mark_unsaved();
+                self.scratch_count += 1;
+                self.buffer.scratch = self.scratch_count;
+                self.toast(message);
+            }
+            Err(err) => Tui::alert(
+                self,
+                t!("error.delete_failed").to_string(),
+                Error::get_error_message(&err).to_string(),
+            )?,
+        }
+        Ok(())
+    }
 
-                        MouseEventKind::Up(MouseButton::Left) => {
-                            mouse = None;
-                            dragging_sidebar = false;
-                        }
+    /// Throw away the changes after asking, and read the file again from disk.
+    fn revert_file(&mut self) -> Result<(), Error> {
+        let Some(name) = self.buffer.filename.clone() else {
+            self.toast(t!("revert.no_file"));
+            return Ok(());
+        };
+        if Tui::confirm_revert(self, &name)? != Some(true) {
+            return Ok(());
+        }
+        match self.reload_file(&name) {
+            Ok(()) => self.toast(t!("revert.done", name)),
+            Err(err) => Tui::alert(
+                self,
+                t!("error.read_failed").to_string(),
+                Error::get_error_message(&err).to_string(),
+            )?,
+        }
+        Ok(())
+    }
 
-                        MouseEventKind::Down(MouseButton::Right) => {
-                            // Fix wrong deletion when selection is empty
-                            if let Some((begin, end)) = self.get_selection() {
-                                if begin == end {
-                                    self.anchor = None;
-                                }
-                            }
+    /// Replace the text with what is in the file `name` as a step that can be undone,
+    /// leaving the buffer clean. The cursor stays on its line if the file still has it.
+    fn reload_file(&mut self, name: &str) -> std::io::Result<()> {
+        let file = Buffer::from_file(Path::new(name))?;
+        self.update_last_history_state();
+        self.buffer.history.mark_boundary();
+        self.buffer.rows = file.rows;
+        self.folds.clear();
+        self.tabstops.clear();
+        self.anchor = None;
+        self.cursor = self.buffer.clamp(self.cursor);
+        self.create_history();
+        // Typing right after is a step of its own, so undo comes back to the file
+        self.buffer.history.mark_boundary();
+        self.buffer.mark_saved();
+        Ok(())
+    }
 
-                            if let Some((_, end)) = self.get_selection() {
-                                self.trigger_copy()?;
-                                self.cursor = end;
-                                self.anchor = None;
-                            } else {
-                                self.trigger_paste();
-                            }
-                        }
+    /// Open an empty scratch buffer after the others, leaving the current one as it is.
+    fn new_scratch_buffer(&mut self) {
+        let current = self.stash_buffer();
+        self.buffers.insert(self.buffer_index, current);
+        self.buffer_index = self.buffers.len();
+        self.scratch_count += 1;
+        self.buffer.scratch = self.scratch_count;
+        self.create_history();
+        self.buffer.mark_saved();
+    }
 
-                        _ => {
-                            should_update_viewbox = false;
-                        }
-                    },
+    /// Show the buffer at `index` in the order of `all_buffers`.
+    fn switch_buffer(&mut self, index: usize) {
+        if index == self.buffer_index || index > self.buffers.len() {
+            return;
+        }
+        let current = self.stash_buffer();
+        self.buffers.insert(self.buffer_index, current);
+        let background = self.buffers.remove(index);
+        self.buffer_index = index;
+        self.show_buffer(background);
+    }
 
-                    Event::Resize(width, height) => {
-                        self.terminal.update_window_size(height, width);
-                    }
-                    _ => {}
+    /// Save every buffer with unsaved changes, showing each in turn so that scratch
+    /// buffers can be given a file name. Returns `false` if one of them wasn't saved.
+    fn save_all(&mut self) -> Result<bool, Error> {
+        for index in 0..=self.buffers.len() {
+            if self
+                .all_buffers()
+                .nth(index)
+                .is_some_and(|buffer| buffer.dirty)
+            {
+                self.switch_buffer(index);
+                if !self.try_save_file(false)? {
+                    return Ok(false);
                 }
-            } else if mouse.is_none() {
-                continue;
-            } else {
-                #[cfg(feature = "debug")]
-                continue;
             }
+        }
+        Ok(true)
+    }
 
-            if let Some(event) = mouse {
-                if !(event.kind == MouseEventKind::Down(MouseButton::Left)
-                    && (event.row as usize) >= self.terminal.height - 2)
-                    || dragging_sidebar
-                {
-                    self.cursor.y = event.row as usize + self.viewbox.y;
-                    let x =
-                        (event.column as usize + self.viewbox.x).saturating_sub(self.sidebar_width);
+    /// Replace the selection with its conversion, as one history entry, and select the
+    /// result. Input that can't be decoded is reported in a toast instead.
+    fn transform_selection(&mut self, transform: Transform) {
+        let Some(((begin, _), text)) = self.get_selection().zip(self.selection_text()) else {
+            return;
+        };
+        let result = match transform.apply(&text) {
+            Ok(result) => result,
+            Err(message) => return self.toast(message),
+        };
 
-                    if self.cursor.y >= self.buffer.len() {
-                        self.cursor.y = self.buffer.len() - 1;
-                        self.cursor.x = self.get_width();
-                    }
-                    if (event.column as usize) < self.sidebar_width {
-                        self.cursor.x = 0;
-                        self.cursor.y = event.row as usize + self.viewbox.y;
-                        if event.kind == MouseEventKind::Down(MouseButton::Left) {
-                            self.anchor = Some(self.cursor);
-                            dragging_sidebar = true;
-                            should_update_viewbox = false;
-                        }
-                        if dragging_sidebar && self.cursor.y >= self.anchor.unwrap_or(self.cursor).y
-                        {
-                            self.cursor.y += 1;
-                            if self.cursor.y >= self.buffer.len() {
-                                self.cursor.y = self.buffer.len() - 1;
-                                self.cursor.x = self.get_width();
-                            }
-                        }
-                    } else {
-                        if event.column + 1 >= self.terminal.width as u16 {
-                            self.cursor.x = self.get_width();
-                        } else {
-                            let visual_width = self.buffer[self.cursor.y]
-                                .rope
-                                .iter()
-                                .map(|g| g.1)
-                                .sum::<usize>();
-                            if x >= visual_width {
-                                self.cursor.x = self.get_width();
-                            } else {
-                                let mut width = 0;
-                                for (i, cell) in self.buffer[self.cursor.y].rope.iter().enumerate()
-                                {
-                                    if width >= x {
-                                        self.cursor.x = i;
-                                        break;
-                                    }
-                                    width += cell.1;
-                                }
-                            }
-                        }
+        self.update_last_history_state();
+        self.buffer.dirty = true;
+        self.buffer.history.begin_transaction();
+        self.delete_selection();
+        self.cursor = self.insert_at(begin, &result);
+        self.anchor = Some(begin);
+        self.create_history();
+        self.buffer.history.commit();
+    }
 
-                        // TODO: Make Shift+Drag work
-                        // && event.modifiers != KeyModifiers::SHIFT
-                        if event.kind == MouseEventKind::Down(MouseButton::Left) {
-                            self.anchor = Some(self.cursor);
-                        }
+    /// Clean up the whole buffer as one history entry, saying in a toast how many lines
+    /// or escape sequences went.
+    fn apply_line_operation(&mut self, operation: LineOperation) {
+        self.update_last_history_state();
+        let (changed, message) = match operation {
+            LineOperation::RemoveBlankLines | LineOperation::SqueezeBlankLines => {
+                let squeeze = operation == LineOperation::SqueezeBlankLines;
+                let removed = self.buffer.remove_blank_lines(squeeze);
+                // Positions on a deleted line go to the start of the line after it
+                for p in std::iter::once(&mut self.cursor).chain(&mut self.anchor) {
+                    if removed.binary_search(&p.y).is_ok() {
+                        p.x = 0;
                     }
+                    p.y -= removed.partition_point(|&y| y < p.y);
                 }
+                (!removed.is_empty(), t!("lines.removed", removed.len()))
             }
-
-            let c = self.get_cursor_position();
-            self.status_string = format!(
-                " viewbox: ({}, {}) | cursor: ({}, {}) @ {:?} | view cursor: ({}, {}) | Frame = {}",
-                self.viewbox.y + 1,
-                self.viewbox.x + 1,
-                self.cursor.y + 1,
-                self.cursor.x + 1,
-                self.anchor.map(|a| (a.y + 1, a.x + 1)),
-                c.y + 1,
-                c.x + 1,
-                cnt,
-            );
-            cnt += 1;
-
-            if !self.check_minimum_window_size() {
-                continue;
+            LineOperation::StripAnsi => {
+                let count = self.buffer.strip_ansi();
+                self.cursor = self.buffer.clamp(self.cursor);
+                self.anchor = self.anchor.map(|anchor| self.buffer.clamp(anchor));
+                (count > 0, t!("lines.ansi_stripped", count))
             }
+        };
+        if changed {
+            self.folds.clear();
+            self.create_history();
+            self.buffer.history.mark_boundary();
+        }
+        self.toast(message);
+    }
 
-            if should_update_viewbox {
-                self.update_viewbox();
+    /// Pretty-print the selected JSON, or the whole buffer's, with `indent` spaces per
+    /// level, or minify it if `None`, keeping the cursor on the same value. A syntax
+    /// error is shown in an alert and changes nothing.
+    fn reformat_json(&mut self, indent: Option<usize>) -> Result<(), Error> {
+        let (begin, end) = (self.get_selection())
+            .filter(|(begin, end)| begin != end)
+            .unwrap_or((
+                Position::default(),
+                self.buffer.clamp((0, usize::MAX).into()),
+            ));
+        let (begin, end) = (begin, self.buffer.clamp(end));
+        let text = self.buffer.text_range(begin, end);
+        let node = match json::Node::parse(&text) {
+            Ok(node) => node,
+            Err(err) => {
+                let at = position_after(begin, &text[..err.offset]);
+                let message = t!("json.error_at", at.y + 1, at.x + 1, err.message);
+                return Tui::alert(self, t!("json.parse_failed").to_string(), message);
             }
+        };
 
-            self.render()?;
+        let cursor = self.buffer.clamp(self.cursor).clamp(begin, end);
+        let offset = self.buffer.text_range(begin, cursor).len();
+        let (mut printed, found) = node.print(indent, &node.path_at(offset));
+        if text.ends_with('\n') {
+            printed.push('\n');
+        }
+        if printed == text {
+            return Ok(());
         }
 
+        self.update_last_history_state();
+        self.delete_at(begin, end);
+        self.insert_at(begin, &printed);
+        self.folds.clear();
+        self.anchor = None;
+        self.cursor = position_after(begin, &printed[..found]);
+        self.create_history();
         Ok(())
     }
 
-    fn delete_selection_range(&mut self, begin: Position, end: Position) {
-        // Range delete
-        self.buffer[begin.y] = Row::from(
-            self.buffer[begin.y]
-                .rope
-                .iter()
-                .take(begin.x)
-                .chain(self.buffer[end.y].rope.iter().skip(end.x))
-                .cloned()
-                .collect::<Vec<_>>(),
-        );
-        for index in (begin.y + 1..=end.y).rev() {
-            self.buffer.remove(index);
+    /// How the editor was built and which syntax definitions, theme and configuration
+    /// file it uses, for the About dialog.
+    fn about_lines(&self) -> Vec<String> {
+        let built = DateTime::from_timestamp(crate::build_timestamp(), 0).format("%F");
+        let dir = &self.config.syntax_dir;
+        let mut syntax_dir = dir.display().to_string();
+        if !dir.is_dir() {
+            syntax_dir += t!("about.builtin");
         }
-        // Reset cursor and anchor
-        self.cursor = begin;
-        self.anchor = None;
+        let syntax = match self.buffer.syntax.name.as_str() {
+            "" => t!("about.none").to_string(),
+            name => name.to_string(),
+        };
+        let path = |path: Option<&Path>| match path {
+            Some(path) => path.display().to_string(),
+            None => t!("about.none").to_string(),
+        };
+        vec![
+            t!("about.built", built, crate::build_info()),
+            t!("about.syntax", syntax),
+            t!("about.syntax_dir", syntax_dir),
+            t!("about.theme", path(self.config.theme.as_deref())),
+            t!("about.config", path(Config::path().as_deref())),
+        ]
     }
 
-    fn get_selection(&self) -> Option<(Position, Position)> {
-        self.anchor.map(|anchor| {
-            let cursor = self.cursor;
-            if anchor < cursor {
-                (anchor, cursor)
-            } else {
-                (cursor, anchor)
-            }
-        })
+    /// List the keys with the commands they run, as the `[keys]` section names them, and
+    /// run the one picked. Returns `true` if the editor should exit.
+    fn show_key_help(&mut self) -> Result<bool, Error> {
+        let keys = self.config.keymap.help();
+        let width = keys.iter().map(|(chord, _)| chord.len()).max().unwrap_or(0);
+        let items = (keys.iter())
+            .map(|(chord, command)| format!("{chord:width$}  {}", command.name()))
+            .collect();
+        match Tui::select(self, t!("dialog.keys").to_string(), items)? {
+            Some(index) => self.execute(keys[index].1),
+            None => Ok(false),
+        }
     }
 
-    fn update_selection(&mut self, modifiers: KeyModifiers) {
-        if modifiers.contains(KeyModifiers::SHIFT) {
-            // if anchor is None, set it to cursor
-            self.anchor.get_or_insert(self.cursor);
-        } else {
+    /// List the definitions or headings with their line numbers, and jump to the one
+    /// picked.
+    fn show_outline(&mut self) -> Result<(), Error> {
+        let lines = self.buffer.outline().to_vec();
+        if lines.is_empty() {
+            self.toast(t!("outline.empty"));
+            return Ok(());
+        }
+        let digits = lines[lines.len() - 1].to_string().len();
+        let items = (lines.iter())
+            .map(|&y| {
+                format!(
+                    "{:>digits$}  {}",
+                    y + 1,
+                    self.buffer.rows[y].to_string().trim()
+                )
+            })
+            .collect();
+        let title = t!("dialog.outline").to_string();
+        if let Some(index) = Tui::select(self, title, items)? {
+            let row = &self.buffer.rows[lines[index]];
+            let x = (row.rope.iter())
+                .position(|(g, _)| !g.chars().all(char::is_whitespace))
+                .unwrap_or(0);
             self.anchor = None;
+            self.cursor = Position { x, y: lines[index] };
+            self.reveal_cursor();
         }
+        Ok(())
     }
 
-    fn render(&mut self) -> Result<(), Error> {
-        self.terminal.clear_buffer();
-        self.terminal.begin_render()?;
-
-        self.render_to_buffer();
-        self.render_cursor();
-
-        self.terminal.end_render()?;
+    /// Move the cursor to where the next line wider than `max_line_length` crosses it,
+    /// starting over from the top after the last line.
+    fn next_long_line(&mut self) {
+        let Some(limit) = self.config.max_line_length else {
+            return self.toast(t!("long_line.no_limit"));
+        };
+        let count = self.buffer.line_count();
+        let next = (1..=count)
+            .map(|i| (self.cursor.y + i) % count)
+            .find(|&y| self.buffer.rows[y].visual_width() > limit);
+        match next {
+            Some(y) => {
+                self.anchor = None;
+                self.cursor = Position {
+                    x: self.buffer.rows[y].x_to_index(limit),
+                    y,
+                };
+            }
+            None => self.toast(t!("long_line.none", limit)),
+        }
+    }
 
-        Ok(())
+    /// Move the cursor to the start of `line`, or to `column` of it, both counting from
+    /// 1. The cursor's own line if only the column is given.
+    fn goto(&mut self, line: Option<usize>, column: Option<Column>) {
+        let last = self.buffer.line_count() - 1;
+        let y = line.map_or(self.cursor.y, |line| line - 1).min(last);
+        let row = &self.buffer.rows[y];
+        let x = match column {
+            None => 0,
+            Some(Column::Visual(x)) => row.x_to_index(x - 1),
+            Some(Column::Char(x)) => (x - 1).min(row.len()),
+            Some(Column::Byte(x)) => row.byte_to_index(x - 1),
+        };
+        self.anchor = None;
+        self.cursor = Position { x, y };
+        self.reveal_cursor();
+    }
+
+    /// Select the next cell with a hard to see character in it and name the character
+    /// in a toast, starting over from the top after the last line.
+    fn next_anomaly(&mut self) {
+        let (count, from) = (self.buffer.line_count(), self.cursor);
+        // The line the cursor is on comes around again at the end, from its start
+        let next = (0..=count).find_map(|i| {
+            let y = (from.y + i) % count;
+            let cells = self.buffer.anomalies_on(y);
+            let x = match i {
+                0 => cells.iter().find(|&&x| x >= from.x),
+                _ => cells.first(),
+            };
+            x.map(|&x| Position { x, y })
+        });
+        let Some(found) = next else {
+            return self.toast(t!("anomaly.none"));
+        };
+        self.anchor = Some(found);
+        self.cursor = Position {
+            x: found.x + 1,
+            ..found
+        };
+        self.reveal_cursor();
+        let cell = &self.buffer.rows[found.y].rope[found.x].0;
+        let c = cell.chars().find(|&c| anomaly::is_anomaly(c));
+        self.toast(anomaly::describe(c.unwrap_or_default()));
+    }
+
+    /// How many cells `clean_anomalies` would change.
+    fn cleanable_anomalies(&self) -> usize {
+        (0..self.buffer.line_count())
+            .flat_map(|y| (self.buffer.anomalies_on(y).iter()).map(move |&x| (x, y)))
+            .filter(|&(x, y)| Self::cleaned(&self.buffer.rows[y].rope[x].0).is_some())
+            .count()
+    }
+
+    /// A cell with the spaces that do not break made plain and the zero-width
+    /// characters left out, or `None` if it has neither.
+    fn cleaned(cell: &str) -> Option<String> {
+        let replace = |c| anomaly::replacement(c).filter(|_| anomaly::is_anomaly(c));
+        cell.chars().any(|c| replace(c).is_some()).then(|| {
+            cell.chars()
+                .map(|c| replace(c).map_or(c.to_string(), str::to_string))
+                .collect()
+        })
+    }
+
+    /// Replace the spaces that do not break with plain ones and remove the zero-width
+    /// characters, as one step in the history. The bidirectional controls stay.
+    fn clean_anomalies(&mut self) {
+        self.update_last_history_state();
+        let mut changed = false;
+        for y in 0..self.buffer.line_count() {
+            // From the right, so that the cells to go keep their indices
+            for x in self.buffer.anomalies_on(y).to_vec().into_iter().rev() {
+                let Some(text) = Self::cleaned(&self.buffer.rows[y].rope[x].0) else {
+                    continue;
+                };
+                let at = Position { x, y };
+                self.buffer.delete_range(at, Position { x: x + 1, y });
+                let end = self.buffer.insert(at, &text);
+                // Keep the cursor and anchor by the same characters
+                let shift = |pos: &mut Position| {
+                    if pos.y == y && pos.x > x {
+                        pos.x = pos.x + end.x - (x + 1);
+                    }
+                };
+                shift(&mut self.cursor);
+                if let Some(anchor) = &mut self.anchor {
+                    shift(anchor);
+                }
+                changed = true;
+            }
+        }
+        if changed {
+            self.create_history();
+            self.buffer.history.mark_boundary();
+        }
+    }
+
+    /// Count where the selection, or else the word under the cursor, appears in the
+    /// buffer, matching as search does, and show the numbers in a toast.
+    fn count_occurrences(&mut self) {
+        let needle = match self.get_selection() {
+            Some((begin, end)) if begin.y != end.y => {
+                self.toast(t!("count.single_line"));
+                return;
+            }
+            Some((begin, end)) if begin != end => self.buffer.text_range(begin, end),
+            _ => {
+                let cursor = self.buffer.clamp(self.cursor);
+                let row = &self.buffer.rows[cursor.y];
+                let word = row.word_at(cursor.x);
+                row.rope[word].iter().map(|(g, _)| g.as_str()).collect()
+            }
+        };
+        if needle.trim().is_empty() {
+            self.toast(t!("count.nothing"));
+            return;
+        }
+
+        let (matches, lines, complete) = count_matches(
+            &self.buffer.rows,
+            &needle,
+            self.config.search_ignore_case,
+            COUNT_TIME_LIMIT,
+        );
+        let count = |n: usize| match complete {
+            true => n.to_string(),
+            false => format!("≥ {n}"),
+        };
+        self.toast(t!("count.result", needle, count(matches), count(lines)));
+    }
+
+    /// The URL the cursor is in or right after.
+    fn url_under_cursor(&self) -> Option<String> {
+        let cursor = self.buffer.clamp(self.cursor);
+        let row = &self.buffer.rows[cursor.y];
+        let url = row
+            .urls()
+            .into_iter()
+            .find(|url| url.contains(&cursor.x) || url.end == cursor.x)?;
+        Some(row.rope[url].iter().map(|(g, _)| g.as_str()).collect())
+    }
+
+    /// Open the URL under the cursor in the browser. Returns `false` if there is none.
+    fn open_url_under_cursor(&mut self) -> bool {
+        let Some(url) = self.url_under_cursor() else {
+            return false;
+        };
+        match open_url(&url) {
+            Ok(()) => self.toast(t!("url.opening", url)),
+            Err(err) => self.toast(t!("url.failed", Error::get_error_message(&err))),
+        }
+        true
+    }
+
+    fn event_loop(&mut self) -> Result<(), Error> {
+        loop {
+            if !self.prompts.is_empty() {
+                self.show_prompts()?;
+                self.refresh(true)?;
+            }
+            if self.poll_event()? {
+                let mut event = self.read_event()?;
+                // Scroll through the wheel events queued behind this one without
+                // drawing each step, so a fast flick doesn't lag behind
+                while let Some(command) = self.wheel_command(&event) {
+                    if !event::poll(Duration::ZERO)? {
+                        break;
+                    }
+                    self.execute(command)?;
+                    event = self.read_event()?;
+                }
+                if !self.handle_event(&event)? {
+                    break;
+                }
+            } else if self.expire_toast()
+                || self.autosave()
+                || self.watch_files()
+                || self.buffer.highlight_more(SYNTAX_CHUNK)
+            {
+                self.refresh(false)?;
+            } else if let Some(following) = self.follow_file() {
+                self.refresh(following)?;
+            } else if self.drag.is_some() && !cfg!(feature = "debug") {
+                // Keep scrolling while the mouse is held past the edge of the text
+                self.refresh(true)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Wait a little for an event, unless a signal arrived and the editor has to exit.
+    /// Failing to means the terminal has most likely gone away, and the signal saying
+    /// so may be on its way. Every loop waiting for input goes through this, dialogs
+    /// included.
+    pub(crate) fn poll_event(&mut self) -> Result<bool, Error> {
+        if let Some(signal) = signal::received() {
+            self.emergency_exit(signal);
+        }
+        match event::poll(Duration::from_millis(25)) {
+            Ok(ready) => Ok(ready),
+            Err(err) => self.after_terminal_error(err),
+        }
+    }
+
+    pub(crate) fn read_event(&mut self) -> Result<Event, Error> {
+        match event::read() {
+            Ok(event) => Ok(event),
+            Err(err) => self.after_terminal_error(err),
+        }
+    }
+
+    /// Give the signal explaining a terminal error a moment to arrive, and exit the way
+    /// it asks for if it does.
+    fn after_terminal_error<T>(&mut self, err: std::io::Error) -> Result<T, Error> {
+        std::thread::sleep(Duration::from_millis(100));
+        if let Some(signal) = signal::received() {
+            self.emergency_exit(signal);
+        }
+        Err(err.into())
+    }
+
+    /// Exit after the terminal was closed or the editor was told to stop. The buffers
+    /// with unsaved changes are written to the recovery directory rather than over their
+    /// files, which the user didn't ask to save.
+    fn emergency_exit(&mut self, signal: i32) -> ! {
+        let recovered: Vec<PathBuf> = (self.all_buffers())
+            .filter(|buffer| buffer.dirty)
+            .filter_map(|buffer| {
+                let path = signal::recovery_path(buffer)?;
+                fs::create_dir_all(path.parent()?).ok()?;
+                buffer.write_to(&path).ok()?;
+                Some(path)
+            })
+            .collect();
+        let _ = self.terminal.cleanup();
+        for path in recovered {
+            eprintln!("{}", t!("recovery.saved", path.display()));
+        }
+        std::process::exit(signal::exit_code(signal))
+    }
+
+    /// The command a mouse wheel event scrolls the view with, if `event` is one.
+    fn wheel_command(&self, event: &Event) -> Option<Command> {
+        let Event::Mouse(event) = event else {
+            return None;
+        };
+        keymap::mouse_command(event, self.config.wheel)
+            .filter(|command| matches!(command, Command::Scroll(..) | Command::ScrollPage(_)))
+    }
+
+    /// Handle a key, mouse or resize event and draw the next frame. Returns `false`
+    /// once the editor should exit.
+    ///
+    /// Commands that open a dialog (saving, quitting) read further events from the
+    /// terminal themselves.
+    pub fn handle_event(&mut self, event: &Event) -> Result<bool, Error> {
+        let mut should_update_viewbox = true;
+        match event {
+            // Keyboard Event
+            Event::Key(event) if event.kind != KeyEventKind::Release => {
+                // The key after `SelectRegister` names the register
+                if self.awaiting_register {
+                    self.awaiting_register = false;
+                    self.toast = None;
+                    if let KeyCode::Char(name) = event.code {
+                        if name.is_ascii_alphanumeric() {
+                            let name = name.to_ascii_lowercase();
+                            self.register = Some(name);
+                            self.toast(t!("register.selected", name));
+                        }
+                    }
+                } else if event.code == KeyCode::Esc && self.escape_cooling_down() {
+                    // Closing the search box was all this press, or this repeat, did
+                } else if event.code == KeyCode::Esc && !self.tabstops.is_empty() {
+                    // Esc leaves snippet mode before doing whatever it is bound to
+                    self.tabstops.clear();
+                } else if let Some(commands) = self.modal_key(event.modifiers, event.code) {
+                    if self.execute_all(&commands)? {
+                        return Ok(false);
+                    }
+                    should_update_viewbox = !commands.iter().all(|command| command.keeps_view());
+                } else if let Some(command) =
+                    self.config
+                        .keymap
+                        .translate((event.modifiers, event.code, event.state))
+                {
+                    if self.execute(command)? {
+                        return Ok(false);
+                    }
+                    should_update_viewbox = !command.keeps_view();
+                } else if self.config.show_unbound_keys {
+                    // Name the chord as `[keys]` would, to tell what the terminal sent
+                    let chord = (event.modifiers, event.code, event.state);
+                    self.toast(t!("key.unbound", keymap::format_chord(chord)));
+                }
+            }
+
+            // Mouse Event
+            Event::Mouse(event) => {
+                // A click in the other pane moves the focus there, and the wheel scrolls
+                // the pane under the mouse either way. Drags stay in the pane they began in.
+                let (column, row) = (event.column as usize, event.row as usize);
+                let mut other_pane = self.drag.is_none() && self.in_other_pane(column, row);
+                if other_pane && matches!(event.kind, MouseEventKind::Down(_)) {
+                    self.focus_other_pane();
+                    other_pane = false;
+                }
+                if let Some(command) = keymap::mouse_command(event, self.config.wheel) {
+                    if other_pane {
+                        self.swap_panes();
+                    }
+                    self.execute(command)?;
+                    if other_pane {
+                        self.swap_panes();
+                    }
+                    should_update_viewbox = !command.keeps_view();
+                } else {
+                    match event.kind {
+                        MouseEventKind::Down(MouseButton::Left) => {
+                            self.drag = self
+                                .hit_test(event.column as usize, event.row as usize)
+                                .map(|area| (*event, area));
+
+                            // Ctrl+Click opens a link instead of selecting
+                            let text = self.drag.is_some_and(|(_, area)| area == MouseArea::Text);
+                            if text && event.modifiers.contains(KeyModifiers::CONTROL) {
+                                self.drag = None;
+                                self.drag_mouse(event, MouseArea::Text);
+                                self.anchor = None;
+                                self.open_url_under_cursor();
+                            }
+                            // Alt+Drag from inside the selection moves its lines
+                            if text
+                                && event.modifiers.contains(KeyModifiers::ALT)
+                                && self.mouse_in_selection(event)
+                            {
+                                self.drag = Some((*event, MouseArea::Lines));
+                            }
+                        }
+                        MouseEventKind::Drag(MouseButton::Left) => {
+                            if let Some((_, area)) = self.drag {
+                                self.drag = Some((*event, area));
+                            }
+                        }
+
+                        MouseEventKind::Up(MouseButton::Left) => {
+                            if let Some((_, MouseArea::Lines)) = self.drag {
+                                self.drop_lines(self.drop_line(event.row as usize));
+                            }
+                            self.drag = None;
+                        }
+
+                        // Only redraw when the mouse reaches another cell
+                        MouseEventKind::Moved if self.hover_info => {
+                            let hover = Position {
+                                x: event.column as usize,
+                                y: event.row as usize,
+                            };
+                            if self.hover == Some(hover) {
+                                return Ok(true);
+                            }
+                            self.hover = Some(hover);
+                            should_update_viewbox = false;
+                        }
+
+                        _ => {
+                            should_update_viewbox = false;
+                        }
+                    }
+                }
+            }
+
+            Event::Resize(width, height) => {
+                self.terminal.update_window_size(*height, *width);
+                self.on_resize();
+            }
+            _ => {}
+        }
+
+        self.refresh(should_update_viewbox)?;
+        Ok(true)
+    }
+
+    /// Follow the mouse if it is being dragged, then draw a frame.
+    fn refresh(&mut self, mut should_update_viewbox: bool) -> Result<(), Error> {
+        if let Some((event, area)) = self.drag {
+            if !self.drag_mouse(&event, area) {
+                should_update_viewbox = false;
+            }
+        }
+
+        let c = self.get_cursor_position();
+        self.status_string = format!(
+            " viewbox: ({}, {}) | cursor: ({}, {}) @ {:?} | view cursor: ({}, {}) | Frame = {}",
+            self.viewbox.y + 1,
+            self.viewbox.x + 1,
+            self.cursor.y + 1,
+            self.cursor.x + 1,
+            self.anchor.map(|a| (a.y + 1, a.x + 1)),
+            c.y + 1,
+            c.x + 1,
+            self.frame,
+        );
+        if cfg!(debug_assertions) {
+            self.status_string += &format!(
+                " | history: -{} +{}",
+                self.buffer.history.undo_depth(),
+                self.buffer.history.redo_depth()
+            );
+        }
+        if let Some(info) = self.hover.and_then(|hover| self.hover_info_at(hover)) {
+            self.status_string = info;
+        }
+        self.frame += 1;
+
+        if !self.check_minimum_window_size() {
+            return Ok(());
+        }
+
+        if should_update_viewbox {
+            self.update_viewbox();
+        }
+
+        self.render()
+    }
+
+    /// Run a command bound in the keymap. Returns `true` if the editor should exit.
+    fn execute(&mut self, command: Command) -> Result<bool, Error> {
+        let edit = self.edit_span(command);
+        if command == Command::InsertTab && (self.next_tabstop() || self.expand_snippet()) {
+            self.track_folds(edit);
+            return Ok(false);
+        }
+        if Self::edits(command, edit) && self.refuse_edit() {
+            return Ok(false);
+        }
+        // The matches would no longer be where the text was changed
+        if Self::edits(command, edit) {
+            self.search_result.clear();
+        }
+        let reference = self.tabstop_reference(command);
+
+        match command {
+            Command::Save | Command::SaveAs => {
+                self.try_save_file(command == Command::SaveAs)?;
+            }
+
+            Command::Cancel if self.transient().is_some() => self.cancel_transient(),
+
+            Command::Quit | Command::Cancel => {
+                let unsaved = (self.all_buffers())
+                    .filter(|buffer| buffer.dirty)
+                    .map(buffer_name)
+                    .collect();
+                match Tui::confirm_exit(self, unsaved)? {
+                    Some(true) => return self.save_all(),
+                    Some(false) => {
+                        for buffer in self.all_buffers() {
+                            self.keep_discarded(buffer);
+                        }
+                        return Ok(true);
+                    }
+                    None => {}
+                }
+            }
+
+            Command::NewBuffer => self.new_scratch_buffer(),
+
+            Command::RecentFiles => self.open_recent()?,
+
+            Command::Outline => self.show_outline()?,
+            Command::GotoLine => {
+                let title = t!("dialog.goto").to_string();
+                let yes = t!("button.go").to_string();
+                if let Some((line, column)) = Tui::prompt(self, title, yes, parse_goto)? {
+                    self.goto(line, column);
+                }
+            }
+            Command::About => {
+                let title = format!("arcaea {}", crate::version());
+                Tui::about(self, title, self.about_lines())?;
+            }
+            Command::KeyHelp => return self.show_key_help(),
+            Command::DeleteFile => self.delete_file()?,
+            Command::RevertFile => self.revert_file()?,
+
+            // The new pane below starts with the same view; closing keeps the focused one
+            Command::SplitView => match self.split {
+                Some(_) => self.split = None,
+                None => {
+                    self.split = Some(Pane {
+                        viewbox: self.viewbox,
+                        cursor: self.cursor,
+                        anchor: self.anchor,
+                        below: true,
+                    })
+                }
+            },
+            Command::SwitchPane => match self.split {
+                Some(_) => self.focus_other_pane(),
+                None => self.toast(t!("split.none")),
+            },
+
+            Command::SwitchBuffer => {
+                let items = (self.all_buffers())
+                    .map(|buffer| match buffer.dirty {
+                        true => t!("status.unsaved", buffer_name(buffer)),
+                        false => buffer_name(buffer),
+                    })
+                    .collect();
+                let title = t!("dialog.switch_buffer").to_string();
+                if let Some(index) = Tui::select(self, title, items)? {
+                    self.switch_buffer(index);
+                }
+            }
+
+            Command::SelectAll => {
+                self.anchor = Some(Position { x: 0, y: 0 });
+                self.cursor.y = self.buffer.line_count() - 1;
+                self.cursor.x = self.get_width();
+            }
+
+            Command::Undo => {
+                if self.buffer.history.undo() {
+                    self.restore_history();
+                    self.toast(t!("history.undo", self.buffer.history.redo_depth()));
+                } else {
+                    self.toast(t!("history.nothing_to_undo"));
+                }
+            }
+
+            Command::Redo => {
+                if self.buffer.history.redo() {
+                    self.restore_history();
+                    self.toast(t!("history.redo", self.buffer.history.undo_depth()));
+                } else {
+                    self.toast(t!("history.nothing_to_redo"));
+                }
+            }
+
+            // Jump to another branch of the undo tree
+            Command::UndoBranch => {
+                let branches = self.buffer.history.branches();
+                if let Some(i) = Tui::select_undo_branch(self, &branches)? {
+                    if self.buffer.history.jump_to(branches[i].id) {
+                        self.restore_history();
+                    }
+                }
+            }
+
+            Command::Copy | Command::Cut => {
+                self.trigger_copy();
+
+                if command == Command::Cut {
+                    self.update_last_history_state();
+                    self.buffer.dirty = true;
+                    if let Some((begin, end)) = self.get_selection() {
+                        self.delete_selection_range(begin, end);
+                    } else {
+                        // Delete the whole line, with its line break
+                        let y = self.cursor.y;
+                        if y + 1 < self.buffer.line_count() {
+                            self.buffer.delete_range((0, y).into(), (0, y + 1).into());
+                        } else if y > 0 {
+                            let end = (self.get_width(), y).into();
+                            let width = self.buffer.rows[y - 1].len();
+                            self.buffer.delete_range((width, y - 1).into(), end);
+                        } else {
+                            self.buffer.rows[0] = Row::default();
+                        }
+                        self.cursor = Position {
+                            x: 0,
+                            y: y.min(self.buffer.line_count() - 1),
+                        };
+                    }
+                    self.create_history();
+                }
+            }
+
+            Command::CopyAnsi | Command::CopyHtml => self.copy_highlighted(command),
+
+            Command::Paste => self.trigger_paste(false),
+            Command::PasteReindent => self.trigger_paste(true),
+            Command::SelectRegister => {
+                self.awaiting_register = true;
+                self.toast(t!("register.prompt"));
+            }
+            Command::Registers => {
+                let mut registers: Vec<_> = self.registers.clone().into_iter().collect();
+                registers.sort_unstable();
+                if registers.is_empty() {
+                    self.toast(t!("register.none"));
+                } else if let Some(i) = Tui::select_register(self, &registers)? {
+                    let (_, text) = registers.swap_remove(i);
+                    self.paste_text(text, false, false);
+                }
+            }
+
+            Command::Find => self.search(false)?,
+            Command::FindReverse => self.search(true)?,
+
+            Command::Format => {
+                self.format_document()?;
+            }
+
+            Command::InsertUnicode => {
+                let title = t!("dialog.code_points").to_string();
+                let yes = t!("button.insert").to_string();
+                if let Some(text) = Tui::prompt(self, title, yes, parse_code_points)? {
+                    self.insert_over_selection(&text);
+                }
+            }
+
+            Command::InsertVariable => {
+                let variables = self.variables();
+                let items = (variables.iter())
+                    .map(|(name, value)| format!("{name}: {value}"))
+                    .collect();
+                let title = t!("dialog.insert_variable").to_string();
+                if let Some(index) = Tui::select(self, title, items)? {
+                    self.insert_over_selection(&variables[index].1);
+                }
+            }
+            Command::InsertDate => {
+                let date = DateTime::now().format(&self.config.date_format);
+                self.insert_over_selection(&date);
+            }
+
+            Command::OpenPath => self.open_path_under_cursor()?,
+
+            Command::CountOccurrences => self.count_occurrences(),
+
+            Command::JsonPretty => self.reformat_json(Some(self.config.tab_width))?,
+            Command::JsonMinify => self.reformat_json(None)?,
+
+            Command::NextLongLine => self.next_long_line(),
+            Command::NextAnomaly => self.next_anomaly(),
+            Command::CleanAnomalies => {
+                let count = self.cleanable_anomalies();
+                if count == 0 {
+                    self.toast(t!("anomaly.nothing_to_clean"));
+                } else if Tui::confirm_clean_anomalies(self, count)? == Some(true) {
+                    self.clean_anomalies();
+                    self.toast(t!("anomaly.cleaned", count));
+                }
+            }
+
+            Command::TransformSelection => {
+                if self.selection_text().is_none() {
+                    self.toast(t!("transform.no_selection"));
+                } else {
+                    let title = t!("dialog.transform").to_string();
+                    let items = Transform::ALL.map(|t| t.label().to_string()).to_vec();
+                    if let Some(index) = Tui::select(self, title, items)? {
+                        self.transform_selection(Transform::ALL[index]);
+                    }
+                }
+            }
+
+            Command::LineOperations => {
+                let title = t!("dialog.line_operations").to_string();
+                let items = LineOperation::ALL.map(|o| o.label().to_string()).to_vec();
+                if let Some(index) = Tui::select(self, title, items)? {
+                    self.apply_line_operation(LineOperation::ALL[index]);
+                }
+            }
+
+            Command::InsertFile => {
+                let title = t!("dialog.insert_file").to_string();
+                let yes = t!("button.insert").to_string();
+                let read = |path: &str| {
+                    fs::read(path)
+                        .map(|bytes| (path.to_string(), bytes))
+                        .map_err(|err| Error::get_error_message(&err).to_string())
+                };
+                if let Some((path, bytes)) = Tui::prompt_path(self, title, yes, read)? {
+                    let binary = bytes.contains(&0) || std::str::from_utf8(&bytes).is_err();
+                    if !binary || Tui::confirm_binary(self, &path)? == Some(true) {
+                        // Inserted lines take the buffer's line ending
+                        self.insert_over_selection(&String::from_utf8_lossy(&bytes));
+                    }
+                }
+            }
+
+            Command::Reindent => {
+                let (first, last) = self.selected_lines();
+                let indents: Vec<_> = (self.buffer.rows[first..=last].iter())
+                    .map(Row::first_non_blank)
+                    .collect();
+
+                self.update_last_history_state();
+                if self.buffer.reindent(first..=last, self.config.tab_width) {
+                    // Keep the cursor and anchor on the same text
+                    let rows = &self.buffer.rows;
+                    let follow = |p: &mut Position| {
+                        if let Some(&old) = indents.get(p.y.wrapping_sub(first)) {
+                            let new = rows[p.y].first_non_blank();
+                            p.x = if p.x >= old {
+                                p.x - old + new
+                            } else {
+                                p.x.min(new)
+                            };
+                        }
+                    };
+                    follow(&mut self.cursor);
+                    if let Some(anchor) = &mut self.anchor {
+                        follow(anchor);
+                    }
+                    self.create_history();
+                    self.buffer.history.mark_boundary();
+                }
+            }
+
+            Command::ReloadConfig => {
+                self.apply_width_mode();
+                self.reload_syntax();
+                self.reload_theme();
+            }
+
+            Command::Fold => self.fold(),
+            Command::Unfold => {
+                let y = self.cursor.y;
+                self.folds.retain(|fold| fold.start != y + 1);
+            }
+
+            Command::ToggleHoverInfo => {
+                self.hover_info = !self.hover_info;
+                self.hover = None;
+            }
+            Command::ConvertLineEndings => {
+                let ending = match self.buffer.line_ending() {
+                    ending if self.buffer.has_mixed_line_endings() => ending,
+                    LineEnding::Lf => LineEnding::Crlf,
+                    LineEnding::Crlf => LineEnding::Lf,
+                };
+                self.update_last_history_state();
+                if self.buffer.set_line_ending(ending) {
+                    self.create_history();
+                    self.buffer.history.mark_boundary();
+                }
+                self.toast(t!("line_ending.converted", ending.name()));
+            }
+
+            Command::InsertChar(char) => {
+                self.update_last_history_state();
+                self.buffer.dirty = true;
+
+                self.settle_selection();
+
+                self.buffer.history.begin_transaction();
+                self.delete_selection();
+
+                // An accent typed after its letter, as some dead keys send it, joins the
+                // letter's cell
+                let mut text = char.to_string();
+                let before = self.cursor.x.checked_sub(1);
+                if let Some(x) = before.filter(|_| self.config.normalize_unicode) {
+                    let joined = self.buffer.rows[self.cursor.y].rope[x].0.clone() + &text;
+                    if joined.graphemes(true).count() == 1 {
+                        let start = Position { x, ..self.cursor };
+                        self.cursor = self.buffer.delete_range(start, self.cursor);
+                        text = row::nfc(&joined).into_owned();
+                    }
+                }
+                self.cursor = self.buffer.insert(self.cursor, &text);
+
+                self.create_history();
+                self.buffer.history.commit();
+            }
+
+            Command::InsertTab => {
+                self.update_last_history_state();
+                self.buffer.dirty = true;
+
+                self.settle_selection();
+
+                self.buffer.history.begin_transaction();
+                self.delete_selection();
+
+                let tab_width = self.config.tab_width;
+                self.buffer.rows[self.cursor.y]
+                    .insert_cell(self.cursor.x, (" ".repeat(tab_width), tab_width));
+                self.cursor.x += 1;
+
+                self.create_history();
+                self.buffer.history.commit();
+            }
+
+            Command::NewLine => {
+                self.update_last_history_state();
+                self.buffer.dirty = true;
+
+                self.settle_selection();
+
+                self.buffer.history.begin_transaction();
+                let selected = self
+                    .get_selection()
+                    .is_some_and(|(begin, end)| begin != end);
+                self.delete_selection();
+
+                // Between brackets, the closing one goes on a line of its own below an
+                // indented empty line for the cursor
+                if !selected && self.buffer.in_bracket_pair(self.cursor) {
+                    let tab_width = self.config.tab_width;
+                    let (indent, deeper) = self.buffer.indents(self.cursor.y, tab_width);
+                    self.buffer
+                        .insert(self.cursor, &format!("\n{deeper}\n{indent}"));
+                    self.cursor = Position {
+                        x: deeper.graphemes(true).count(),
+                        y: self.cursor.y + 1,
+                    };
+                } else {
+                    self.cursor = self.buffer.insert(self.cursor, "\n");
+                }
+
+                self.create_history();
+                self.buffer.history.commit();
+            }
+
+            Command::GrowSelection => self.grow_selection(),
+
+            Command::ShrinkSelection => {
+                if self.selection_stack.last() == Some(&(self.anchor, self.cursor))
+                    && self.selection_stack.len() > 1
+                {
+                    self.selection_stack.pop();
+                    (self.anchor, self.cursor) = *self.selection_stack.last().unwrap();
+                }
+            }
+
+            Command::SwapSelectionEnds => {
+                if let Some(anchor) = self.anchor {
+                    self.anchor = Some(self.buffer.clamp(self.cursor));
+                    self.cursor = anchor;
+                }
+            }
+
+            Command::TransposeChars | Command::TransposeWords => {
+                self.update_last_history_state();
+                self.anchor = None;
+                let Position { x, y } = self.buffer.clamp(self.cursor);
+                let row = &mut self.buffer.rows[y];
+                let after = match command {
+                    Command::TransposeChars => row.transpose_cells(x),
+                    _ => row.transpose_words(x),
+                };
+                match after {
+                    Some(x) => {
+                        self.buffer.dirty = true;
+                        self.cursor = Position { x, y };
+                        self.create_history();
+                        self.buffer.history.mark_boundary();
+                    }
+                    None => self.toast(t!("transpose.nothing")),
+                }
+            }
+
+            Command::DeleteToLineEnd | Command::DeleteToLineStart => {
+                self.update_last_history_state();
+
+                // Only the cursor matters, the selection is dropped
+                self.anchor = None;
+                self.settle_selection();
+
+                let Position { x, y } = self.cursor;
+                let (begin, end) = match command {
+                    Command::DeleteToLineStart => (Position { x: 0, y }, self.cursor),
+                    _ if x < self.get_width() => (self.cursor, (self.get_width(), y).into()),
+                    _ => (self.cursor, Position { x: 0, y: y + 1 }),
+                };
+                self.cursor = self.buffer.delete_range(begin, end);
+
+                self.create_history();
+            }
+
+            Command::DeleteBackward => {
+                self.update_last_history_state();
+                self.buffer.dirty = true;
+
+                self.settle_selection();
+
+                // Fix wrong deletion when selection is empty
+                if let Some((begin, end)) = self.get_selection() {
+                    if begin == end {
+                        self.anchor = None;
+                    }
+                }
+
+                if let Some((begin, end)) = self.get_selection() {
+                    self.delete_selection_range(begin, end);
+                } else if self.buffer.in_bracket_pair(self.cursor) {
+                    // Both brackets of an empty pair go together
+                    self.cursor.x -= 1;
+                    let x = self.cursor.x;
+                    self.buffer.rows[self.cursor.y].remove_range(x..x + 2);
+                } else if self.cursor.x > 0 {
+                    // The cursor is in the middle, just delete the char
+                    self.cursor.x -= 1;
+                    let x = self.cursor.x;
+                    self.buffer.rows[self.cursor.y].remove_range(x..x + 1);
+                } else if self.cursor.y > 0 {
+                    // The cursor is in the beginning, and not at the first line
+                    // Merge the current line with the previous line
+                    let end = self.cursor;
+                    self.cursor.y -= 1;
+                    self.cursor.x = self.get_width();
+                    self.buffer.delete_range(self.cursor, end);
+                }
+
+                self.create_history();
+            }
+
+            Command::DeleteForward => {
+                self.update_last_history_state();
+                self.buffer.dirty = true;
+
+                self.settle_selection();
+
+                // Fix wrong deletion when selection is empty
+                if let Some((begin, end)) = self.get_selection() {
+                    if begin == end {
+                        self.anchor = None;
+                    }
+                }
+
+                if let Some((begin, end)) = self.get_selection() {
+                    self.delete_selection_range(begin, end);
+                } else if self.cursor.x < self.get_width() {
+                    // The cursor is in the middle, just delete the char
+                    let x = self.cursor.x;
+                    self.buffer.rows[self.cursor.y].remove_range(x..x + 1);
+                } else if self.cursor.y < self.buffer.line_count() - 1 {
+                    // The cursor is in the end, and not at the last line
+                    // Merge the current line with the next line
+                    let next = (0, self.cursor.y + 1).into();
+                    self.buffer.delete_range(self.cursor, next);
+                }
+
+                self.create_history();
+            }
+
+            // TODO: Move cursor by visual offset, not logical offset
+            Command::MoveCursor(Motion::Up, extend) => {
+                self.update_selection(extend);
+                self.move_up();
+            }
+            Command::MoveCursor(Motion::Down, extend) => {
+                self.update_selection(extend);
+                self.move_down();
+            }
+
+            Command::MoveCursor(motion @ (Motion::Left | Motion::WordLeft), extend) => {
+                self.settle_selection();
+
+                // Fix wrong deletion when selection is empty
+                if let Some((begin, end)) = self.get_selection() {
+                    if begin == end {
+                        self.anchor = None;
+                    }
+                }
+
+                // Without Shift the selection collapses to its start; with Shift the
+                // cursor keeps moving from whichever end it is on
+                let mut flag = false;
+                if let Some((begin, _)) = self.get_selection().filter(|_| !extend) {
+                    self.cursor = begin;
+                    flag = true;
+                }
+                self.update_selection(extend);
+
+                let above = self.line_above(self.cursor.y);
+                if motion == Motion::WordLeft {
+                    // Move to the beginning of the word
+                    if let Some(y) = above.filter(|_| self.cursor.x == 0) {
+                        self.cursor.y = y;
+                        self.cursor.x = self.get_width();
+                    }
+                    self.cursor.x = self.buffer.rows[self.cursor.y].word_start(self.cursor.x);
+                } else if !flag && self.cursor.x > 0 {
+                    self.cursor.x -= 1;
+                } else if let Some(y) = above.filter(|_| !flag) {
+                    self.cursor.y = y;
+                    self.cursor.x = self.get_width();
+                }
+            }
+
+            Command::MoveCursor(motion @ (Motion::Right | Motion::WordRight), extend) => {
+                self.settle_selection();
+
+                // Fix wrong deletion when selection is empty
+                if let Some((begin, end)) = self.get_selection() {
+                    if begin == end {
+                        self.anchor = None;
+                    }
+                }
+
+                let mut flag = false;
+                if let Some((_, end)) = self.get_selection().filter(|_| !extend) {
+                    self.cursor = end;
+                    flag = true;
+                }
+                self.update_selection(extend);
+
+                let below = self.line_below(self.cursor.y);
+                if motion == Motion::WordRight {
+                    // Move to the end of the word
+                    if let Some(y) = below.filter(|_| self.cursor.x == self.get_width()) {
+                        self.cursor.y = y;
+                        self.cursor.x = 0;
+                    }
+                    self.cursor.x = self.buffer.rows[self.cursor.y].word_end(self.cursor.x);
+                } else if !flag && self.cursor.x < self.get_width() {
+                    self.cursor.x += 1;
+                } else if let Some(y) = below.filter(|_| !flag) {
+                    self.cursor.y = y;
+                    self.cursor.x = 0;
+                }
+            }
+
+            Command::MoveCursor(Motion::PageUp, extend) => {
+                self.update_selection(extend);
+                self.page(Direction::Up);
+            }
+            Command::MoveCursor(Motion::PageDown, extend) => {
+                self.update_selection(extend);
+                self.page(Direction::Down);
+            }
+            Command::MoveCursor(Motion::Home, extend) => {
+                self.update_selection(extend);
+                self.cursor.x = 0;
+            }
+            Command::MoveCursor(Motion::End, extend) => {
+                self.update_selection(extend);
+                self.cursor.x = self.get_width();
+            }
+
+            Command::MoveCursor(Motion::BufferStart, extend) => {
+                self.update_selection(extend);
+                self.cursor = Position::default();
+            }
+            Command::MoveCursor(Motion::BufferEnd, extend) => {
+                self.update_selection(extend);
+                self.cursor = self.buffer.clamp((0, usize::MAX).into());
+            }
+
+            Command::MoveCursor(Motion::ParagraphUp, extend) => {
+                self.update_selection(extend);
+                self.cursor.y = self.paragraph_boundary(Direction::Up);
+            }
+            Command::MoveCursor(Motion::ParagraphDown, extend) => {
+                self.update_selection(extend);
+                self.cursor.y = self.paragraph_boundary(Direction::Down);
+            }
+
+            Command::ScrollLine(direction, extend) => {
+                self.update_selection(extend);
+                self.scroll(direction, 1);
+            }
+
+            Command::Scroll(direction, amount) => self.scroll(direction, amount),
+            Command::ScrollPage(direction) => self.scroll(direction, self.text_area().2),
+
+            Command::MoveLines(direction) => {
+                let (first, last) = self.selected_lines();
+                let delta = match direction {
+                    Direction::Up if first > 0 => -1,
+                    Direction::Down if last + 1 < self.buffer.line_count() => 1,
+                    _ => 0,
+                };
+                if delta != 0 {
+                    self.update_last_history_state();
+                    self.buffer.dirty = true;
+
+                    let rows = &mut self.buffer.rows;
+                    if delta < 0 {
+                        rows[first - 1..=last].rotate_left(1);
+                    } else {
+                        rows[first..=last + 1].rotate_right(1);
+                    }
+                    // The cursor and anchor follow the lines
+                    self.shift_selection(delta);
+
+                    let lines = match delta < 0 {
+                        true => first - 1..last + 1,
+                        false => first..last + 2,
+                    };
+                    self.create_history_for(lines);
+                }
+            }
+
+            Command::DuplicateLines(direction) => {
+                let (first, last) = self.selected_lines();
+                self.update_last_history_state();
+                self.buffer.dirty = true;
+
+                let copy = self.buffer.rows[first..=last].to_vec();
+                self.buffer.rows.splice(last + 1..last + 1, copy);
+
+                // Select the copy below
+                if direction == Direction::Down {
+                    self.shift_selection((last - first + 1) as isize);
+                }
+
+                self.create_history_for(last + 1..2 * last - first + 2);
+            }
+
+            Command::CopyOrPaste => {
+                // Fix wrong deletion when selection is empty
+                if let Some((begin, end)) = self.get_selection() {
+                    if begin == end {
+                        self.anchor = None;
+                    }
+                }
+
+                if let Some((_, end)) = self.get_selection() {
+                    self.trigger_copy();
+                    self.cursor = end;
+                    self.anchor = None;
+                } else {
+                    self.trigger_paste(false);
+                }
+            }
+        }
+        if let Some(reference) = reference {
+            self.track_tabstops(reference);
+        }
+        self.clamp_cursor();
+        self.track_folds(edit);
+        self.reveal_cursor();
+        Ok(false)
+    }
+
+    /// Pass a key to vim-like editing, if it is enabled. Returns the commands it stands
+    /// for, or `None` if it is left to the keymap.
+    fn modal_key(&mut self, modifiers: KeyModifiers, code: KeyCode) -> Option<Vec<Command>> {
+        let at_line_end = self.cursor.x >= self.get_width();
+        let modal = self.modal.as_mut()?;
+        let mode = modal.mode;
+        let commands = modal.key(modifiers, code, at_line_end)?;
+        match (mode, modal.mode) {
+            (Mode::Visual, Mode::Visual) => {}
+            (_, Mode::Visual) => self.anchor = Some(self.cursor),
+            // Leaving visual mode with Esc or `v`; the other ways act on the selection
+            (Mode::Visual, _) if commands.is_empty() => self.anchor = None,
+            _ => {}
+        }
+        Some(commands)
+    }
+
+    /// Run several commands as one step of the history. Returns `true` if the editor
+    /// should exit.
+    fn execute_all(&mut self, commands: &[Command]) -> Result<bool, Error> {
+        let [command] = commands else {
+            // A rollback puts the cursor back here too
+            self.update_last_history_state();
+            self.buffer.history.begin_transaction();
+            for &command in commands {
+                match self.execute(command) {
+                    Ok(true) => {
+                        self.buffer.history.commit();
+                        return Ok(true);
+                    }
+                    Ok(false) => {}
+                    Err(err) => {
+                        self.rollback();
+                        return Err(err);
+                    }
+                }
+            }
+            self.buffer.history.commit();
+            return Ok(false);
+        };
+        // Undo and redo must not be wrapped in a transaction
+        self.execute(*command)
+    }
+
+    /// Expand the snippet whose trigger is the word right before the cursor and select
+    /// its first placeholder. Returns `false` if there is no such snippet.
+    fn expand_snippet(&mut self) -> bool {
+        if self.selection_text().is_some() {
+            return false;
+        }
+        let y = self.cursor.y;
+        let x = self.cursor.x.min(self.get_width());
+        let row = &self.buffer.rows[y];
+        let start = row.word_start(x);
+        if start == x || row.word_end(start) != x {
+            return false;
+        }
+        let trigger = self.buffer.text_range((start, y).into(), (x, y).into());
+        let Some(body) = (self.buffer.syntax.snippets.get(&trigger))
+            .or_else(|| self.config.snippets.get(&trigger))
+        else {
+            return false;
+        };
+        let indent = self
+            .buffer
+            .text_range((0, y).into(), (row.first_non_blank(), y).into());
+        let snippet = Snippet::parse(body, &indent);
+
+        self.update_last_history_state();
+        self.buffer.history.mark_boundary();
+        self.anchor = None;
+        let mut pos = self.delete_at((start, y).into(), (x, y).into());
+
+        // Insert the text piece by piece to learn where each placeholder ends up
+        let mut offsets: Vec<_> = (snippet.placeholders.iter())
+            .flat_map(|range| [range.start, range.end])
+            .collect();
+        offsets.sort_unstable();
+        offsets.dedup();
+        let mut positions = Vec::new();
+        let mut last = 0;
+        for offset in offsets {
+            pos = self.insert_at(pos, &snippet.text[last..offset]);
+            positions.push((offset, pos));
+            last = offset;
+        }
+        self.insert_at(pos, &snippet.text[last..]);
+
+        let at = |offset| positions.iter().find(|(o, _)| *o == offset).unwrap().1;
+        self.tabstops = (snippet.placeholders.iter())
+            .map(|range| (at(range.start), at(range.end)))
+            .collect();
+        self.select_tabstop();
+
+        self.create_history();
+        self.buffer.history.mark_boundary();
+        true
+    }
+
+    /// Select the current placeholder of the snippet. Snippet mode ends on the last one.
+    fn select_tabstop(&mut self) {
+        let (begin, end) = self.tabstops[0];
+        self.anchor = (begin != end).then_some(begin);
+        self.cursor = end;
+        if self.tabstops.len() == 1 {
+            self.tabstops.clear();
+        }
+    }
+
+    /// Move on to the next placeholder, if a snippet is being filled in.
+    fn next_tabstop(&mut self) -> bool {
+        if self.tabstops.is_empty() {
+            return false;
+        }
+        self.tabstops.remove(0);
+        self.select_tabstop();
+        true
+    }
+
+    /// Where `command` is about to edit: the end of the selection or the cursor, with
+    /// the width of its line and the line count. Commands that may edit anywhere else
+    /// end snippet mode and return `None`.
+    fn tabstop_reference(&mut self, command: Command) -> Option<(Position, usize, usize)> {
+        if self.tabstops.is_empty() {
+            return None;
+        }
+        let mut reference = self.get_selection().map_or(self.cursor, |(_, end)| end);
+        let width = self.buffer.rows[reference.y].len();
+        reference.x = reference.x.min(width);
+
+        let in_place = match command {
+            Command::InsertChar(_)
+            | Command::NewLine
+            | Command::DeleteBackward
+            | Command::Copy
+            | Command::CopyAnsi
+            | Command::CopyHtml
+            | Command::Cancel
+            | Command::MoveCursor(
+                Motion::Left | Motion::Right | Motion::WordLeft | Motion::WordRight,
+                _,
+            ) => true,
+            // Joining the next line would pull the placeholders after it
+            Command::DeleteForward => self.selection_text().is_some() || reference.x < width,
+            _ => false,
+        };
+        if !in_place {
+            self.tabstops.clear();
+            return None;
+        }
+        Some((reference, width, self.buffer.line_count()))
+    }
+
+    /// Keep the placeholders with the text after `reference` once it has been edited.
+    /// Snippet mode ends when the cursor leaves the current placeholder.
+    fn track_tabstops(&mut self, (reference, width, lines): (Position, usize, usize)) {
+        let count = self.buffer.line_count();
+        // The line the text after `reference` is now on
+        let y = ((reference.y + count).saturating_sub(lines)).min(count - 1);
+        let new_width = self.buffer.rows[y].len();
+        let shift = |p: Position, after: bool| {
+            if p.y > reference.y {
+                Position {
+                    x: p.x,
+                    y: (p.y + count).saturating_sub(lines),
+                }
+            } else if p.y == reference.y && (p.x > reference.x || p.x == reference.x && after) {
+                Position {
+                    x: (p.x + new_width).saturating_sub(width),
+                    y,
+                }
+            } else {
+                p
+            }
+        };
+        for (i, (begin, end)) in self.tabstops.iter_mut().enumerate() {
+            // Text typed at the start of the current placeholder goes into it
+            *begin = shift(*begin, i > 0);
+            *end = shift(*end, true);
+        }
+
+        let Some(&(begin, end)) = self.tabstops.first() else {
+            return;
+        };
+        if self.cursor < begin || self.cursor > end {
+            self.tabstops.clear();
+        }
+    }
+
+    /// Select the smallest of the word, line, paragraph (lines up to a blank one) or
+    /// whole buffer that is larger than the current selection.
+    fn grow_selection(&mut self) {
+        if self.selection_stack.last() != Some(&(self.anchor, self.cursor)) {
+            self.selection_stack = vec![(self.anchor, self.cursor)];
+        }
+
+        let cursor = self.buffer.clamp(self.cursor);
+        let (begin, end) = match self.get_selection() {
+            Some((begin, end)) => (self.buffer.clamp(begin), self.buffer.clamp(end)),
+            None => (cursor, cursor),
+        };
+        let line_end = |y: usize| Position {
+            x: self.buffer.rows[y].len(),
+            y,
+        };
+
+        let mut candidates = vec![];
+        if begin.y == end.y {
+            let word = self.buffer.rows[begin.y].word_at(begin.x);
+            candidates.push(((word.start, begin.y).into(), (word.end, end.y).into()));
+        }
+        candidates.push((Position { x: 0, y: begin.y }, line_end(end.y)));
+        let (mut top, mut bottom) = (begin.y, end.y);
+        while top > 0 && !self.buffer.rows[top - 1].is_blank() {
+            top -= 1;
+        }
+        while bottom + 1 < self.buffer.line_count() && !self.buffer.rows[bottom + 1].is_blank() {
+            bottom += 1;
+        }
+        candidates.push((Position { x: 0, y: top }, line_end(bottom)));
+        candidates.push((Position::default(), line_end(self.buffer.line_count() - 1)));
+
+        let larger = candidates
+            .into_iter()
+            .find(|&(b, e): &(Position, Position)| {
+                b <= begin && end <= e && (b, e) != (begin, end)
+            });
+        if let Some((begin, end)) = larger {
+            self.anchor = Some(begin);
+            self.cursor = end;
+            self.selection_stack.push((self.anchor, self.cursor));
+        }
+    }
+
+    /// Keep at least one line in the buffer, the cursor on an existing line and the
+    /// anchor inside the text, so that indexing rows by them can't go out of bounds.
+    /// The cursor may stay past the end of its line, to keep its column when moving
+    /// up and down.
+    fn clamp_cursor(&mut self) {
+        if self.buffer.rows.is_empty() {
+            self.buffer.rows.push(Row::default());
+        }
+        self.cursor.y = self.cursor.y.min(self.buffer.line_count() - 1);
+        self.anchor = self.anchor.map(|anchor| self.buffer.clamp(anchor));
+    }
+
+    fn move_up(&mut self) {
+        match self.line_above(self.cursor.y) {
+            Some(y) => self.cursor.y = y,
+            None => self.cursor.x = 0,
+        }
+    }
+
+    fn move_down(&mut self) {
+        match self.line_below(self.cursor.y) {
+            Some(y) => self.cursor.y = y,
+            None => self.cursor.x = self.get_width(),
+        }
+    }
+
+    /// The fold hiding line `y`, if any.
+    fn fold_at(&self, y: usize) -> Option<Range<usize>> {
+        self.folds.iter().find(|fold| fold.contains(&y)).cloned()
+    }
+
+    /// Line `y`, or the first line of the fold hiding it.
+    fn shown_line(&self, y: usize) -> usize {
+        self.fold_at(y).map_or(y, |fold| fold.start - 1)
+    }
+
+    /// The nearest line above `y` that isn't folded away.
+    fn line_above(&self, y: usize) -> Option<usize> {
+        y.checked_sub(1).map(|y| self.shown_line(y))
+    }
+
+    /// The nearest line below `y` that isn't folded away.
+    fn line_below(&self, y: usize) -> Option<usize> {
+        let below = self.fold_at(y + 1).map_or(y + 1, |fold| fold.end);
+        (below < self.buffer.line_count()).then_some(below)
+    }
+
+    /// The row line `y` is drawn on, counting from the top of the buffer rather than
+    /// the view: hidden lines take no rows and each fold takes one for its placeholder.
+    fn screen_row(&self, y: usize) -> usize {
+        self.folds.iter().fold(y, |row, fold| {
+            if fold.end <= y {
+                row - (fold.len() - 1)
+            } else if fold.contains(&y) {
+                row - (y - fold.start)
+            } else {
+                row
+            }
+        })
+    }
+
+    /// The line drawn on a row counted like in `screen_row`. A fold's placeholder row
+    /// gives the first hidden line.
+    fn row_line(&self, row: usize) -> usize {
+        let mut y = row;
+        for fold in &self.folds {
+            if fold.start >= y {
+                break;
+            }
+            y += fold.len() - 1;
+        }
+        y
+    }
+
+    /// Where the text goes on the screen: its top left corner, width and height. The
+    /// line numbers are to its left, and below it are the status bar and, unless it's
+    /// hidden, the line for toasts and debug info.
+    pub fn text_area(&self) -> (Position, usize, usize) {
+        let (origin, width, height) =
+            self.pane_area(self.split.as_ref().is_some_and(|pane| !pane.below));
+        let origin = Position {
+            x: origin.x + self.sidebar_width,
+            y: origin.y,
+        };
+        (origin, width.saturating_sub(self.sidebar_width), height)
+    }
+
+    /// The row of the status bar, below the text.
+    fn status_bar_row(&self) -> usize {
+        let below = if self.hide_message_line { 1 } else { 2 };
+        self.terminal.height.saturating_sub(below)
+    }
+
+    /// Where a pane goes on the screen, line numbers included: all of it above the
+    /// status bar, or while the view is split, the upper or lower half of that with a
+    /// divider between them.
+    fn pane_area(&self, below: bool) -> (Position, usize, usize) {
+        let (width, height) = (self.terminal.width, self.status_bar_row());
+        if self.split.is_none() {
+            return (Position::default(), width, height);
+        }
+        let upper = height.saturating_sub(1) / 2;
+        match below {
+            true => {
+                let origin = Position { x: 0, y: upper + 1 };
+                (origin, width, height.saturating_sub(upper + 1))
+            }
+            false => (Position::default(), width, upper),
+        }
+    }
+
+    /// Whether a screen cell is in the pane that doesn't have the focus.
+    fn in_other_pane(&self, column: usize, row: usize) -> bool {
+        let Some(pane) = &self.split else {
+            return false;
+        };
+        let (origin, width, height) = self.pane_area(pane.below);
+        (origin.x..origin.x + width).contains(&column)
+            && (origin.y..origin.y + height).contains(&row)
+    }
+
+    /// Swap the view of the current pane with that of the other one, kept within the
+    /// buffer, which may have changed under it.
+    fn swap_panes(&mut self) {
+        let Some(pane) = &mut self.split else {
+            return;
+        };
+        std::mem::swap(&mut self.viewbox, &mut pane.viewbox);
+        std::mem::swap(&mut self.cursor, &mut pane.cursor);
+        std::mem::swap(&mut self.anchor, &mut pane.anchor);
+        pane.below = !pane.below;
+        // Lines may have gone from under the view coming in
+        let lines = self.buffer.line_count();
+        let clamp = |pos: Position| match pos.y < lines {
+            true => pos,
+            false => self.buffer.clamp(pos),
+        };
+        (self.cursor, self.anchor) = (clamp(self.cursor), self.anchor.map(clamp));
+        self.viewbox.y = self.viewbox.y.min(lines - 1);
+        self.update_sidebar_width();
+    }
+
+    /// Move the focus to the other pane, leaving behind what belongs to the cursor.
+    fn focus_other_pane(&mut self) {
+        self.swap_panes();
+        self.set_viewbox(self.viewbox);
+        self.tabstops.clear();
+        self.selection_stack.clear();
+    }
+
+    /// The lines in the text area, from the top. Folds show as their first hidden line.
+    fn visible_lines(&self) -> Vec<usize> {
+        let top = self.screen_row(self.viewbox.y);
+        (top..top + self.text_area().2)
+            .map(|row| self.row_line(row))
+            .take_while(|&y| y < self.buffer.line_count())
+            .collect()
+    }
+
+    /// Fold the lines below the cursor's line that are indented deeper than it, up
+    /// to the last such line before one that isn't. Folds inside the new one merge
+    /// into it.
+    fn fold(&mut self) {
+        let y = self.cursor.y;
+        let rows = &self.buffer.rows;
+        let indent = |row: &Row| row.index_to_x(row.first_non_blank());
+        if rows[y].is_blank() {
+            return;
+        }
+        let depth = indent(&rows[y]);
+        let mut end = y + 1;
+        for (i, row) in rows.iter().enumerate().skip(y + 1) {
+            // Blank lines inside the block belong to it, the ones after it don't
+            if row.is_blank() {
+                continue;
+            }
+            if indent(row) <= depth {
+                break;
+            }
+            end = i + 1;
+        }
+        if end > y + 1 {
+            self.folds
+                .retain(|fold| fold.start <= y || fold.start >= end);
+            self.folds.push(y + 1..end);
+            self.folds.sort_by_key(|fold| fold.start);
+        }
+    }
+
+    /// Unfold the fold the cursor ended up in, by a search or a jump.
+    fn reveal_cursor(&mut self) {
+        let y = self.cursor.y;
+        self.folds.retain(|fold| !fold.contains(&y));
+    }
+
+    /// Insert `text` in place of the selection, as one step in the history.
+    fn insert_over_selection(&mut self, text: &str) {
+        self.update_last_history_state();
+        self.buffer.dirty = true;
+
+        self.settle_selection();
+
+        self.buffer.history.begin_transaction();
+        self.delete_selection();
+
+        self.cursor = self.buffer.insert(self.cursor, text);
+
+        self.create_history();
+        self.buffer.history.commit();
+    }
+
+    /// The values the insert-variable command offers, with their names: the date in
+    /// `date_format`, the time, the ISO week and, if the buffer has a file, its name and
+    /// full path.
+    fn variables(&self) -> Vec<(&'static str, String)> {
+        let now = DateTime::now();
+        let mut variables = vec![
+            (t!("variable.date"), now.format(&self.config.date_format)),
+            (t!("variable.time"), now.format("%H:%M")),
+            (t!("variable.iso_week"), now.format("%G-W%V")),
+        ];
+        if let Some(filename) = &self.buffer.filename {
+            let path = Path::new(filename);
+            let name = path.file_name().unwrap_or(path.as_os_str());
+            variables.push((t!("variable.file_name"), name.to_string_lossy().into()));
+            let full = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+            variables.push((t!("variable.file_path"), full.to_string_lossy().into()));
+        }
+        variables
+    }
+
+    /// The lines `command` is about to edit, from the line before to the line after
+    /// for edits that join or swap lines, and the line count. `None` for commands that
+    /// don't edit the text.
+    fn edit_span(&self, command: Command) -> Option<(usize, usize, usize)> {
+        let cursor = self.buffer.clamp(self.cursor);
+        let (begin, end) = self.get_selection().unwrap_or((cursor, cursor));
+        let at_end = cursor.x == self.buffer.rows[cursor.y].len();
+        let (first, last) = match command {
+            Command::InsertChar(_)
+            | Command::InsertTab
+            | Command::NewLine
+            | Command::Paste
+            | Command::PasteReindent
+            | Command::Registers
+            | Command::Cut
+            | Command::InsertUnicode
+            | Command::InsertVariable
+            | Command::InsertDate
+            | Command::InsertFile
+            | Command::TransformSelection
+            | Command::Reindent => (begin.y, end.y),
+            Command::CopyOrPaste if self.anchor.is_none() => (cursor.y, cursor.y),
+            Command::DeleteBackward if begin == end && cursor.x == 0 => {
+                (cursor.y.saturating_sub(1), cursor.y)
+            }
+            Command::DeleteForward if begin == end && at_end => (cursor.y, cursor.y + 1),
+            Command::DeleteBackward | Command::DeleteForward => (begin.y, end.y),
+            Command::DeleteToLineStart => (cursor.y, cursor.y),
+            Command::TransposeChars | Command::TransposeWords => (cursor.y, cursor.y),
+            Command::DeleteToLineEnd if at_end => (cursor.y, cursor.y + 1),
+            Command::DeleteToLineEnd => (cursor.y, cursor.y),
+            Command::MoveLines(_) | Command::DuplicateLines(_) => {
+                (begin.y.saturating_sub(1), end.y + 1)
+            }
+            _ => return None,
+        };
+        Some((first, last, self.buffer.line_count()))
+    }
+
+    /// Update the folds after the edit `edit_span` described: the ones it reached
+    /// into are unfolded, the ones below it move with their lines. Editing the first
+    /// line of a fold without adding or removing lines keeps the fold.
+    ///
+    /// The view of the other pane of a split view moves with its lines too.
+    fn track_folds(&mut self, edit: Option<(usize, usize, usize)>) {
+        let Some((first, last, lines)) = edit else {
+            return;
+        };
+        let count = self.buffer.line_count();
+        if let Some(pane) = &mut self.split {
+            let shift = |y: usize| match y > last {
+                true => (y + count).saturating_sub(lines),
+                false => y,
+            };
+            pane.viewbox.y = shift(pane.viewbox.y);
+            pane.cursor.y = shift(pane.cursor.y);
+            if let Some(anchor) = &mut pane.anchor {
+                anchor.y = shift(anchor.y);
+            }
+        }
+        self.folds.retain_mut(|fold| {
+            let header = fold.start - 1;
+            if last < header {
+                fold.start = (fold.start + count).saturating_sub(lines);
+                fold.end = (fold.end + count).saturating_sub(lines);
+                true
+            } else {
+                first >= fold.end || (first == header && last == header && count == lines)
+            }
+        });
+    }
+
+    /// The nearest blank line above or below the cursor that borders a paragraph, or the
+    /// first or last line if there is none. Whitespace-only lines count as blank.
+    fn paragraph_boundary(&self, direction: Direction) -> usize {
+        let rows = &self.buffer.rows;
+        let y = self.cursor.y;
+        if direction == Direction::Up {
+            (1..y)
+                .rev()
+                .find(|&i| rows[i].is_blank() && !rows[i + 1].is_blank())
+                .unwrap_or(0)
+        } else {
+            (y + 1..rows.len())
+                .find(|&i| rows[i].is_blank() && !rows[i - 1].is_blank())
+                .unwrap_or(rows.len() - 1)
+        }
+    }
+
+    /// Scroll the view without moving the cursor.
+    fn scroll(&mut self, direction: Direction, amount: usize) {
+        match direction {
+            Direction::Up => {
+                let top = self.screen_row(self.viewbox.y).saturating_sub(amount);
+                let y = self.row_line(top);
+                self.set_viewbox(Position { y, ..self.viewbox });
+            }
+            Direction::Down => {
+                let rows = self.screen_row(self.buffer.line_count() - 1) + 1;
+                // The last line can go to the top, or only just above the status bar
+                let max_top = match self.config.scroll_past_end {
+                    true => rows - 1,
+                    false => (rows + EXTRA_GAP).saturating_sub(self.text_area().2),
+                };
+                let top = (self.screen_row(self.viewbox.y) + amount).min(max_top);
+                let y = self.row_line(top);
+                self.set_viewbox(Position { y, ..self.viewbox });
+            }
+            Direction::Left => {
+                let x = self.viewbox.x.saturating_sub(amount);
+                self.set_viewbox(Position { x, ..self.viewbox });
+            }
+            Direction::Right => {
+                let x = self.viewbox.x + amount;
+                self.set_viewbox(Position { x, ..self.viewbox });
+            }
+        }
+    }
+
+    /// Move the view to `viewbox`, kept within the buffer: its top line exists, and it
+    /// goes no further right than the widest line it then shows needs.
+    fn set_viewbox(&mut self, viewbox: Position) {
+        self.viewbox.y = viewbox.y.min(self.buffer.line_count() - 1);
+        self.viewbox.x = viewbox.x.min(self.max_viewbox_x());
+    }
+
+    /// How far the view can scroll right: just far enough to show the end of the widest
+    /// visible line, with a small gap after it.
+    fn max_viewbox_x(&self) -> usize {
+        let width = (self.visible_lines().into_iter())
+            .map(|y| self.buffer.rows[y].visual_width())
+            .max()
+            .unwrap_or(0);
+        (width + EXTRA_GAP + 1).saturating_sub(self.text_area().1)
+    }
+
+    /// The part of the screen a left click at a position starts dragging in. Clicks on
+    /// the status bar and the line below it, or anywhere else outside the text and the
+    /// line numbers, don't touch the cursor.
+    fn hit_test(&self, column: usize, row: usize) -> Option<MouseArea> {
+        let (origin, width, height) = self.text_area();
+        if !(origin.y..origin.y + height).contains(&row) || column >= origin.x + width {
+            None
+        } else if column < origin.x {
+            Some(MouseArea::Sidebar)
+        } else {
+            Some(MouseArea::Text)
+        }
+    }
+
+    /// The grapheme at screen cell `at`, its line and column, the token it was
+    /// highlighted as and the state its row ends in, or `None` past the end of a line
+    /// or outside the text area.
+    fn hover_info_at(&self, at: Position) -> Option<String> {
+        if !self.hover_info || self.hit_test(at.x, at.y) != Some(MouseArea::Text) {
+            return None;
+        }
+        let (origin, ..) = self.text_area();
+        let y = self.row_line(self.screen_row(self.viewbox.y) + at.y - origin.y);
+        let row = self.buffer.rows.get(y)?;
+        let x = row.x_to_index(at.x - origin.x + self.viewbox.x);
+        let (grapheme, _) = row.rope.get(x)?;
+        Some(format!(
+            " {grapheme:?} at ({}, {}) | token: {:?} | final state: {:?}",
+            y + 1,
+            x + 1,
+            row.syntax.get(x).unwrap_or(&crate::TokenType::Normal),
+            row.final_state,
+        ))
+    }
+
+    /// Whether the mouse is over selected text.
+    fn mouse_in_selection(&self, event: &MouseEvent) -> bool {
+        let Some((begin, end)) = self.get_selection() else {
+            return false;
+        };
+        let (origin, ..) = self.text_area();
+        let y = self.row_line(self.screen_row(self.viewbox.y) + event.row as usize - origin.y);
+        let Some(row) = self.buffer.rows.get(y) else {
+            return false;
+        };
+        let x = row.x_to_index((event.column as usize + self.viewbox.x).saturating_sub(origin.x));
+        (begin..end).contains(&Position { x, y })
+    }
+
+    /// The line that lines dropped on screen row `row` go in front of, or the line count
+    /// if they go after the last line.
+    fn drop_line(&self, row: usize) -> usize {
+        let (origin, _, height) = self.text_area();
+        let row = row.clamp(origin.y, origin.y + height - 1) - origin.y;
+        let line = self.row_line(self.screen_row(self.viewbox.y) + row);
+        line.min(self.buffer.line_count())
+    }
+
+    /// The first and last line a whole-line command acts on: those of the selection, or
+    /// the cursor's. A selection ending at the start of a line doesn't include it.
+    fn selected_lines(&self) -> (usize, usize) {
+        let cursor = self.buffer.clamp(self.cursor);
+        let (begin, end) = self.get_selection().unwrap_or((cursor, cursor));
+        if end.x == 0 && end.y > begin.y {
+            (begin.y, end.y - 1)
+        } else {
+            (begin.y, end.y)
+        }
+    }
+
+    /// Move the cursor and the anchor `delta` lines down, or up if it is negative, along
+    /// with the lines of a whole-line command. Which of them comes first is kept, and so
+    /// are their columns unless one goes past the last line.
+    fn shift_selection(&mut self, delta: isize) {
+        let shift = |p: Position| Position {
+            y: p.y.saturating_add_signed(delta),
+            ..p
+        };
+        self.cursor = shift(self.cursor);
+        if self.cursor.y >= self.buffer.line_count() {
+            self.cursor = self.buffer.clamp(self.cursor);
+        }
+        if let Some(anchor) = self.anchor {
+            self.anchor = Some(self.buffer.clamp(shift(anchor)));
+        }
+    }
+
+    /// Move the selected lines in front of line `to`, or after the last line if it is
+    /// the line count, as one undo step, and select them there. A selection ending at
+    /// the start of a line doesn't take that line along. Dropping the lines next to or
+    /// onto themselves does nothing.
+    fn drop_lines(&mut self, to: usize) {
+        let Some(anchor) = self.anchor else {
+            return;
+        };
+        let reversed = self.cursor < anchor;
+        let (first, last) = self.selected_lines();
+        if (first..=last + 1).contains(&to) {
+            return;
+        }
+        if self.refuse_edit() {
+            return;
+        }
+
+        self.update_last_history_state();
+        self.buffer.dirty = true;
+        let lines: Vec<_> = self.buffer.rows.drain(first..=last).collect();
+        let count = lines.len();
+        let y = if to > last { to - count } else { to };
+        self.buffer.rows.splice(y..y, lines);
+        // The lines may have moved into or out of folds
+        self.folds.clear();
+        self.search_result.clear();
+
+        self.anchor = Some(Position { x: 0, y });
+        self.cursor = if y + count < self.buffer.line_count() {
+            Position { x: 0, y: y + count }
+        } else {
+            let y = y + count - 1;
+            Position {
+                x: self.buffer.rows[y].len(),
+                y,
+            }
+        };
+        if reversed {
+            self.cursor = self.anchor.replace(self.cursor).unwrap();
+        }
+        self.create_history();
+        self.buffer.history.mark_boundary();
+    }
+
+    /// Move the cursor to the mouse while the left button is held, selecting whole
+    /// lines if the drag started on the line numbers. Dragging past the text area
+    /// clamps to its last row. Returns whether the view should follow the cursor.
+    fn drag_mouse(&mut self, event: &MouseEvent, area: MouseArea) -> bool {
+        // Grabbed lines stay put until they are dropped
+        if area == MouseArea::Lines {
+            return false;
+        }
+        let down = event.kind == MouseEventKind::Down(MouseButton::Left);
+        let (origin, width, height) = self.text_area();
+        let row = (event.row as usize).clamp(origin.y, origin.y + height - 1) - origin.y;
+        let last = self.buffer.line_count() - 1;
+        let line = self.row_line(self.screen_row(self.viewbox.y) + row);
+        self.cursor.y = self.shown_line(line.min(last));
+
+        match area {
+            MouseArea::Sidebar => {
+                // Select the lines between the pressed one, kept in the anchor, and the
+                // current one, with the cursor on the far side of the current line
+                let (line, y) = match self.anchor {
+                    Some(anchor) if !down => (anchor.y, self.cursor.y),
+                    _ => (self.cursor.y, self.cursor.y),
+                };
+                if y >= line {
+                    self.anchor = Some(Position { x: 0, y: line });
+                    self.cursor = if let Some(below) = self.line_below(y) {
+                        Position { x: 0, y: below }
+                    } else {
+                        Position {
+                            x: self.buffer.rows[last].len(),
+                            y: last,
+                        }
+                    };
+                } else {
+                    self.anchor = Some(Position {
+                        x: self.buffer.rows[line].len(),
+                        y: line,
+                    });
+                    self.cursor = Position { x: 0, y };
+                }
+                !down
+            }
+            MouseArea::Text => {
+                if event.column as usize + 1 >= origin.x + width {
+                    self.cursor.x = self.get_width();
+                } else {
+                    let x = (event.column as usize + self.viewbox.x).saturating_sub(origin.x);
+                    self.cursor.x = self.buffer.rows[self.cursor.y].x_to_index(x);
+                }
+
+                // TODO: Make Shift+Drag work
+                // && event.modifiers != KeyModifiers::SHIFT
+                if down {
+                    self.anchor = Some(self.cursor);
+                }
+                true
+            }
+            MouseArea::Lines => false,
+        }
+    }
+
+    /// Scroll the view by a page and move the cursor along with it, keeping its row on
+    /// the screen and its column. Once the view can't scroll any further, the cursor
+    /// moves to the first or last line instead.
+    fn page(&mut self, direction: Direction) {
+        let page = self.text_area().2;
+        // No text shows on a terminal too short for it
+        if page == 0 {
+            return;
+        }
+        let scrolloff = self.config.scrolloff;
+        let row = self
+            .screen_row(self.cursor.y)
+            .saturating_sub(self.screen_row(self.viewbox.y))
+            .min((page - 1).saturating_sub(scrolloff))
+            .max(scrolloff);
+
+        let last = self.buffer.line_count() - 1;
+        let viewbox = self.viewbox.y;
+        self.scroll(direction, page);
+        self.cursor.y = if self.viewbox.y != viewbox {
+            let line = self.row_line(self.screen_row(self.viewbox.y) + row);
+            self.shown_line(line.min(last))
+        } else if direction == Direction::Up {
+            0
+        } else {
+            last
+        };
+    }
+
+    /// Show a short notification on the bottom line.
+    pub fn toast(&mut self, message: impl Into<String>) {
+        self.toast = Some((message.into(), Instant::now()));
+    }
+
+    /// Clear the toast once it has been shown long enough.
+    /// Returns `true` if it was cleared and the screen needs a repaint.
+    fn expire_toast(&mut self) -> bool {
+        if self
+            .toast
+            .as_ref()
+            .is_some_and(|(_, time)| time.elapsed() >= TOAST_DURATION)
+        {
+            self.toast = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn delete_selection_range(&mut self, begin: Position, end: Position) {
+        // Reset cursor and anchor
+        self.cursor = self.delete_at(begin, end);
+        self.anchor = None;
+    }
+
+    /// Delete the selected range, if any, as an intermediate step of the current
+    /// history transaction.
+    fn delete_selection(&mut self) {
+        if let Some((begin, end)) = self.get_selection() {
+            self.delete_selection_range(begin, end);
+            self.create_history();
+        }
+    }
+
+    /// The selected text, or `None` if nothing is selected.
+    pub fn selection_text(&self) -> Option<String> {
+        self.get_selection()
+            .filter(|(begin, end)| begin != end)
+            .map(|(begin, end)| self.buffer.text_range(begin, end))
+    }
+
+    /// Select the text between two positions, clamped to the buffer. The cursor is
+    /// placed at `end`.
+    pub fn set_selection(&mut self, begin: Position, end: Position) {
+        self.anchor = Some(self.buffer.clamp(begin));
+        self.cursor = self.buffer.clamp(end);
+        self.update_viewbox();
+    }
+
+    /// The start and end of the selection. An end past the last cell of its row takes
+    /// in the line break, and is given as the start of the next row.
+    fn get_selection(&self) -> Option<(Position, Position)> {
+        self.anchor.map(|anchor| {
+            let (anchor, cursor) = (
+                self.buffer.range_bound(anchor),
+                self.buffer.range_bound(self.cursor),
+            );
+            if anchor < cursor {
+                (anchor, cursor)
+            } else {
+                (cursor, anchor)
+            }
+        })
+    }
+
+    fn update_selection(&mut self, extend: bool) {
+        if extend {
+            // if anchor is None, set it to cursor
+            self.anchor.get_or_insert(self.cursor);
+        } else {
+            self.anchor = None;
+        }
+    }
+
+    fn render(&mut self) -> Result<(), Error> {
+        self.terminal.clear_buffer();
+        self.terminal.begin_render()?;
+
+        self.render_to_buffer();
+        // The search box puts the cursor in itself
+        if !self.is_searching {
+            self.render_cursor();
+        }
+
+        self.terminal.end_render()?;
+
+        Ok(())
     }
 
     pub fn render_to_buffer(&mut self) {
         self.update_sidebar_width();
+        let height = self.text_area().2;
+        let end = self.row_line(self.screen_row(self.viewbox.y) + height);
+        self.buffer.highlight_to(end);
+
+        self.terminal
+            .fill(ContentStyle::new().on(style::background()));
+
+        let status_bar = self.status_bar_row();
+
+        // draw statusbar
+        {
+            const LOGO_WIDTH: usize = 8;
+            self.terminal.write(
+                (0, status_bar).into(),
+                " ARCAEA "
+                    .to_string()
+                    .with(style::text_primary())
+                    .on(style::background_primary()),
+            );
+            let content_left = format!(" {}", buffer_name(&self.buffer));
+            let content_left = match self.buffer.preview {
+                Some(bytes) => t!("status.preview", content_left, bytes.div_ceil(1 << 20)),
+                None => content_left,
+            };
+            let content_left = if self.buffer.dirty {
+                t!("status.unsaved", content_left)
+            } else {
+                content_left
+            };
+            let content_left = match &self.modal {
+                Some(modal) => format!(" {} {}", modal.mode.label(), content_left),
+                None => content_left,
+            };
+            let content_left = match &self.follow {
+                Some(_) if self.following() => {
+                    format!(" {}{}", t!("follow.following"), content_left)
+                }
+                Some(_) => format!(" {}{}", t!("follow.paused"), content_left),
+                None => content_left,
+            };
+            let mut counts = String::new();
+            if let Some((begin, end)) = self.get_selection().filter(|(b, e)| b != e) {
+                let chars = self
+                    .buffer
+                    .row_ranges(begin, end)
+                    .map(|(_, range)| range.len())
+                    .sum::<usize>()
+                    + (end.y - begin.y);
+                counts = t!("status.selection", end.y - begin.y + 1, chars) + "  ";
+            }
+            if let Some(limit) = self.config.max_line_length {
+                let long_lines = self.buffer.long_lines(limit);
+                if long_lines > 0 {
+                    counts += &(t!("status.long_lines", long_lines) + "  ");
+                }
+            }
+            let anomalies = self.buffer.anomaly_count();
+            if anomalies > 0 {
+                counts += &(t!("status.anomalies", anomalies) + "  ");
+            }
+            let stats = self.buffer.stats();
+            counts += &t!("status.stats", stats.chars, stats.words);
+            let column = self.get_cursor_position().x + 1;
+            let mut position = t!("status.position", self.cursor.y + 1, column);
+            if self.config.show_offset {
+                let byte = self.buffer.byte_column(self.cursor) + 1;
+                position += &t!("status.columns", self.cursor.x + 1, byte);
+                let offset = self.buffer.byte_offset(self.cursor);
+                let percent = (offset * 100).checked_div(self.buffer.byte_len());
+                position += "  ";
+                position += &t!("status.offset", offset, percent.unwrap_or(100));
+            }
+            let content_right = format!(
+                "{}  {}  {}  {} ",
+                counts,
+                position,
+                if self.buffer.has_mixed_line_endings() {
+                    "MIXED"
+                } else {
+                    self.buffer.line_ending().name()
+                },
+                self.buffer.syntax.name,
+            );
+            self.terminal.write(
+                (LOGO_WIDTH, status_bar).into(),
+                format!(
+                    "{}{}{}",
+                    content_left,
+                    " ".repeat(
+                        self.terminal.width.saturating_sub(
+                            content_left.width() + content_right.width() + LOGO_WIDTH
+                        )
+                    ),
+                    content_right,
+                )
+                .with(style::text_statusbar())
+                .on(style::background_sidebar()),
+            );
+        }
+
+        // draw the toast, or debug info if there is none, on bottom
+        if !self.hide_message_line {
+            let line = match &self.toast {
+                Some((message, _)) => format!(" {message}").with(style::text_statusbar()),
+                None => self.status_string.clone().with(style::text_dimmed()),
+            };
+            self.terminal
+                .write((0, status_bar + 1).into(), line.on(style::background()));
+        }
+
+        self.render_pane();
+
+        // The other pane of a split view, and the divider between them
+        if self.split.is_some() {
+            let drag = self.drag.take();
+            self.swap_panes();
+            self.render_pane();
+            self.swap_panes();
+            self.drag = drag;
+            let (_, width, upper) = self.pane_area(false);
+            self.terminal.write(
+                (0, upper).into(),
+                "─"
+                    .repeat(width)
+                    .with(style::text_dimmed())
+                    .on(style::background()),
+            );
+        }
+
+        if self.is_searching {
+            self.render_search();
+        }
+    }
+
+    /// Draw the line numbers and text of the current pane.
+    fn render_pane(&mut self) {
+        self.render_sidebar();
+
+        let (origin, width, _) = self.text_area();
+        for (i, line_number) in self.visible_lines().into_iter().enumerate() {
+            let screen_y = origin.y + i;
+            if let Some(fold) = self.folds.iter().find(|fold| fold.start == line_number) {
+                let header = &self.buffer.rows[line_number - 1];
+                let indent = header.index_to_x(header.first_non_blank());
+                self.terminal.write(
+                    (origin.x + indent.saturating_sub(self.viewbox.x), screen_y).into(),
+                    t!("fold.placeholder", fold.len())
+                        .to_string()
+                        .with(style::text_dimmed())
+                        .on(style::background()),
+                );
+                continue;
+            }
+
+            // Skip the cells scrolled out on the left
+            let matches = self.line_matches(line_number).to_vec();
+            let row = &self.buffer.rows[line_number];
+            let urls = row.urls();
+            let start = row.x_to_index(self.viewbox.x);
+            let mut dx = (origin.x + row.index_to_x(start)) as isize - self.viewbox.x as isize;
+            // Runs of cells in the same style are written together
+            let mut span: Vec<(&str, usize)> = Vec::new();
+            let (mut span_x, mut span_style) = (0, ContentStyle::default());
+            let line_end = ("\n".to_string(), 1);
+            for (i, (g, w)) in row
+                .rope
+                .iter()
+                .chain([&line_end]) // Append a virtual space to the end of the line
+                .enumerate()
+                .skip(start)
+            {
+                dx += *w as isize;
+                if dx >= (origin.x + width) as isize {
+                    break;
+                }
+                if dx >= (origin.x + w) as isize {
+                    let mut str = g.as_str();
+                    let mut fg_color = self.buffer.rows[line_number]
+                        .syntax
+                        .get(i)
+                        .map_or(style::token_normal(), |token| style::token_color(*token));
+                    let mut bg_color = style::background();
+                    let limit = self.config.max_line_length.unwrap_or(usize::MAX);
+                    if i < row.len() && row.index_to_x(i) >= limit {
+                        bg_color = style::background_long_line();
+                    }
+                    if self.buffer.anomalies_on(line_number).contains(&i) {
+                        bg_color = style::background_anomaly();
+                    }
+                    if matches
+                        .iter()
+                        .any(|(begin, end)| (begin.x..end.x).contains(&i))
+                    {
+                        bg_color = style::background_match();
+                    }
+
+                    if let Some((begin, end)) = self.get_selection() {
+                        let current = (i, line_number).into();
+                        if begin <= current && current < end {
+                            bg_color = style::background_selected();
+                        }
+                    }
+                    if str == "\n" {
+                        str = " ";
+                    }
+                    // A grapheme of spaces is a single one
+                    if self.config.show_whitespace && g.bytes().all(|b| b == b' ') {
+                        str = "·";
+                        fg_color = style::text_dimmed();
+                    }
+                    let mut cell_style = ContentStyle::new().with(fg_color).on(bg_color);
+                    if urls.iter().any(|url| url.contains(&i)) {
+                        cell_style = cell_style.underlined();
+                    }
+                    if cell_style != span_style && !span.is_empty() {
+                        let pos = (span_x, screen_y).into();
+                        self.terminal.write_cells(pos, &span, span_style);
+                        span.clear();
+                    }
+                    if span.is_empty() {
+                        (span_x, span_style) = (dx as usize - w, cell_style);
+                    }
+                    span.push((str, *w));
+                }
+            }
+            self.terminal
+                .write_cells((span_x, screen_y).into(), &span, span_style);
+
+            // The columns selected on this line, the line break taking one past its end
+            let selected = self.get_selection().and_then(|(begin, end)| {
+                if !(begin.y..=end.y).contains(&line_number) {
+                    return None;
+                }
+                let from = if begin.y == line_number { begin.x } else { 0 };
+                let to = if end.y == line_number {
+                    end.x
+                } else {
+                    row.len() + 1
+                };
+                let columns = row.index_to_x(from)..row.index_to_x(to) + (to > row.len()) as usize;
+                (from < to).then_some(columns)
+            });
+            let tint = |selected: bool| match selected {
+                true => style::background_selected(),
+                false => style::background(),
+            };
+
+            // Mark lines that go on past the edges of the view, in the selection's color
+            // if it goes on too. The last column is never drawn to; a wide character in
+            // the first one is covered whole. A selected line with nothing in view
+            // still gets one selected cell.
+            let right = self.viewbox.x + width - 1;
+            let selected_right = selected.as_ref().is_some_and(|columns| columns.end > right);
+            if row.visual_width() > right || selected_right {
+                let mark = if row.visual_width() > right {
+                    "»"
+                } else {
+                    " "
+                };
+                self.terminal.write(
+                    (origin.x + width - 1, screen_y).into(),
+                    mark.to_string()
+                        .with(style::text_dimmed())
+                        .on(tint(selected_right)),
+                );
+            }
+            let selected_left =
+                (selected.as_ref()).is_some_and(|columns| columns.start < self.viewbox.x);
+            if self.viewbox.x > 0 && (row.visual_width() > 0 || selected_left) {
+                let covered = (row.rope.get(start))
+                    .filter(|_| row.index_to_x(start) == self.viewbox.x)
+                    .map_or(1, |(_, w)| *w);
+                let mark = if row.visual_width() > 0 { "«" } else { " " };
+                self.terminal.write(
+                    (origin.x, screen_y).into(),
+                    format!("{mark}{}", " ".repeat(covered.saturating_sub(1)))
+                        .with(style::text_dimmed())
+                        .on(tint(selected_left)),
+                );
+            }
+        }
+    }
+
+    /// Fit the view to a new terminal size, also while a dialog is open: the cursor and
+    /// view stay within the buffer, the line numbers get the width the lines now shown
+    /// need, and the view moves so that the cursor is in it. Left alone while the
+    /// window is too small to show anything.
+    pub fn on_resize(&mut self) {
+        if self.terminal.width < MIN_WIDTH || self.terminal.height < MIN_HEIGHT {
+            return;
+        }
+        self.cursor = self.buffer.clamp(self.cursor);
+        self.set_viewbox(self.viewbox);
+        self.update_viewbox();
+    }
+
+    /// Draw everything again, e.g. once a dialog has closed, rather than only what
+    /// changed since the last frame.
+    pub fn redraw(&mut self) -> Result<(), Error> {
+        self.terminal.invalidate();
+        if self.check_minimum_window_size() {
+            self.render()?;
+        }
+        Ok(())
+    }
+
+    /// Whether the window is large enough for the editor. If not, a notice with the
+    /// current and minimum size is drawn instead.
+    pub fn check_minimum_window_size(&mut self) -> bool {
+        let too_small = self.terminal.width < MIN_WIDTH || self.terminal.height < MIN_HEIGHT;
+
+        // Nothing on screen is worth keeping when switching to or from the notice
+        if too_small != self.window_too_small {
+            self.window_too_small = too_small;
+            self.terminal.invalidate();
+        }
+
+        if too_small {
+            self.draw_window_too_small();
+            let _ = self
+                .terminal
+                .begin_render()
+                .and_then(|_| self.terminal.end_render());
+        }
+        !too_small
+    }
+
+    fn draw_window_too_small(&mut self) {
+        let (w, h) = (self.terminal.width, self.terminal.height);
+        let size = |value: usize, enough: bool| {
+            if enough {
+                value.to_string().green().bold()
+            } else {
+                value.to_string().red().bold().slow_blink()
+            }
+        };
+        let lines = [
+            vec![t!("status.window_too_small").to_string().bold()],
+            vec![
+                "Width = ".to_string().stylize(),
+                size(w, w >= MIN_WIDTH),
+                ", Height = ".to_string().stylize(),
+                size(h, h >= MIN_HEIGHT),
+            ],
+            vec![format!("(min width = {}, height = {})", MIN_WIDTH, MIN_HEIGHT).stylize()],
+        ];
+
+        self.terminal.clear_buffer();
+        self.terminal.cursor = None;
+        for (i, line) in lines.into_iter().enumerate() {
+            // Lines wider than the window are cut off on the right
+            let width = line
+                .iter()
+                .map(|part| part.content().width())
+                .sum::<usize>();
+            let mut pos = Position {
+                x: w.saturating_sub(width) / 2,
+                y: (h / 2 + i).saturating_sub(1),
+            };
+            for part in line {
+                let width = part.content().width();
+                self.terminal.write(pos, part);
+                pos.x += width;
+            }
+        }
+    }
+
+    fn render_sidebar(&mut self) {
+        let cursor = self.get_cursor_position();
+        let selected = self.get_selection().map(|(start, end)| start.y..=end.y);
+        let anchor = self
+            .anchor
+            .map(|anchor| anchor.y)
+            .filter(|&y| y != cursor.y);
+        let lines = self.visible_lines();
+        let (origin, _, height) = self.text_area();
+        // Where grabbed lines would be dropped: in front of the line on this row
+        let drop = match self.drag {
+            Some((event, MouseArea::Lines)) => {
+                let line = self.drop_line(event.row as usize);
+                lines.iter().position(|&y| y >= line).or(Some(lines.len()))
+            }
+            _ => None,
+        };
+        for i in 0..height {
+            if drop == Some(i) {
+                self.terminal.write(
+                    (0, origin.y + i).into(),
+                    format!("{}▶", "─".repeat(self.sidebar_width - 1))
+                        .with(style::text_sidebar_selected())
+                        .on(style::background_sidebar()),
+                );
+                continue;
+            }
+            // Fold placeholders get no number
+            if let Some(&y) = lines.get(i).filter(|&&y| self.fold_at(y).is_none()) {
+                let lineno = format!("{:>width$} ", y + 1, width = self.sidebar_width - 1);
+                // The lines of a selection stand out, and the line it started from
+                let in_selection = selected.as_ref().is_some_and(|lines| lines.contains(&y));
+                let num = if anchor == Some(y) {
+                    lineno.with(style::text_sidebar_anchor())
+                } else if y == cursor.y || in_selection {
+                    lineno.with(style::text_sidebar_selected())
+                } else {
+                    lineno.with(style::text_dimmed())
+                };
+                let background = match in_selection {
+                    true => style::background_sidebar_selected(),
+                    false => style::background_sidebar(),
+                };
+                self.terminal
+                    .write((0, origin.y + i).into(), num.on(background));
+            } else {
+                self.terminal.write(
+                    (0, origin.y + i).into(),
+                    format!("{:>width$} ", " ", width = self.sidebar_width - 1)
+                        .with(style::text_dimmed())
+                        .on(style::background_sidebar()),
+                );
+            }
+        }
+    }
+
+    fn render_cursor(&mut self) {
+        let cursor = self.get_cursor_position();
+        let (origin, width, height) = self.text_area();
+        let (x, y) = (
+            cursor.x as isize - self.viewbox.x as isize,
+            self.screen_row(cursor.y) as isize - self.screen_row(self.viewbox.y) as isize,
+        );
+
+        if x >= 0 && x < width as isize && y >= 0 && y < height as isize {
+            self.terminal.cursor = Some((origin.x + x as usize, origin.y + y as usize).into());
+        } else {
+            self.terminal.cursor = None;
+        }
+    }
+
+    fn get_cursor_position(&mut self) -> Position {
+        self.cursor.y = self.cursor.y.min(self.buffer.line_count() - 1);
+        Position {
+            x: self.buffer.rows[self.cursor.y].index_to_x(self.cursor.x),
+            y: self.cursor.y,
+        }
+    }
+
+    fn update_sidebar_width(&mut self) {
+        // Calculate sidebar width based on maximum possible line number
+        let max_line_num = (self.viewbox.y + self.text_area().2).min(self.buffer.line_count());
+        self.sidebar_width = if max_line_num > 99 {
+            (max_line_num as f64).log10().floor() as usize + 1
+        } else {
+            2
+        } + 2;
+    }
+
+    fn update_viewbox(&mut self) {
+        // An edit may have changed how many digits the line numbers take
+        self.update_sidebar_width();
+        let Position { x, y } = self.get_cursor_position();
+
+        let (_, text_width, height) = self.text_area();
+        // Small panes can't keep as many lines around the cursor
+        let scrolloff = self.config.scrolloff.min(height.saturating_sub(1) / 2);
+
+        let row = self.screen_row(y);
+        let top = self.screen_row(self.viewbox.y).clamp(
+            (row + scrolloff + 1).saturating_sub(height),
+            row.saturating_sub(scrolloff),
+        );
+        self.viewbox.y = self.row_line(top);
+
+        let row = &self.buffer.rows[y];
+        // Keep a few columns after the cursor in view, so End doesn't leave it at the edge
+        let right = scrolloff.max(EXTRA_GAP);
+        if row.visual_width() + right < text_width {
+            // The whole line fits
+            self.viewbox.x = 0;
+            return;
+        }
+        let mut min_x = (x + right + 1).saturating_sub(text_width);
+        // Selecting to the end of a long line doesn't scroll away from where it started
+        if let Some(anchor) = self
+            .anchor
+            .filter(|a| a.y == y && self.cursor.x >= row.len())
+        {
+            let anchor_x = row.index_to_x(anchor.x);
+            if (self.viewbox.x..self.viewbox.x + text_width).contains(&anchor_x) {
+                min_x = min_x.min(self.viewbox.x);
+            }
+        }
+        self.viewbox.x =
+            (self.viewbox.x.min(self.max_viewbox_x())).clamp(min_x, x.saturating_sub(scrolloff));
+    }
+
+    fn create_history(&mut self) {
+        self.buffer.update_syntax();
+
+        self.buffer
+            .history
+            .push_state(&self.buffer.rows, self.viewbox, self.cursor, self.anchor);
+    }
+    /// `create_history` for a change confined to `lines`, highlighting only from there
+    /// on instead of the whole buffer.
+    fn create_history_for(&mut self, lines: Range<usize>) {
+        self.buffer.update_syntax_lines(lines);
+
+        self.buffer
+            .history
+            .push_state(&self.buffer.rows, self.viewbox, self.cursor, self.anchor);
+    }
+    fn update_last_history_state(&mut self) {
+        self.buffer
+            .history
+            .update_state(self.viewbox, self.cursor, self.anchor);
+    }
+
+    /// Abandon the innermost history transaction, putting the text and the cursor back
+    /// as they were when it began.
+    fn rollback(&mut self) {
+        if self.buffer.history.rollback() {
+            self.restore_history();
+        }
+    }
+
+    /// Load the buffer and view state of the current history version.
+    fn restore_history(&mut self) {
+        self.buffer.rows = self.buffer.history.current.clone();
+        // The lines may have changed anywhere
+        self.folds.clear();
+        self.cursor = self.buffer.history.current_state.cursor;
+        self.anchor = self.buffer.history.current_state.anchor;
+        self.clamp_cursor();
+        self.set_viewbox(self.buffer.history.current_state.viewbox);
+        self.buffer.dirty = self.buffer.differs_from_saved();
+    }
+
+    /// Copy the selection, or the current line, to the internal clipboard and the
+    /// system one. Failing to reach the system clipboard is only reported in a toast.
+    fn trigger_copy(&mut self) {
+        // Fix wrong deletion when selection is empty
+        if let Some((begin, end)) = self.get_selection() {
+            if begin == end {
+                self.anchor = None;
+            }
+        }
+
+        // Copy the current line, with its line break, if nothing is selected. Whole lines
+        // are pasted as lines of their own rather than into the middle of one.
+        let linewise = self
+            .get_selection()
+            .map_or(true, |(begin, end)| begin.x == 0 && end.x == 0);
+        let clipboard = self
+            .selection_text()
+            .unwrap_or_else(|| self.buffer.rows[self.cursor.y].to_string() + "\n");
+        if let Some(name) = self.register.take() {
+            self.registers.insert(name, clipboard);
+            self.toast(t!("register.copied", name));
+        } else {
+            self.clipboard_linewise = linewise;
+            self.set_clipboard(clipboard);
+        }
+    }
+
+    /// Copy the selection, or the current line, with syntax highlighting as ANSI
+    /// escapes or an HTML snippet.
+    fn copy_highlighted(&mut self, command: Command) {
+        let (begin, end) = self
+            .get_selection()
+            .filter(|(begin, end)| begin != end)
+            .unwrap_or_else(|| {
+                let y = self.cursor.y;
+                ((0, y).into(), (self.buffer.rows[y].len(), y).into())
+            });
+        let rows = self.buffer.row_ranges(begin, end);
+        let (text, format) = match command {
+            Command::CopyHtml => (cat::to_html(rows), "HTML"),
+            _ => (cat::to_ansi(rows), "ANSI"),
+        };
+
+        self.clipboard_linewise = false;
+        if self.set_clipboard(text) {
+            self.toast(t!("clipboard.copied_as", format));
+        }
+    }
+
+    /// Put text on the system clipboard, keeping a copy in case it is unavailable.
+    /// Returns whether the system clipboard was reached.
+    fn set_clipboard(&mut self, text: String) -> bool {
+        self.clipboard_synced = match terminal_clipboard::set_string(&text) {
+            Ok(()) => true,
+            Err(err) => {
+                self.toast(t!("clipboard.copy_failed", err));
+                false
+            }
+        };
+        self.clipboard = Some(text);
+        self.clipboard_synced
+    }
+
+    fn trigger_paste(&mut self, reindent: bool) {
+        if let Some(name) = self.register.take() {
+            match self.registers.get(&name) {
+                Some(text) => self.paste_text(text.clone(), false, reindent),
+                None => self.toast(t!("register.empty", name)),
+            }
+            return;
+        }
+
+        // The system clipboard may hold something newer, unless our last copy never made
+        // it there
+        let system = terminal_clipboard::get_string().ok();
+        let clipboard = match (system, &self.clipboard) {
+            (Some(system), None) => system,
+            (Some(system), Some(_)) if self.clipboard_synced && !system.is_empty() => system,
+            (_, internal) => internal.clone().unwrap_or_default(),
+        };
+
+        let linewise = self.clipboard_linewise && self.clipboard.as_ref() == Some(&clipboard);
+        self.paste_text(clipboard, linewise, reindent);
+    }
+
+    /// Replace the selection with `text` as one undo step. Whole lines go above the
+    /// current line instead if nothing is selected. With `reindent`, the lines take
+    /// the indentation of the one they are pasted on in place of their own.
+    fn paste_text(&mut self, text: String, linewise: bool, reindent: bool) {
+        if text.is_empty() {
+            return;
+        }
+        self.update_last_history_state();
+        self.buffer.dirty = true;
+
+        let clipboard = match self.config.normalize_unicode {
+            true => row::nfc(&text).into_owned(),
+            false => text,
+        };
+        let linewise = linewise
+            && self
+                .get_selection()
+                .map_or(true, |(begin, end)| begin == end);
+
+        self.buffer.history.begin_transaction();
+        self.delete_selection();
+
+        // Only what is inserted changes, not what was copied
+        let tab_width = self.config.tab_width;
+        let indent = reindent.then(|| self.buffer.indents(self.cursor.y, tab_width).0);
+        let clipboard = match indent.is_some() || self.config.paste_convert_indent {
+            true => (self.buffer).fit_indent(&clipboard, tab_width, linewise, indent.as_deref()),
+            false => clipboard,
+        };
+
+        if linewise {
+            // Whole lines go above the current line
+            let y = self.cursor.y;
+            self.insert_at((0, y).into(), &clipboard);
+            self.cursor = Position {
+                x: self.buffer.rows[y].first_non_blank(),
+                y,
+            };
+        } else {
+            self.cursor = self.insert_at(self.cursor, &clipboard);
+        }
+
+        self.create_history();
+        self.buffer.history.commit();
+    }
+
+    fn on_exit(&mut self) -> Result<(), Error> {
+        self.terminal.cleanup()?;
+
+        Ok(())
+    }
+
+    /// Attempts to save the file. Returns `true` if the file was saved successfully, `false` otherwise.
+    fn try_save_file(&mut self, is_save_as: bool) -> Result<bool, Error> {
+        if self.buffer.preview.is_some() {
+            self.toast(t!("preview.read_only"));
+            return Ok(false);
+        }
+        self.update_last_history_state();
 
-        for i in 0..self.terminal.height {
-            self.terminal.write(
-                (0, i).into(),
-                " ".repeat(self.terminal.width).on(style::background),
-            );
+        if is_save_as || self.buffer.filename.is_none() {
+            if let Some(ref filename) = Tui::prompt_filename(self)? {
+                let path = Path::new(filename);
+                if path.is_dir() {
+                    Tui::alert(
+                        self,
+                        t!("error.title").to_string(),
+                        t!("error.is_directory").to_string(),
+                    )?;
+                    return Ok(false);
+                }
+
+                let is_open = |buffer: &Buffer| {
+                    (buffer.filename.as_deref())
+                        .is_some_and(|name| same_file(Path::new(name), path))
+                };
+                // Saving the file under its own name is just saving it
+                if !is_open(&self.buffer) {
+                    let other = self.buffers.iter().position(|b| is_open(&b.buffer));
+                    if let Some(index) = other {
+                        // The other buffer would no longer match the file, so this one
+                        // takes its place
+                        if Tui::confirm_overwrite_open(self, filename)? != Some(true) {
+                            return Ok(false);
+                        }
+                        self.buffers.remove(index);
+                        if index < self.buffer_index {
+                            self.buffer_index -= 1;
+                        }
+                    } else if path.exists() {
+                        if let Some(false) = Tui::confirm_overwrite(self, filename)? {
+                            return Ok(false);
+                        }
+                    }
+
+                    self.buffer.filename = Some(clean_path(path).to_string_lossy().into_owned());
+                    if !self.buffer.syntax_forced {
+                        self.detect_syntax(filename);
+                    }
+                }
+            }
         }
 
-        // draw statusbar
+        if let Some(filename) = self.buffer.filename.clone() {
+            if self.config.format_on_save && self.format_command().is_some() {
+                self.format_document()?;
+            }
+
+            if let Err(err) = self.buffer.write_to(Path::new(&filename)) {
+                Tui::alert(
+                    self,
+                    t!("error.save_failed").to_string(),
+                    t!("error.message", Error::get_error_message(&err)),
+                )?;
+                return Ok(false);
+            }
+
+            self.create_history();
+            self.buffer.history.mark_boundary();
+            self.buffer.mark_saved();
+
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// The formatter configured for the file's extension, or the default one.
+    fn format_command(&self) -> Option<String> {
+        let ext = self
+            .buffer
+            .filename
+            .as_deref()
+            .and_then(|name| Path::new(name).extension())
+            .and_then(std::ffi::OsStr::to_str);
+        ext.and_then(|ext| self.config.formatters.get(ext))
+            .or(self.config.format_command.as_ref())
+            .cloned()
+    }
+
+    /// Replace the buffer with the output of the formatter, as a single undo step,
+    /// keeping the cursor on the same line. If the formatter fails its error output is
+    /// shown and the buffer is left as it was. Returns `true` on success.
+    fn format_document(&mut self) -> Result<bool, Error> {
+        let Some(command) = self.format_command() else {
+            self.toast(t!("format.no_command"));
+            return Ok(false);
+        };
+
+        let result = format::run(&command, &self.buffer.to_string(), format::TIMEOUT);
+        // The formatter may have written to the terminal directly
+        self.terminal.repair()?;
+
+        let text = match result {
+            Ok(text) => text,
+            Err(err) => {
+                let message = match err {
+                    Error::Formatter(message) => message,
+                    Error::Io(err) => t!("error.message", Error::get_error_message(&err)),
+                    // `format::run` fails in no other way
+                    _ => t!("error.message", t!("io.unknown")),
+                };
+                Tui::alert(self, t!("format.failed").to_string(), message)?;
+                return Ok(false);
+            }
+        };
+
+        let formatted: Buffer = text.parse().unwrap_or_default();
+        if formatted.rows != self.buffer.rows {
+            self.update_last_history_state();
+            self.buffer.rows = formatted.rows;
+            self.folds.clear();
+            self.buffer.dirty = true;
+
+            self.cursor = self.buffer.clamp(self.cursor);
+            self.anchor = None;
+
+            self.create_history();
+        }
+        Ok(true)
+    }
+
+    /// Reload the syntax definition or theme if its file has changed since it was
+    /// loaded. Returns `true` if the screen needs a repaint.
+    fn watch_files(&mut self) -> bool {
+        if self
+            .files_checked
+            .is_some_and(|time| time.elapsed() < WATCH_INTERVAL)
         {
-            const LOGO_WIDTH: usize = 8;
-            self.terminal.write(
-                (0, self.terminal.height - 2).into(),
-                " ARCAEA "
-                    .to_string()
-                    .with(style::text_primary)
-                    .on(style::background_primary),
-            );
-            let content_left = format!(" {}", self.filename.as_deref().unwrap_or("Untitled"));
-            let content_left = if self.dirty {
-                format!("{} (未保存)", content_left)
-            } else {
-                content_left
-            };
-            let content_right = format!(
-                "行 {}，列 {}  {} {} ",
-                self.cursor.y + 1,
-                self.cursor.x + 1,
-                if self.is_crlf { "CRLF " } else { "LF " },
-                self.syntax.name,
-            );
-            self.terminal.write(
-                (LOGO_WIDTH, self.terminal.height.saturating_sub(2)).into(),
-                format!(
-                    "{}{}{}",
-                    content_left,
-                    " ".repeat(
-                        self.terminal.width.saturating_sub(
-                            content_left.width() + content_right.width() + LOGO_WIDTH
-                        )
-                    ),
-                    content_right,
-                )
-                .with(style::text_statusbar)
-                .on(style::background_sidebar),
-            );
+            return false;
+        }
+        self.files_checked = Some(Instant::now());
+
+        let syntax_path = self.buffer.syntax.path.clone();
+        let syntax_changed =
+            syntax_path.is_some_and(|path| modified(&path) != self.syntax_modified);
+        if syntax_changed {
+            self.reload_syntax();
+        }
+        let theme_path = self.config.theme.clone();
+        let theme_changed = theme_path.is_some_and(|path| modified(&path) != self.theme_modified);
+        if theme_changed {
+            self.reload_theme();
+        }
+        syntax_changed || theme_changed
+    }
+
+    /// Whether the buffer is read-only, because it is followed or a preview, saying so
+    /// if it is.
+    fn refuse_edit(&mut self) -> bool {
+        let message = if self.follow.is_some() {
+            t!("follow.read_only")
+        } else if self.buffer.preview.is_some() {
+            t!("preview.read_only")
+        } else {
+            return false;
+        };
+        self.toast(message);
+        true
+    }
+
+    /// Whether `command` would change the text, given its `edit_span`.
+    fn edits(command: Command, edit: Option<(usize, usize, usize)>) -> bool {
+        edit.is_some()
+            || matches!(
+                command,
+                Command::Undo
+                    | Command::Redo
+                    | Command::UndoBranch
+                    | Command::Format
+                    | Command::JsonPretty
+                    | Command::JsonMinify
+                    | Command::ConvertLineEndings
+                    | Command::LineOperations
+                    | Command::RevertFile
+                    | Command::CleanAnomalies
+            )
+    }
+
+    /// Whether the end of a followed file is being kept in view: the cursor is on the
+    /// last line and the view shows it. Scrolling or moving up pauses following, going
+    /// back to the end resumes it.
+    fn following(&self) -> bool {
+        let last = self.buffer.line_count() - 1;
+        let bottom = self.screen_row(self.viewbox.y) + self.text_area().2;
+        self.cursor.y == last && self.anchor.is_none() && self.screen_row(last) < bottom
+    }
+
+    /// Add what was written to the followed file since it was last checked. Returns
+    /// `None` if nothing changed, or whether the view should move to the new end.
+    fn follow_file(&mut self) -> Option<bool> {
+        if self
+            .follow_checked
+            .is_some_and(|time| time.elapsed() < FOLLOW_INTERVAL)
+        {
+            return None;
+        }
+        self.follow_checked = Some(Instant::now());
+
+        let following = self.following();
+        match self.follow.as_mut()?.poll(&mut self.buffer) {
+            Ok(Update::Appended) => {}
+            Ok(Update::Reloaded) => {
+                self.folds.clear();
+                self.anchor = None;
+                self.create_history();
+                self.toast(t!("follow.reloaded"));
+            }
+            // The file may be in the middle of being replaced
+            Ok(Update::Unchanged) | Err(_) => return None,
+        }
+        if following {
+            self.cursor = self.buffer.clamp((0, usize::MAX).into());
+        }
+        self.clamp_cursor();
+        Some(following)
+    }
+
+    /// Load the syntax definition again and highlight the buffer with it. If the file
+    /// cannot be parsed, the old definition is kept and the error shown in a toast.
+    fn reload_syntax(&mut self) {
+        let Some(path) = self.buffer.syntax.path.clone() else {
+            return;
+        };
+        self.syntax_modified = modified(&path);
+        match Syntax::from_file(&path) {
+            Ok((syntax, _)) => {
+                self.toast(t!("reload.syntax", &syntax.name));
+                self.buffer.syntax = syntax;
+                self.buffer.update_syntax();
+            }
+            Err(err) => self.toast(t!("reload.failed", format!("{:?}", err))),
+        }
+    }
+
+    /// Measure ambiguous characters as the `cjk_width` setting, or else the locale,
+    /// says, and measure the open buffers again if that is a change.
+    fn apply_width_mode(&mut self) {
+        let cjk = self.config.cjk_width.unwrap_or_else(width::locale_is_cjk);
+        if cjk == width::cjk() {
+            return;
+        }
+        width::set_cjk(cjk);
+        self.buffer.remeasure();
+        for background in &mut self.buffers {
+            background.buffer.remeasure();
+        }
+        self.terminal.invalidate();
+    }
+
+    /// Load the theme file again, keeping the current theme if it cannot be parsed.
+    fn reload_theme(&mut self) {
+        let Some(path) = self.config.theme.clone() else {
+            return;
+        };
+        self.theme_modified = modified(&path);
+        match Theme::from_file(&path) {
+            Ok(theme) => {
+                style::set_theme(theme);
+                self.terminal.invalidate();
+                self.toast(t!("reload.theme"));
+            }
+            Err(err) => self.toast(t!("reload.failed", format!("{:?}", err))),
+        }
+    }
+
+    /// Save the file in the background once it has stayed modified for the configured
+    /// interval. Returns `true` if the screen needs a repaint.
+    fn autosave(&mut self) -> bool {
+        let Some(interval) = self.config.autosave else {
+            return false;
+        };
+        let Some(filename) = self.buffer.filename.clone().filter(|_| self.buffer.dirty) else {
+            self.autosave_pending = None;
+            return false;
+        };
+        if self
+            .autosave_pending
+            .get_or_insert_with(Instant::now)
+            .elapsed()
+            < interval
+        {
+            return false;
+        }
+        self.autosave_pending = None;
+
+        match self.buffer.write_to(Path::new(&filename)) {
+            Ok(()) => {
+                self.buffer.history.mark_boundary();
+                self.buffer.mark_saved();
+            }
+            Err(err) => self.toast(t!("error.autosave_failed", Error::get_error_message(&err))),
+        }
+        true
+    }
+
+    /// Open the search box on the bottom line. The query typed into it is looked for
+    /// in the whole buffer, and the match nearest the cursor selected: the first at or
+    /// after it, or the last before it when searching in `reverse`.
+    fn search(&mut self, reverse: bool) -> Result<(), Error> {
+        self.open_search(reverse);
+        self.refresh(true)?;
+        while self.is_searching {
+            if self.poll_event()? {
+                let event = self.read_event()?;
+                let should_update_viewbox = self.handle_search_event(&event)?;
+                self.refresh(should_update_viewbox)?;
+            } else if self.expire_toast() {
+                self.refresh(false)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn open_search(&mut self, reverse: bool) {
+        self.is_searching = true;
+        // Laid out for real when it is drawn
+        self.search.max_width = self.search.max_width.max(1);
+        self.search_reverse = reverse;
+        self.search_origin = (self.anchor, self.cursor);
+        // The last query is kept, for going on from where the last search stopped
+        self.find_matches();
+    }
+
+    /// Handle an event while the search box is open: Enter goes to the next match in
+    /// the search direction, Shift+Enter turns the direction around and goes on that
+    /// way, Up and Down go to the match before or after, and Esc closes the box, putting
+    /// the cursor and selection back to how they were when it was opened.
+    /// Returns whether the view should move to the cursor.
+    fn handle_search_event(&mut self, event: &Event) -> Result<bool, Error> {
+        match event {
+            Event::Key(key) if key.kind != KeyEventKind::Release => match key.code {
+                KeyCode::Enter if key.modifiers.contains(KeyModifiers::SHIFT) => {
+                    self.search_reverse = !self.search_reverse;
+                    self.next_match(!self.search_reverse);
+                }
+                KeyCode::Enter => self.next_match(!self.search_reverse),
+                KeyCode::Up => self.next_match(false),
+                KeyCode::Down => self.next_match(true),
+                KeyCode::Esc => {
+                    self.is_searching = false;
+                    (self.anchor, self.cursor) = self.search_origin;
+                    self.search_closed = Some(Instant::now());
+                }
+                _ => {
+                    let query = self.search.buffer.to_string();
+                    self.search.handle_event(event)?;
+                    if self.search.buffer.to_string() != query {
+                        self.find_matches();
+                    }
+                }
+            },
+            Event::Mouse(mouse) => {
+                if let Some(command) = keymap::mouse_command(mouse, self.config.wheel) {
+                    self.execute(command)?;
+                    return Ok(false);
+                }
+                self.search.handle_event(event)?;
+            }
+            Event::Resize(width, height) => {
+                self.terminal.update_window_size(*height, *width);
+                self.on_resize();
+            }
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    /// Whether Esc closed the search box moments ago, or was ignored for that moments
+    /// ago. A repeating key keeps being ignored until it is let go of.
+    fn escape_cooling_down(&mut self) -> bool {
+        let cooling = (self.search_closed).is_some_and(|time| time.elapsed() < ESCAPE_COOLDOWN);
+        self.search_closed = cooling.then(Instant::now);
+        cooling
+    }
+
+    /// Look for the query in the buffer and select the match nearest to where the
+    /// search started, in the search direction.
+    fn find_matches(&mut self) {
+        let query = self.search.buffer.to_string();
+        let ignore_case = self.config.search_ignore_case;
+        self.search_result = (self.buffer.rows.iter().enumerate())
+            .flat_map(|(y, row)| {
+                (row.find_all(&query, ignore_case).into_iter())
+                    .map(move |x| (Position { x: x.start, y }, Position { x: x.end, y }))
+            })
+            .collect();
+        if self.search_result.is_empty() {
+            return;
+        }
+
+        let (anchor, cursor) = self.search_origin;
+        let (begin, end) = match anchor.map(|anchor| self.buffer.range_bound(anchor)) {
+            Some(anchor) => {
+                let cursor = self.buffer.range_bound(cursor);
+                (anchor.min(cursor), anchor.max(cursor))
+            }
+            None => (cursor, cursor),
+        };
+        let after = self.search_result.partition_point(|(b, _)| *b < end);
+        let before = self.search_result.partition_point(|(b, _)| *b < begin);
+        self.search_index = match self.search_reverse {
+            false if after < self.search_result.len() => after,
+            true if before > 0 => before - 1,
+            _ => {
+                self.toast(t!("search.wrapped"));
+                if self.search_reverse {
+                    self.search_result.len() - 1
+                } else {
+                    0
+                }
+            }
+        };
+        self.select_match();
+    }
+
+    /// Select the match after the current one, or the one before it, going around at
+    /// either end of the buffer.
+    fn next_match(&mut self, forward: bool) {
+        let Some(last) = self.search_result.len().checked_sub(1) else {
+            return;
+        };
+        self.search_index = match forward {
+            true if self.search_index < last => self.search_index + 1,
+            false if self.search_index > 0 => self.search_index - 1,
+            _ => {
+                self.toast(t!("search.wrapped"));
+                if forward {
+                    0
+                } else {
+                    last
+                }
+            }
+        };
+        self.select_match();
+    }
+
+    fn select_match(&mut self) {
+        let (begin, end) = self.search_result[self.search_index];
+        self.anchor = Some(begin);
+        self.cursor = end;
+        self.selection_stack.clear();
+    }
+
+    /// The search matches on line `y`.
+    fn line_matches(&self, y: usize) -> &[(Position, Position)] {
+        let start = self.search_result.partition_point(|(begin, _)| begin.y < y);
+        let end = self
+            .search_result
+            .partition_point(|(begin, _)| begin.y <= y);
+        &self.search_result[start..end]
+    }
+
+    /// Draw the search box on the bottom line, after the search direction and before
+    /// which match is current.
+    fn render_search(&mut self) {
+        let (width, y) = (self.terminal.width, self.terminal.height - 1);
+        let arrow = if self.search_reverse { '↑' } else { '↓' };
+        let label = format!(" {} {arrow} ", t!("search.label"));
+        let count = match self.search_result.len() {
+            0 if self.search.buffer.is_empty() => String::new(),
+            0 => format!(" {} ", t!("search.none")),
+            n => format!(" {}/{n} ", self.search_index + 1),
+        };
+        self.terminal
+            .write((0, y).into(), " ".repeat(width).on(style::background()));
+        self.terminal.write(
+            (0, y).into(),
+            label
+                .clone()
+                .with(style::text_statusbar())
+                .on(style::background()),
+        );
+        self.terminal.write(
+            (width.saturating_sub(count.width()), y).into(),
+            count
+                .clone()
+                .with(style::text_dimmed())
+                .on(style::background()),
+        );
+        self.search.viewbox = (label.width(), y).into();
+        self.search.max_width = width.saturating_sub(label.width() + count.width() + 1);
+        self.search.render(&mut self.terminal);
+    }
+}
+
+/// Where `pos` ends up once text from `at` to `end` has been inserted. Positions
+/// before `at` stay put.
+fn shift_after_insert(pos: Position, at: Position, end: Position) -> Position {
+    match pos {
+        pos if pos < at => pos,
+        Position { x, y } if y == at.y => Position {
+            x: end.x + x - at.x,
+            y: end.y,
+        },
+        Position { x, y } => Position {
+            x,
+            y: y + end.y - at.y,
+        },
+    }
+}
+
+/// Where `pos` ends up once the text from `begin` to `end` has been deleted. Positions
+/// inside it go to `begin`.
+fn shift_after_delete(pos: Position, begin: Position, end: Position) -> Position {
+    match pos {
+        pos if pos <= begin => pos,
+        pos if pos <= end => begin,
+        Position { x, y } if y == end.y => Position {
+            x: begin.x + x - end.x,
+            y: begin.y,
+        },
+        Position { x, y } => Position {
+            x,
+            y: y - (end.y - begin.y),
+        },
+    }
+}
+
+/// Where `text` ends if it starts at `begin`.
+fn position_after(begin: Position, text: &str) -> Position {
+    let y = begin.y + text.matches('\n').count();
+    let last = Row::from(text.rsplit('\n').next().unwrap_or_default());
+    Position {
+        x: last.len() + if y == begin.y { begin.x } else { 0 },
+        y,
+    }
+}
+
+/// Count the matches of `needle` in `rows` and the lines they are on, as search finds
+/// them. Stops once `limit` has passed, returning `false` with the counts so far.
+fn count_matches(
+    rows: &[Row],
+    needle: &str,
+    ignore_case: bool,
+    limit: Duration,
+) -> (usize, usize, bool) {
+    let start = Instant::now();
+    let (mut matches, mut lines) = (0, 0);
+    for (y, row) in rows.iter().enumerate() {
+        if y > 0 && start.elapsed() > limit {
+            return (matches, lines, false);
+        }
+        let found = row.find_all(needle, ignore_case).len();
+        matches += found;
+        lines += usize::from(found > 0);
+    }
+    (matches, lines, true)
+}
+
+/// Whether `a` and `b` name the same file, following `..` and symbolic links. Paths
+/// that don't exist yet are compared as written, once cleaned up.
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        (Err(_), Err(_)) => clean_path(a) == clean_path(b),
+        _ => false,
+    }
+}
+
+/// `path` without `.` components and with each `..` taking away the directory before
+/// it, relative to the working directory if it is inside it.
+fn clean_path(path: &Path) -> PathBuf {
+    let mut clean = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(clean.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                clean.pop();
+            }
+            component => clean.push(component),
+        }
+    }
+    let cwd = std::env::current_dir().unwrap_or_default();
+    match clean.strip_prefix(&cwd) {
+        Ok(relative) if clean.is_absolute() && !relative.as_os_str().is_empty() => {
+            relative.to_path_buf()
+        }
+        _ => clean,
+    }
+}
+
+/// The name shown for a buffer: its file name, or `Untitled-N` for a scratch buffer.
+fn buffer_name(buffer: &Buffer) -> String {
+    match (&buffer.filename, buffer.scratch) {
+        (Some(filename), _) => filename.clone(),
+        (None, 0) => t!("status.untitled").to_string(),
+        (None, n) => format!("{}-{}", t!("status.untitled"), n),
+    }
+}
+
+/// Parse space-separated hexadecimal code points, written as `2192` or `U+2192`, into
+/// the text they spell. Surrogates and control characters are rejected.
+fn parse_code_points(input: &str) -> Result<String, String> {
+    input
+        .split_whitespace()
+        .map(|code| {
+            let hex = code
+                .strip_prefix("U+")
+                .or_else(|| code.strip_prefix("u+"))
+                .unwrap_or(code);
+            Some(hex)
+                .filter(|hex| hex.chars().all(|c| c.is_ascii_hexdigit()))
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .and_then(char::from_u32)
+                .filter(|c| !c.is_control())
+                .ok_or_else(|| t!("dialog.invalid_code_point", code))
+        })
+        .collect()
+}
+
+/// A column to go to, counting from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Column {
+    /// On screen, where wide characters take two columns, as the status bar shows.
+    Visual(usize),
+    /// In characters, as the cursor moves.
+    Char(usize),
+    /// In bytes of UTF-8, as compilers tend to report.
+    Byte(usize),
+}
+
+/// Parse where to go to: `line`, `line:column`, or `:column` on the current line. The
+/// column is on screen, or after `c` or `b` in characters or bytes, like `12:c5` or
+/// `:b40`; `v` marks one on screen.
+fn parse_goto(input: &str) -> Result<(Option<usize>, Option<Column>), String> {
+    let input = input.trim();
+    let invalid = || t!("dialog.invalid_goto", input);
+    let number = |text: &str| text.parse().ok().filter(|&n| n > 0).ok_or_else(invalid);
+    let (line, column) = match input.split_once(':') {
+        Some((line, column)) => (line, Some(column)),
+        None => (input, None),
+    };
+    let line = match line {
+        "" if column.is_some() => None,
+        line => Some(number(line)?),
+    };
+    let Some(column) = column else {
+        return Ok((line, None));
+    };
+    let digits = column.find(|c: char| c.is_ascii_digit()).unwrap_or(0);
+    let column = match column.split_at(digits) {
+        ("" | "v", n) => Column::Visual(number(n)?),
+        ("c", n) => Column::Char(number(n)?),
+        ("b", n) => Column::Byte(number(n)?),
+        _ => return Err(invalid()),
+    };
+    Ok((line, Some(column)))
+}
+
+/// The path around cell `x` of `row`, like `src/main.rs` or `../a.txt:12:5`, and the
+/// line number after it, if any. Paths are runs of letters, digits and `/\._-~+@:`.
+fn path_at(row: &Row, x: usize) -> Option<(String, Option<usize>)> {
+    let plausible = |i: usize| {
+        row.rope.get(i).is_some_and(|(g, _)| {
+            g.chars()
+                .all(|c| c.is_alphanumeric() || "/\\._-~+@:".contains(c))
+        })
+    };
+    // The cursor may also be right after the path
+    let x = [x, x.wrapping_sub(1)].into_iter().find(|&x| plausible(x))?;
+    let begin = (0..x).rev().find(|&i| !plausible(i)).map_or(0, |i| i + 1);
+    let end = (x..row.len()).find(|&i| !plausible(i)).unwrap_or(row.len());
+    let text: String = row.rope[begin..end]
+        .iter()
+        .map(|(g, _)| g.as_str())
+        .collect();
+
+    // Punctuation ending a sentence isn't part of the path
+    let mut path = text.trim_end_matches(['.', ':']);
+    let mut numbers = Vec::new();
+    while let Some((rest, digits)) = path.rsplit_once(':') {
+        let is_number = !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit());
+        if numbers.len() == 2 || !is_number {
+            break;
+        }
+        numbers.push(digits.parse().ok()?);
+        path = rest;
+    }
+    (!path.is_empty()).then(|| (path.to_string(), numbers.pop()))
+}
+
+/// Open a URL with the platform's opener. It runs on its own, with its output
+/// discarded so that it can't draw over the screen.
+fn open_url(url: &str) -> std::io::Result<()> {
+    use std::process::{Command, Stdio};
+
+    let mut command = if cfg!(windows) {
+        // `start` would go through cmd, which treats `&` in the URL as a separator
+        let mut command = Command::new("rundll32");
+        command.arg("url.dll,FileProtocolHandler");
+        command
+    } else if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else {
+        Command::new("xdg-open")
+    };
+    let mut child = command
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // Reap it once it exits, without waiting here
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// When a file was last modified, if that can be read.
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod script;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(x: usize, y: usize) -> Position {
+        Position { x, y }
+    }
+
+    fn editor(text: &str) -> Editor {
+        let mut editor = Editor {
+            buffer: text.parse().unwrap(),
+            ..Default::default()
+        };
+        editor.update_sidebar_width();
+        editor
+    }
+
+    /// Press each key in turn, e.g. `["shift+end", "a"]`.
+    fn press(editor: &mut Editor, keys: &[&str]) {
+        for key in keys {
+            let chord = keymap::parse_chord(key).unwrap();
+            let command = editor.config.keymap.translate(chord).unwrap();
+            editor.execute(command).unwrap();
+        }
+    }
+
+    /// Like `press`, but through `handle_event`, so the view follows and a frame is drawn.
+    fn send(editor: &mut Editor, keys: &[&str]) {
+        for key in keys {
+            let (modifiers, code, state) = keymap::parse_chord(key).unwrap();
+            let mut event = crossterm::event::KeyEvent::new(code, modifiers);
+            event.state = state;
+            let event = Event::Key(event);
+            assert!(editor.handle_event(&event).unwrap());
+        }
+    }
+
+    /// The buffer with `|` at the cursor and `^` at the selection anchor.
+    fn snapshot(editor: &Editor) -> String {
+        let mut lines = Vec::new();
+        for (y, row) in editor.buffer.rows.iter().enumerate() {
+            let mut line = String::new();
+            for x in 0..=row.len() {
+                if editor.anchor == Some(pos(x, y)) {
+                    line.push('^');
+                }
+                if editor.cursor == pos(x, y) {
+                    line.push('|');
+                }
+                if let Some((g, _)) = row.rope.get(x) {
+                    line.push_str(g);
+                }
+            }
+            lines.push(line);
+        }
+        lines.join("\n")
+    }
+
+    #[test]
+    fn test_typing() {
+        let mut editor = editor("");
+        press(&mut editor, &["h", "i", "enter", "shift+x"]);
+        assert_eq!(snapshot(&editor), "hi\nx|");
+        press(&mut editor, &["backspace", "backspace", "ctrl+enter"]);
+        assert_eq!(snapshot(&editor), "hi\n|");
+
+        let mut editor = self::editor("ab\ncd");
+        press(&mut editor, &["down", "backspace"]);
+        assert_eq!(snapshot(&editor), "ab|cd");
+        press(&mut editor, &["delete", "end", "delete"]);
+        assert_eq!(snapshot(&editor), "abd|");
+        press(&mut editor, &["home", "tab"]);
+        assert_eq!(editor.buffer.to_string(), "    abd");
+    }
+
+    #[test]
+    fn test_movement() {
+        let mut editor = editor("foo bar  baz\n你好");
+        press(&mut editor, &["ctrl+right", "ctrl+right"]);
+        assert_eq!(snapshot(&editor), "foo bar|  baz\n你好");
+        press(&mut editor, &["ctrl+left"]);
+        assert_eq!(snapshot(&editor), "foo |bar  baz\n你好");
+        press(&mut editor, &["down", "right"]);
+        assert_eq!(snapshot(&editor), "foo bar  baz\n你好|");
+        press(&mut editor, &["up", "home", "left", "alt+right"]);
+        assert_eq!(snapshot(&editor), "f|oo bar  baz\n你好");
+    }
+
+    #[test]
+    fn test_page_movement() {
+        let text = (0..100)
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut editor = editor(&text);
+        let (_, _, page) = editor.text_area();
+
+        // The view moves by a page, and the cursor keeps its place on the screen
+        press(&mut editor, &["down", "down", "down", "right", "pagedown"]);
+        assert_eq!(editor.viewbox.y, page);
+        assert_eq!(editor.cursor, pos(1, page + 3));
+        press(&mut editor, &["shift+pagedown"]);
+        assert_eq!(editor.viewbox.y, page * 2);
+        assert_eq!(editor.cursor, pos(1, page * 2 + 3));
+        assert_eq!(editor.anchor, Some(pos(1, page + 3)));
+        press(&mut editor, &["pageup"]);
+        assert_eq!(editor.viewbox.y, page);
+        assert_eq!(editor.cursor, pos(1, page + 3));
+        assert_eq!(editor.anchor, None);
+
+        // At the ends of the file the cursor goes to the first or last line
+        press(&mut editor, &["pageup", "pageup"]);
+        assert_eq!((editor.viewbox.y, editor.cursor), (0, pos(1, 0)));
+        press(&mut editor, &["pagedown"; 6]);
+        assert_eq!(editor.cursor, pos(1, 99));
+
+        // Without any rows for the text, the cursor stays put
+        let mut editor = Editor::open_str(&text, 40, 2);
+        assert_eq!(editor.text_area().2, 0);
+        editor.cursor = pos(1, 3);
+        press(&mut editor, &["pagedown"]);
+        assert_eq!(editor.cursor, pos(1, 3));
+        press(&mut editor, &["pageup"]);
+        assert_eq!(editor.cursor, pos(1, 3));
+    }
+
+    #[test]
+    fn test_scroll_past_end() {
+        let text = "line\n".repeat(99);
+        let mut editor = Editor::open_str(&text, 40, 10);
+        let scroll = Command::Scroll(Direction::Down, 1000);
+        editor.execute(scroll).unwrap();
+        assert_eq!(editor.viewbox.y, 100 + EXTRA_GAP - 8);
+
+        editor.config.scroll_past_end = true;
+        editor.execute(scroll).unwrap();
+        assert_eq!(editor.viewbox.y, 99);
+        editor.refresh(false).unwrap();
+        let screen = editor.terminal.snapshot();
+        assert_eq!((screen[0].as_str(), screen[1].trim()), (" 100", ""));
+        // The cursor still brings the view back to it
+        editor.refresh(true).unwrap();
+        assert_eq!(editor.viewbox.y, 0);
+    }
+
+    #[test]
+    fn test_overflow_indicators() {
+        let text = format!("{}\nab\n中文中文", "x".repeat(100));
+        let mut editor = Editor::open_str(&text, 40, 10);
+        editor.refresh(false).unwrap();
+        let screen = editor.terminal.snapshot();
+        assert!(screen[0].ends_with("x»"));
+        assert_eq!(screen[1].trim_end(), "  2 ab");
+        assert_eq!(screen[0].chars().count(), 40);
+
+        editor.viewbox.x = 2;
+        editor.refresh(false).unwrap();
+        let screen = editor.terminal.snapshot();
+        assert!(screen[0].starts_with("  1 «xx"));
+        assert!(screen[0].ends_with("x»"));
+        assert_eq!(screen[1].trim_end(), "  2 «");
+        // The wide character is covered, not split
+        assert_eq!(screen[2].trim_end(), "  3 « 中文");
+    }
+
+    #[test]
+    fn test_render_spans() {
+        use crate::TokenType;
+
+        let mut editor = Editor::open_str("let s = \"中文\"; // ok", 40, 5);
+        editor.buffer.syntax = Syntax::builtin("rs").unwrap();
+        editor.buffer.update_syntax();
+        editor.anchor = Some(pos(4, 0));
+        editor.cursor = pos(7, 0);
+        editor.render_to_buffer();
+        assert_eq!(editor.terminal.snapshot()[0], "  1 let s = \"中文\"; // ok");
+        let style = |x: usize| editor.terminal.style_at(pos(4 + x, 0));
+        let colors = |x| (style(x).foreground_color, style(x).background_color);
+
+        let background = Some(style::background());
+        let token = |token| Some(style::token_color(token));
+        assert_eq!(colors(0), (token(TokenType::Keyword1), background));
+        assert_eq!(colors(3), (token(TokenType::Normal), background));
+        let selected = Some(style::background_selected());
+        assert_eq!(colors(4), (token(TokenType::Normal), selected));
+        assert_eq!(colors(6), (token(TokenType::Normal), selected));
+        assert_eq!(colors(7), (token(TokenType::Normal), background));
+        // Both columns of a wide character
+        assert_eq!(colors(9), (token(TokenType::MlString), background));
+        assert_eq!(colors(10), (token(TokenType::MlString), background));
+        assert_eq!(colors(17), (token(TokenType::Comment), background));
+        // The cell after the end of the line
+        assert_eq!(colors(21), (token(TokenType::Normal), background));
+        assert_eq!(colors(22), (None, background));
+    }
+
+    #[test]
+    fn test_selection_past_edges() {
+        let text = format!("{}\n\nab\nyy", "x".repeat(100));
+        let mut editor = Editor::open_str(&text, 40, 10);
+        editor.anchor = Some(pos(50, 0));
+        editor.cursor = pos(1, 3);
+        let bg = |editor: &Editor, x, y| editor.terminal.style_at(pos(x, y)).background_color;
+        let selected = Some(style::background_selected());
+
+        // Selected on past the right edge
+        editor.refresh(false).unwrap();
+        assert_eq!(bg(&editor, 39, 0), selected);
+        assert_eq!(bg(&editor, 39, 2), Some(style::background()));
+
+        // And from before the left edge, with the empty line getting one cell
+        editor.viewbox.x = 2;
+        editor.refresh(false).unwrap();
+        let screen = editor.terminal.snapshot();
+        assert_eq!(screen[1], "  2");
+        assert_eq!(screen[2], "  3 «");
+        assert_ne!(bg(&editor, 4, 0), selected);
+        assert_eq!(bg(&editor, 39, 0), selected);
+        for y in 1..=3 {
+            assert_eq!(bg(&editor, 4, y), selected);
+            assert_ne!(bg(&editor, 5, y), selected);
+        }
+    }
+
+    #[test]
+    fn test_horizontal_scroll() {
+        let mut editor = editor(&format!("a\n{}\nb", "中文".repeat(50)));
+        let (_, width, _) = editor.text_area();
+
+        // The limit comes from the widest visible line, not the cursor's line
+        editor
+            .execute(Command::Scroll(Direction::Right, 1000))
+            .unwrap();
+        assert_eq!(editor.viewbox.x, 200 + EXTRA_GAP + 1 - width);
+        assert!(editor.viewbox.x + width > 200);
+
+        // Moving the cursor to a short line scrolls back to it
+        editor.cursor = pos(1, 2);
+        editor.update_viewbox();
+        assert_eq!(editor.viewbox.x, 0);
+
+        // The end of the wide line can be reached with the cursor
+        press(&mut editor, &["up", "end"]);
+        editor.update_viewbox();
+        assert_eq!(editor.viewbox.x, 200 + editor.config.scrolloff + 1 - width);
+    }
+
+    #[test]
+    fn test_end_scroll() {
+        let long = "x".repeat(100);
+        let mut editor = editor(&format!("{long}\nshort\n{long}"));
+        editor.config.scrolloff = 0;
+        let (_, width, _) = editor.text_area();
+
+        // End leaves room after the cursor even without `scrolloff`
+        press(&mut editor, &["end"]);
+        editor.update_viewbox();
+        assert_eq!(editor.viewbox.x, 100 + EXTRA_GAP + 1 - width);
+
+        // A short line is shown from the start however far the view was scrolled
+        editor.cursor = pos(5, 1);
+        editor.update_viewbox();
+        assert_eq!(editor.viewbox.x, 0);
+
+        // Selecting to the end of a long line keeps its start in view
+        press(&mut editor, &["down", "home", "right", "shift+end"]);
+        editor.update_viewbox();
+        assert_eq!(editor.viewbox.x, 0);
+        assert_eq!(editor.anchor, Some(pos(1, 2)));
+        // Unless the selection started out of view
+        editor.viewbox.x = 50;
+        press(&mut editor, &["shift+home", "shift+end"]);
+        editor.update_viewbox();
+        assert_eq!(editor.viewbox.x, 100 + EXTRA_GAP + 1 - width);
+    }
+
+    #[test]
+    fn test_undo_scrolled() {
+        let long = "x".repeat(200);
+        let mut editor = Editor::open_str(&format!("{long}\n{long}"), 40, 10);
+        editor.cursor = pos(150, 0);
+        editor.refresh(true).unwrap();
+        let before = editor.viewbox;
+        send(&mut editor, &["a", "down"]);
+        let after = editor.viewbox;
+        assert!(before.x > 100 && after.x > before.x);
+        send(&mut editor, &["b"]);
+
+        // Undo and redo go back to where each edit was, far from the left edge
+        send(&mut editor, &["ctrl+z"]);
+        assert_eq!((editor.cursor, editor.viewbox), (pos(151, 1), after));
+        send(&mut editor, &["ctrl+z"]);
+        assert_eq!((editor.cursor, editor.viewbox), (pos(150, 0), before));
+        send(&mut editor, &["ctrl+y"]);
+        assert_eq!((editor.cursor, editor.viewbox), (pos(151, 1), after));
+
+        // Even after the view was scrolled back to the start
+        send(&mut editor, &["home"]);
+        assert_eq!(editor.viewbox.x, 0);
+        send(&mut editor, &["ctrl+y"]);
+        assert_eq!(editor.cursor, pos(152, 1));
+        assert!(editor.viewbox.x > after.x);
+
+        // Switching buffers keeps each one's view
+        let view = editor.viewbox;
+        send(&mut editor, &["ctrl+n"]);
+        assert_eq!(editor.viewbox, pos(0, 0));
+        editor.switch_buffer(0);
+        editor.refresh(true).unwrap();
+        assert_eq!((editor.cursor, editor.viewbox), (pos(152, 1), view));
+
+        // A view restored past the end of a shorter buffer is pulled back
+        editor.buffer.history.current_state.viewbox = pos(500, 7);
+        editor.restore_history();
+        assert_eq!(editor.viewbox.y, 1);
+        assert!(editor.viewbox.x <= 200);
+    }
+
+    #[test]
+    fn test_resize_in_dialog() {
+        let mut editor = Editor::open_str(&"line\n".repeat(50), 40, 40);
+        editor.cursor = pos(0, 30);
+        editor.refresh(true).unwrap();
+        assert_eq!(editor.viewbox.y, 0);
+
+        // The view follows the cursor into the smaller window
+        editor.terminal.update_window_size(12, 40);
+        editor.on_resize();
+        assert!(editor.viewbox.y > 0);
+        let (_, _, rows) = editor.text_area();
+        assert!((editor.viewbox.y..editor.viewbox.y + rows).contains(&30));
+
+        // But is left alone while the window is too small to show anything
+        let viewbox = editor.viewbox;
+        editor.terminal.update_window_size(5, 20);
+        editor.on_resize();
+        assert_eq!(editor.viewbox, viewbox);
+
+        // A cursor left past the end of the buffer is brought back into it
+        editor.cursor = pos(3, 200);
+        editor.terminal.update_window_size(40, 40);
+        editor.on_resize();
+        assert_eq!(editor.cursor, pos(0, 50));
+        assert!(editor.screen_row(editor.viewbox.y) <= editor.screen_row(50));
+    }
+
+    #[test]
+    fn test_insert_text() {
+        let mut editor = Editor::open_str("one\ntwo three\nfour", 40, 10);
+        editor.cursor = pos(4, 1);
+        editor.anchor = Some(pos(1, 2));
+
+        // Text before the cursor and selection pushes them along
+        assert_eq!(editor.insert_text(pos(2, 1), "X\nY"), pos(1, 2));
+        assert_eq!(editor.buffer.to_string(), "one\ntwX\nYo three\nfour");
+        assert_eq!((editor.cursor, editor.anchor), (pos(3, 2), Some(pos(1, 3))));
+        // Text after them leaves them alone
+        editor.insert_text(pos(4, 3), "!");
+        assert_eq!((editor.cursor, editor.anchor), (pos(3, 2), Some(pos(1, 3))));
+
+        // Deleting around the cursor moves it to where the text was
+        assert_eq!(editor.delete_range(pos(3, 2), pos(1, 1)), "wX\nYo ");
+        assert_eq!(editor.buffer.to_string(), "one\ntthree\nfour!");
+        assert_eq!((editor.cursor, editor.anchor), (pos(1, 1), Some(pos(1, 2))));
+
+        // Each edit is an undo step of its own
+        press(&mut editor, &["ctrl+z"]);
+        assert_eq!(editor.buffer.to_string(), "one\ntwX\nYo three\nfour!");
+        press(&mut editor, &["ctrl+z", "ctrl+z"]);
+        assert_eq!(editor.buffer.to_string(), "one\ntwo three\nfour");
+    }
+
+    #[test]
+    fn test_insert_date() {
+        let mut editor = Editor::open_str("ab\ncd", 40, 10);
+        editor.config.date_format = "[%Y]".to_string();
+        editor.cursor = pos(1, 0);
+        editor.anchor = Some(pos(1, 1));
+        press(&mut editor, &["alt+d"]);
+        let text = editor.buffer.to_string();
+        assert!(text.starts_with("a[20") && text.ends_with("]d"), "{text}");
+        assert_eq!(editor.cursor, pos(7, 0));
+        press(&mut editor, &["ctrl+z"]);
+        assert_eq!(editor.buffer.to_string(), "ab\ncd");
+
+        // The file name and path are only offered for files
+        assert_eq!(editor.variables().len(), 3);
+        editor.buffer.filename = Some("notes/today.md".to_string());
+        let variables = editor.variables();
+        assert_eq!(variables[3].1, "today.md");
+        assert!(Path::new(&variables[4].1).is_absolute());
+    }
+
+    #[test]
+    fn test_mouse() {
+        let mut editor = editor("first\nsecond\nthird");
+        editor.sidebar_width = 4;
+        let event = |kind, column, row| MouseEvent {
+            kind,
+            column,
+            row,
+            modifiers: crossterm::event::KeyModifiers::NONE,
+        };
+        let status_bar = editor.text_area().2;
+        assert_eq!(editor.hit_test(10, status_bar), None);
+        assert_eq!(editor.hit_test(10, status_bar + 1), None);
+        assert_eq!(editor.hit_test(2, 0), Some(MouseArea::Sidebar));
+        assert_eq!(editor.hit_test(4, 0), Some(MouseArea::Text));
+
+        // Dragging onto the status bar clamps to the last text row
+        let down = MouseEventKind::Down(MouseButton::Left);
+        let drag = MouseEventKind::Drag(MouseButton::Left);
+        editor.drag_mouse(&event(down, 6, 0), MouseArea::Text);
+        assert_eq!(snapshot(&editor), "fi^|rst\nsecond\nthird");
+        editor.drag_mouse(&event(drag, 7, status_bar as u16), MouseArea::Text);
+        assert_eq!(snapshot(&editor), "fi^rst\nsecond\nthi|rd");
+    }
+
+    #[test]
+    fn test_cjk_width() {
+        let mut editor = Editor::open_str("§x\n±", 40, 10);
+        editor.config.cjk_width = Some(false);
+        editor.apply_width_mode();
+        press(&mut editor, &["end"]);
+        assert_eq!(editor.get_cursor_position(), pos(2, 0));
+
+        // Ambiguous characters take two columns once the setting says so
+        editor.config.cjk_width = Some(true);
+        press(&mut editor, &["f5"]);
+        assert_eq!(editor.get_cursor_position(), pos(3, 0));
+        assert_eq!(editor.buffer.rows[1].visual_width(), 2);
+        assert_eq!(editor.buffer.rows[0].x_to_index(2), 1);
+
+        // And the text undone to is measured the same way
+        press(&mut editor, &["!", "ctrl+z"]);
+        assert_eq!(editor.buffer.rows[0].visual_width(), 3);
+
+        editor.config.cjk_width = Some(false);
+        editor.apply_width_mode();
+        assert_eq!(editor.get_cursor_position(), pos(2, 0));
+    }
+
+    #[test]
+    fn test_long_line_click() {
+        const CLICKS: u32 = 100;
+        let mut editor = editor(&"一二三四五".repeat(10_000));
+        editor.sidebar_width = 4;
+        editor.viewbox.x = 99_000;
+        let down = MouseEvent {
+            kind: MouseEventKind::Down(MouseButton::Left),
+            column: 20,
+            row: 0,
+            modifiers: KeyModifiers::NONE,
+        };
+        editor.drag_mouse(&down, MouseArea::Text);
+        assert_eq!(editor.cursor, pos(49_508, 0));
+        assert_eq!(editor.get_cursor_position(), pos(99_016, 0));
+
+        // The line was measured once, and clicks on a 50k character line look the
+        // column up without walking along it again
+        let measured = editor.buffer.rows[0].cells_measured.get();
+        assert_eq!(measured, 50_000);
+        for _ in 0..CLICKS {
+            editor.drag_mouse(&down, MouseArea::Text);
+            editor.get_cursor_position();
         }
+        assert_eq!(editor.buffer.rows[0].cells_measured.get(), measured);
+    }
 
-        // draw debug info on bottom
-        self.terminal.write(
-            (0, self.terminal.height - 1).into(),
-            self.status_string
-                .clone()
-                .with(style::text_dimmed)
-                .on(style::background),
+    #[test]
+    fn test_text_area() {
+        let text = (1..=200)
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut editor = Editor::open_str(&text, 40, 12);
+        assert_eq!(editor.text_area(), (pos(4, 0), 36, 10));
+        editor.render_cursor();
+        assert_eq!(editor.terminal.cursor, Some(pos(4, 0)));
+
+        // Without the message line, the text takes the row and the status bar moves down
+        editor.hide_message_line = true;
+        assert_eq!(editor.text_area(), (pos(4, 0), 36, 11));
+        assert_eq!(editor.visible_lines().len(), 11);
+        assert_eq!(editor.hit_test(10, 10), Some(MouseArea::Text));
+        assert_eq!(editor.hit_test(10, 11), None);
+        editor
+            .execute(Command::ScrollPage(Direction::Down))
+            .unwrap();
+        assert_eq!(editor.viewbox.y, 11);
+
+        // The cursor is kept `scrolloff` lines above the status bar, wherever it is
+        editor.cursor = pos(0, 40);
+        editor.update_viewbox();
+        let (_, _, height) = editor.text_area();
+        assert_eq!(
+            editor.viewbox.y + height - 1 - editor.config.scrolloff,
+            editor.cursor.y
         );
 
-        self.render_sidebar();
+        // Three digit line numbers widen the sidebar and narrow the text
+        editor.cursor = pos(0, 150);
+        editor.update_viewbox();
+        editor.update_sidebar_width();
+        assert_eq!(editor.text_area(), (pos(5, 0), 35, 11));
+    }
 
-        let begin = self.viewbox.y;
-        let end = (self.viewbox.y + self.terminal.height - 2).min(self.buffer.len());
+    #[test]
+    fn test_split_view() {
+        let text = (1..=30)
+            .map(|i| format!("line {i}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut editor = Editor::open_str(&text, 40, 12);
+        let mouse = |kind, row| {
+            Event::Mouse(MouseEvent {
+                kind,
+                column: 10,
+                row,
+                modifiers: KeyModifiers::NONE,
+            })
+        };
+
+        // The upper pane keeps the focus, the lower one shows the same place
+        send(&mut editor, &["ctrl+\\"]);
+        assert_eq!(editor.text_area(), (pos(4, 0), 36, 4));
+        let screen = editor.terminal.snapshot();
+        assert_eq!(screen[0], "  1 line 1");
+        assert_eq!(screen[4], "─".repeat(40));
+        assert_eq!(screen[5], "  1 line 1");
+
+        // Edits show in both, and the other pane's cursor stays on its text
+        send(&mut editor, &["f6", "down", "down", "f6", "x", "enter"]);
+        let screen = editor.terminal.snapshot();
+        assert_eq!((&screen[0][4..], &screen[1][4..]), ("x", "line 1"));
+        assert_eq!((&screen[5][4..], &screen[6][4..]), ("x", "line 1"));
+        assert_eq!(editor.split.as_ref().unwrap().cursor, pos(0, 3));
+        send(&mut editor, &["f6"]);
+        assert_eq!(editor.text_area(), (pos(4, 5), 36, 5));
+        assert_eq!(snapshot(&editor).lines().nth(3), Some("|line 3"));
+
+        // The wheel scrolls the pane under the mouse, a click moves the focus there
+        let top = editor.viewbox.y;
+        editor
+            .handle_event(&mouse(MouseEventKind::ScrollDown, 1))
+            .unwrap();
+        assert_eq!(editor.split.as_ref().unwrap().viewbox.y, 2);
+        assert_eq!(editor.viewbox.y, top);
+        editor
+            .handle_event(&mouse(MouseEventKind::Down(MouseButton::Left), 1))
+            .unwrap();
+        editor
+            .handle_event(&mouse(MouseEventKind::Up(MouseButton::Left), 1))
+            .unwrap();
+        assert_eq!(editor.text_area().0, pos(4, 0));
+        assert_eq!(editor.cursor, pos(6, 3));
+        assert_eq!(editor.split.as_ref().unwrap().cursor, pos(0, 3));
+
+        // Undo, history and saving belong to the buffer; closing keeps the focused pane
+        send(&mut editor, &["ctrl+z", "ctrl+z"]);
+        assert_eq!(editor.buffer.to_string(), text);
+        assert!(!editor.buffer.dirty);
+        send(&mut editor, &["ctrl+4"]);
+        assert!(editor.split.is_none());
+        assert_eq!(editor.text_area(), (pos(4, 0), 36, 10));
+    }
 
-        for line_number in begin..end {
-            let mut dx = self.sidebar_width as isize - self.viewbox.x as isize;
-            for (i, (g, w)) in self.buffer[line_number]
-                .rope
-                .iter()
-                .chain([(&("\n".to_string(), 1))]) // Append a virtual space to the end of the line
-                .enumerate()
-            {
-                dx += *w as isize;
-                if dx >= self.terminal.width as isize {
-                    break;
-                }
-                if dx >= (self.sidebar_width + w) as isize {
-                    let mut str = g.as_str();
-                    let fg_color = if let Some(token) = self.buffer[line_number].syntax.get(i) {
-                        match token {
-                            TokenType::Normal => style::token_normal,
-                            TokenType::Number => style::token_number,
-                            TokenType::Match => style::token_match,
-                            TokenType::String => style::token_string,
-                            TokenType::MlString => style::token_ml_string,
-                            TokenType::Comment => style::token_comment,
-                            TokenType::MlComment => style::token_ml_comment,
-                            TokenType::Keyword1 => style::token_keyword1,
-                            TokenType::Keyword2 => style::token_keyword2,
-                            TokenType::Keyword3 => style::token_keyword3,
-                        }
-                    } else {
-                        style::token_normal
-                    };
-                    let mut bg_color = style::background;
+    #[test]
+    fn test_scroll_page() {
+        let text = (1..=100)
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut editor = Editor::open_str(&text, 40, 12);
+        editor
+            .execute(Command::ScrollPage(Direction::Down))
+            .unwrap();
+        assert_eq!((editor.viewbox.y, editor.cursor), (10, pos(0, 0)));
+        editor.execute(Command::ScrollPage(Direction::Up)).unwrap();
+        assert_eq!(editor.viewbox.y, 0);
+    }
 
-                    if let Some((begin, end)) = self.get_selection() {
-                        let current = (i, line_number).into();
-                        if begin <= current && current < end {
-                            bg_color = style::background_selected;
-                        }
-                    }
-                    if str == "\n" {
-                        str = " ";
-                    }
-                    self.terminal.write_char(
-                        (dx as usize - w, line_number - self.viewbox.y).into(),
-                        str.with(fg_color).on(bg_color),
-                    );
-                }
-            }
-        }
+    #[test]
+    fn test_drag_lines() {
+        let mut editor = Editor::open_str("one\ntwo\nthree\nfour\nfive", 40, 10);
+        let column = editor.sidebar_width as u16 + 1;
+        let mouse = |editor: &mut Editor, kind, row, modifiers| {
+            let event = MouseEvent {
+                kind,
+                column,
+                row,
+                modifiers,
+            };
+            editor.handle_event(&Event::Mouse(event)).unwrap();
+        };
+        let (down, drag, up) = (
+            MouseEventKind::Down(MouseButton::Left),
+            MouseEventKind::Drag(MouseButton::Left),
+            MouseEventKind::Up(MouseButton::Left),
+        );
+        editor.anchor = Some(pos(0, 1));
+        editor.cursor = pos(0, 3);
+
+        mouse(&mut editor, down, 2, KeyModifiers::ALT);
+        mouse(&mut editor, drag, 4, KeyModifiers::ALT);
+        assert_eq!(editor.terminal.snapshot()[4], "───▶five");
+        assert_eq!(snapshot(&editor), "one\n^two\nthree\n|four\nfive");
+        mouse(&mut editor, up, 4, KeyModifiers::ALT);
+        assert_eq!(snapshot(&editor), "one\nfour\n^two\nthree\n|five");
+        editor.execute(Command::Undo).unwrap();
+        assert_eq!(editor.buffer.to_string(), "one\ntwo\nthree\nfour\nfive");
+
+        // Dropping next to themselves leaves the lines where they are
+        editor.anchor = Some(pos(1, 1));
+        editor.cursor = pos(2, 2);
+        mouse(&mut editor, down, 1, KeyModifiers::ALT);
+        mouse(&mut editor, up, 3, KeyModifiers::ALT);
+        assert_eq!(snapshot(&editor), "one\nt^wo\nth|ree\nfour\nfive");
+
+        // Below the last line moves them to the end, above the first to the start
+        mouse(&mut editor, down, 2, KeyModifiers::ALT);
+        mouse(&mut editor, up, 8, KeyModifiers::ALT);
+        assert_eq!(snapshot(&editor), "one\nfour\nfive\n^two\nthree|");
+        mouse(&mut editor, down, 3, KeyModifiers::ALT);
+        mouse(&mut editor, up, 0, KeyModifiers::ALT);
+        assert_eq!(snapshot(&editor), "^two\nthree\n|one\nfour\nfive");
+
+        // Outside the selection Alt+Click is a plain click
+        mouse(&mut editor, down, 4, KeyModifiers::ALT);
+        mouse(&mut editor, up, 0, KeyModifiers::ALT);
+        assert_eq!(snapshot(&editor), "two\nthree\none\nfour\nf^|ive");
+    }
 
-        if self.is_searching {
-            self.render_search();
-        }
+    #[test]
+    fn test_sidebar_selection() {
+        let mut editor = Editor::open_str("a\nb\nc\nd\ne", 40, 10);
+        editor.anchor = Some(pos(0, 3));
+        editor.cursor = pos(0, 1);
+        editor.refresh(false).unwrap();
+        let style = |y| editor.terminal.style_at(pos(0, y));
+        let sidebar = |y| (style(y).foreground_color, style(y).background_color);
+        let selected = Some(style::background_sidebar_selected());
+        let normal = Some(style::background_sidebar());
+
+        assert_eq!(sidebar(0), (Some(style::text_dimmed()), normal));
+        assert_eq!(sidebar(1), (Some(style::text_sidebar_selected()), selected));
+        assert_eq!(sidebar(2), (Some(style::text_sidebar_selected()), selected));
+        assert_eq!(sidebar(3), (Some(style::text_sidebar_anchor()), selected));
+        assert_eq!(sidebar(4), (Some(style::text_dimmed()), normal));
+
+        // Within one line there's no anchor line to tell apart
+        editor.anchor = Some(pos(1, 1));
+        editor.refresh(false).unwrap();
+        let style = |y| editor.terminal.style_at(pos(0, y));
+        assert_eq!(
+            style(1).foreground_color,
+            Some(style::text_sidebar_selected())
+        );
+        assert_eq!(style(3).background_color, Some(style::background_sidebar()));
     }
 
-    pub fn check_minimum_window_size(&mut self) -> bool {
-        const MIN_WIDTH: usize = 40;
-        const MIN_HEIGHT: usize = 9;
-        if self.terminal.width < MIN_WIDTH || self.terminal.height < MIN_HEIGHT {
-            let mut stdout = io::stdout();
+    #[test]
+    fn test_sidebar_drag() {
+        let mut editor = editor("one\ntwo\nthree\nfour");
+        let mut drag = |pressed, row| {
+            let event = MouseEvent {
+                kind: MouseEventKind::Drag(MouseButton::Left),
+                column: 0,
+                row,
+                modifiers: crossterm::event::KeyModifiers::NONE,
+            };
+            let kind = if pressed {
+                MouseEventKind::Down(MouseButton::Left)
+            } else {
+                event.kind
+            };
+            editor.drag_mouse(&MouseEvent { kind, ..event }, MouseArea::Sidebar);
+            snapshot(&editor)
+        };
+
+        // Pressing selects the line, dragging down selects the lines below it
+        assert_eq!(drag(true, 1), "one\n^two\n|three\nfour");
+        assert_eq!(drag(false, 2), "one\n^two\nthree\n|four");
+        // Dragging up keeps the pressed line selected
+        assert_eq!(drag(false, 0), "|one\ntwo^\nthree\nfour");
+        assert_eq!(drag(false, 1), "one\n^two\n|three\nfour");
+        // Past the last line, and into the area below the text
+        assert_eq!(drag(false, 3), "one\n^two\nthree\nfour|");
+        assert_eq!(drag(false, 10), "one\n^two\nthree\nfour|");
+        assert_eq!(drag(true, 10), "one\ntwo\nthree\n^four|");
+        assert_eq!(drag(false, 0), "|one\ntwo\nthree\nfour^");
+    }
 
-            let _ = queue!(
-                stdout,
-                crossterm::cursor::Hide,
-                crossterm::terminal::Clear(crossterm::terminal::ClearType::All)
-            );
-            let (w, h) = (self.terminal.width, self.terminal.height);
-            let (w_str, h_str) = (format!("{}", w), format!("{}", h));
-
-            let hint_0 = "窗口过小";
-            let _ = queue!(
-                stdout,
-                cursor::MoveTo(
-                    ((w - hint_0.width()) / 2) as u16,
-                    (h / 2).saturating_sub(1) as u16
-                ),
-                crossterm::style::Print(hint_0.bold()),
-            );
-            let hint_1 = format!("Width = {}, Height = {}", w, h);
-            let _ = queue!(
-                stdout,
-                cursor::MoveTo(((w - hint_1.width()) / 2) as u16, (h / 2) as u16),
-                crossterm::style::Print(format!(
-                    "Width = {}, Height = {}",
-                    if w < MIN_WIDTH {
-                        w_str.red().bold().slow_blink()
-                    } else {
-                        w_str.green().bold()
-                    },
-                    if h < MIN_HEIGHT {
-                        h_str.red().bold().rapid_blink()
-                    } else {
-                        h_str.green().bold()
-                    }
-                ),),
-            );
-            let hint_2 = format!("(min width = {}, height = {})   ", MIN_WIDTH, MIN_HEIGHT);
-            let _ = queue!(
-                stdout,
-                cursor::MoveTo(((w - hint_2.width()) / 2) as u16, (h / 2 + 1) as u16),
-                crossterm::style::Print(hint_2),
-            );
-            let _ = stdout.flush();
+    #[test]
+    fn test_window_too_small() {
+        let mut editor = editor("text");
+        editor.terminal.update_window_size(5, 8);
+        editor.draw_window_too_small();
+        let screen = editor.terminal.snapshot();
+        // Hints wider than the window are cut off instead of overflowing
+        assert!(screen.iter().all(|line| line.width() <= 8));
+        assert!(t!("status.window_too_small").starts_with(screen[1].trim()));
+        assert_eq!(screen[2], "Width =");
+        assert_eq!(screen[3], "(min wid");
+        assert_eq!(screen.len(), 5);
+
+        // Hints still fit in a window that is large enough for them
+        editor.terminal.update_window_size(9, 39);
+        editor.draw_window_too_small();
+        assert_eq!(
+            editor.terminal.snapshot()[4].trim(),
+            "Width = 39, Height = 9"
+        );
+    }
 
-            false
-        } else {
-            true
-        }
+    #[test]
+    fn test_headless() {
+        use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+        let mut editor = Editor::open_str("hello\nworld", 40, 10);
+        let key = |code| Event::Key(KeyEvent::new(code, KeyModifiers::NONE));
+        assert!(editor.handle_event(&key(KeyCode::End)).unwrap());
+        assert!(editor.handle_event(&key(KeyCode::Char('!'))).unwrap());
+        assert_eq!(editor.buffer.to_string(), "hello!\nworld");
+        let screen = editor.terminal.snapshot();
+        assert!(screen[0].ends_with(" hello!"));
+        assert!(screen[1].ends_with(" world"));
+
+        // Undo goes back to the state it was opened in
+        editor.execute(Command::Undo).unwrap();
+        assert_eq!(editor.buffer.to_string(), "hello\nworld");
+        editor.execute(Command::Redo).unwrap();
+
+        let click = |kind, column| {
+            Event::Mouse(MouseEvent {
+                kind,
+                column,
+                row: 1,
+                modifiers: KeyModifiers::NONE,
+            })
+        };
+        let column = editor.sidebar_width as u16;
+        editor
+            .handle_event(&click(MouseEventKind::Down(MouseButton::Left), column + 2))
+            .unwrap();
+        editor
+            .handle_event(&click(MouseEventKind::Up(MouseButton::Left), column + 2))
+            .unwrap();
+        assert_eq!(snapshot(&editor), "hello!\nwo^|rld");
+
+        editor.handle_event(&Event::Resize(20, 5)).unwrap();
+        let screen = editor.terminal.snapshot();
+        assert_eq!(screen.len(), 5);
+        assert!(screen.iter().any(|line| line.contains("Width = 20")));
     }
 
-    fn render_sidebar(&mut self) {
-        let cursor = self.get_cursor_position();
-        for i in 0..(self.terminal.height.saturating_sub(2)) {
-            if self.viewbox.y + i < self.buffer.len() {
-                let lineno = format!(
-                    "{:>width$} ",
-                    i + self.viewbox.y + 1,
-                    width = self.sidebar_width - 1
-                );
-                let num = if i + self.viewbox.y == cursor.y {
-                    lineno.with(style::text_sidebar_selected)
-                } else {
-                    lineno.with(style::text_dimmed)
+    #[test]
+    fn test_random_events() {
+        use crossterm::event::KeyModifiers;
+
+        let commands = [
+            Command::SelectAll,
+            Command::Undo,
+            Command::Redo,
+            Command::Copy,
+            Command::Cut,
+            Command::Paste,
+            Command::CopyOrPaste,
+            Command::InsertChar('x'),
+            Command::InsertChar('中'),
+            Command::InsertTab,
+            Command::NewLine,
+            Command::DeleteBackward,
+            Command::DeleteForward,
+            Command::MoveLines(Direction::Up),
+            Command::MoveLines(Direction::Down),
+            Command::DuplicateLines(Direction::Up),
+            Command::DuplicateLines(Direction::Down),
+            Command::ScrollLine(Direction::Down, true),
+            Command::Scroll(Direction::Down, 5),
+            Command::Scroll(Direction::Right, 5),
+        ];
+        let motions = [
+            Motion::Up,
+            Motion::Down,
+            Motion::Left,
+            Motion::Right,
+            Motion::WordLeft,
+            Motion::WordRight,
+            Motion::PageUp,
+            Motion::PageDown,
+            Motion::Home,
+            Motion::End,
+        ];
+        let mouse = [
+            MouseEventKind::Down(MouseButton::Left),
+            MouseEventKind::Drag(MouseButton::Left),
+            MouseEventKind::Up(MouseButton::Left),
+        ];
+
+        for seed in 1..=10u64 {
+            let mut editor = Editor::open_str("fn main() {\n\t你好, world\n}\n", 40, 12);
+            // A small linear congruential generator, so that failures can be replayed
+            let mut state = seed;
+            let mut random = |n: usize| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 33) as usize % n
+            };
+
+            for step in 0..200 {
+                let event = match random(4) {
+                    0 => {
+                        let event = MouseEvent {
+                            kind: mouse[random(mouse.len())],
+                            column: random(42) as u16,
+                            row: random(14) as u16,
+                            modifiers: KeyModifiers::NONE,
+                        };
+                        editor.handle_event(&Event::Mouse(event)).unwrap();
+                        format!("{event:?}")
+                    }
+                    1 => {
+                        let motion = motions[random(motions.len())];
+                        let command = Command::MoveCursor(motion, random(2) == 0);
+                        editor.execute(command).unwrap();
+                        editor.refresh(true).unwrap();
+                        format!("{command:?}")
+                    }
+                    _ => {
+                        let command = commands[random(commands.len())];
+                        editor.execute(command).unwrap();
+                        editor.refresh(!command.keeps_view()).unwrap();
+                        format!("{command:?}")
+                    }
                 };
-                self.terminal
-                    .write((0, i).into(), num.on(style::background_sidebar));
-            } else {
-                self.terminal.write(
-                    (0, i).into(),
-                    format!("{:>width$} ", " ", width = self.sidebar_width - 1)
-                        .with(style::text_dimmed)
-                        .on(style::background_sidebar),
-                );
+
+                let context = format!("seed {seed}, step {step}: {event}");
+                let lines = editor.buffer.line_count();
+                assert!(lines > 0, "{context}");
+                assert!(editor.cursor.y < lines, "{context}");
+                if let Some(anchor) = editor.anchor {
+                    assert_eq!(editor.buffer.clamp(anchor), anchor, "{context}");
+                }
             }
         }
     }
 
-    fn render_cursor(&mut self) {
-        let cursor = self.get_cursor_position();
-        let (x, y) = (
-            cursor.x as isize - self.viewbox.x as isize + self.sidebar_width as isize,
-            cursor.y as isize - self.viewbox.y as isize,
-        );
+    #[test]
+    fn test_word_movement() {
+        // Word motions stop between words and punctuation
+        let mut editor = editor("foo.bar(baz)");
+        press(&mut editor, &["ctrl+right", "ctrl+right", "ctrl+right"]);
+        assert_eq!(snapshot(&editor), "foo.bar|(baz)");
+        press(&mut editor, &["ctrl+left", "ctrl+left"]);
+        assert_eq!(snapshot(&editor), "foo|.bar(baz)");
+    }
 
-        if x >= 0 && x < self.terminal.width as isize && y >= 0 && y < self.terminal.height as isize
-        {
-            self.terminal.cursor = Some((x as usize, y as usize).into());
-        } else {
-            self.terminal.cursor = None;
-        }
+    #[test]
+    fn test_selection_editing() {
+        let mut editor = editor("hello world");
+        press(&mut editor, &["ctrl+shift+right"]);
+        assert_eq!(snapshot(&editor), "^hello| world");
+        press(&mut editor, &["b", "y", "e"]);
+        assert_eq!(snapshot(&editor), "bye| world");
+
+        press(&mut editor, &["home", "shift+end"]);
+        assert_eq!(snapshot(&editor), "^bye world|");
+        press(&mut editor, &["backspace"]);
+        assert_eq!(snapshot(&editor), "|");
     }
 
-    fn get_cursor_position(&mut self) -> Position {
-        self.cursor.y = self.cursor.y.min(self.buffer.len() - 1);
-        Position {
-            x: self.buffer[self.cursor.y]
-                .rope
-                .iter()
-                .take(self.cursor.x)
-                .map(|g| g.1)
-                .sum::<usize>(),
-            y: self.cursor.y,
-        }
+    #[test]
+    fn test_line_commands() {
+        let mut editor = editor("a\nb\nc");
+        press(&mut editor, &["down", "down", "alt+up"]);
+        assert_eq!(snapshot(&editor), "a\n|c\nb");
+        press(&mut editor, &["alt+shift+down"]);
+        assert_eq!(snapshot(&editor), "a\nc\n|c\nb");
+        press(&mut editor, &["alt+shift+up"]);
+        assert_eq!(snapshot(&editor), "a\nc\n|c\nc\nb");
     }
 
-    fn update_sidebar_width(&mut self) {
-        // Calculate sidebar width based on maximum possible line number
-        let max_line_num = (self.viewbox.y + self.terminal.height)
-            .saturating_sub(2)
-            .min(self.buffer.len());
-        self.sidebar_width = if max_line_num > 99 {
-            (max_line_num as f64).log10().floor() as usize + 1
-        } else {
-            2
-        } + 2;
+    #[test]
+    fn test_line_commands_keep_selection() {
+        // Anchor above the cursor
+        let mut editor = editor("a\nbb\ncc\nd");
+        editor.anchor = Some(pos(1, 1));
+        editor.cursor = pos(1, 2);
+        press(&mut editor, &["alt+up"]);
+        assert_eq!(snapshot(&editor), "b^b\nc|c\na\nd");
+        // Nothing moves past the first line, not even the cursor
+        press(&mut editor, &["alt+up"]);
+        assert_eq!(snapshot(&editor), "b^b\nc|c\na\nd");
+        press(&mut editor, &["alt+down", "alt+down"]);
+        assert_eq!(snapshot(&editor), "a\nd\nb^b\nc|c");
+        press(&mut editor, &["alt+down"]);
+        assert_eq!(snapshot(&editor), "a\nd\nb^b\nc|c");
+        press(&mut editor, &["alt+shift+down"]);
+        assert_eq!(snapshot(&editor), "a\nd\nbb\ncc\nb^b\nc|c");
+
+        // Anchor below the cursor
+        let mut editor = self::editor("a\nbb\ncc\nd");
+        editor.anchor = Some(pos(2, 2));
+        editor.cursor = pos(0, 1);
+        press(&mut editor, &["alt+down"]);
+        assert_eq!(snapshot(&editor), "a\nd\n|bb\ncc^");
+        press(&mut editor, &["alt+up", "alt+up"]);
+        assert_eq!(snapshot(&editor), "|bb\ncc^\na\nd");
+        press(&mut editor, &["alt+shift+up"]);
+        assert_eq!(snapshot(&editor), "|bb\ncc^\nbb\ncc\na\nd");
+
+        // A selection ending at the start of a line leaves that line out
+        let mut editor = self::editor("a\nb\nc");
+        editor.anchor = Some(pos(0, 0));
+        editor.cursor = pos(0, 1);
+        press(&mut editor, &["alt+down"]);
+        assert_eq!(snapshot(&editor), "b\n^a\n|c");
+        press(&mut editor, &["alt+down"]);
+        assert_eq!(snapshot(&editor), "b\nc\n^a|");
+        press(&mut editor, &["alt+shift+up"]);
+        assert_eq!(snapshot(&editor), "b\nc\n^a|\na");
+
+        // A single line without a selection
+        let mut editor = self::editor("a\nbcd\ne");
+        editor.cursor = pos(2, 1);
+        press(&mut editor, &["alt+down"]);
+        assert_eq!(snapshot(&editor), "a\ne\nbc|d");
+        press(&mut editor, &["alt+up", "alt+up", "alt+up"]);
+        assert_eq!(snapshot(&editor), "bc|d\na\ne");
+        assert_eq!(editor.anchor, None);
+
+        // Dropped lines are selected whole, in the same direction
+        let mut editor = self::editor("a\nb\nc\nd");
+        editor.anchor = Some(pos(0, 2));
+        editor.cursor = pos(1, 1);
+        editor.drop_lines(4);
+        assert_eq!(snapshot(&editor), "a\nc\nd\n|b^");
     }
 
-    fn update_viewbox(&mut self) {
-        let Position { x, y } = self.get_cursor_position();
+    #[test]
+    fn test_delete_to_line_edge() {
+        let mut editor = editor("你好世界\nsecond");
+        press(&mut editor, &["right", "right", "shift+right", "ctrl+k"]);
+        assert_eq!(snapshot(&editor), "你好世|\nsecond");
+        // At the end of the line, the next line is joined
+        press(&mut editor, &["ctrl+k"]);
+        assert_eq!(snapshot(&editor), "你好世|second");
+        press(&mut editor, &["ctrl+z"]);
+        assert_eq!(snapshot(&editor), "你好世|\nsecond");
+
+        press(&mut editor, &["down", "right", "ctrl+u"]);
+        assert_eq!(snapshot(&editor), "你好世\n|nd");
+        press(&mut editor, &["ctrl+u", "end", "down", "ctrl+k"]);
+        assert_eq!(snapshot(&editor), "你好世\nnd|");
+    }
+
+    #[test]
+    fn test_grow_selection() {
+        let mut editor = editor("intro\n\nfoo.bar baz\nnext line\n\nend");
+        press(&mut editor, &["down", "down", "right", "alt+shift+right"]);
+        assert_eq!(
+            snapshot(&editor),
+            "intro\n\n^foo|.bar baz\nnext line\n\nend"
+        );
+        press(&mut editor, &["alt+shift+right"]);
+        assert_eq!(
+            snapshot(&editor),
+            "intro\n\n^foo.bar baz|\nnext line\n\nend"
+        );
+        press(&mut editor, &["alt+shift+right"]);
+        assert_eq!(
+            snapshot(&editor),
+            "intro\n\n^foo.bar baz\nnext line|\n\nend"
+        );
+        press(&mut editor, &["alt+shift+right"]);
+        assert_eq!(
+            snapshot(&editor),
+            "^intro\n\nfoo.bar baz\nnext line\n\nend|"
+        );
+        press(&mut editor, &["alt+shift+right"]);
+        assert_eq!(
+            snapshot(&editor),
+            "^intro\n\nfoo.bar baz\nnext line\n\nend|"
+        );
 
-        self.viewbox.y = self.viewbox.y.clamp(
-            (y + EXTRA_GAP + 3).saturating_sub(self.terminal.height),
-            y.saturating_sub(EXTRA_GAP),
+        // Shrinking steps back to the cursor
+        press(&mut editor, &["alt+shift+left", "alt+shift+left"]);
+        assert_eq!(
+            snapshot(&editor),
+            "intro\n\n^foo.bar baz|\nnext line\n\nend"
+        );
+        press(
+            &mut editor,
+            &["alt+shift+left", "alt+shift+left", "alt+shift+left"],
         );
+        assert_eq!(snapshot(&editor), "intro\n\nf|oo.bar baz\nnext line\n\nend");
 
-        self.viewbox.x = self.viewbox.x.clamp(
-            (x + EXTRA_GAP + 1).saturating_sub(self.terminal.width - self.sidebar_width),
-            x.saturating_sub(EXTRA_GAP),
+        // Moving the cursor starts over
+        press(
+            &mut editor,
+            &["alt+shift+right", "alt+shift+right", "down", "home"],
         );
+        press(&mut editor, &["alt+shift+right"]);
+        assert_eq!(
+            snapshot(&editor),
+            "intro\n\nfoo.bar baz\n^next| line\n\nend"
+        );
+        press(&mut editor, &["alt+shift+left", "alt+shift+left"]);
+        assert_eq!(snapshot(&editor), "intro\n\nfoo.bar baz\n|next line\n\nend");
     }
 
-    fn create_history(&mut self) {
-        self.update_syntax();
+    #[test]
+    fn test_paragraph_movement() {
+        let mut editor = editor("first\nline\n  \n\nsecond\n\nlast line");
+        press(&mut editor, &["right", "right", "alt+pagedown"]);
+        assert_eq!(snapshot(&editor), "first\nline\n  |\n\nsecond\n\nlast line");
+        // Runs of blank lines are skipped, and the column is kept
+        press(&mut editor, &["alt+pagedown", "alt+pagedown"]);
+        assert_eq!(snapshot(&editor), "first\nline\n  \n\nsecond\n\nla|st line");
+        press(&mut editor, &["alt+shift+pageup"]);
+        assert_eq!(snapshot(&editor), "first\nline\n  \n\nsecond\n\nla^st line");
+        press(&mut editor, &["alt+pageup", "alt+pageup"]);
+        assert_eq!(snapshot(&editor), "fi|rst\nline\n  \n\nsecond\n\nlast line");
+    }
 
-        self.history
-            .push_state(&self.buffer, self.viewbox, self.cursor, self.anchor);
+    #[test]
+    fn test_snippets() {
+        use crossterm::event::{KeyEvent, KeyModifiers};
+
+        let mut editor = editor("  fn");
+        let body = r"fn ${1:name}($2) {\n    $0\n}";
+        editor.config.snippets.insert("fn".into(), body.into());
+        press(&mut editor, &["end", "tab"]);
+        assert_eq!(snapshot(&editor), "  fn ^name|() {\n      \n  }");
+
+        // The placeholders after the current one move along with the typing
+        press(&mut editor, &["a", "c", "backspace", "b", "tab"]);
+        assert_eq!(snapshot(&editor), "  fn ab(|) {\n      \n  }");
+        press(&mut editor, &["x", "left", "tab"]);
+        assert_eq!(snapshot(&editor), "  fn ab(x) {\n      |\n  }");
+        // Snippet mode is over, so Tab indents again
+        press(&mut editor, &["tab"]);
+        assert_eq!(snapshot(&editor), "  fn ab(x) {\n          |\n  }");
+
+        // Esc leaves the placeholders as they are
+        press(&mut editor, &["enter", "f", "n", "tab", "enter"]);
+        let esc = Event::Key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE));
+        assert!(editor.handle_event(&esc).unwrap());
+        press(&mut editor, &["tab"]);
+        assert_eq!(
+            snapshot(&editor),
+            "  fn ab(x) {\n          \nfn \n    |() {\n    \n}\n  }"
+        );
     }
-    fn update_last_history_state(&mut self) {
-        self.history
-            .update_state(self.viewbox, self.cursor, self.anchor);
+
+    #[test]
+    fn test_unbound_keys() {
+        let mut editor = Editor::open_str("text", 40, 10);
+        send(&mut editor, &["ctrl+alt+f7"]);
+        assert!(editor.toast.is_none());
+
+        // Keys that do nothing are named the way `[keys]` reads them
+        editor.config.show_unbound_keys = true;
+        send(&mut editor, &["ctrl+alt+f7"]);
+        let toast = editor.toast.take().unwrap().0;
+        assert!(toast.contains("ctrl+alt+f7"), "{toast}");
+        send(&mut editor, &["shift+kpbegin"]);
+        assert!(editor.toast.take().unwrap().0.contains("shift+kpbegin"));
+        send(&mut editor, &["kp5"]);
+        assert!(editor.toast.is_none());
+        assert_eq!(snapshot(&editor), "5|text");
     }
 
-    fn trigger_copy(&mut self) -> Result<(), Error> {
-        // Fix wrong deletion when selection is empty
-        if let Some((begin, end)) = self.get_selection() {
-            if begin == end {
-                self.anchor = None;
-            }
+    #[test]
+    fn test_force_syntax() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Dockerfile.dev");
+        fs::write(&path, "fn main() {}").unwrap();
+        let mut editor = Editor::open_str("", 80, 24);
+        editor.config.syntax_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("syntax.d");
+
+        editor.load_file(&path.to_string_lossy());
+        assert_eq!(editor.buffer.syntax.name, Syntax::default().name);
+        editor.force_syntax("rust");
+        assert_eq!(editor.buffer.syntax.name, "Rust");
+        assert_ne!(editor.buffer.rows[0].syntax[0], crate::TokenType::Normal);
+        assert!(editor.buffer.syntax_forced);
+    }
+
+    #[test]
+    fn test_lazy_syntax() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("big.rs");
+        let line = "fn main() { /* comment */ let x = \"string\"; }\n";
+        fs::write(&path, line.repeat(20_000)).unwrap();
+        let mut editor = Editor::open_str("", 80, 24);
+        editor.config.syntax_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("syntax.d");
+
+        let highlighted = |editor: &Editor| {
+            (editor.buffer.rows.iter())
+                .take_while(|row| !row.syntax.is_empty())
+                .count()
+        };
+
+        // Only about the first screen is highlighted when the file is opened and drawn
+        editor.load_file(&path.to_string_lossy());
+        editor.render_to_buffer();
+        let height = editor.terminal.height;
+        assert!((height..=height * 2).contains(&highlighted(&editor)));
+        assert!(editor.buffer.rows[19_999].syntax.is_empty());
+
+        // Scrolling down highlights the lines that come into view, and no further
+        editor.set_viewbox(pos(0, 10_000));
+        editor.render_to_buffer();
+        assert!(!editor.buffer.rows[10_000 + height / 2].syntax.is_empty());
+        assert!((10_000..=10_000 + height * 2).contains(&highlighted(&editor)));
+        assert!(editor.buffer.rows[19_999].syntax.is_empty());
+
+        // The rest is done a chunk at a time while waiting for input
+        while editor.buffer.highlight_more(SYNTAX_CHUNK) {}
+        assert!(!editor.buffer.rows[19_999].syntax.is_empty());
+    }
+
+    #[test]
+    fn test_move_lines_syntax() {
+        let text: String = (0..2_000)
+            .map(|i| format!("fn f{i}() {{ /* comment */ let x = \"string\"; }}\n"))
+            .collect();
+        let mut editor = Editor::open_str(&text, 80, 24);
+        editor.buffer.syntax = Syntax::builtin("rs").unwrap();
+        editor.cursor = pos(0, 1_000);
+        editor.buffer.update_syntax();
+
+        // Moving a line highlights the few lines around it, however many come after,
+        // and as many each time
+        let mut counts = vec![];
+        for i in 0..9 {
+            let direction = match i % 2 {
+                0 => Direction::Up,
+                _ => Direction::Down,
+            };
+            let before = editor.buffer.lines_highlighted;
+            editor.execute(Command::MoveLines(direction)).unwrap();
+            counts.push(editor.buffer.lines_highlighted - before);
         }
+        assert!(counts.iter().all(|&count| count == counts[0]), "{counts:?}");
+        assert!(counts[0] <= 3, "{counts:?}");
+
+        // And leaves the same highlighting as doing all of it, as does duplicating
+        editor
+            .execute_all(&[Command::InsertChar('/'), Command::InsertChar('*')])
+            .unwrap();
+        editor
+            .execute(Command::DuplicateLines(Direction::Down))
+            .unwrap();
+        let rows = editor.buffer.rows.clone();
+        editor.buffer.update_syntax();
+        assert!(editor.buffer.rows == rows);
+    }
 
-        let mut clipboard = String::new();
-        if let Some((begin, end)) = self.get_selection() {
-            for i in begin.y..=end.y {
-                let row = &self.buffer[i];
-                let l = if i == begin.y { begin.x } else { 0 };
-                let r = if i == end.y { end.x } else { row.len() };
-                clipboard.push_str(
-                    &row.rope[l..r.max(row.len())]
-                        .iter()
-                        .map(|(g, _)| g.as_str())
-                        .collect::<String>(),
-                );
-                if i != end.y {
-                    clipboard.push('\n');
-                }
+    #[test]
+    fn test_hover_info() {
+        let mut editor = Editor::open_str("fn main() {}\n/* a", 40, 10);
+        editor.buffer.syntax = Syntax::builtin("rs").unwrap();
+        editor.buffer.update_syntax();
+        let hover = |column, row| {
+            Event::Mouse(MouseEvent {
+                kind: MouseEventKind::Moved,
+                column,
+                row,
+                modifiers: KeyModifiers::NONE,
+            })
+        };
+        let column = editor.sidebar_width as u16;
+
+        // Off unless toggled, and never moves the cursor
+        editor.handle_event(&hover(column, 0)).unwrap();
+        assert_eq!(editor.hover, None);
+        editor.execute(Command::ToggleHoverInfo).unwrap();
+        editor.handle_event(&hover(column + 1, 0)).unwrap();
+        assert_eq!(
+            editor.status_string,
+            " \"n\" at (1, 2) | token: Keyword1 | final state: Normal"
+        );
+        assert_eq!(editor.cursor, pos(0, 0));
+        let frame = editor.frame;
+        editor.handle_event(&hover(column + 1, 0)).unwrap();
+        assert_eq!(editor.frame, frame);
+
+        assert!(editor
+            .hover_info_at(Position {
+                x: column as usize + 3,
+                y: 1
+            })
+            .unwrap()
+            .ends_with("final state: MultiLineComment"));
+        // Past the end of a line, or over the line numbers
+        assert_eq!(
+            editor.hover_info_at(Position {
+                x: column as usize + 20,
+                y: 0
+            }),
+            None
+        );
+        assert_eq!(editor.hover_info_at(Position { x: 0, y: 0 }), None);
+    }
+
+    #[test]
+    fn test_normalize_unicode() {
+        let type_text = |editor: &mut Editor, text: &str| {
+            for c in text.chars() {
+                editor.execute(Command::InsertChar(c)).unwrap();
             }
-        } else {
-            // Just copy the current line
-            clipboard = self.buffer[self.cursor.y].to_string();
-        }
+        };
+        let mut plain = editor("");
+        type_text(&mut plain, "e\u{301}");
+        assert_eq!(plain.buffer.to_string(), "e\u{301}");
+
+        // Typed accents join their letter in one composed character
+        let mut editor = Editor::open_str("", 40, 10);
+        editor.config.normalize_unicode = true;
+        type_text(&mut editor, "cafe\u{301}\u{301}");
+        assert_eq!(editor.buffer.to_string(), "café\u{301}");
+        assert_eq!(snapshot(&editor), "café\u{301}|");
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nfd.txt");
+        fs::write(&path, "cafe\u{301}\n").unwrap();
+        editor.load_file(&path.to_string_lossy());
+        assert_eq!(editor.buffer.to_string(), "café\n");
+        assert!(editor.buffer.dirty);
+    }
 
-        terminal_clipboard::set_string(clipboard)?;
+    #[test]
+    fn test_keep_discarded() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut editor = Editor::open_str("", 40, 10);
+        editor.config.keep_discarded = true;
+        editor.discarded_dir = Some(dir.path().to_path_buf());
+        let kept = || discarded::list(dir.path()).len();
+
+        // Empty or saved buffers are not worth keeping
+        editor.keep_discarded(&editor.buffer);
+        editor.execute(Command::InsertChar('a')).unwrap();
+        editor.buffer.filename = Some("a.txt".to_string());
+        editor.keep_discarded(&editor.buffer);
+        assert_eq!(kept(), 0);
+
+        editor.buffer.filename = None;
+        editor.keep_discarded(&editor.buffer);
+        assert_eq!(kept(), 1);
+        editor.config.keep_discarded = false;
+        editor.keep_discarded(&editor.buffer);
+        assert_eq!(kept(), 1);
+    }
 
-        Ok(())
+    #[test]
+    fn test_about() {
+        let mut editor = editor("");
+        editor.config.syntax_dir = PathBuf::from("/nonexistent/syntax.d");
+        editor.buffer.syntax = Syntax::builtin("rs").unwrap();
+        let lines = editor.about_lines();
+        assert!(lines[0].contains(crate::build_info()));
+        assert!(crate::build_info().contains("tui"));
+        assert_eq!(lines[1], "Syntax: Rust");
+        assert_eq!(
+            lines[2],
+            "Syntax definitions: /nonexistent/syntax.d (missing, using the built-in ones)"
+        );
+        assert_eq!(lines[3], "Theme: none");
+        assert!(crate::version().starts_with(env!("CARGO_PKG_VERSION")));
     }
 
-    fn trigger_paste(&mut self) {
-        self.update_last_history_state();
-        self.dirty = true;
+    #[test]
+    fn test_reload_syntax() {
+        use crate::syntax::TokenType;
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "name=Test\nkeywords_1=foo\n").unwrap();
+        let mut editor = editor("foo bar");
+        editor.buffer.syntax = Syntax::from_file(file.path()).unwrap().0;
+        editor.buffer.update_syntax();
+        assert_eq!(editor.buffer.rows[0].syntax[0], TokenType::Keyword1);
+
+        fs::write(file.path(), "name=Test\nkeywords_1=bar\n").unwrap();
+        press(&mut editor, &["f5"]);
+        assert_eq!(editor.buffer.rows[0].syntax[0], TokenType::Normal);
+        assert_eq!(editor.buffer.rows[0].syntax[4], TokenType::Keyword1);
+
+        // A broken file keeps the previous definition
+        fs::write(file.path(), "name=Test\nkeywords_1=foo\noops\n").unwrap();
+        press(&mut editor, &["f5"]);
+        assert!(editor.toast.as_ref().unwrap().0.contains("line 3"));
+        assert_eq!(editor.buffer.rows[0].syntax[4], TokenType::Keyword1);
+    }
 
-        let clipboard = terminal_clipboard::get_string().unwrap_or_default();
+    #[test]
+    fn test_parse_code_points() {
+        assert_eq!(parse_code_points("2192").unwrap(), "→");
+        assert_eq!(parse_code_points(" U+1F600  u+4e2d 41 ").unwrap(), "😀中A");
+        assert_eq!(
+            parse_code_points("41 D800").unwrap_err(),
+            "Invalid code point: D800"
+        );
+        assert!(parse_code_points("110000").is_err());
+        assert!(parse_code_points("+41").is_err());
+        assert!(parse_code_points("a").is_err());
+    }
 
-        if clipboard.is_empty() {
-            return;
+    #[test]
+    fn test_goto() {
+        assert_eq!(parse_goto(" 12 "), Ok((Some(12), None)));
+        assert_eq!(parse_goto("3:5"), Ok((Some(3), Some(Column::Visual(5)))));
+        assert_eq!(parse_goto(":v5"), Ok((None, Some(Column::Visual(5)))));
+        assert_eq!(parse_goto(":c5"), Ok((None, Some(Column::Char(5)))));
+        assert_eq!(parse_goto("2:b40"), Ok((Some(2), Some(Column::Byte(40)))));
+        assert_eq!(parse_goto("0").unwrap_err(), "Invalid position: 0");
+        for input in ["", ":", "x", "1:x5", "1:c", "1:中"] {
+            assert!(parse_goto(input).is_err(), "{input:?}");
         }
 
-        if let Some((begin, end)) = self.get_selection() {
-            self.delete_selection_range(begin, end);
+        // The same spot after the two wide characters, in each of the three ways
+        let mut editor = Editor::open_str("one\n中文ab\nthree", 100, 10);
+        for column in [Column::Visual(5), Column::Char(3), Column::Byte(7)] {
+            editor.goto(Some(2), Some(column));
+            assert_eq!(editor.cursor, pos(2, 1));
         }
-        let lines = clipboard
-            .split('\n')
-            .map(|line| line.strip_suffix('\r').unwrap_or(line))
-            .collect::<Vec<&str>>();
-        let line_count = lines.len();
-        if line_count == 1 {
-            // Paste to the current line
-            let middle: Row = lines[0].into();
-            let (left, right) = self.buffer[self.cursor.y].rope.split_at(self.cursor.x);
-            self.buffer[self.cursor.y] = Row::from([left, &middle.rope, right].concat());
-            self.cursor.x += middle.len();
-        } else {
-            let current_line = self.buffer[self.cursor.y].rope.clone();
-            let (left, right) = current_line.split_at(self.cursor.x);
-            for (i, &line) in lines.iter().enumerate() {
-                let line: Row = line.into();
-                if i == 0 {
-                    self.buffer[self.cursor.y] = Row::from([left, &line.rope].concat());
-                } else if i == line_count - 1 {
-                    self.buffer
-                        .insert(self.cursor.y + i, Row::from([&line.rope, right].concat()));
-                    self.cursor.x = line.len();
-                    self.cursor.y += i;
+        editor.goto(Some(9), None);
+        assert_eq!(editor.cursor, pos(0, 2));
+        editor.goto(None, Some(Column::Byte(2)));
+        assert_eq!(editor.cursor, pos(1, 2));
+
+        editor.goto(Some(2), Some(Column::Char(4)));
+        editor.refresh(false).unwrap();
+        assert!(editor.terminal.snapshot()[8].contains("Ln 2, Col 6  "));
+        editor.config.show_offset = true;
+        editor.refresh(false).unwrap();
+        assert!(editor.terminal.snapshot()[8].contains("Ln 2, Col 6 (char 4, byte 8)"));
+    }
+
+    #[test]
+    fn test_vim_mode() {
+        use crossterm::event::KeyEvent;
+
+        fn type_keys(editor: &mut Editor, keys: &str) {
+            for c in keys.chars() {
+                let code = if c == '\x1b' {
+                    KeyCode::Esc
                 } else {
-                    self.buffer.insert(self.cursor.y + i, line);
-                }
+                    KeyCode::Char(c)
+                };
+                let event = Event::Key(KeyEvent::new(code, KeyModifiers::NONE));
+                assert!(editor.handle_event(&event).unwrap());
             }
         }
 
-        self.create_history();
+        let mut editor = Editor::open_str("one\ntwo\nthree", 80, 24);
+        editor.modal = Some(Modal::default());
+        type_keys(&mut editor, "jdd");
+        type_keys(&mut editor, "2lix\x1b");
+        assert_eq!(snapshot(&editor), "one\nthx|ree");
+        assert!(editor.terminal.snapshot()[22].contains("-- NORMAL --"));
+
+        type_keys(&mut editor, "0vlly");
+        assert_eq!(snapshot(&editor), "one\n|thxree");
+        assert_eq!(editor.clipboard.as_deref(), Some("th"));
+        type_keys(&mut editor, "v$d");
+        assert_eq!(snapshot(&editor), "one\n|");
+        type_keys(&mut editor, "u");
+        assert_eq!(snapshot(&editor), "one\n^thxree|");
     }
 
-    fn update_syntax(&mut self) {
-        let mut state = TokenState::default();
-        for line in self.buffer.iter_mut() {
-            line.update_syntax(&self.syntax, &mut state);
-        }
+    #[test]
+    fn test_reindent() {
+        let mut editor = editor("a\n   b\n c\nd");
+        press(
+            &mut editor,
+            &["down", "end", "shift+down", "shift+down", "alt+i"],
+        );
+        assert_eq!(snapshot(&editor), "a\n    b^\nc\nd");
     }
 
-    fn on_exit(&mut self) -> Result<(), Error> {
-        self.terminal.cleanup()?;
+    #[test]
+    fn test_paste_reindent() {
+        let mut editor = Editor::open_str("fn f() {\n    a\n    \n}", 40, 10);
+        let copied = "if x {\n\t\ty();\n\t}";
+        editor.registers.insert('a', copied.to_string());
+        press(&mut editor, &["down", "down", "end"]);
+        editor.register = Some('a');
+        press(&mut editor, &["alt+shift+v"]);
+        assert_eq!(
+            snapshot(&editor),
+            "fn f() {\n    a\n    if x {\n        y();\n    }|\n}"
+        );
+        assert_eq!(editor.registers[&'a'], copied);
+
+        // All in one step
+        press(&mut editor, &["ctrl+z"]);
+        assert_eq!(editor.buffer.to_string(), "fn f() {\n    a\n    \n}");
+
+        // A plain paste only converts the tabs, if asked to
+        editor.config.paste_convert_indent = true;
+        editor.register = Some('a');
+        press(&mut editor, &["ctrl+v"]);
+        assert_eq!(
+            editor.buffer.to_string(),
+            "fn f() {\n    a\n    if x {\n        y();\n    }\n}"
+        );
+    }
 
-        Ok(())
+    #[test]
+    fn test_folding() {
+        let text = "// a\nfn a() {\n    one\n\n    two\n}\nend";
+        let mut editor = Editor::open_str(text, 40, 10);
+        press(&mut editor, &["down", "alt+-"]);
+        assert_eq!(editor.folds, vec![2..5]);
+        editor.refresh(true).unwrap();
+        let screen = editor.terminal.snapshot();
+        assert!(screen[1].ends_with(" fn a() {"));
+        assert!(screen[2].contains(t!("fold.placeholder", 3).as_str()));
+        assert!(screen[3].starts_with("  6 }"));
+        assert!(screen[4].starts_with("  7 end"));
+
+        // The cursor steps over the hidden lines
+        press(&mut editor, &["down"]);
+        assert_eq!(editor.cursor, pos(0, 5));
+        press(&mut editor, &["left"]);
+        assert_eq!(editor.cursor, pos(8, 1));
+        press(&mut editor, &["alt+="]);
+        assert!(editor.folds.is_empty());
+        press(&mut editor, &["alt+-"]);
+        assert_eq!(editor.buffer.to_string(), text);
+
+        // Editing the first line keeps the fold, and lines added above move it
+        press(&mut editor, &["x", "up", "end", "enter"]);
+        assert_eq!(editor.folds, vec![3..6]);
+        // Deleting across it unfolds it
+        press(&mut editor, &["down", "home", "shift+down", "ctrl+x"]);
+        assert_eq!(snapshot(&editor), "// a\n\n|}\nend");
+        assert!(editor.folds.is_empty());
     }
 
-    /// Attempts to save the file. Returns `true` if the file was saved successfully, `false` otherwise.
-    fn try_save_file(&mut self, is_save_as: bool) -> Result<bool, Error> {
-        self.update_last_history_state();
+    #[test]
+    fn test_follow() {
+        use std::io::Write;
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let text = "line\n".repeat(10);
+        file.write_all(text.as_bytes()).unwrap();
+        let mut editor = Editor::open_str(&text, 40, 10);
+        editor.follow = Some(Follow::new(file.path(), &editor.buffer));
+        press(&mut editor, &["ctrl+end"]);
+        editor.refresh(true).unwrap();
+        assert!(editor.following());
+
+        // Only edits are refused
+        press(&mut editor, &["x", "ctrl+v", "shift+up"]);
+        assert_eq!(editor.buffer.to_string(), text);
+        assert!(!editor.following());
+
+        // New lines move the view along until it is scrolled up
+        press(&mut editor, &["ctrl+end"]);
+        file.write_all(b"new\n").unwrap();
+        assert_eq!(editor.follow_file(), Some(true));
+        assert_eq!(editor.cursor, pos(0, 11));
+        editor.refresh(true).unwrap();
+        press(&mut editor, &["ctrl+up", "ctrl+up", "ctrl+up", "ctrl+up"]);
+        assert!(!editor.following());
+        file.write_all(b"more\n").unwrap();
+        editor.follow_checked = None;
+        assert_eq!(editor.follow_file(), Some(false));
+        assert_eq!(editor.cursor, pos(0, 11));
+        assert_eq!(editor.buffer.line_count(), 13);
+    }
 
-        if is_save_as || self.filename.is_none() {
-            if let Some(ref filename) = Tui::prompt_filename(self)? {
-                if Path::new(filename).is_dir() {
-                    Tui::alert(
-                        self,
-                        "错误".to_string(),
-                        "输入的文件名是一个目录".to_string(),
-                    )?;
-                    return Ok(false);
-                }
+    #[test]
+    fn test_search() {
+        let mut editor = editor("foo 1\nfoo 2\nbar foo");
+        let type_keys = |editor: &mut Editor, keys: &[&str]| {
+            for key in keys {
+                let (modifiers, code, _) = keymap::parse_chord(key).unwrap();
+                let event = Event::Key(crossterm::event::KeyEvent::new(code, modifiers));
+                editor.handle_search_event(&event).unwrap();
+            }
+        };
+
+        // The first match is the one at or after the cursor
+        press(&mut editor, &["down", "right"]);
+        editor.open_search(false);
+        type_keys(&mut editor, &["f", "o", "o"]);
+        assert_eq!(snapshot(&editor), "foo 1\nfoo 2\nbar ^foo|");
+        assert_eq!(editor.search_result.len(), 3);
+        assert_eq!(editor.line_matches(1), &[(pos(0, 1), pos(3, 1))]);
+
+        // Going past the last match wraps around to the first
+        type_keys(&mut editor, &["enter"]);
+        assert_eq!(snapshot(&editor), "^foo| 1\nfoo 2\nbar foo");
+        assert_eq!(editor.toast.as_ref().unwrap().0, t!("search.wrapped"));
+
+        // Shift+Enter turns around and goes up from then on
+        type_keys(&mut editor, &["shift+enter"]);
+        assert_eq!(snapshot(&editor), "foo 1\nfoo 2\nbar ^foo|");
+        type_keys(&mut editor, &["enter", "down"]);
+        assert_eq!(snapshot(&editor), "foo 1\nfoo 2\nbar ^foo|");
+        type_keys(&mut editor, &["esc"]);
+        assert!(!editor.is_searching);
+
+        // Searching in reverse starts from the last match before the cursor
+        editor.anchor = None;
+        editor.cursor = pos(2, 1);
+        editor.open_search(true);
+        assert!(editor.search_reverse);
+        assert_eq!(snapshot(&editor), "foo 1\n^foo| 2\nbar foo");
+
+        // Edits leave the matches behind
+        press(&mut editor, &["a"]);
+        assert!(editor.search_result.is_empty());
+    }
 
-                if Path::new(filename).exists() {
-                    if let Some(false) = Tui::confirm_overwrite(self, filename)? {
-                        return Ok(false);
+    #[test]
+    fn test_cancel() {
+        let mut editor = editor("fn");
+        editor
+            .buffer
+            .syntax
+            .snippets
+            .insert("fn".into(), "fn ${1:name}()".into());
+        editor.search_result = vec![(pos(0, 0), pos(0, 1))];
+        press(&mut editor, &["end", "tab"]);
+        assert_eq!(snapshot(&editor), "fn ^name|()");
+
+        // Each press clears one layer: the snippet, the selection, the search results
+        press(&mut editor, &["esc"]);
+        assert_eq!(editor.transient(), Some(Transient::Selection));
+        assert_eq!(snapshot(&editor), "fn ^name|()");
+        press(&mut editor, &["esc"]);
+        assert_eq!(editor.transient(), Some(Transient::SearchResults));
+        assert_eq!(snapshot(&editor), "fn name|()");
+        press(&mut editor, &["esc"]);
+        assert_eq!(editor.transient(), None);
+        assert!(editor.search_result.is_empty());
+
+        // Then it quits, asking first if there are unsaved changes
+        editor.buffer.dirty = false;
+        let chord = keymap::parse_chord("esc").unwrap();
+        let command = editor.config.keymap.translate(chord).unwrap();
+        assert_eq!(command, Command::Cancel);
+        assert!(editor.execute(command).unwrap());
+    }
+
+    #[test]
+    fn test_selection_over_line_break() {
+        // Selections from the start of the first line to before its last character, to
+        // its end, and one past its end, which takes in the line break
+        let ends = [pos(1, 0), pos(2, 0), pos(3, 0)];
+        let edits = [
+            ("x", ["xb\ncd", "x\ncd", "xcd"]),
+            ("delete", ["b\ncd", "\ncd", "cd"]),
+            ("backspace", ["b\ncd", "\ncd", "cd"]),
+            ("paste", ["P\nQb\ncd", "P\nQ\ncd", "P\nQcd"]),
+        ];
+        for (name, expected) in edits {
+            for (end, expected) in ends.into_iter().zip(expected) {
+                // Whichever end the cursor is at
+                for (anchor, cursor) in [(pos(0, 0), end), (end, pos(0, 0))] {
+                    let mut editor = editor("ab\ncd");
+                    (editor.anchor, editor.cursor) = (Some(anchor), cursor);
+                    match name {
+                        "paste" => editor.paste_text("P\nQ".into(), false, false),
+                        key => press(&mut editor, &[key]),
                     }
+                    assert_eq!(editor.buffer.to_string(), expected, "{name} to {end:?}");
                 }
-
-                self.filename = Some(filename.to_string());
             }
         }
 
-        if let Some(filename) = self.filename.clone() {
-            if let Err(err) = std::fs::write(
-                filename,
-                self.buffer
-                    .iter()
-                    .map(|line| line.to_string())
-                    .collect::<Vec<_>>()
-                    .join(if self.is_crlf { "\r\n" } else { "\n" }),
-            ) {
-                Tui::alert(
-                    self,
-                    "保存失败".to_string(),
-                    "错误: ".to_string() + Error::get_error_message(&err),
-                )?;
-                return Ok(false);
-            }
+        // The line break shows as selected
+        let mut editor = Editor::open_str("ab\ncd", 40, 10);
+        (editor.anchor, editor.cursor) = (Some(pos(0, 0)), pos(3, 0));
+        editor.render_to_buffer();
+        let background = editor.terminal.style_at(pos(4 + 2, 0)).background_color;
+        assert_eq!(background, Some(style::background_selected()));
+    }
 
-            self.dirty = false;
+    #[test]
+    fn test_escape_after_search() {
+        let mut editor = editor("foo 1\nfoo 2");
+        editor.buffer.dirty = false;
+        editor.open_search(false);
+        for key in ["f", "o", "o", "esc"] {
+            let (modifiers, code, _) = keymap::parse_chord(key).unwrap();
+            let event = Event::Key(crossterm::event::KeyEvent::new(code, modifiers));
+            editor.handle_search_event(&event).unwrap();
+        }
+        // Esc only closed the search box, putting the cursor back where it was
+        assert!(!editor.is_searching);
+        assert_eq!(snapshot(&editor), "|foo 1\nfoo 2");
+
+        // The same press repeating does nothing, for as long as it keeps repeating
+        send(&mut editor, &["esc"]);
+        std::thread::sleep(ESCAPE_COOLDOWN / 2);
+        send(&mut editor, &["esc"]);
+        std::thread::sleep(ESCAPE_COOLDOWN / 2);
+        send(&mut editor, &["esc"]);
+        assert_eq!(editor.transient(), Some(Transient::SearchResults));
+
+        // Later presses clear the matches, then quit
+        editor.search_closed = Some(Instant::now() - ESCAPE_COOLDOWN);
+        send(&mut editor, &["esc"]);
+        assert_eq!(editor.transient(), None);
+        let (modifiers, code, _) = keymap::parse_chord("esc").unwrap();
+        let event = Event::Key(crossterm::event::KeyEvent::new(code, modifiers));
+        assert!(!editor.handle_event(&event).unwrap());
+    }
 
-            self.create_history();
+    #[test]
+    fn test_line_operations() {
+        let mut editor = editor("a\n\n\n\x1b[1mb\x1b[0m\n\nc");
+        press(&mut editor, &["down", "down"]);
+        editor.apply_line_operation(LineOperation::SqueezeBlankLines);
+        assert_eq!(snapshot(&editor), "a\n\n|\x1b[1mb\x1b[0m\n\nc");
+        assert_eq!(editor.toast.as_ref().unwrap().0, t!("lines.removed", 1));
+
+        editor.apply_line_operation(LineOperation::StripAnsi);
+        assert_eq!(snapshot(&editor), "a\n\n|b\n\nc");
+        editor.apply_line_operation(LineOperation::RemoveBlankLines);
+        assert_eq!(snapshot(&editor), "a\n|b\nc");
+
+        // Each undoes in one step
+        press(&mut editor, &["ctrl+z"]);
+        assert_eq!(editor.buffer.to_string(), "a\n\nb\n\nc");
+        press(&mut editor, &["ctrl+z"]);
+        assert_eq!(editor.buffer.to_string(), "a\n\n\x1b[1mb\x1b[0m\n\nc");
+    }
 
-            Ok(true)
-        } else {
-            Ok(false)
-        }
+    #[test]
+    fn test_transform_selection() {
+        let mut editor = editor("x 你好 y");
+        press(
+            &mut editor,
+            &["right", "right", "shift+right", "shift+right"],
+        );
+        editor.transform_selection(Transform::HexEncode);
+        assert_eq!(snapshot(&editor), "x ^e4 bd a0 e5 a5 bd| y");
+
+        // A hex dump longer than a line is split into rows
+        press(&mut editor, &["ctrl+a"]);
+        editor.transform_selection(Transform::Base64Encode);
+        editor.transform_selection(Transform::HexEncode);
+        assert_eq!(editor.buffer.line_count(), 2);
+
+        // Invalid input leaves the buffer alone
+        press(&mut editor, &["ctrl+a"]);
+        let text = editor.buffer.to_string();
+        editor.transform_selection(Transform::Base64Decode);
+        assert_eq!(editor.buffer.to_string(), text);
+        assert_eq!(editor.toast.as_ref().unwrap().0, t!("transform.not_utf8"));
+
+        // Each transform undoes in one step
+        editor.transform_selection(Transform::HexDecode);
+        editor.transform_selection(Transform::Base64Decode);
+        assert_eq!(editor.buffer.to_string(), "x e4 bd a0 e5 a5 bd y");
+        press(&mut editor, &["ctrl+z"]);
+        assert_eq!(editor.buffer.to_string(), "eCBlNCBiZCBhMCBlNSBhNSBiZCB5");
     }
 
-    fn toggle_search_mode(&mut self) -> Result<(), Error> {
-        self.is_searching = true;
+    #[test]
+    fn test_reformat_json() {
+        let mut editor =
+            editor("{\"a\": [1, {\"中\": 10000000000000000000001}], \"b\": \"\\n\"}\n");
+        editor.cursor = pos(23, 0);
+        press(&mut editor, &["alt+j"]);
+        assert_eq!(
+            snapshot(&editor),
+            "{\n    \"a\": [\n        1,\n        {\n            \"中\": |10000000000000000000001\n        }\n    ],\n    \"b\": \"\\n\"\n}\n"
+        );
+        press(&mut editor, &["alt+shift+j"]);
+        assert_eq!(
+            snapshot(&editor),
+            "{\"a\":[1,{\"中\":|10000000000000000000001}],\"b\":\"\\n\"}\n"
+        );
 
-        // let anchor = self.cursor;
+        // A selection is reformatted on its own, in one step
+        editor = Editor::open_str("x = [1,2] + {\"a\":3};", 80, 24);
+        press(&mut editor, &["right"; 4]);
+        press(&mut editor, &["shift+right"; 5]);
+        editor.config.tab_width = 2;
+        press(&mut editor, &["alt+j"]);
+        assert_eq!(snapshot(&editor), "x = |[\n  1,\n  2\n] + {\"a\":3};");
+        press(&mut editor, &["ctrl+z"]);
+        assert_eq!(editor.buffer.to_string(), "x = [1,2] + {\"a\":3};");
+
+        assert_eq!(position_after(pos(4, 2), "ab"), pos(6, 2));
+        assert_eq!(position_after(pos(4, 2), "ab\n中文"), pos(2, 3));
+    }
 
-        if self.check_minimum_window_size() {
-            self.render_to_buffer();
-        }
+    #[test]
+    fn test_long_lines() {
+        let mut editor = Editor::open_str("abcdef\nab\n中文中文\nabc", 40, 10);
+        press(&mut editor, &["alt+l"]);
+        assert_eq!(
+            editor.toast.as_ref().unwrap().0,
+            "max_line_length is not set"
+        );
 
-        let mut last_input = String::new();
-        loop {
-            if event::poll(std::time::Duration::from_millis(25))? {
-                // let event = event::read()?;
-                // match self.search.handle_event(&event)? {
-                //     Some(true) => {}
-                //     Some(false) => {
-                //         self.is_searching = false;
-                //         return Ok(());
-                //     }
-                //     None => {
-                //         if let Event::Mouse(event) = event {
-                //             match event.kind {
-                //                 MouseEventKind::Down(MouseButton::Left) => {
-                //                     self.cursor = event.into();
-                //                     self.anchor = Some(self.cursor);
-                //                 }
-                //                 MouseEventKind::Drag(MouseButton::Left) => {
-                //                     self.cursor = event.into();
-                //                     self.anchor = Some(self.cursor);
-                //                 }
-                //                 _ => {}
-                //             }
-                //         }
-                //     }
-                // }
-            }
-
-            let input = self.search.buffer.to_string();
-            if input != last_input {
-                self.search_result.clear();
-                for line in self.buffer.iter().map(|line| line.to_string()) {
-                    let mut i = 0;
-                    while let Some(pos) = line[i..].find(&input) {
-                        self.search_result
-                            .push((line[..i].graphemes(true).count(), self.cursor.y).into());
-                        i = pos + input.len();
+        editor.config.max_line_length = Some(4);
+        press(&mut editor, &["alt+l"]);
+        assert_eq!(editor.cursor, pos(2, 2));
+        press(&mut editor, &["alt+l"]);
+        assert_eq!(editor.cursor, pos(4, 0));
+        press(&mut editor, &["alt+l"]);
+        assert_eq!(editor.cursor, pos(2, 2));
+
+        editor.refresh(false).unwrap();
+        assert!(editor.terminal.snapshot()[8].contains("2 long lines"));
+        let bg = |x: usize| editor.terminal.style_at(pos(4 + x, 0)).background_color;
+        assert_eq!(bg(3), Some(style::background()));
+        assert_eq!(bg(4), Some(style::background_long_line()));
+    }
+
+    #[test]
+    fn test_anomalies() {
+        let text = "a\u{a0}b\nplain\n\u{202e}x\u{200b}";
+        let mut editor = Editor::open_str(text, 40, 10);
+        let next = |editor: &mut Editor, at: Position| {
+            press(editor, &["alt+a"]);
+            assert_eq!((editor.anchor, editor.cursor.x), (Some(at), at.x + 1));
+            editor.toast.take().unwrap().0
+        };
+        assert_eq!(next(&mut editor, pos(1, 0)), "U+00A0 NO-BREAK SPACE");
+        assert_eq!(
+            next(&mut editor, pos(0, 2)),
+            "U+202E RIGHT-TO-LEFT OVERRIDE"
+        );
+        assert_eq!(next(&mut editor, pos(2, 2)), "U+200B ZERO WIDTH SPACE");
+        next(&mut editor, pos(1, 0));
+
+        editor.anchor = None;
+        editor.refresh(false).unwrap();
+        assert!(editor.terminal.snapshot()[8].contains("3 invisible chars"));
+        let bg = |x: usize| editor.terminal.style_at(pos(4 + x, 0)).background_color;
+        assert_eq!(bg(0), Some(style::background()));
+        assert_eq!(bg(1), Some(style::background_anomaly()));
+
+        // The bidirectional control stays
+        editor.cursor = pos(3, 2);
+        assert_eq!(editor.cleanable_anomalies(), 2);
+        editor.clean_anomalies();
+        assert_eq!(editor.buffer.to_string(), "a b\nplain\n\u{202e}x");
+        assert_eq!(editor.cursor, pos(2, 2));
+        assert_eq!(editor.buffer.anomaly_count(), 1);
+        press(&mut editor, &["ctrl+z"]);
+        assert_eq!(editor.buffer.to_string(), text);
+    }
+
+    #[test]
+    fn test_rollback() {
+        let mut editor = Editor::open_str("one\ntwo", 40, 10);
+        editor.cursor = pos(3, 0);
+        editor.update_last_history_state();
+        editor.buffer.history.begin_transaction();
+        press(&mut editor, &["enter", "x", "down", "ctrl+k"]);
+        assert_ne!(editor.buffer.to_string(), "one\ntwo");
+
+        editor.rollback();
+        assert_eq!(editor.buffer.to_string(), "one\ntwo");
+        assert_eq!(editor.cursor, pos(3, 0));
+        assert!(!editor.buffer.dirty);
+        assert!(!editor.buffer.history.in_transaction());
+        press(&mut editor, &["ctrl+z"]);
+        assert_eq!(editor.buffer.to_string(), "one\ntwo");
+    }
+
+    #[test]
+    fn test_undo_to_loaded_file() {
+        let scripts: [&[&str]; 6] = [
+            &["ctrl+a", "TYPE", "backspace", "backspace", "TYPE", "enter"],
+            &["ctrl+a", "ctrl+x", "ctrl+v", "ctrl+v"],
+            &["ctrl+end", "enter", "TYPE", "ctrl+x", "ctrl+x", "ctrl+x"],
+            &["down", "ctrl+k", "ctrl+k", "ctrl+k", "delete", "delete"],
+            &[
+                "ctrl+end",
+                "backspace",
+                "backspace",
+                "backspace",
+                "backspace",
+            ],
+            &[
+                "alt+down",
+                "alt+shift+down",
+                "ctrl+end",
+                "alt+up",
+                "ctrl+z",
+                "TYPE",
+            ],
+        ];
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        for text in [
+            "one\r\ntwo\r\n",
+            "one\ntwo",
+            "one\ntwo\n",
+            "",
+            "\n",
+            "a\r\nb\nc\n\n",
+        ] {
+            for script in scripts {
+                fs::write(&path, text).unwrap();
+                // As `init` loads a file
+                let mut editor = Editor {
+                    terminal: Terminal::headless(40, 10),
+                    ..Default::default()
+                };
+                editor.load_file(&path.to_string_lossy());
+                let (rows, viewbox) = (&editor.buffer.rows, editor.viewbox);
+                (editor.buffer.history).push_state(rows, viewbox, editor.cursor, None);
+                editor.buffer.mark_saved();
+
+                for &key in script {
+                    match key {
+                        "TYPE" => {
+                            editor.execute(Command::InsertChar('x')).unwrap();
+                            editor.execute(Command::InsertChar('y')).unwrap();
+                        }
+                        _ => press(&mut editor, &[key]),
                     }
                 }
+                assert!(editor.buffer.dirty);
+                while editor.buffer.history.undo_depth() > 0 {
+                    press(&mut editor, &["ctrl+z"]);
+                }
+                assert_eq!(editor.buffer.to_string(), text, "{text:?} {script:?}");
+                assert!(!editor.buffer.dirty);
             }
+        }
+    }
 
-            if !self.check_minimum_window_size() {
-                continue;
-            }
+    #[test]
+    fn test_open_then_render() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        fs::write(&path, "first\nsecond").unwrap();
+
+        let mut editor = Editor::default();
+        let name = path.to_string_lossy().into_owned();
+        editor.open(&Some(name.clone())).unwrap();
+        assert_eq!(editor.buffer.filename, Some(name));
+        assert!(!editor.buffer.dirty);
+
+        editor.insert_text(pos(0, 0), "zeroth\n");
+        editor.delete_range(pos(5, 2), pos(6, 2));
+        assert!(editor.buffer.dirty);
+        editor.render_to_buffer();
+        let screen = editor.terminal.snapshot();
+        assert_eq!(screen[0].trim_end(), "  1 zeroth");
+        assert_eq!(screen[2].trim_end(), "  3 secon");
+        assert!(screen[22].contains("notes.txt"));
+
+        // Undone like any other edit
+        press(&mut editor, &["ctrl+z", "ctrl+z"]);
+        assert_eq!(editor.buffer.to_string(), "first\nsecond");
+    }
+
+    #[test]
+    fn test_open_puts_off_prompts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("big.txt");
+        fs::write(&path, "big").unwrap();
+
+        let mut editor = Editor::default();
+        editor.config.theme = Some(dir.path().join("missing.ini"));
+        editor.config.large_file_mb = Some(0);
+        let name = path.to_string_lossy().into_owned();
+        editor.open(&Some(name.clone())).unwrap();
+        assert!(matches!(editor.prompts[0], Prompt::Alert(..)));
+        assert_eq!(editor.prompts[1], Prompt::LargeFile(name));
+        assert_eq!(editor.buffer.to_string(), "");
+        assert!(!editor.buffer.dirty);
+        // Nothing is settled until the file is loaded
+        assert!(editor.buffer.history.is_empty());
+    }
+
+    #[test]
+    fn test_reload_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "one\r\ntwo\nthree").unwrap();
+        let name = path.to_string_lossy();
+        let mut editor = Editor::open_str("one\r\ntwo\nthree", 40, 10);
+        editor.buffer.history.coalesce_window = Duration::from_secs(3600);
+        editor.buffer.filename = Some(name.to_string());
+        press(&mut editor, &["ctrl+end", "enter", "y"]);
+        assert!(editor.buffer.dirty);
+
+        // Written elsewhere meanwhile
+        fs::write(&path, "one\ntwo").unwrap();
+        editor.reload_file(&name).unwrap();
+        assert_eq!(editor.buffer.to_string(), "one\ntwo");
+        assert_eq!(editor.cursor, pos(3, 1));
+        assert!(!editor.buffer.dirty);
+
+        // The revert can be undone, and redone back to a clean buffer
+        press(&mut editor, &["ctrl+z"]);
+        assert_eq!(editor.buffer.to_string(), "one\r\ntwo\nthree\ny");
+        assert!(editor.buffer.dirty);
+        press(&mut editor, &["ctrl+y"]);
+        assert_eq!(editor.buffer.to_string(), "one\ntwo");
+        assert!(!editor.buffer.dirty);
+
+        // An edit right after the revert is undone on its own
+        editor.reload_file(&name).unwrap();
+        press(&mut editor, &["!"]);
+        assert_eq!(editor.buffer.to_string(), "one\ntwo!");
+        press(&mut editor, &["ctrl+z"]);
+        assert_eq!(editor.buffer.to_string(), "one\ntwo");
+        assert!(!editor.buffer.dirty);
+
+        assert!(editor.reload_file("missing.txt").is_err());
+        assert_eq!(editor.buffer.to_string(), "one\ntwo");
+    }
+
+    #[test]
+    fn test_preview_read_only() {
+        let mut editor = Editor::open_str("one\ntwo", 60, 10);
+        editor.buffer.preview = Some(50 << 20);
+        press(&mut editor, &["ctrl+k", "ctrl+s"]);
+        assert_eq!(
+            editor.toast.as_ref().unwrap().0,
+            "The file is a read-only preview and can't be edited or saved"
+        );
+        assert_eq!(editor.buffer.to_string(), "one\ntwo");
+        assert!(!editor.buffer.dirty);
+        editor.refresh(false).unwrap();
+        let snapshot = editor.terminal.snapshot();
+        assert!(snapshot[8].contains("Untitled-1 (preview of first 50 MB)"));
+    }
+
+    #[test]
+    fn test_bracket_pairs() {
+        let mut editor = Editor::open_str("  if x {}\nf(); \"()\"", 40, 10);
+        editor.buffer.syntax = Syntax::builtin("rs").unwrap();
+        editor.buffer.update_syntax();
+        editor.config.tab_width = 4;
+        editor.cursor = pos(8, 0);
+        press(&mut editor, &["enter"]);
+        assert_eq!(snapshot(&editor), "  if x {\n      |\n  }\nf(); \"()\"");
+        press(&mut editor, &["ctrl+z"]);
+        assert_eq!(editor.buffer.to_string(), "  if x {}\nf(); \"()\"");
+
+        editor.cursor = pos(2, 1);
+        press(&mut editor, &["backspace"]);
+        assert_eq!(snapshot(&editor), "  if x {}\nf|; \"()\"");
+        press(&mut editor, &["ctrl+z"]);
+        assert_eq!(editor.buffer.to_string(), "  if x {}\nf(); \"()\"");
+
+        // Not inside a string
+        editor.buffer.update_syntax();
+        editor.cursor = pos(7, 1);
+        press(&mut editor, &["backspace", "enter"]);
+        assert_eq!(snapshot(&editor), "  if x {}\nf(); \"\n|)\"");
+    }
 
-            self.render_to_buffer();
+    #[test]
+    fn test_transpose() {
+        let mut editor = Editor::open_str("teh 中a\nfoo bar", 40, 10);
+        editor.cursor = pos(2, 0);
+        press(&mut editor, &["ctrl+t"]);
+        assert_eq!(snapshot(&editor), "the| 中a\nfoo bar");
+        editor.cursor = pos(6, 0);
+        press(&mut editor, &["ctrl+t"]);
+        assert_eq!(snapshot(&editor), "the a中|\nfoo bar");
+        press(&mut editor, &["ctrl+z"]);
+        assert_eq!(editor.buffer.to_string(), "the 中a\nfoo bar");
+
+        editor.cursor = pos(0, 1);
+        press(&mut editor, &["alt+t"]);
+        assert_eq!(snapshot(&editor), "the 中a\nbar foo|");
+        press(&mut editor, &["alt+t"]);
+        assert_eq!(editor.toast.as_ref().unwrap().0, "Nothing to swap here");
+        assert_eq!(editor.buffer.to_string(), "the 中a\nbar foo");
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut editor = Editor::open_str("one two\nthree", 40, 10);
+        send(&mut editor, &["shift+right", "shift+right", "shift+right"]);
+        send(&mut editor, &["alt+'", "a", "ctrl+c"]);
+        assert_eq!(editor.toast.as_ref().unwrap().0, "Copied to register a");
+        assert_eq!(editor.clipboard, None);
+
+        editor.anchor = None;
+        editor.cursor = pos(2, 1);
+        send(&mut editor, &["alt+'", "B", "ctrl+x"]);
+        assert_eq!(editor.buffer.to_string(), "one two");
+        assert_eq!(editor.registers[&'b'], "three\n");
+
+        editor.cursor = pos(7, 0);
+        send(&mut editor, &["alt+'", "a", "ctrl+v"]);
+        assert_eq!(snapshot(&editor), "one twoone|");
+        send(&mut editor, &["ctrl+z"]);
+        assert_eq!(editor.buffer.to_string(), "one two");
+
+        // Esc gives up on naming a register
+        send(&mut editor, &["alt+'", "esc"]);
+        assert_eq!(editor.register, None);
+        send(&mut editor, &["alt+'", "c", "ctrl+v"]);
+        assert_eq!(editor.toast.as_ref().unwrap().0, "Register c is empty");
+        assert_eq!(editor.buffer.to_string(), "one two");
+    }
+
+    #[test]
+    fn test_count_occurrences() {
+        let mut editor = editor("foo Foo\nbar foo\n\nfoobar");
+        press(&mut editor, &["alt+n"]);
+        assert_eq!(
+            editor.toast.as_ref().unwrap().0,
+            "foo: 3 matches on 3 lines"
+        );
+        assert_eq!(editor.cursor, pos(0, 0));
+
+        editor.config.search_ignore_case = true;
+        press(&mut editor, &["shift+right", "shift+right", "alt+n"]);
+        assert_eq!(editor.toast.as_ref().unwrap().0, "fo: 4 matches on 3 lines");
+        assert_eq!(snapshot(&editor).lines().next(), Some("^fo|o Foo"));
+
+        let rows = &editor.buffer.rows;
+        assert_eq!(
+            count_matches(rows, "foo", false, Duration::ZERO),
+            (1, 1, false)
+        );
+        assert_eq!(
+            count_matches(rows, "bar", false, Duration::MAX),
+            (2, 2, true)
+        );
+    }
+
+    #[test]
+    fn test_clean_path() {
+        let clean = |path: &str| clean_path(Path::new(path));
+        assert_eq!(clean("./a/./b/../c.txt"), Path::new("a/c.txt"));
+        assert_eq!(clean("../x/../y"), Path::new("../y"));
+        assert_eq!(clean("/srv/../etc/hosts"), Path::new("/etc/hosts"));
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(
+            clean_path(&cwd.join("src/../notes.md")),
+            Path::new("notes.md")
+        );
 
-            last_input = input;
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file.txt");
+        fs::write(&file, "").unwrap();
+        let sub = dir.path().join("sub");
+        fs::create_dir(&sub).unwrap();
+        assert!(same_file(&file, &sub.join("../file.txt")));
+        assert!(!same_file(&file, &sub));
+        #[cfg(unix)]
+        {
+            let link = dir.path().join("link.txt");
+            std::os::unix::fs::symlink(&file, &link).unwrap();
+            assert!(same_file(&file, &link));
         }
+        // Files that don't exist yet are compared by name
+        assert!(same_file(
+            &sub.join("new.txt"),
+            &dir.path().join("./sub/new.txt")
+        ));
+        assert!(!same_file(&file, &sub.join("new.txt")));
     }
 
-    fn render_search(&mut self) {
-        self.search.render(&mut self.terminal);
+    #[test]
+    fn test_scratch_buffers() {
+        let mut editor = Editor::open_str("file", 40, 10);
+        editor.config.history_coalesce = Duration::ZERO;
+        editor.buffer.filename = Some("a.txt".to_string());
+        press(&mut editor, &["end", "!"]);
+
+        // New buffers are numbered and leave the others as they were
+        press(&mut editor, &["ctrl+n", "x", "ctrl+n"]);
+        assert_eq!(buffer_name(&editor.buffer), "Untitled-3");
+        assert_eq!(editor.buffer.history.coalesce_window, Duration::ZERO);
+        assert_eq!(editor.buffer.to_string(), "");
+        let names: Vec<_> = editor.all_buffers().map(buffer_name).collect();
+        assert_eq!(names, ["a.txt", "Untitled-2", "Untitled-3"]);
+        let dirty: Vec<_> = editor.all_buffers().map(|b| b.dirty).collect();
+        assert_eq!(dirty, [true, true, false]);
+
+        // Each buffer comes back with its view and history
+        editor.switch_buffer(0);
+        assert_eq!(snapshot(&editor), "file!|");
+        press(&mut editor, &["ctrl+z"]);
+        assert_eq!(editor.buffer.to_string(), "file");
+        editor.switch_buffer(1);
+        assert_eq!(snapshot(&editor), "x|");
+        assert_eq!(editor.buffer_index, 1);
+        let names: Vec<_> = editor.all_buffers().map(buffer_name).collect();
+        assert_eq!(names, ["a.txt", "Untitled-2", "Untitled-3"]);
+    }
+
+    #[test]
+    fn test_path_at() {
+        let row = Row::from("see ../src/main.rs:12:5, or a.txt.");
+        let path = |x| path_at(&row, x);
+        assert_eq!(path(4), Some(("../src/main.rs".into(), Some(12))));
+        assert_eq!(path(20), Some(("../src/main.rs".into(), Some(12))));
+        // Right after the path, but not in the space before it
+        assert_eq!(path(23), Some(("../src/main.rs".into(), Some(12))));
+        assert_eq!(path(3), Some(("see".into(), None)));
+        assert_eq!(path(33), Some(("a.txt".into(), None)));
+        assert_eq!(path_at(&Row::from("x: y"), 3), Some(("y".into(), None)));
+        assert_eq!(path_at(&Row::from("a  b"), 2), None);
+    }
+
+    #[test]
+    fn test_url_under_cursor() {
+        let mut editor = editor("see https://a.io/x now");
+        for (x, url) in [
+            (3, None),
+            (4, Some("https://a.io/x")),
+            (18, Some("https://a.io/x")),
+        ] {
+            editor.cursor = pos(x, 0);
+            assert_eq!(editor.url_under_cursor().as_deref(), url);
+        }
+    }
+
+    #[test]
+    fn test_swap_selection_ends() {
+        let mut editor = editor("one two\nthree four");
+        press(&mut editor, &["right", "right", "right", "right"]);
+        press(&mut editor, &["shift+right", "shift+right", "shift+down"]);
+        assert_eq!(snapshot(&editor), "one ^two\nthree |four");
+
+        // The selection stays the same, only its ends trade places
+        press(&mut editor, &["ctrl+shift+o"]);
+        assert_eq!(snapshot(&editor), "one |two\nthree ^four");
+        press(&mut editor, &["shift+left", "shift+left"]);
+        assert_eq!(snapshot(&editor), "on|e two\nthree ^four");
+        press(&mut editor, &["alt+o", "shift+right"]);
+        assert_eq!(snapshot(&editor), "on^e two\nthree f|our");
+
+        // Without a selection there is nothing to swap
+        press(&mut editor, &["left", "alt+o"]);
+        assert_eq!(snapshot(&editor), "on|e two\nthree four");
+    }
+
+    #[test]
+    fn test_linewise_clipboard() {
+        let mut editor = editor("one\n  two\nthree");
+        press(&mut editor, &["down", "right", "ctrl+c", "down", "ctrl+v"]);
+        assert_eq!(snapshot(&editor), "one\n  two\n  |two\nthree");
+
+        press(&mut editor, &["ctrl+x"]);
+        assert_eq!(snapshot(&editor), "one\n  two\n|three");
+        press(&mut editor, &["ctrl+x"]);
+        assert_eq!(snapshot(&editor), "one\n|  two");
+        press(&mut editor, &["up", "ctrl+x", "ctrl+x"]);
+        assert_eq!(snapshot(&editor), "|");
+
+        // A partial selection is pasted where the cursor is
+        let mut editor = self::editor("abc\nxyz");
+        press(
+            &mut editor,
+            &["shift+right", "ctrl+c", "down", "end", "ctrl+v"],
+        );
+        assert_eq!(snapshot(&editor), "abc\nxyza|");
+    }
+
+    #[test]
+    fn test_copy_range() {
+        let mut editor = editor("hello world\n\n\n中文🤗ok\nlast");
+        let mut copy = |begin, end| {
+            editor.set_selection(begin, end);
+            editor.selection_text().unwrap()
+        };
+
+        // Single line, ending before the end of the line
+        assert_eq!(copy(pos(2, 0), pos(7, 0)), "llo w");
+        // Starting mid-line, ending mid-line, with empty lines in between
+        assert_eq!(copy(pos(6, 0), pos(2, 3)), "world\n\n\n中文");
+        assert_eq!(copy(pos(0, 1), pos(1, 4)), "\n\n中文🤗ok\nl");
+        // Wide characters are copied as whole graphemes
+        assert_eq!(copy(pos(1, 3), pos(3, 3)), "文🤗");
+        assert_eq!(copy(pos(3, 3), pos(0, 4)), "ok\n");
+    }
+
+    #[test]
+    fn test_selection() {
+        let mut editor = editor("first line\n第二行\nthird");
+        assert_eq!(editor.selection_text(), None);
+
+        editor.set_selection(pos(6, 0), pos(2, 1));
+        assert_eq!(editor.selection_text().as_deref(), Some("line\n第二"));
+        assert_eq!(editor.cursor, pos(2, 1));
+
+        // Backwards selections and out of range positions
+        editor.set_selection(pos(99, 99), pos(1, 1));
+        assert_eq!(editor.anchor, Some(pos(5, 2)));
+        assert_eq!(editor.selection_text().as_deref(), Some("二行\nthird"));
+
+        editor.set_selection(pos(3, 0), pos(3, 0));
+        assert_eq!(editor.selection_text(), None);
     }
 }
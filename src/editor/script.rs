@@ -0,0 +1,258 @@
+//! Replaying scripts of input events against a headless editor, and checking what it
+//! ends up with against golden files, for tests of behavior that takes many steps.
+//!
+//! A script has one event per line. Blank lines and lines starting with `#` are left
+//! out.
+//!
+//! ```text
+//! open "one\ntwo"     # start over with this text, at the current size
+//! resize 40 10        # the terminal's new width and height
+//! type "hello\n"      # one key per character; \n is Enter and \t is Tab
+//! key ctrl+shift+z    # a key as the `[keys]` config section writes it
+//! mouse down 10 4     # down, up, drag, move, scroll_up or scroll_down at column
+//!                     # and row, followed by modifiers such as `alt` if any
+//! ```
+//!
+//! Scripts are `tests/scripts/<name>.script`. What the editor shows after the last
+//! event is compared with `<name>.golden` next to it, which is written instead when
+//! the `UPDATE_GOLDEN` environment variable is set.
+
+use std::{fs, path::PathBuf};
+
+use crossterm::event::{
+    Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+};
+
+use super::Editor;
+use crate::keymap;
+
+/// What a script starts with, until it opens text or resizes.
+const SIZE: (usize, usize) = (60, 12);
+
+/// A line of a script.
+#[derive(Debug, PartialEq)]
+enum Step {
+    Open(String),
+    Event(Event),
+}
+
+/// Parse a script into its steps. An error names the line it is on.
+fn parse(script: &str) -> Result<Vec<Step>, String> {
+    let mut steps = Vec::new();
+    for (number, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (word, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let parsed = match word {
+            "open" => unquote(rest).map(|text| vec![Step::Open(text)]),
+            "type" => unquote(rest).map(|text| text.chars().map(type_char).collect()),
+            "key" => keymap::parse_chord(rest).map(|(modifiers, code, state)| {
+                let mut event = KeyEvent::new(code, modifiers);
+                event.state = state;
+                vec![Step::Event(Event::Key(event))]
+            }),
+            "mouse" => mouse(rest).map(|event| vec![Step::Event(event)]),
+            "resize" => {
+                numbers(rest).map(|[width, height]| vec![Step::Event(Event::Resize(width, height))])
+            }
+            _ => Err(format!("Unknown event: {word}")),
+        };
+        steps.extend(parsed.map_err(|err| format!("Line {}: {err}", number + 1))?);
+    }
+    Ok(steps)
+}
+
+fn key(code: KeyCode, modifiers: KeyModifiers) -> Step {
+    Step::Event(Event::Key(KeyEvent::new(code, modifiers)))
+}
+
+fn type_char(c: char) -> Step {
+    match c {
+        '\n' => key(KeyCode::Enter, KeyModifiers::NONE),
+        '\t' => key(KeyCode::Tab, KeyModifiers::NONE),
+        c if c.is_uppercase() => key(KeyCode::Char(c), KeyModifiers::SHIFT),
+        c => key(KeyCode::Char(c), KeyModifiers::NONE),
+    }
+}
+
+/// The text of a string in double quotes, with `\n`, `\t`, `\"` and `\\` escapes.
+fn unquote(quoted: &str) -> Result<String, String> {
+    let inner = (quoted.trim().strip_prefix('"'))
+        .and_then(|rest| rest.strip_suffix('"'))
+        .ok_or_else(|| format!("Expected a quoted string: {quoted}"))?;
+    let mut text = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        text.push(match (c, c == '\\') {
+            (_, false) => c,
+            (_, true) => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some(c @ ('"' | '\\')) => c,
+                other => return Err(format!("Unknown escape: \\{}", other.unwrap_or(' '))),
+            },
+        });
+    }
+    Ok(text)
+}
+
+fn numbers<const N: usize>(text: &str) -> Result<[u16; N], String> {
+    let numbers: Vec<u16> = text
+        .split_whitespace()
+        .map(|word| {
+            word.parse()
+                .map_err(|_| format!("Expected a number: {word}"))
+        })
+        .collect::<Result<_, _>>()?;
+    numbers
+        .try_into()
+        .map_err(|_| format!("Expected {N} numbers: {text}"))
+}
+
+/// A mouse event written as its kind, column and row, and modifiers.
+fn mouse(text: &str) -> Result<Event, String> {
+    let mut words = text.split_whitespace();
+    let kind = match words.next().unwrap_or_default() {
+        "down" => MouseEventKind::Down(MouseButton::Left),
+        "up" => MouseEventKind::Up(MouseButton::Left),
+        "drag" => MouseEventKind::Drag(MouseButton::Left),
+        "move" => MouseEventKind::Moved,
+        "scroll_up" => MouseEventKind::ScrollUp,
+        "scroll_down" => MouseEventKind::ScrollDown,
+        kind => return Err(format!("Unknown mouse event: {kind}")),
+    };
+    let [column, row] = numbers(&words.by_ref().take(2).collect::<Vec<_>>().join(" "))?;
+    let mut modifiers = KeyModifiers::NONE;
+    for word in words {
+        modifiers |= match word {
+            "shift" => KeyModifiers::SHIFT,
+            "ctrl" => KeyModifiers::CONTROL,
+            "alt" => KeyModifiers::ALT,
+            _ => return Err(format!("Unknown modifier: {word}")),
+        };
+    }
+    Ok(Event::Mouse(MouseEvent {
+        kind,
+        column,
+        row,
+        modifiers,
+    }))
+}
+
+/// Feed the steps to a new editor and describe what it ends up with: the text, the
+/// cursor and selection, the toast if there is one, and the screen.
+fn replay(steps: Vec<Step>) -> String {
+    let new = |text: &str, (width, height)| {
+        let mut editor = Editor::open_str(text, width, height);
+        // The debug line is different in release builds
+        editor.hide_message_line = true;
+        editor
+    };
+    let mut editor = new("", SIZE);
+    for step in steps {
+        match step {
+            Step::Open(text) => {
+                editor = new(&text, (editor.terminal.width, editor.terminal.height));
+            }
+            Step::Event(event) => {
+                editor.handle_event(&event).unwrap();
+            }
+        }
+    }
+    editor.render_to_buffer();
+
+    let mut output = format!("cursor: {:?}\n", editor.cursor);
+    output += &format!("anchor: {:?}\n", editor.anchor);
+    if let Some((toast, _)) = &editor.toast {
+        output += &format!("toast: {toast}\n");
+    }
+    output += "--- text\n";
+    output += &editor.buffer.to_string();
+    output += "\n--- screen\n";
+    for line in editor.terminal.snapshot() {
+        output += &line;
+        output += "\n";
+    }
+    output
+}
+
+/// Replay `tests/scripts/<name>.script` and compare the outcome with its golden file.
+fn check(name: &str) {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/scripts");
+    let script = fs::read_to_string(dir.join(format!("{name}.script"))).unwrap();
+    let output = replay(parse(&script).unwrap());
+    let golden = dir.join(format!("{name}.golden"));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&golden, output).unwrap();
+    } else {
+        let expected = fs::read_to_string(&golden).unwrap_or_default();
+        assert!(
+            output == expected,
+            "{name}: got\n{output}\nexpected\n{expected}\nRun with UPDATE_GOLDEN=1 to accept it"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let steps = parse("# A comment\n\nopen \"a\\\"b\\n\"\ntype \"Hi\\n\"").unwrap();
+        assert_eq!(
+            steps,
+            [
+                Step::Open("a\"b\n".into()),
+                key(KeyCode::Char('H'), KeyModifiers::SHIFT),
+                key(KeyCode::Char('i'), KeyModifiers::NONE),
+                key(KeyCode::Enter, KeyModifiers::NONE),
+            ]
+        );
+        let steps = parse("key ctrl+z\nresize 80 24\nmouse drag 10 4 alt").unwrap();
+        assert_eq!(steps[0], key(KeyCode::Char('z'), KeyModifiers::CONTROL));
+        assert_eq!(steps[1], Step::Event(Event::Resize(80, 24)));
+        let Step::Event(Event::Mouse(event)) = &steps[2] else {
+            panic!("{:?}", steps[2]);
+        };
+        assert_eq!(event.kind, MouseEventKind::Drag(MouseButton::Left));
+        assert_eq!((event.column, event.row), (10, 4));
+        assert_eq!(event.modifiers, KeyModifiers::ALT);
+
+        assert_eq!(
+            parse("type \"a\"\nresize 80").unwrap_err(),
+            "Line 2: Expected 2 numbers: 80"
+        );
+        assert!(parse("wait 1")
+            .unwrap_err()
+            .starts_with("Line 1: Unknown event"));
+        assert!(parse("type unquoted").is_err());
+    }
+
+    #[test]
+    fn test_selection_delete() {
+        check("selection_delete");
+    }
+
+    #[test]
+    fn test_paste_lines() {
+        check("paste_lines");
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        check("undo_redo");
+    }
+
+    #[test]
+    fn test_cjk_cursor() {
+        check("cjk_cursor");
+    }
+
+    #[test]
+    fn test_mouse_selection() {
+        check("mouse_selection");
+    }
+}
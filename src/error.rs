@@ -1,7 +1,10 @@
 use std::{fmt, path::PathBuf};
 
+#[cfg(feature = "tui")]
 use terminal_clipboard::ClipboardError;
 
+use crate::t;
+
 pub enum Error {
     UnrecognizedOption(String),
     TooManyArguments(usize),
@@ -9,6 +12,11 @@ pub enum Error {
     Fmt(std::fmt::Error),
     ClipboardError(String),
     FileError(PathBuf, usize, String),
+    Formatter(String),
+    /// Standard output is not a terminal, so there is nowhere to edit.
+    NotATerminal,
+    /// The size of the terminal could not be found out.
+    TerminalSize(std::io::Error),
 }
 
 // Provides detailed and user-friendly error messages for debugging purposes.
@@ -29,6 +37,14 @@ impl fmt::Debug for Error {
                 line,
                 message
             ),
+            Error::Formatter(message) => write!(f, "Formatter error: {}", message),
+            Error::NotATerminal => write!(
+                f,
+                "Standard output is not a terminal. Use --cat FILE to print a file with highlighting."
+            ),
+            Error::TerminalSize(error) => {
+                write!(f, "Terminal error: cannot get the terminal size: {}", error)
+            }
             // _ => write!(f, "An unknown error occurred."),
         }
     }
@@ -46,6 +62,7 @@ impl From<std::fmt::Error> for Error {
     }
 }
 
+#[cfg(feature = "tui")]
 impl From<ClipboardError> for Error {
     fn from(error: ClipboardError) -> Self {
         Self::ClipboardError(error.to_string())
@@ -53,44 +70,44 @@ impl From<ClipboardError> for Error {
 }
 
 impl Error {
-    pub fn get_error_message(err: &std::io::Error) -> &str {
+    pub fn get_error_message(err: &std::io::Error) -> &'static str {
         use std::io::ErrorKind::*;
-        match err.kind() {
-            AddrInUse => "地址被占用",
-            AddrNotAvailable => "地址不可用",
-            AlreadyExists => "文件已存在",
-            ArgumentListTooLong => "参数列表过长",
-            BrokenPipe => "管道已断开",
-            ConnectionAborted => "连接已中止",
-            ConnectionRefused => "连接被拒绝",
-            ConnectionReset => "连接已重置",
-            CrossesDevices => "不能跨设备进行链接或重命名",
-            Deadlock => "检测到死锁",
-            DirectoryNotEmpty => "文件夹不是空的，里面还有东西",
-            ExecutableFileBusy => "可执行文件正在使用中",
-            FileTooLarge => "文件太大",
-            HostUnreachable => "主机不可达",
-            Interrupted => "操作被中断",
-            InvalidData => "数据无效",
-            InvalidInput => "输入参数无效",
-            IsADirectory => "该路径是一个目录",
-            NetworkDown => "网络连接已断开",
-            NetworkUnreachable => "网络不可达",
-            NotADirectory => "不是一个目录",
-            NotConnected => "未连接",
-            NotFound => "未找到文件",
-            NotSeekable => "文件不支持查找",
-            Other => "发生未知错误",
-            OutOfMemory => "内存不足（OOM）",
-            PermissionDenied => "需要管理员权限",
-            ReadOnlyFilesystem => "文件系统为只读",
-            ResourceBusy => "资源正忙",
-            StaleNetworkFileHandle => "网络文件句柄已失效",
-            StorageFull => "存储空间不足",
-            TimedOut => "操作超时",
-            UnexpectedEof => "遇到意外 EOF 结束符，拼尽全力无法战胜",
-            _ => "未知错误",
-        }
+        t!(match err.kind() {
+            AddrInUse => "io.addr_in_use",
+            AddrNotAvailable => "io.addr_not_available",
+            AlreadyExists => "io.already_exists",
+            ArgumentListTooLong => "io.argument_list_too_long",
+            BrokenPipe => "io.broken_pipe",
+            ConnectionAborted => "io.connection_aborted",
+            ConnectionRefused => "io.connection_refused",
+            ConnectionReset => "io.connection_reset",
+            CrossesDevices => "io.crosses_devices",
+            Deadlock => "io.deadlock",
+            DirectoryNotEmpty => "io.directory_not_empty",
+            ExecutableFileBusy => "io.executable_file_busy",
+            FileTooLarge => "io.file_too_large",
+            HostUnreachable => "io.host_unreachable",
+            Interrupted => "io.interrupted",
+            InvalidData => "io.invalid_data",
+            InvalidInput => "io.invalid_input",
+            IsADirectory => "io.is_a_directory",
+            NetworkDown => "io.network_down",
+            NetworkUnreachable => "io.network_unreachable",
+            NotADirectory => "io.not_a_directory",
+            NotConnected => "io.not_connected",
+            NotFound => "io.not_found",
+            NotSeekable => "io.not_seekable",
+            Other => "io.other",
+            OutOfMemory => "io.out_of_memory",
+            PermissionDenied => "io.permission_denied",
+            ReadOnlyFilesystem => "io.read_only_filesystem",
+            ResourceBusy => "io.resource_busy",
+            StaleNetworkFileHandle => "io.stale_network_file_handle",
+            StorageFull => "io.storage_full",
+            TimedOut => "io.timed_out",
+            UnexpectedEof => "io.unexpected_eof",
+            _ => "io.unknown",
+        })
     }
 }
 
@@ -150,6 +167,16 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_terminal_error_debug() {
+        assert!(format!("{:?}", Error::NotATerminal).contains("--cat"));
+        let io_error = std::io::Error::new(std::io::ErrorKind::NotFound, "no tty");
+        assert_eq!(
+            format!("{:?}", Error::TerminalSize(io_error)),
+            "Terminal error: cannot get the terminal size: no tty"
+        );
+    }
+
     #[test]
     fn test_from_io_error() {
         let io_error = std::io::Error::new(std::io::ErrorKind::Other, "test error");
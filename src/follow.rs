@@ -0,0 +1,103 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::PathBuf,
+};
+
+use crate::Buffer;
+
+/// What `Follow::poll` found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Update {
+    Unchanged,
+    /// New lines or text were added at the end of the buffer.
+    Appended,
+    /// The file got shorter, truncated or rotated, and was read again from the start.
+    Reloaded,
+}
+
+/// Keeps a buffer in step with a file that is being written to, like `tail -f`.
+#[derive(Debug)]
+pub struct Follow {
+    path: PathBuf,
+    /// How many bytes of the file have been read.
+    offset: u64,
+    /// The start of a character split by the end of the last read.
+    partial: Vec<u8>,
+}
+
+impl Follow {
+    /// Follow the file `buffer` was loaded from, which already holds its content.
+    pub fn new(path: impl Into<PathBuf>, buffer: &Buffer) -> Self {
+        Follow {
+            path: path.into(),
+            offset: buffer.to_string().len() as u64,
+            partial: Vec::new(),
+        }
+    }
+
+    /// Add whatever was written to the file since the last call to `buffer`, or load
+    /// it again if it got shorter.
+    pub fn poll(&mut self, buffer: &mut Buffer) -> io::Result<Update> {
+        let len = std::fs::metadata(&self.path)?.len();
+        if len < self.offset {
+            let mut reloaded = Buffer::from_file(&self.path)?;
+            reloaded.syntax = std::mem::take(&mut buffer.syntax);
+            reloaded.update_syntax();
+            *buffer = reloaded;
+            *self = Follow::new(self.path.clone(), buffer);
+            return Ok(Update::Reloaded);
+        }
+        if len == self.offset {
+            return Ok(Update::Unchanged);
+        }
+
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut bytes = std::mem::take(&mut self.partial);
+        self.offset += file.read_to_end(&mut bytes)? as u64;
+
+        // Keep an incomplete character for the next read
+        let valid = match std::str::from_utf8(&bytes) {
+            Ok(_) => bytes.len(),
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            Err(_) => bytes.len(),
+        };
+        self.partial = bytes.split_off(valid);
+        if bytes.is_empty() {
+            return Ok(Update::Unchanged);
+        }
+        buffer.append(&String::from_utf8_lossy(&bytes));
+        Ok(Update::Appended)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_poll() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, "first\nsec").unwrap();
+        let mut buffer = Buffer::from_file(file.path()).unwrap();
+        let mut follow = Follow::new(file.path(), &buffer);
+        assert_eq!(follow.poll(&mut buffer).unwrap(), Update::Unchanged);
+
+        // A character split across two writes waits for its second half
+        file.write_all(b"ond\n\xe4\xbd").unwrap();
+        assert_eq!(follow.poll(&mut buffer).unwrap(), Update::Appended);
+        assert_eq!(buffer.to_string(), "first\nsecond\n");
+        file.write_all(b"\xa0\n").unwrap();
+        assert_eq!(follow.poll(&mut buffer).unwrap(), Update::Appended);
+        assert_eq!(buffer.to_string(), "first\nsecond\n你\n");
+
+        // Truncating the file starts over
+        file.as_file().set_len(0).unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.write_all(b"new").unwrap();
+        assert_eq!(follow.poll(&mut buffer).unwrap(), Update::Reloaded);
+        assert_eq!(buffer.to_string(), "new");
+    }
+}
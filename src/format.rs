@@ -0,0 +1,140 @@
+//! Piping the buffer through an external formatter such as `rustfmt` or `prettier`.
+
+use std::{
+    io::{self, Read, Write},
+    process::{Command, Stdio},
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{t, Error};
+
+/// How long a formatter may run before it is killed.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Run `command` with `input` on its stdin and return what it printed to stdout.
+///
+/// The command line is split on whitespace, without any quoting. A non-zero exit
+/// status is reported with the formatter's stderr as `Error::Formatter`.
+pub fn run(command: &str, input: &str, timeout: Duration) -> Result<String, Error> {
+    let mut args = command.split_whitespace();
+    let Some(program) = args.next() else {
+        return Err(Error::Formatter(t!("format.no_command").to_string()));
+    };
+
+    let mut process = Command::new(program);
+    process
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // Keep the formatter out of the terminal's foreground process group, so that it
+    // can't read keys meant for the editor; it is stopped if it tries to
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut process, 0);
+    let mut child = process.spawn()?;
+
+    // Feed stdin and drain the outputs on their own threads, so that a formatter
+    // filling up one pipe can't block on us
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_string();
+    thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let stdout = drain(child.stdout.take().unwrap());
+    let stderr = drain(child.stderr.take().unwrap());
+
+    let start = Instant::now();
+    let timed_out = || Error::Formatter(t!("format.timeout", timeout.as_secs()));
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if start.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(timed_out());
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    // Something the formatter started in the background can keep its outputs open
+    // after it exits, so they are only waited for until the deadline
+    let remaining = || timeout.saturating_sub(start.elapsed());
+    let stdout = stdout
+        .recv_timeout(remaining())
+        .map_err(|_| timed_out())??;
+    if !status.success() {
+        let stderr = stderr.recv_timeout(remaining()).ok().and_then(Result::ok);
+        let stderr = String::from_utf8_lossy(stderr.as_deref().unwrap_or_default());
+        let message = match stderr.trim() {
+            "" => t!("format.exit_status", status),
+            stderr => stderr.to_string(),
+        };
+        return Err(Error::Formatter(message));
+    }
+    String::from_utf8(stdout).map_err(|_| Error::Formatter(t!("format.invalid_output").to_string()))
+}
+
+/// Read `pipe` to the end on a thread of its own, which sends what it read.
+fn drain(mut pipe: impl Read + Send + 'static) -> Receiver<io::Result<Vec<u8>>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = sender.send(pipe.read_to_end(&mut buf).map(|_| buf));
+    });
+    receiver
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let output = run("tr a-z A-Z", "hello\n世界\n", TIMEOUT).unwrap();
+        assert_eq!(output, "HELLO\n世界\n");
+
+        // Large inputs don't deadlock on full pipes
+        let input = "x".repeat(1 << 20);
+        assert_eq!(run("cat", &input, TIMEOUT).unwrap().len(), 1 << 20);
+    }
+
+    #[test]
+    fn test_failure() {
+        match run("ls /nonexistent-arcaea-path", "", TIMEOUT) {
+            Err(Error::Formatter(message)) => assert!(message.contains("nonexistent")),
+            result => panic!("Unexpected result {:?}", result.err()),
+        }
+        assert!(matches!(
+            run("false", "", TIMEOUT),
+            Err(Error::Formatter(_))
+        ));
+        assert!(matches!(
+            run("arcaea-no-such-formatter", "", TIMEOUT),
+            Err(Error::Io(_))
+        ));
+        assert!(matches!(run("  ", "", TIMEOUT), Err(Error::Formatter(_))));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let start = Instant::now();
+        match run("sleep 5", "", Duration::from_millis(100)) {
+            Err(Error::Formatter(_)) => {}
+            result => panic!("Unexpected result {:?}", result.err()),
+        }
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        // Nor wait for what it left running in the background
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("background.sh");
+        std::fs::write(&script, "sleep 5 &\necho done\n").unwrap();
+        let start = Instant::now();
+        let command = format!("sh {}", script.display());
+        match run(&command, "", Duration::from_millis(300)) {
+            Err(Error::Formatter(_)) => {}
+            result => panic!("Unexpected result {:?}", result.err()),
+        }
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -1,4 +1,28 @@
-use crate::editor::Position;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::time::Instant;
+use std::{collections::BTreeMap, time::Duration};
+
+use crate::Position;
+
+/// wasm32-unknown-unknown has no clock, and `std::time::Instant::now` panics there.
+/// Without one, edits are never coalesced and branches are ordered by their ids alone.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant;
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+impl Instant {
+    fn now() -> Self {
+        Instant
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Duration::MAX
+    }
+}
+
+/// The maximum number of versions kept in the history.
+const MAX_VERSIONS: usize = 1000;
 
 #[derive(Debug)]
 struct Diff<T> {
@@ -24,40 +48,92 @@ pub struct State {
     pub anchor: Option<Position>,
 }
 
+/// A version in the undo tree.
+///
+/// The diff describes the edge from the parent to this version: `old` holds the
+/// parent's rows that differ (used to undo), `new` holds this version's rows that
+/// differ (used to redo), and `len` is the row count of this version.
+struct Node<T> {
+    parent: Option<usize>,
+    children: Vec<usize>,
+    /// The child that `redo` moves to, i.e. the most recently created or visited one.
+    redo_child: Option<usize>,
+
+    diff: Diff<T>,
+    state: State,
+    time: Instant,
+
+    /// The first and last row changed relative to the parent.
+    range: Option<(usize, usize)>,
+    /// Whether later pushes must not be coalesced into this version.
+    boundary: bool,
+}
+
+/// The tip of a branch in the undo tree, as listed by `History::branches`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Branch {
+    pub id: usize,
+    pub time: Instant,
+    /// Number of undo steps between the root and the tip.
+    pub depth: usize,
+    /// Whether the current version lies on this branch.
+    pub active: bool,
+}
+
 #[derive(Default)]
 pub struct History<T> {
-    buffer: Vec<Diff<T>>,
-    state: Vec<State>,
+    nodes: BTreeMap<usize, Node<T>>,
+    next_id: usize,
+    root: Option<usize>,
+    version: Option<usize>,
 
     pub current: Vec<T>,
     pub current_state: State,
 
-    version: usize,
+    /// Snapshots taken by `begin_transaction`, one per nesting level.
+    transaction: Vec<(Vec<T>, State)>,
+
+    /// Pushes arriving within this window after the previous one, and touching an
+    /// overlapping range of rows, are merged into it. Zero disables coalescing.
+    pub coalesce_window: Duration,
 }
 
-/// A history structure that maintains a list of states and allows undo/redo operations.
+/// A history structure that maintains a tree of states and allows undo/redo operations.
 ///
-/// The history keeps track of states through a version number, which points to the current state.
-/// When undoing, the version number decreases, and when redoing, it increases.
+/// Every pushed state becomes a child of the current one, so undoing a few steps and
+/// then editing starts a new branch instead of discarding the newer edits. `undo` moves
+/// to the parent and `redo` follows the most recent child; `branches` and `jump_to`
+/// reach the abandoned ones.
 ///
 /// # Examples
 ///
 /// ```
-/// let mut history = History::new();
-/// history.push_state("first");  // version = 0
-/// history.push_state("second"); // version = 1
+/// use arcaea::History;
 ///
-/// assert_eq!(history.current, Some(&"second"));
+/// let mut history: History<String> = History::new();
+/// let state = Default::default();
+/// history.push_state(&["first".into()], state, state, None);
+/// history.push_state(&["second".into()], state, state, None);
+/// assert_eq!(history.current, ["second"]);
 ///
 /// history.undo(); // Goes back to "first"
-/// assert_eq!(history.current, Some(&"first"));
+/// assert_eq!(history.current, ["first"]);
 ///
 /// history.redo(); // Returns to "second"
-/// assert_eq!(history.current, Some(&"second"));
+/// assert_eq!(history.current, ["second"]);
 ///
-/// // If we push a new state after undoing, all future states are discarded
-/// history.undo();
-/// history.push_state("new_state"); // Discards "second"
+/// // Pushes inside a transaction are recorded as a single version
+/// history.begin_transaction();
+/// history.push_state(&["third".into()], state, state, None);
+/// history.push_state(&["fourth".into()], state, state, None);
+/// history.commit();
+///
+/// history.undo(); // Skips "third" entirely
+/// assert_eq!(history.current, ["second"]);
+///
+/// // Pushing after an undo starts a new branch, "fourth" is still reachable
+/// history.push_state(&["new_state".into()], state, state, None);
+/// assert_eq!(history.branches().len(), 2);
 /// ```
 ///
 /// # Methods
@@ -66,7 +142,8 @@ pub struct History<T> {
 /// - `push_state()`: Adds a new state to the history
 /// - `undo()`: Moves back one version in history
 /// - `redo()`: Moves forward one version in history
-/// - `current()`: Returns a reference to the current state
+/// - `begin_transaction()`, `commit()`, `rollback()`: Group several pushes into one version
+/// - `branches()`, `jump_to()`: List and switch between branches of the undo tree
 impl<T> History<T>
 where
     T: Clone + Default + PartialEq,
@@ -75,8 +152,7 @@ where
         Self::default()
     }
 
-    /// Push a new state to the history.
-    /// If the current version isn't the newest, it will truncate the history to the current version.
+    /// Push a new state to the history as a child of the current version.
     pub fn push_state(
         &mut self,
         item: &[T],
@@ -84,108 +160,385 @@ where
         cursor: Position,
         anchor: Option<Position>,
     ) {
-        if self.state.len() > 1000 {
-            self.state.remove(0);
-            self.buffer.remove(0);
-            self.version -= 1;
+        let state = State {
+            viewbox,
+            cursor,
+            anchor,
+        };
+
+        // Inside a transaction, only remember the latest state; `commit` records it.
+        if !self.transaction.is_empty() {
+            self.current = item.to_owned();
+            self.current_state = state.clone();
+            return;
         }
 
-        let v = self.version;
-        let old_len = self.current.len();
-        let new_len = item.len();
+        let range = changed_range(&self.current, item);
+        if self.should_coalesce(range) {
+            // Rewind to the parent and record the combined change from there instead.
+            self.undo();
+            let parent = self.version.unwrap();
+            let merged = self.nodes[&parent].redo_child.unwrap();
+            let parent_node = self.nodes.get_mut(&parent).unwrap();
+            parent_node.children.retain(|&c| c != merged);
+            parent_node.redo_child = parent_node.children.last().copied();
+            self.nodes.remove(&merged);
 
-        self.buffer.truncate(v);
-        self.buffer.push(Diff::new(new_len));
+            if self.current == item {
+                self.current_state = state;
+                return;
+            }
+        }
+        let range = changed_range(&self.current, item);
 
-        self.state.truncate(v);
-        self.state.push(State {
-            viewbox,
-            cursor,
-            anchor,
-        });
+        let id = self.next_id;
+        self.next_id += 1;
 
-        // [..., old, new] <- self.history
-        //       v-1   v   <- index
+        let old_len = self.current.len();
+        let new_len = item.len();
+        let mut diff = Diff::new(new_len);
 
-        if v == 0 {
-            self.current = item.to_owned();
-        } else {
-            self.buffer[v - 1].new.resize(new_len, None);
-            self.buffer[v].old.resize(old_len, None);
+        if let Some(parent) = self.version {
+            diff.old.resize(old_len, None);
+            diff.new.resize(new_len, None);
 
+            // Only the rows in the changed range can differ
             let min_len = old_len.min(new_len);
+            let (first, last) = range.unwrap_or((min_len, min_len));
             #[allow(clippy::needless_range_loop)]
-            for i in 0..min_len {
+            for i in first..min_len.min(last + 1) {
                 let old_row = &mut self.current[i];
                 let new_row = &item[i];
                 if old_row != new_row {
-                    self.buffer[v - 1].new[i] = Some(new_row.clone());
-                    self.buffer[v].old[i] = Some(old_row.clone());
+                    diff.new[i] = Some(new_row.clone());
+                    diff.old[i] = Some(old_row.clone());
                     *old_row = new_row.clone();
                 }
             }
             #[allow(clippy::needless_range_loop)]
             for i in min_len..old_len {
-                self.buffer[v].old[i] = Some(self.current[i].clone());
+                diff.old[i] = Some(self.current[i].clone());
             }
 
             self.current.resize(new_len, T::default());
             #[allow(clippy::needless_range_loop)]
             for i in min_len..new_len {
-                self.buffer[v - 1].new[i] = Some(item[i].clone());
+                diff.new[i] = Some(item[i].clone());
                 self.current[i] = item[i].clone();
             }
+
+            let parent = self.nodes.get_mut(&parent).expect("current version exists");
+            parent.children.push(id);
+            parent.redo_child = Some(id);
+        } else {
+            self.current = item.to_owned();
+            self.root = Some(id);
         }
-        self.version += 1;
+
+        self.nodes.insert(
+            id,
+            Node {
+                parent: self.version,
+                children: vec![],
+                redo_child: None,
+                diff,
+                state: state.clone(),
+                time: Instant::now(),
+                range,
+                boundary: false,
+            },
+        );
+        self.version = Some(id);
+        self.current_state = state;
+
+        self.prune();
+    }
+
+    /// Prevent the next push from being coalesced into the current version,
+    /// e.g. after saving or running an explicit command.
+    pub fn mark_boundary(&mut self) {
+        if let Some(node) = self.version.and_then(|v| self.nodes.get_mut(&v)) {
+            node.boundary = true;
+        }
+    }
+
+    /// Whether a push changing `range` should be merged into the current version.
+    fn should_coalesce(&self, range: Option<(usize, usize)>) -> bool {
+        let Some(node) = self.version.map(|v| &self.nodes[&v]) else {
+            return false;
+        };
+        let (Some((begin, end)), Some((last_begin, last_end))) = (range, node.range) else {
+            return false;
+        };
+        node.parent.is_some()
+            && node.children.is_empty()
+            && !node.boundary
+            && node.time.elapsed() < self.coalesce_window
+            && begin <= last_end
+            && last_begin <= end
     }
 
-    /// Push a new state to the history.
-    /// If the current version isn't the newest, it will truncate the history to the current version.
+    /// Update the view state (viewbox, cursor, anchor) of the current version.
     pub fn update_state(&mut self, viewbox: Position, cursor: Position, anchor: Option<Position>) {
-        let v = self.version;
+        let state = State {
+            viewbox,
+            cursor,
+            anchor,
+        };
 
-        if v > 0 {
-            self.state[v - 1] = State {
-                viewbox,
-                cursor,
-                anchor,
-            };
+        if !self.transaction.is_empty() {
+            self.current_state = state;
+        } else if let Some(node) = self.version.and_then(|v| self.nodes.get_mut(&v)) {
+            node.state = state.clone();
+            self.current_state = state;
         }
     }
 
-    pub fn undo(&mut self) -> bool {
-        if self.version > 1 {
-            self.version -= 1;
-            self.current
-                .resize(self.buffer[self.version - 1].len, T::default());
-            for (i, row) in self.buffer[self.version].old.iter().enumerate() {
-                if let Some(row) = row {
-                    self.current[i] = row.clone();
-                }
-            }
-            self.current_state = self.state[self.version - 1].clone();
+    /// Start grouping pushes into a single version. Transactions can be nested;
+    /// only the outermost `commit` records a version.
+    pub fn begin_transaction(&mut self) {
+        self.transaction
+            .push((self.current.clone(), self.current_state.clone()));
+    }
+
+    /// Whether a transaction is currently open.
+    pub fn in_transaction(&self) -> bool {
+        !self.transaction.is_empty()
+    }
+
+    /// Close the innermost transaction. Returns `true` if a new version was recorded,
+    /// which only happens when the outermost transaction changed the content.
+    pub fn commit(&mut self) -> bool {
+        let Some((snapshot, _)) = self.transaction.pop() else {
+            return false;
+        };
+        if !self.transaction.is_empty() || self.current == snapshot {
+            return false;
+        }
+
+        // Rewind to the pre-transaction content so `push_state` diffs against it.
+        let item = std::mem::replace(&mut self.current, snapshot);
+        let State {
+            viewbox,
+            cursor,
+            anchor,
+        } = self.current_state.clone();
+        self.mark_boundary();
+        self.push_state(&item, viewbox, cursor, anchor);
+        self.mark_boundary();
+        true
+    }
+
+    /// Abort the innermost transaction, restoring `current` and `current_state` to
+    /// what they were when it began. Returns `false` if no transaction is open.
+    ///
+    /// Like `undo`, this only moves the history: the caller copies `current` and
+    /// `current_state` back into the document.
+    pub fn rollback(&mut self) -> bool {
+        if let Some((snapshot, state)) = self.transaction.pop() {
+            self.current = snapshot;
+            self.current_state = state;
             true
         } else {
             false
         }
     }
 
+    /// Move to the parent version.
+    pub fn undo(&mut self) -> bool {
+        if self.in_transaction() {
+            return false;
+        }
+        let Some(v) = self.version else {
+            return false;
+        };
+        let Some(parent) = self.nodes[&v].parent else {
+            return false;
+        };
+
+        let node = &self.nodes[&v];
+        self.current
+            .resize(self.nodes[&parent].diff.len, T::default());
+        for (i, row) in node.diff.old.iter().enumerate() {
+            if let Some(row) = row {
+                self.current[i] = row.clone();
+            }
+        }
+
+        let parent_node = self.nodes.get_mut(&parent).unwrap();
+        parent_node.redo_child = Some(v);
+        self.current_state = parent_node.state.clone();
+        self.version = Some(parent);
+        true
+    }
+
+    /// Move to the most recent child version.
     pub fn redo(&mut self) -> bool {
-        if self.version < self.buffer.len() {
-            self.current
-                .resize(self.buffer[self.version].len, T::default());
-            for (i, row) in self.buffer[self.version - 1].new.iter().enumerate() {
-                if let Some(row) = row {
-                    self.current[i] = row.clone();
-                }
+        if self.in_transaction() {
+            return false;
+        }
+        let Some(child) = self.version.and_then(|v| self.nodes[&v].redo_child) else {
+            return false;
+        };
+
+        let node = &self.nodes[&child];
+        self.current.resize(node.diff.len, T::default());
+        for (i, row) in node.diff.new.iter().enumerate() {
+            if let Some(row) = row {
+                self.current[i] = row.clone();
             }
-            self.current_state = self.state[self.version].clone();
-            self.version += 1;
-            true
-        } else {
-            false
         }
+        self.current_state = node.state.clone();
+        self.version = Some(child);
+        true
+    }
+
+    /// List the tips of all branches in the undo tree, newest first.
+    pub fn branches(&self) -> Vec<Branch> {
+        let active = self.version.map(|v| self.redo_tip(v));
+        let mut branches = self
+            .nodes
+            .iter()
+            .filter(|(_, node)| node.children.is_empty())
+            .map(|(&id, node)| Branch {
+                id,
+                time: node.time,
+                depth: self.depth(id),
+                active: Some(id) == active,
+            })
+            .collect::<Vec<_>>();
+        branches.sort_by(|a, b| b.time.cmp(&a.time).then(b.id.cmp(&a.id)));
+        branches
+    }
+
+    /// Move to an arbitrary version of the undo tree, undoing up to the common
+    /// ancestor and redoing down to the target. Returns `false` if `id` is unknown.
+    pub fn jump_to(&mut self, id: usize) -> bool {
+        if self.in_transaction() || !self.nodes.contains_key(&id) {
+            return false;
+        }
+
+        let mut path = vec![id];
+        while let Some(parent) = self.nodes[path.last().unwrap()].parent {
+            path.push(parent);
+        }
+
+        while let Some(v) = self.version {
+            if path.contains(&v) {
+                break;
+            }
+            self.undo();
+        }
+
+        let Some(v) = self.version else {
+            return false;
+        };
+        let steps = path.iter().position(|&p| p == v).unwrap_or(0);
+        for i in (0..steps).rev() {
+            let parent = path[i + 1];
+            self.nodes.get_mut(&parent).unwrap().redo_child = Some(path[i]);
+            self.redo();
+        }
+        true
+    }
+
+    /// The id of the current version, which changes whenever the content does
+    /// (outside of transactions). Ids are never reused.
+    pub fn version(&self) -> Option<usize> {
+        self.version
+    }
+
+    /// The number of steps `undo` can take from the current version.
+    pub fn undo_depth(&self) -> usize {
+        self.version.map_or(0, |v| self.depth(v))
     }
+
+    /// The number of steps `redo` can take from the current version.
+    pub fn redo_depth(&self) -> usize {
+        let mut depth = 0;
+        let mut id = self.version;
+        while let Some(child) = id.and_then(|v| self.nodes[&v].redo_child) {
+            depth += 1;
+            id = Some(child);
+        }
+        depth
+    }
+
+    /// The number of versions kept in the history.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Whether the history has no versions at all.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// The number of rows stored in all diffs, a measure of the memory used by the history.
+    pub fn stored_rows(&self) -> usize {
+        self.nodes
+            .values()
+            .map(|node| {
+                node.diff.old.iter().flatten().count() + node.diff.new.iter().flatten().count()
+            })
+            .sum()
+    }
+
+    /// The number of versions between the root and `id`.
+    fn depth(&self, mut id: usize) -> usize {
+        let mut depth = 0;
+        while let Some(parent) = self.nodes[&id].parent {
+            depth += 1;
+            id = parent;
+        }
+        depth
+    }
+
+    /// The version reached by redoing from `id` as far as possible.
+    fn redo_tip(&self, mut id: usize) -> usize {
+        while let Some(child) = self.nodes[&id].redo_child {
+            id = child;
+        }
+        id
+    }
+
+    /// Drop the oldest versions once the history grows beyond `MAX_VERSIONS`.
+    ///
+    /// The root is removed together with every branch that doesn't lead to the current
+    /// version, and its child on the current path becomes the new root.
+    fn prune(&mut self) {
+        while self.nodes.len() > MAX_VERSIONS {
+            let (Some(root), Some(mut keep)) = (self.root, self.version) else {
+                return;
+            };
+            if keep == root {
+                return;
+            }
+            while self.nodes[&keep].parent != Some(root) {
+                keep = self.nodes[&keep].parent.unwrap();
+            }
+
+            let mut stack = vec![root];
+            while let Some(id) = stack.pop() {
+                let node = self.nodes.remove(&id).unwrap();
+                stack.extend(node.children.into_iter().filter(|&c| c != keep));
+            }
+
+            let node = self.nodes.get_mut(&keep).unwrap();
+            node.parent = None;
+            node.diff.old = vec![];
+            node.diff.new = vec![];
+            self.root = Some(keep);
+        }
+    }
+}
+
+/// The first and last index at which `old` and `new` differ.
+fn changed_range<T: PartialEq>(old: &[T], new: &[T]) -> Option<(usize, usize)> {
+    let len = old.len().max(new.len());
+    let first = (0..len).find(|&i| old.get(i) != new.get(i))?;
+    let last = (first..len).rev().find(|&i| old.get(i) != new.get(i))?;
+    Some((first, last))
 }
 
 #[cfg(test)]
@@ -228,7 +581,293 @@ mod tests {
             Position::default(),
             Position::default(),
             None,
-        ); // version = 1, drops old version 1 and 2
+        ); // version = 1, starts a new branch next to old version 1 and 2
         assert_eq!(history.current, vec!["TvT".into()]);
     }
+
+    #[test]
+    fn test_transaction() {
+        let mut history: History<Row> = History::new();
+        let p = Position::default();
+        history.push_state(&["a".into()], p, p, None);
+
+        history.begin_transaction();
+        history.push_state(&["ab".into()], p, p, None);
+        history.push_state(&["abc".into(), "d".into()], p, p, None);
+        assert!(history.commit());
+        assert_eq!(history.current, vec!["abc".into(), "d".into()]);
+
+        assert!(history.undo());
+        assert_eq!(history.current, vec!["a".into()]);
+        assert!(!history.undo());
+        assert!(history.redo());
+        assert_eq!(history.current, vec!["abc".into(), "d".into()]);
+
+        // An empty transaction doesn't create a version
+        history.begin_transaction();
+        assert!(!history.commit());
+        assert!(history.undo());
+        assert!(!history.undo());
+    }
+
+    #[test]
+    fn test_nested_transaction() {
+        let mut history: History<Row> = History::new();
+        let p = Position::default();
+        history.push_state(&["a".into()], p, p, None);
+
+        history.begin_transaction();
+        history.push_state(&["b".into()], p, p, None);
+        history.begin_transaction();
+        history.push_state(&["c".into()], p, p, None);
+        assert!(!history.commit()); // inner commit records nothing yet
+        assert!(history.in_transaction());
+        history.push_state(&["d".into()], p, p, None);
+        assert!(history.commit());
+        assert!(!history.in_transaction());
+
+        assert_eq!(history.current, vec!["d".into()]);
+        assert!(history.undo());
+        assert_eq!(history.current, vec!["a".into()]);
+    }
+
+    #[test]
+    fn test_failed_transaction() {
+        let mut history: History<Row> = History::new();
+        let p = Position::default();
+        let moved = Position { x: 1, y: 0 };
+        history.push_state(&["a".into()], p, p, None);
+
+        history.begin_transaction();
+        history.push_state(&["b".into()], moved, moved, None);
+        history.begin_transaction();
+        history.push_state(&["c".into()], moved, moved, Some(p));
+
+        // Undo and redo are unavailable while a transaction is open
+        assert!(!history.undo());
+
+        // Rolling back the inner transaction keeps the outer one's changes
+        assert!(history.rollback());
+        assert_eq!(history.current, vec!["b".into()]);
+        assert_eq!(history.current_state.anchor, None);
+
+        // Rolling back the outer transaction restores the original content
+        assert!(history.rollback());
+        assert_eq!(history.current, vec!["a".into()]);
+        assert_eq!(history.current_state.cursor, p);
+        assert!(!history.rollback());
+
+        assert!(!history.undo());
+        history.push_state(&["e".into()], p, p, None);
+        assert!(history.undo());
+        assert_eq!(history.current, vec!["a".into()]);
+    }
+
+    #[test]
+    fn test_branch_creation() {
+        let mut history: History<Row> = History::new();
+        let p = Position::default();
+        history.push_state(&["a".into()], p, p, None);
+        history.push_state(&["ab".into()], p, p, None);
+        history.push_state(&["abc".into()], p, p, None);
+        assert_eq!(history.branches().len(), 1);
+
+        assert!(history.undo());
+        assert!(history.undo());
+        history.push_state(&["ax".into()], p, p, None);
+
+        let branches = history.branches();
+        assert_eq!(branches.len(), 2);
+        assert_eq!(branches[0].depth, 1);
+        assert!(branches[0].active);
+        assert_eq!(branches[1].depth, 2);
+        assert!(!branches[1].active);
+
+        assert_eq!(history.undo_depth(), 1);
+        assert_eq!(history.redo_depth(), 0);
+
+        // Redo follows the newest child, the old branch is not lost
+        assert!(!history.redo());
+        assert!(history.undo());
+        assert!(history.redo());
+        assert_eq!(history.current, vec!["ax".into()]);
+        assert_eq!(history.len(), 4);
+    }
+
+    #[test]
+    fn test_branch_switching() {
+        let mut history: History<Row> = History::new();
+        let p = Position::default();
+        let q = Position { x: 3, y: 1 };
+        history.push_state(&["a".into()], p, p, None);
+        history.push_state(&["a".into(), "b".into(), "c".into()], q, q, None);
+        history.undo();
+        history.push_state(&["x".into()], p, p, None);
+        history.push_state(&["x".into(), "y".into()], p, p, None);
+
+        let old = history.branches()[1].clone();
+        assert!(!old.active);
+        assert!(history.jump_to(old.id));
+        assert_eq!(history.current, vec!["a".into(), "b".into(), "c".into()]);
+        assert_eq!(history.current_state.cursor, q);
+        assert!(history.branches()[1].active);
+
+        // Linear undo/redo now walks the branch we switched to
+        assert!(history.undo());
+        assert_eq!(history.current, vec!["a".into()]);
+        assert!(history.redo());
+        assert_eq!(history.current, vec!["a".into(), "b".into(), "c".into()]);
+
+        let new = history.branches()[0].clone();
+        assert!(history.jump_to(new.id));
+        assert_eq!(history.current, vec!["x".into(), "y".into()]);
+        assert!(!history.jump_to(usize::MAX));
+    }
+
+    #[test]
+    fn test_memory_accounting() {
+        let mut history: History<Row> = History::new();
+        let p = Position::default();
+        let mut rows: Vec<Row> = (0..100)
+            .map(|i| Row::from(i.to_string().as_str()))
+            .collect();
+        history.push_state(&rows, p, p, None);
+        assert_eq!(history.stored_rows(), 0);
+
+        // Only the changed row is stored, on both sides of the diff
+        rows[50] = "changed".into();
+        history.push_state(&rows, p, p, None);
+        assert_eq!(history.stored_rows(), 2);
+
+        // A branch stores its own diff without copying the abandoned one
+        history.undo();
+        rows[50] = "branch".into();
+        history.push_state(&rows, p, p, None);
+        assert_eq!(history.stored_rows(), 4);
+
+        // Old versions are pruned once the limit is reached
+        for i in 0..MAX_VERSIONS + 10 {
+            rows[0] = Row::from(i.to_string().as_str());
+            history.push_state(&rows, p, p, None);
+        }
+        assert_eq!(history.len(), MAX_VERSIONS);
+        assert_eq!(history.branches().len(), 1);
+        assert!(history.stored_rows() <= 2 * MAX_VERSIONS);
+        while history.undo() {}
+        assert_eq!(history.current[0], Row::from("10"));
+    }
+
+    #[test]
+    fn test_depth() {
+        let mut history: History<Row> = History::new();
+        let p = Position::default();
+        assert_eq!(history.undo_depth(), 0);
+        assert_eq!(history.redo_depth(), 0);
+
+        for s in ["a", "b", "c", "d"] {
+            history.push_state(&[s.into()], p, p, None);
+        }
+        assert_eq!(history.undo_depth(), 3);
+        assert_eq!(history.redo_depth(), 0);
+
+        history.undo();
+        history.undo();
+        assert_eq!(history.undo_depth(), 1);
+        assert_eq!(history.redo_depth(), 2);
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let mut history: History<Row> = History::new();
+        history.coalesce_window = Duration::from_secs(3600);
+        let p = Position::default();
+        history.push_state(&["a".into(), "x".into()], p, p, None);
+        history.push_state(&["ab".into(), "x".into()], p, p, None);
+        history.push_state(&["abc".into(), "x".into()], p, p, None);
+        history.push_state(&["abcd".into(), "x".into()], p, p, None);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.undo_depth(), 1);
+
+        assert!(history.undo());
+        assert_eq!(history.current, vec!["a".into(), "x".into()]);
+        assert!(history.redo());
+        assert_eq!(history.current, vec!["abcd".into(), "x".into()]);
+
+        // Changes that cancel out leave no version behind
+        history.push_state(&["abcde".into(), "x".into()], p, p, None);
+        history.push_state(&["abcd".into(), "x".into()], p, p, None);
+        assert_eq!(history.current, vec!["abcd".into(), "x".into()]);
+        assert_eq!(history.undo_depth(), 1);
+    }
+
+    #[test]
+    fn test_coalesce_boundaries() {
+        let mut history: History<Row> = History::new();
+        history.coalesce_window = Duration::from_secs(3600);
+        let p = Position::default();
+        history.push_state(&["a".into(), "x".into()], p, p, None);
+        history.push_state(&["ab".into(), "x".into()], p, p, None);
+
+        // Disjoint rows are not merged
+        history.push_state(&["ab".into(), "xy".into()], p, p, None);
+        assert_eq!(history.undo_depth(), 2);
+
+        // Nor is anything after an explicit boundary
+        history.mark_boundary();
+        history.push_state(&["ab".into(), "xyz".into()], p, p, None);
+        assert_eq!(history.undo_depth(), 3);
+
+        // Nor a version that already has children
+        history.undo();
+        history.push_state(&["ab".into(), "xyw".into()], p, p, None);
+        assert_eq!(history.undo_depth(), 3);
+        assert_eq!(history.branches().len(), 2);
+
+        // Transactions are boundaries on both sides
+        history.begin_transaction();
+        history.push_state(&["ab".into(), "xywv".into()], p, p, None);
+        history.commit();
+        history.push_state(&["ab".into(), "xywvu".into()], p, p, None);
+        assert_eq!(history.undo_depth(), 5);
+    }
+
+    #[test]
+    fn test_coalesce_window() {
+        let mut history: History<Row> = History::new();
+        let p = Position::default();
+        history.push_state(&["a".into()], p, p, None);
+        history.push_state(&["ab".into()], p, p, None);
+        history.push_state(&["abc".into()], p, p, None);
+        assert_eq!(history.undo_depth(), 2);
+
+        history.coalesce_window = Duration::from_millis(1);
+        std::thread::sleep(Duration::from_millis(5));
+        history.push_state(&["abcd".into()], p, p, None);
+        assert_eq!(history.undo_depth(), 3);
+    }
+
+    #[test]
+    fn test_undo_to_root() {
+        let mut history: History<Row> = History::new();
+        history.coalesce_window = Duration::from_secs(3600);
+        let p = Position::default();
+        let rows = |text: &str| text.split('|').map(Row::from).collect::<Vec<_>>();
+        let root = rows("a|b|c");
+        history.push_state(&root, p, p, None);
+        // Fewer rows, then more, merged into the first change or not
+        for text in ["a", "a|x|y|z", "b|x", "b|x|y|z|w", "", "q|r"] {
+            history.push_state(&rows(text), p, p, None);
+        }
+        history.begin_transaction();
+        history.push_state(&rows("1|2|3|4|5|6"), p, p, None);
+        history.push_state(&rows("1"), p, p, None);
+        history.commit();
+        history.mark_boundary();
+        history.push_state(&rows("1|2"), p, p, None);
+
+        while history.undo() {}
+        assert_eq!(history.current, root);
+        while history.redo() {}
+        assert_eq!(history.current, rows("1|2"));
+    }
 }
@@ -0,0 +1,641 @@
+//! A tiny message catalog for all user-visible strings.
+//!
+//! Every language is a single table of `(id, message)` pairs registered in
+//! `CATALOGS`. Messages may contain `{}` placeholders which are filled in order by
+//! the `t!` macro. Lookups fall back to English, then to the id itself.
+
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+type Table = &'static [(&'static str, &'static str)];
+
+static ZH_CN: Table = &[
+    // Dialogs
+    ("dialog.save_changes", "是否要保存对 {} 的更改？"),
+    ("dialog.enter_filename", "请输入文件名: "),
+    ("dialog.overwrite", "文件 {} 已存在，是否覆盖？"),
+    (
+        "dialog.overwrite_open",
+        "{} 已在另一个缓冲区中打开，覆盖后那个缓冲区会被当前缓冲区取代，是否继续？",
+    ),
+    ("dialog.undo_branch", "选择要跳转的撤销分支: "),
+    (
+        "dialog.code_points",
+        "Unicode 码位（如 2192 或 U+1F600，用空格分隔）: ",
+    ),
+    ("dialog.invalid_code_point", "无效的码位: {}"),
+    (
+        "dialog.goto",
+        "跳转到行:列（列前加 c 按字符数、b 按字节数计，如 12:c5、:b40）: ",
+    ),
+    ("dialog.invalid_goto", "无效的位置: {}"),
+    ("button.go", "跳转"),
+    ("dialog.insert_file", "要插入的文件（Tab 补全）: "),
+    ("dialog.switch_buffer", "切换缓冲区"),
+    ("dialog.recent_files", "最近打开的文件"),
+    ("dialog.keys", "快捷键"),
+    ("dialog.outline", "跳转到定义或标题"),
+    ("dialog.filter", "筛选：{}"),
+    ("outline.empty", "没有找到定义或标题"),
+    ("dialog.insert_variable", "插入变量"),
+    ("recovery.saved", "未保存的更改已写入 {}"),
+    ("variable.date", "日期"),
+    ("variable.time", "时间"),
+    ("variable.iso_week", "ISO 周"),
+    ("variable.file_name", "文件名"),
+    ("variable.file_path", "完整路径"),
+    ("dialog.list_separator", "、"),
+    ("dialog.binary_file", "{} 看起来是二进制文件，仍然插入吗？"),
+    ("button.save", "保存"),
+    ("button.dont_save", "不保存"),
+    ("button.cancel", "取消"),
+    ("button.overwrite", "覆盖"),
+    ("button.ok", "好吧"),
+    ("button.insert", "插入"),
+    ("button.delete", "删除"),
+    (
+        "dialog.delete",
+        "把 {} 移到回收站吗？缓冲区中的内容会保留为未保存的缓冲区",
+    ),
+    (
+        "dialog.delete_permanently",
+        "无法移到回收站。要永久删除 {} 吗？此操作无法撤销",
+    ),
+    ("delete.no_file", "当前缓冲区没有对应的文件"),
+    ("delete.trashed", "已将 {} 移到回收站"),
+    ("delete.deleted", "已永久删除 {}"),
+    ("button.revert", "还原"),
+    (
+        "dialog.revert",
+        "放弃所有更改并从磁盘重新读取 {} 吗？还原之后仍可以撤销",
+    ),
+    ("revert.no_file", "无文件可还原"),
+    ("revert.done", "已从磁盘重新读取 {}"),
+    ("button.open_whole", "全部打开"),
+    ("button.preview", "只读预览前 {}MB"),
+    (
+        "dialog.large_file",
+        "{} 有 {} MB，全部打开可能很慢。要全部打开，还是只读地预览前 {} MB？",
+    ),
+    ("preview.read_only", "预览的文件为只读，不能编辑或保存"),
+    // Status bar
+    ("status.untitled", "Untitled"),
+    ("status.unsaved", "{} (未保存)"),
+    ("status.preview", "{} (预览前 {}MB)"),
+    ("status.position", "行 {}，列 {}"),
+    ("status.offset", "字节 {}（{}%）"),
+    ("status.columns", "（第 {} 个字符，第 {} 字节）"),
+    ("status.stats", "{} 字符，{} 词"),
+    ("status.long_lines", "{} 行过长"),
+    ("long_line.none", "没有超过 {} 列的行"),
+    ("long_line.no_limit", "没有设置 max_line_length"),
+    ("status.anomalies", "⚠ {} 个隐形字符"),
+    ("anomaly.none", "没有隐形字符"),
+    (
+        "anomaly.nothing_to_clean",
+        "没有可以清理的不换行空格或零宽字符",
+    ),
+    ("anomaly.cleaned", "已清理 {} 个隐形字符"),
+    ("button.replace", "替换"),
+    (
+        "dialog.clean_anomalies",
+        "清理 {} 个隐形字符吗？不换行空格换成普通空格，零宽字符删除，双向文本控制字符保持不变",
+    ),
+    ("status.selection", "已选择 {} 行，{} 个字符"),
+    ("status.window_too_small", "窗口过小"),
+    // Undo history
+    ("history.undo", "撤销 ({} 步可重做)"),
+    ("history.redo", "重做 ({} 步可撤销)"),
+    ("history.nothing_to_undo", "没有可撤销的操作"),
+    ("history.nothing_to_redo", "没有可重做的操作"),
+    ("history.branch", "{} 步  {}"),
+    ("history.branch_active", "{} 步  {}  (当前)"),
+    ("time.just_now", "刚刚"),
+    ("time.seconds_ago", "{} 秒前"),
+    ("time.minutes_ago", "{} 分钟前"),
+    ("time.hours_ago", "{} 小时前"),
+    // Errors
+    ("error.title", "错误"),
+    ("error.message", "错误: {}"),
+    ("error.read_failed", "文件读取失败"),
+    ("error.save_failed", "保存失败"),
+    ("error.delete_failed", "删除失败"),
+    ("error.syntax_failed", "语法高亮加载失败"),
+    ("error.theme_failed", "颜色主题加载失败"),
+    ("error.is_directory", "输入的文件名是一个目录"),
+    ("error.autosave_failed", "自动保存失败: {}"),
+    // Configuration
+    ("config.warning", "配置文件有误: {}"),
+    ("config.unknown_key", "未知的配置项 {}"),
+    ("config.invalid_value", "配置项 {} 的值 {} 无效"),
+    ("config.unknown_command", "未知的命令 {}"),
+    ("config.invalid_key", "无效的按键 {}"),
+    ("config.key_conflict", "{} 原本绑定到 {}，现在改为 {}"),
+    (
+        "clipboard.copy_failed",
+        "无法访问系统剪贴板，仅复制到编辑器内部: {}",
+    ),
+    ("clipboard.copied_as", "已复制为 {} 格式"),
+    ("line_ending.converted", "换行符已转换为 {}"),
+    ("reload.syntax", "已重新加载语法高亮 {}"),
+    ("reload.theme", "已重新加载颜色主题"),
+    ("reload.failed", "重新加载失败: {}"),
+    ("fold.placeholder", "… {} 行"),
+    ("follow.following", "跟随中"),
+    ("follow.paused", "跟随已暂停"),
+    ("follow.read_only", "跟随模式下文件为只读"),
+    ("follow.reloaded", "文件变短了，已重新加载"),
+    ("open.not_found", "找不到文件 {}"),
+    ("recent.empty", "还没有打开过文件"),
+    ("recent.discarded", "恢复未保存就关闭的缓冲区（{} 个）"),
+    ("dialog.discarded", "恢复未保存就关闭的缓冲区"),
+    ("recent.missing", "{} 已不存在，已从列表中移除"),
+    ("url.opening", "正在打开 {}"),
+    ("url.failed", "无法打开链接: {}"),
+    ("count.result", "{}: {} 处，分布在 {} 行"),
+    ("search.label", "查找"),
+    ("search.none", "无结果"),
+    ("search.wrapped", "已从头继续"),
+    ("count.nothing", "没有选中内容，光标处也没有单词"),
+    ("transpose.nothing", "这里没有可以交换的两个字符或单词"),
+    ("register.prompt", "寄存器：按一个字母或数字"),
+    ("register.selected", "下一次复制、剪切或粘贴使用寄存器 {}"),
+    ("key.unbound", "{} 没有绑定命令"),
+    ("split.none", "视图没有拆分"),
+    ("register.copied", "已复制到寄存器 {}"),
+    ("register.empty", "寄存器 {} 是空的"),
+    ("register.none", "还没有复制到任何寄存器"),
+    ("dialog.registers", "粘贴寄存器"),
+    ("count.single_line", "只能统计单行内的文本"),
+    ("dialog.transform", "转换选中内容"),
+    ("transform.base64_encode", "Base64 编码"),
+    ("transform.base64_decode", "Base64 解码"),
+    ("transform.url_encode", "URL 编码"),
+    ("transform.url_decode", "URL 解码"),
+    ("transform.hex_encode", "转为十六进制"),
+    ("transform.hex_decode", "从十六进制还原"),
+    ("transform.no_selection", "请先选中要转换的文本"),
+    ("dialog.line_operations", "整理全文"),
+    ("lines.remove_blank", "删除所有空行"),
+    ("lines.squeeze_blank", "将连续的空行合并为一行"),
+    ("lines.strip_ansi", "删除 ANSI 转义序列"),
+    ("lines.removed", "删除了 {} 行"),
+    ("normalize.loaded", "已将 {} 行转换为 Unicode NFC 形式"),
+    ("about.built", "构建于 {}（{}）"),
+    ("about.syntax", "当前语法：{}"),
+    ("about.syntax_dir", "语法定义目录：{}"),
+    ("about.builtin", "（不存在，使用内置定义）"),
+    ("about.theme", "主题：{}"),
+    ("about.config", "配置文件：{}"),
+    ("about.none", "无"),
+    ("lines.ansi_stripped", "删除了 {} 个转义序列"),
+    ("transform.invalid_base64", "不是有效的 Base64"),
+    ("transform.invalid_url", "不是有效的 URL 编码"),
+    ("transform.invalid_hex", "不是有效的十六进制"),
+    ("transform.not_utf8", "解码结果不是 UTF-8 文本"),
+    ("json.parse_failed", "JSON 格式错误"),
+    ("json.error_at", "第 {} 行第 {} 列: {}"),
+    ("json.unexpected", "意外的字符 '{}'"),
+    ("json.unexpected_end", "意外的结尾"),
+    ("json.invalid_escape", "字符串中有无效的转义"),
+    ("json.control_char", "字符串中有控制字符"),
+    ("json.invalid_number", "无效的数字"),
+    ("json.too_deep", "嵌套层数过多"),
+    ("format.failed", "格式化失败"),
+    ("format.no_command", "没有为此文件配置格式化命令"),
+    ("format.timeout", "格式化程序在 {} 秒内没有完成"),
+    ("format.exit_status", "格式化程序异常退出 ({})"),
+    ("format.invalid_output", "格式化程序的输出不是有效的 UTF-8"),
+    ("io.addr_in_use", "地址被占用"),
+    ("io.addr_not_available", "地址不可用"),
+    ("io.already_exists", "文件已存在"),
+    ("io.argument_list_too_long", "参数列表过长"),
+    ("io.broken_pipe", "管道已断开"),
+    ("io.connection_aborted", "连接已中止"),
+    ("io.connection_refused", "连接被拒绝"),
+    ("io.connection_reset", "连接已重置"),
+    ("io.crosses_devices", "不能跨设备进行链接或重命名"),
+    ("io.deadlock", "检测到死锁"),
+    ("io.directory_not_empty", "文件夹不是空的，里面还有东西"),
+    ("io.executable_file_busy", "可执行文件正在使用中"),
+    ("io.file_too_large", "文件太大"),
+    ("io.host_unreachable", "主机不可达"),
+    ("io.interrupted", "操作被中断"),
+    ("io.invalid_data", "数据无效"),
+    ("io.invalid_input", "输入参数无效"),
+    ("io.is_a_directory", "该路径是一个目录"),
+    ("io.network_down", "网络连接已断开"),
+    ("io.network_unreachable", "网络不可达"),
+    ("io.not_a_directory", "不是一个目录"),
+    ("io.not_connected", "未连接"),
+    ("io.not_found", "未找到文件"),
+    ("io.not_seekable", "文件不支持查找"),
+    ("io.other", "发生未知错误"),
+    ("io.out_of_memory", "内存不足（OOM）"),
+    ("io.permission_denied", "需要管理员权限"),
+    ("io.read_only_filesystem", "文件系统为只读"),
+    ("io.resource_busy", "资源正忙"),
+    ("io.stale_network_file_handle", "网络文件句柄已失效"),
+    ("io.storage_full", "存储空间不足"),
+    ("io.timed_out", "操作超时"),
+    ("io.unexpected_eof", "遇到意外 EOF 结束符，拼尽全力无法战胜"),
+    ("io.unknown", "未知错误"),
+];
+
+static EN: Table = &[
+    // Dialogs
+    ("dialog.save_changes", "Save changes to {}?"),
+    ("dialog.enter_filename", "File name: "),
+    ("dialog.overwrite", "{} already exists. Overwrite it?"),
+    (
+        "dialog.overwrite_open",
+        "{} is open in another buffer, which this one will replace. Overwrite it?",
+    ),
+    ("dialog.undo_branch", "Jump to undo branch: "),
+    (
+        "dialog.code_points",
+        "Code points (e.g. 2192 or U+1F600, space separated): ",
+    ),
+    ("dialog.invalid_code_point", "Invalid code point: {}"),
+    (
+        "dialog.goto",
+        "Go to line:column (c or b before the column counts characters or bytes, e.g. 12:c5, :b40): ",
+    ),
+    ("dialog.invalid_goto", "Invalid position: {}"),
+    ("button.go", "Go"),
+    ("dialog.insert_file", "File to insert (Tab completes): "),
+    ("dialog.switch_buffer", "Switch buffer"),
+    ("dialog.recent_files", "Recent files"),
+    ("dialog.keys", "Keys"),
+    ("dialog.outline", "Go to a definition or heading"),
+    ("dialog.filter", "Filter: {}"),
+    ("outline.empty", "No definitions or headings found"),
+    ("dialog.insert_variable", "Insert variable"),
+    ("recovery.saved", "Unsaved changes were written to {}"),
+    ("variable.date", "Date"),
+    ("variable.time", "Time"),
+    ("variable.iso_week", "ISO week"),
+    ("variable.file_name", "File name"),
+    ("variable.file_path", "Full path"),
+    ("dialog.list_separator", ", "),
+    (
+        "dialog.binary_file",
+        "{} looks like a binary file. Insert it anyway?",
+    ),
+    ("button.save", "Save"),
+    ("button.dont_save", "Don't save"),
+    ("button.cancel", "Cancel"),
+    ("button.overwrite", "Overwrite"),
+    ("button.ok", "OK"),
+    ("button.insert", "Insert"),
+    ("button.delete", "Delete"),
+    (
+        "dialog.delete",
+        "Move {} to the trash? The text stays as an unsaved buffer",
+    ),
+    (
+        "dialog.delete_permanently",
+        "The file can't be moved to the trash. Delete {} permanently? This can't be undone",
+    ),
+    ("delete.no_file", "The buffer has no file"),
+    ("delete.trashed", "Moved {} to the trash"),
+    ("delete.deleted", "Deleted {} permanently"),
+    ("button.revert", "Revert"),
+    (
+        "dialog.revert",
+        "Discard all changes and read {} again from disk? The revert can be undone",
+    ),
+    ("revert.no_file", "No file to revert to"),
+    ("revert.done", "Read {} again from disk"),
+    ("button.open_whole", "Open whole"),
+    ("button.preview", "Preview first {} MB"),
+    (
+        "dialog.large_file",
+        "{} is {} MB and may be slow to open. Open it whole, or preview its first {} MB read-only?",
+    ),
+    (
+        "preview.read_only",
+        "The file is a read-only preview and can't be edited or saved",
+    ),
+    // Status bar
+    ("status.untitled", "Untitled"),
+    ("status.unsaved", "{} (unsaved)"),
+    ("status.preview", "{} (preview of first {} MB)"),
+    ("status.position", "Ln {}, Col {}"),
+    ("status.offset", "Byte {} ({}%)"),
+    ("status.columns", " (char {}, byte {})"),
+    ("status.stats", "{} chars, {} words"),
+    ("status.long_lines", "{} long lines"),
+    ("long_line.none", "No line is wider than {} columns"),
+    ("long_line.no_limit", "max_line_length is not set"),
+    ("status.anomalies", "⚠ {} invisible chars"),
+    ("anomaly.none", "No invisible characters"),
+    (
+        "anomaly.nothing_to_clean",
+        "No no-break spaces or zero-width characters to clean up",
+    ),
+    ("anomaly.cleaned", "Cleaned up {} invisible characters"),
+    ("button.replace", "Replace"),
+    (
+        "dialog.clean_anomalies",
+        "Clean up {} invisible characters? No-break spaces become plain spaces and zero-width characters are removed; bidirectional controls are kept",
+    ),
+    ("status.selection", "{} lines, {} chars selected"),
+    ("status.window_too_small", "Window too small"),
+    // Undo history
+    ("history.undo", "Undo ({} steps to redo)"),
+    ("history.redo", "Redo ({} steps to undo)"),
+    ("history.nothing_to_undo", "Nothing to undo"),
+    ("history.nothing_to_redo", "Nothing to redo"),
+    ("history.branch", "{} steps  {}"),
+    ("history.branch_active", "{} steps  {}  (current)"),
+    ("time.just_now", "just now"),
+    ("time.seconds_ago", "{} seconds ago"),
+    ("time.minutes_ago", "{} minutes ago"),
+    ("time.hours_ago", "{} hours ago"),
+    // Errors
+    ("error.title", "Error"),
+    ("error.message", "Error: {}"),
+    ("error.read_failed", "Failed to read file"),
+    ("error.save_failed", "Failed to save"),
+    ("error.delete_failed", "Failed to delete the file"),
+    ("error.syntax_failed", "Failed to load syntax highlighting"),
+    ("error.theme_failed", "Failed to load theme"),
+    ("error.is_directory", "The file name is a directory"),
+    ("error.autosave_failed", "Autosave failed: {}"),
+    // Configuration
+    ("config.warning", "Problem in configuration: {}"),
+    ("config.unknown_key", "Unknown setting {}"),
+    ("config.invalid_value", "Invalid value for setting {}: {}"),
+    ("config.unknown_command", "Unknown command {}"),
+    ("config.invalid_key", "Invalid key binding {}"),
+    ("config.key_conflict", "{} was bound to {}, now bound to {}"),
+    (
+        "clipboard.copy_failed",
+        "System clipboard unavailable, copied within the editor only: {}",
+    ),
+    ("clipboard.copied_as", "Copied as {}"),
+    ("line_ending.converted", "Line endings converted to {}"),
+    ("reload.syntax", "Reloaded syntax highlighting for {}"),
+    ("reload.theme", "Reloaded theme"),
+    ("reload.failed", "Reload failed: {}"),
+    ("fold.placeholder", "… {} lines"),
+    ("follow.following", "FOLLOWING"),
+    ("follow.paused", "PAUSED"),
+    ("follow.read_only", "The file is read-only while following"),
+    ("follow.reloaded", "The file got shorter and was reloaded"),
+    ("open.not_found", "File not found: {}"),
+    ("recent.empty", "No files opened yet"),
+    ("transpose.nothing", "Nothing to swap here"),
+    ("register.prompt", "Register: press a letter or digit"),
+    (
+        "register.selected",
+        "The next copy, cut or paste uses register {}",
+    ),
+    ("key.unbound", "{} is not bound to anything"),
+    ("split.none", "The view is not split"),
+    ("register.copied", "Copied to register {}"),
+    ("register.empty", "Register {} is empty"),
+    ("register.none", "Nothing has been copied to a register yet"),
+    ("dialog.registers", "Paste a register"),
+    (
+        "recent.discarded",
+        "Restore a buffer closed without saving ({})",
+    ),
+    ("dialog.discarded", "Restore a buffer closed without saving"),
+    (
+        "recent.missing",
+        "{} no longer exists and was removed from the list",
+    ),
+    ("url.opening", "Opening {}"),
+    ("url.failed", "Couldn't open the link: {}"),
+    ("count.result", "{}: {} matches on {} lines"),
+    ("search.label", "Find"),
+    ("search.none", "No results"),
+    ("search.wrapped", "Search wrapped around"),
+    (
+        "count.nothing",
+        "Nothing is selected and there is no word at the cursor",
+    ),
+    (
+        "count.single_line",
+        "Only text within one line can be counted",
+    ),
+    ("dialog.transform", "Transform selection"),
+    ("transform.base64_encode", "Base64 encode"),
+    ("transform.base64_decode", "Base64 decode"),
+    ("transform.url_encode", "URL encode"),
+    ("transform.url_decode", "URL decode"),
+    ("transform.hex_encode", "Bytes to hex"),
+    ("transform.hex_decode", "Hex to bytes"),
+    (
+        "transform.no_selection",
+        "Select the text to transform first",
+    ),
+    ("dialog.line_operations", "Clean up the buffer"),
+    ("lines.remove_blank", "Delete all blank lines"),
+    (
+        "lines.squeeze_blank",
+        "Squeeze runs of blank lines into one",
+    ),
+    ("lines.strip_ansi", "Strip ANSI escape sequences"),
+    ("lines.removed", "Deleted {} lines"),
+    ("normalize.loaded", "Normalized {} lines to Unicode NFC"),
+    ("about.built", "Built on {} ({})"),
+    ("about.syntax", "Syntax: {}"),
+    ("about.syntax_dir", "Syntax definitions: {}"),
+    ("about.builtin", " (missing, using the built-in ones)"),
+    ("about.theme", "Theme: {}"),
+    ("about.config", "Configuration file: {}"),
+    ("about.none", "none"),
+    ("lines.ansi_stripped", "Removed {} escape sequences"),
+    ("transform.invalid_base64", "Not valid Base64"),
+    ("transform.invalid_url", "Not valid URL encoding"),
+    ("transform.invalid_hex", "Not valid hex"),
+    ("transform.not_utf8", "The decoded bytes are not UTF-8 text"),
+    ("json.parse_failed", "Invalid JSON"),
+    ("json.error_at", "Line {}, column {}: {}"),
+    ("json.unexpected", "Unexpected '{}'"),
+    ("json.unexpected_end", "Unexpected end of input"),
+    ("json.invalid_escape", "Invalid escape in a string"),
+    ("json.control_char", "Control character in a string"),
+    ("json.invalid_number", "Invalid number"),
+    ("json.too_deep", "Nested too deeply"),
+    ("format.failed", "Formatting failed"),
+    (
+        "format.no_command",
+        "No format command is configured for this file",
+    ),
+    (
+        "format.timeout",
+        "The formatter did not finish within {} seconds",
+    ),
+    ("format.exit_status", "The formatter exited abnormally ({})"),
+    (
+        "format.invalid_output",
+        "The formatter's output is not valid UTF-8",
+    ),
+    ("io.addr_in_use", "Address in use"),
+    ("io.addr_not_available", "Address not available"),
+    ("io.already_exists", "File already exists"),
+    ("io.argument_list_too_long", "Argument list too long"),
+    ("io.broken_pipe", "Broken pipe"),
+    ("io.connection_aborted", "Connection aborted"),
+    ("io.connection_refused", "Connection refused"),
+    ("io.connection_reset", "Connection reset"),
+    ("io.crosses_devices", "Cannot link or rename across devices"),
+    ("io.deadlock", "Deadlock detected"),
+    ("io.directory_not_empty", "Directory not empty"),
+    ("io.executable_file_busy", "Executable file busy"),
+    ("io.file_too_large", "File too large"),
+    ("io.host_unreachable", "Host unreachable"),
+    ("io.interrupted", "Operation interrupted"),
+    ("io.invalid_data", "Invalid data"),
+    ("io.invalid_input", "Invalid input"),
+    ("io.is_a_directory", "Path is a directory"),
+    ("io.network_down", "Network down"),
+    ("io.network_unreachable", "Network unreachable"),
+    ("io.not_a_directory", "Not a directory"),
+    ("io.not_connected", "Not connected"),
+    ("io.not_found", "File not found"),
+    ("io.not_seekable", "File is not seekable"),
+    ("io.other", "An unknown error occurred"),
+    ("io.out_of_memory", "Out of memory"),
+    ("io.permission_denied", "Permission denied"),
+    ("io.read_only_filesystem", "Read-only filesystem"),
+    ("io.resource_busy", "Resource busy"),
+    ("io.stale_network_file_handle", "Stale network file handle"),
+    ("io.storage_full", "No space left on device"),
+    ("io.timed_out", "Operation timed out"),
+    ("io.unexpected_eof", "Unexpected end of file"),
+    ("io.unknown", "Unknown error"),
+];
+
+/// All available languages, keyed by their locale prefix. The first one is the fallback.
+static CATALOGS: &[(&str, Table)] = &[("en", EN), ("zh", ZH_CN)];
+
+/// Index into `CATALOGS` of the active language.
+static LANGUAGE: AtomicUsize = AtomicUsize::new(0);
+
+/// Find the catalog matching a locale string such as `zh_CN.UTF-8`, `en-US` or `zh`.
+fn find_catalog(locale: &str) -> Option<usize> {
+    let code = locale
+        .split(['_', '-', '.', '@'])
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    CATALOGS.iter().position(|(c, _)| *c == code)
+}
+
+/// Select the language from the usual locale environment variables, in POSIX
+/// precedence order. Unknown or missing locales select English.
+pub fn init_from_env() {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty());
+    LANGUAGE.store(
+        locale.as_deref().and_then(find_catalog).unwrap_or(0),
+        Ordering::Relaxed,
+    );
+}
+
+/// Select the language explicitly, e.g. from `--lang`. Returns `false` if there is
+/// no catalog for it.
+pub fn set_language(locale: &str) -> bool {
+    if let Some(index) = find_catalog(locale) {
+        LANGUAGE.store(index, Ordering::Relaxed);
+        true
+    } else {
+        false
+    }
+}
+
+/// Whether there is a catalog for the given locale.
+pub fn is_supported(locale: &str) -> bool {
+    find_catalog(locale).is_some()
+}
+
+/// The codes of all available languages.
+pub fn languages() -> impl Iterator<Item = &'static str> {
+    CATALOGS.iter().map(|(code, _)| *code)
+}
+
+/// Look up a message in the active language.
+pub fn message(id: &'static str) -> &'static str {
+    let lookup = |table: Table| table.iter().find(|(k, _)| *k == id).map(|(_, v)| *v);
+    lookup(CATALOGS[LANGUAGE.load(Ordering::Relaxed)].1)
+        .or_else(|| lookup(CATALOGS[0].1))
+        .unwrap_or(id)
+}
+
+/// Fill the `{}` placeholders of a message in order.
+pub fn format(message: &str, args: &[&dyn Display]) -> String {
+    let mut args = args.iter();
+    let mut parts = message.split("{}");
+    let mut result = parts.next().unwrap_or_default().to_string();
+    for part in parts {
+        if let Some(arg) = args.next() {
+            result += &arg.to_string();
+        }
+        result += part;
+    }
+    result
+}
+
+/// Look up a message and fill in its arguments.
+///
+/// `t!("button.save")` returns a `&'static str`, `t!("dialog.overwrite", name)` a `String`.
+#[macro_export]
+macro_rules! t {
+    ($id:expr) => {
+        $crate::i18n::message($id)
+    };
+    ($id:expr, $($arg:expr),+ $(,)?) => {
+        $crate::i18n::format(
+            $crate::i18n::message($id),
+            &[$(&$arg as &dyn ::std::fmt::Display),+],
+        )
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalogs_complete() {
+        for (code, table) in CATALOGS {
+            for (id, _) in CATALOGS[0].1.iter() {
+                assert!(table.iter().any(|(k, _)| k == id), "{code} is missing {id}");
+            }
+            for (id, message) in table.iter() {
+                let (_, fallback) = CATALOGS[0].1.iter().find(|(k, _)| k == id).unwrap();
+                assert_eq!(
+                    message.matches("{}").count(),
+                    fallback.matches("{}").count(),
+                    "{code} has mismatched placeholders in {id}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_catalog() {
+        assert_eq!(find_catalog("zh_CN.UTF-8"), Some(1));
+        assert_eq!(find_catalog("zh-TW"), Some(1));
+        assert_eq!(find_catalog("en_US.UTF-8"), Some(0));
+        assert_eq!(find_catalog("EN"), Some(0));
+        assert_eq!(find_catalog("fr_FR.UTF-8"), None);
+        assert_eq!(find_catalog("C"), None);
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format("{} of {}", &[&1, &"two"]), "1 of two");
+        assert_eq!(format("no placeholders", &[&1]), "no placeholders");
+        assert_eq!(format("{} and {}", &[&1]), "1 and ");
+    }
+}
@@ -0,0 +1,409 @@
+//! A small JSON parser and printer for pretty-printing and minifying. Numbers and
+//! strings are kept as written, so big numbers and escapes come out unchanged, and
+//! object keys keep their order.
+
+use std::ops::Range;
+
+use crate::t;
+
+/// How deeply arrays and objects may nest, so that parsing, printing and dropping the
+/// tree, which all recurse, stay well within the stack.
+const MAX_DEPTH: usize = 512;
+
+/// A parsed JSON value and where it was in the source text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub value: Value,
+    /// The byte range of the value in the source.
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// A number, string, `true`, `false` or `null`, as written.
+    Literal(String),
+    Array(Vec<Node>),
+    /// The members in their order, each key as written with its quotes.
+    Object(Vec<(String, Node)>),
+}
+
+/// Why the text isn't JSON, and at which byte.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl Node {
+    /// Parse a whole JSON document, with whitespace around it.
+    pub fn parse(text: &str) -> Result<Node, ParseError> {
+        let mut parser = Parser {
+            text,
+            pos: 0,
+            depth: 0,
+        };
+        let node = parser.value()?;
+        parser.skip_whitespace();
+        match parser.peek() {
+            Some(c) => Err(parser.error(t!("json.unexpected", c))),
+            None => Ok(node),
+        }
+    }
+
+    /// Print the value with `indent` spaces per level, or on one line without any
+    /// whitespace if `indent` is `None`. Also returns where the value at `path` (as
+    /// from `path_at`) starts in the output.
+    pub fn print(&self, indent: Option<usize>, path: &[usize]) -> (String, usize) {
+        let mut printer = Printer {
+            out: String::new(),
+            indent,
+            target: path,
+            found: 0,
+        };
+        printer.node(self, 0, Some(0));
+        (printer.out, printer.found)
+    }
+
+    /// The positions among their siblings of the values leading down to the innermost
+    /// one containing byte `offset` of the source.
+    pub fn path_at(&self, offset: usize) -> Vec<usize> {
+        let mut path = Vec::new();
+        let mut node = self;
+        loop {
+            let children: Vec<&Node> = match &node.value {
+                Value::Literal(_) => break,
+                Value::Array(items) => items.iter().collect(),
+                Value::Object(members) => members.iter().map(|(_, node)| node).collect(),
+            };
+            let Some(i) = (children.iter()).position(|child| child.span.contains(&offset)) else {
+                break;
+            };
+            path.push(i);
+            node = children[i];
+        }
+        path
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    /// How many arrays and objects the current position is in.
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn error(&self, message: String) -> ParseError {
+        ParseError {
+            offset: self.pos,
+            message,
+        }
+    }
+
+    /// An error for the character at the current position, or the end of the text.
+    fn unexpected(&self) -> ParseError {
+        match self.peek() {
+            Some(c) => self.error(t!("json.unexpected", c)),
+            None => self.error(t!("json.unexpected_end").to_string()),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    /// Skip whitespace and then `c`, which must be there.
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        self.skip_whitespace();
+        if self.peek() != Some(c) {
+            return Err(self.unexpected());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Node, ParseError> {
+        self.skip_whitespace();
+        let start = self.pos;
+        let value = match self.peek() {
+            Some(c @ ('{' | '[')) => {
+                if self.depth == MAX_DEPTH {
+                    return Err(self.error(t!("json.too_deep").to_string()));
+                }
+                self.depth += 1;
+                let value = if c == '{' {
+                    self.object()
+                } else {
+                    self.array()
+                };
+                self.depth -= 1;
+                value?
+            }
+            Some('"') => Value::Literal(self.string()?.to_string()),
+            Some('-' | '0'..='9') => Value::Literal(self.number()?.to_string()),
+            Some(_) => {
+                let word = ["true", "false", "null"]
+                    .into_iter()
+                    .find(|word| self.text[self.pos..].starts_with(word))
+                    .ok_or_else(|| self.unexpected())?;
+                self.pos += word.len();
+                Value::Literal(word.to_string())
+            }
+            None => return Err(self.unexpected()),
+        };
+        Ok(Node {
+            value,
+            span: start..self.pos,
+        })
+    }
+
+    fn object(&mut self) -> Result<Value, ParseError> {
+        self.pos += 1;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.unexpected());
+            }
+            let key = self.string()?.to_string();
+            self.expect(':')?;
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(members));
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, ParseError> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(Value::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(items));
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
+    }
+
+    /// A string literal with its quotes, as written.
+    fn string(&mut self) -> Result<&str, ParseError> {
+        let start = self.pos;
+        self.pos += 1;
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    self.pos += 1;
+                    return Ok(&self.text[start..self.pos]);
+                }
+                Some('\\') => {
+                    self.pos += 1;
+                    let escape = self.text[self.pos..].chars().next();
+                    let len = match escape {
+                        Some('"' | '\\' | '/' | 'b' | 'f' | 'n' | 'r' | 't') => 1,
+                        Some('u')
+                            if (self.text[self.pos + 1..].chars().take(4))
+                                .filter(char::is_ascii_hexdigit)
+                                .count()
+                                == 4 =>
+                        {
+                            5
+                        }
+                        _ => return Err(self.error(t!("json.invalid_escape").to_string())),
+                    };
+                    self.pos += len;
+                }
+                Some(c) if c < ' ' => return Err(self.error(t!("json.control_char").to_string())),
+                Some(c) => self.pos += c.len_utf8(),
+                None => return Err(self.unexpected()),
+            }
+        }
+    }
+
+    /// A number, as written: `-`, then `0` or digits not starting with `0`, then an
+    /// optional fraction and exponent.
+    fn number(&mut self) -> Result<&str, ParseError> {
+        let start = self.pos;
+        let bytes = self.text.as_bytes();
+        let digits = |pos: &mut usize| {
+            let from = *pos;
+            while bytes.get(*pos).is_some_and(u8::is_ascii_digit) {
+                *pos += 1;
+            }
+            *pos > from
+        };
+        let mut pos = self.pos;
+        if bytes[pos] == b'-' {
+            pos += 1;
+        }
+        let valid = match bytes.get(pos) {
+            Some(b'0') => {
+                pos += 1;
+                true
+            }
+            _ => digits(&mut pos),
+        } && (bytes.get(pos) != Some(&b'.') || {
+            pos += 1;
+            digits(&mut pos)
+        }) && (!matches!(bytes.get(pos), Some(b'e' | b'E')) || {
+            pos += 1;
+            if matches!(bytes.get(pos), Some(b'+' | b'-')) {
+                pos += 1;
+            }
+            digits(&mut pos)
+        });
+        self.pos = pos;
+        if !valid {
+            return Err(self.error(t!("json.invalid_number").to_string()));
+        }
+        Ok(&self.text[start..pos])
+    }
+}
+
+struct Printer<'a> {
+    out: String,
+    indent: Option<usize>,
+    /// The path of the value whose output position is wanted, and that position.
+    target: &'a [usize],
+    found: usize,
+}
+
+impl Printer<'_> {
+    /// Print `node` at nesting `depth`. `matched` is how much of the target path leads
+    /// here, if all of the path so far does.
+    fn node(&mut self, node: &Node, depth: usize, matched: Option<usize>) {
+        if matched.is_some() {
+            self.found = self.out.len();
+        }
+        let (open, close, len) = match &node.value {
+            Value::Literal(text) => return self.out.push_str(text),
+            Value::Array(items) => ('[', ']', items.len()),
+            Value::Object(members) => ('{', '}', members.len()),
+        };
+        self.out.push(open);
+        for i in 0..len {
+            if i > 0 {
+                self.out.push(',');
+            }
+            self.line_break(depth + 1);
+            let child = match &node.value {
+                Value::Array(items) => &items[i],
+                Value::Object(members) => {
+                    let (key, child) = &members[i];
+                    self.out.push_str(key);
+                    self.out.push(':');
+                    if self.indent.is_some() {
+                        self.out.push(' ');
+                    }
+                    child
+                }
+                Value::Literal(_) => unreachable!(),
+            };
+            let matched = matched
+                .filter(|&n| self.target.get(n) == Some(&i))
+                .map(|n| n + 1);
+            self.node(child, depth + 1, matched);
+        }
+        if len > 0 {
+            self.line_break(depth);
+        }
+        self.out.push(close);
+    }
+
+    /// Start a new line indented for `depth`, unless minifying.
+    fn line_break(&mut self, depth: usize) {
+        if let Some(indent) = self.indent {
+            self.out.push('\n');
+            self.out.push_str(&" ".repeat(indent * depth));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pretty(text: &str) -> String {
+        Node::parse(text).unwrap().print(Some(2), &[]).0
+    }
+
+    #[test]
+    fn test_print() {
+        let text = r#" {"b": [1, 2.5e-3, {}], "a":{"x" : "é\n"},"c":[ ] , "d": null} "#;
+        assert_eq!(
+            pretty(text),
+            "{\n  \"b\": [\n    1,\n    2.5e-3,\n    {}\n  ],\n  \"a\": {\n    \"x\": \"é\\n\"\n  },\n  \"c\": [],\n  \"d\": null\n}"
+        );
+        let node = Node::parse(text).unwrap();
+        let minified = r#"{"b":[1,2.5e-3,{}],"a":{"x":"é\n"},"c":[],"d":null}"#;
+        assert_eq!(node.print(None, &[]).0, minified);
+        assert_eq!(Node::parse(minified).unwrap().print(None, &[]).0, minified);
+        // Big numbers keep all their digits
+        assert_eq!(
+            pretty("[123456789012345678901234567890]"),
+            "[\n  123456789012345678901234567890\n]"
+        );
+    }
+
+    #[test]
+    fn test_path() {
+        let text = r#"{"a": [10, {"b": true}], "c": "中文"}"#;
+        let node = Node::parse(text).unwrap();
+        let offset = text.find("true").unwrap();
+        assert_eq!(node.path_at(offset), vec![0, 1, 0]);
+        assert_eq!(node.path_at(text.find("中").unwrap()), vec![1]);
+        assert_eq!(node.path_at(0), vec![]);
+
+        let (out, found) = node.print(None, &[0, 1, 0]);
+        assert!(out[found..].starts_with("true"));
+        let (out, found) = node.print(Some(4), &[1]);
+        assert!(out[found..].starts_with("\"中文\""));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |text: &str| Node::parse(text).unwrap_err().offset;
+        assert_eq!(error(r#"{"a": 1,}"#), 8);
+        assert_eq!(error("[1 2]"), 3);
+        assert_eq!(error(r#"{"a" 1}"#), 5);
+        assert_eq!(error("[01]"), 2);
+        assert_eq!(error("[1.]"), 3);
+        assert_eq!(error(r#"["\x"]"#), 3);
+        assert_eq!(error("[\"a\nb\"]"), 3);
+        assert_eq!(error("[tru]"), 1);
+        assert_eq!(error("[1] x"), 4);
+        assert_eq!(error("  "), 2);
+
+        // Nesting too deeply is an error rather than a stack overflow
+        let nested = |depth: usize| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(Node::parse(&nested(MAX_DEPTH)).is_ok());
+        assert_eq!(error(&nested(MAX_DEPTH + 1)), MAX_DEPTH);
+        assert_eq!(error(&"[".repeat(100_000)), MAX_DEPTH);
+    }
+}
@@ -0,0 +1,1025 @@
+use std::collections::HashMap;
+
+use crossterm::event::{
+    KeyCode, KeyEventState, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+};
+
+use crate::t;
+
+/// A key together with the modifiers held down, e.g. `Ctrl+S`, and whether it is on
+/// the keypad. Terminals only tell keypad keys apart with the kitty keyboard protocol.
+pub type Chord = (KeyModifiers, KeyCode, KeyEventState);
+
+/// A direction to scroll the view or move lines in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// Where `Command::MoveCursor` takes the cursor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Motion {
+    Up,
+    Down,
+    Left,
+    Right,
+    WordLeft,
+    WordRight,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    /// The blank line before or after the current paragraph.
+    ParagraphUp,
+    ParagraphDown,
+    /// The start of the first line or the end of the last one.
+    BufferStart,
+    BufferEnd,
+}
+
+/// An editor action. Key and mouse events are translated into commands, which
+/// `Editor::execute` carries out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Command {
+    Save,
+    SaveAs,
+    Quit,
+    /// Clear the innermost transient state, like the selection, or quit if there is
+    /// none.
+    Cancel,
+    SelectAll,
+    Undo,
+    Redo,
+    UndoBranch,
+    Copy,
+    /// Copy the selection with syntax highlighting, as ANSI escapes or HTML.
+    CopyAnsi,
+    CopyHtml,
+    Cut,
+    Paste,
+    /// Paste with the indentation of the line pasted on, in place of the common
+    /// indentation of the pasted lines.
+    PasteReindent,
+    Find,
+    /// Search upwards from the cursor.
+    FindReverse,
+    /// Pick a cleanup of the whole buffer, like deleting blank lines, from a list.
+    LineOperations,
+    /// Pipe the buffer through the configured formatter.
+    Format,
+    /// Switch every line between LF and CRLF, or unify mixed line endings.
+    ConvertLineEndings,
+    /// Describe the text under the mouse on the bottom line: its syntax token and the
+    /// state its row ends in.
+    ToggleHoverInfo,
+    /// Delete from the cursor to the end of the line, or join the next line if the
+    /// cursor is already there.
+    DeleteToLineEnd,
+    /// Delete from the start of the line to the cursor.
+    DeleteToLineStart,
+    /// Swap the characters before and at the cursor, or the last two at the end of a
+    /// line.
+    TransposeChars,
+    /// Swap the word at or before the cursor with the next one.
+    TransposeWords,
+    /// Read a letter or digit naming the register the next copy, cut or paste uses
+    /// instead of the clipboard.
+    SelectRegister,
+    /// List the registers with their contents, and paste the one picked.
+    Registers,
+    /// Select the word, line, paragraph or whole buffer around the selection.
+    GrowSelection,
+    /// Go back to the selection before the last `GrowSelection`.
+    ShrinkSelection,
+    /// Swap the cursor and the anchor, so the selection can be extended from its
+    /// other end.
+    SwapSelectionEnds,
+    /// Load the syntax definition and theme files again.
+    ReloadConfig,
+    /// Ask for Unicode code points and insert their characters.
+    InsertUnicode,
+    /// Pick the date, the time, the file name or the like from a list and insert it.
+    InsertVariable,
+    /// Insert the current date in `date_format`.
+    InsertDate,
+    /// Rewrite the indentation of the selected lines as whole levels.
+    Reindent,
+    /// Ask for a file and insert its content at the cursor.
+    InsertFile,
+    /// Open the URL under the cursor in the browser, or else the file whose path is
+    /// under it, going to its `:line` if given.
+    OpenPath,
+    /// Hide the lines below the cursor's line that are indented deeper than it.
+    Fold,
+    /// Show the lines folded under the cursor's line again.
+    Unfold,
+    /// Open an empty scratch buffer, keeping the current one open in the background.
+    NewBuffer,
+    /// Pick one of the open buffers to show.
+    SwitchBuffer,
+    /// Count the matches of the selection, or the word under the cursor, in the buffer.
+    CountOccurrences,
+    /// Pick a conversion such as Base64 encoding and apply it to the selection.
+    TransformSelection,
+    /// Pretty-print the selected JSON, or the whole buffer, indented by `tab_width`.
+    JsonPretty,
+    /// Print the selected JSON, or the whole buffer, without any whitespace.
+    JsonMinify,
+    /// Pick a definition or heading from a list of them to jump to.
+    Outline,
+    /// Go to a line and column typed in, the column counted on screen, in characters or
+    /// in bytes.
+    GotoLine,
+    /// Show the version, how the editor was built and where it looks for syntax
+    /// definitions and themes.
+    About,
+    /// List every key with the command it runs, and run the one picked.
+    KeyHelp,
+    /// Move the current file to the trash, or delete it, after asking. The buffer
+    /// keeps its text as a scratch buffer.
+    DeleteFile,
+    /// Throw away all changes, after asking, and read the file again from disk. The
+    /// revert itself can be undone.
+    RevertFile,
+    /// Pick a recently opened file to open in place of the current one.
+    RecentFiles,
+    /// Go to where the next line wider than `max_line_length` crosses it.
+    NextLongLine,
+    /// Select the next hard to see character, like a zero-width space, and name it.
+    NextAnomaly,
+    /// Replace the spaces that do not break with plain ones and remove the zero-width
+    /// characters, after asking.
+    CleanAnomalies,
+    /// Split the view into two panes of the same buffer, one above the other, or go
+    /// back to one.
+    SplitView,
+    /// Move the focus to the other pane of a split view.
+    SwitchPane,
+
+    /// Type a character, replacing the selection.
+    InsertChar(char),
+    InsertTab,
+    NewLine,
+    DeleteBackward,
+    DeleteForward,
+    /// Move the cursor; the flag extends the selection instead of clearing it.
+    MoveCursor(Motion, bool),
+    /// Scroll the view by one line, leaving the cursor where it is; the flag extends
+    /// the selection instead of clearing it.
+    ScrollLine(Direction, bool),
+    /// Scroll the view by a number of lines or columns (mouse wheel).
+    Scroll(Direction, usize),
+    /// Scroll the view up or down by a screenful, leaving the cursor where it is.
+    ScrollPage(Direction),
+    /// Move the selected lines, or the cursor's line, up or down by one.
+    MoveLines(Direction),
+    /// Duplicate the selected lines, or the cursor's line, above or below.
+    DuplicateLines(Direction),
+    /// Copy the selection, or paste if nothing is selected (right click).
+    CopyOrPaste,
+}
+
+impl Command {
+    /// The commands that can be bound in the `[keys]` section of the config file.
+    pub const ALL: [Command; 56] = [
+        Command::Save,
+        Command::SaveAs,
+        Command::Quit,
+        Command::Cancel,
+        Command::SelectAll,
+        Command::Undo,
+        Command::Redo,
+        Command::UndoBranch,
+        Command::Copy,
+        Command::CopyAnsi,
+        Command::CopyHtml,
+        Command::Cut,
+        Command::Paste,
+        Command::PasteReindent,
+        Command::Find,
+        Command::FindReverse,
+        Command::LineOperations,
+        Command::Format,
+        Command::ConvertLineEndings,
+        Command::ToggleHoverInfo,
+        Command::DeleteToLineEnd,
+        Command::DeleteToLineStart,
+        Command::TransposeChars,
+        Command::TransposeWords,
+        Command::SelectRegister,
+        Command::Registers,
+        Command::GrowSelection,
+        Command::ShrinkSelection,
+        Command::SwapSelectionEnds,
+        Command::ReloadConfig,
+        Command::InsertUnicode,
+        Command::InsertVariable,
+        Command::InsertDate,
+        Command::Reindent,
+        Command::InsertFile,
+        Command::OpenPath,
+        Command::Fold,
+        Command::Unfold,
+        Command::NewBuffer,
+        Command::SwitchBuffer,
+        Command::CountOccurrences,
+        Command::TransformSelection,
+        Command::JsonPretty,
+        Command::JsonMinify,
+        Command::NextLongLine,
+        Command::NextAnomaly,
+        Command::CleanAnomalies,
+        Command::RecentFiles,
+        Command::Outline,
+        Command::GotoLine,
+        Command::About,
+        Command::KeyHelp,
+        Command::DeleteFile,
+        Command::RevertFile,
+        Command::SplitView,
+        Command::SwitchPane,
+    ];
+
+    /// The name used for this command in the config file. Only the commands in `ALL`
+    /// can be bound; the others are named for display.
+    pub fn name(self) -> &'static str {
+        match self {
+            Command::Save => "save",
+            Command::SaveAs => "save_as",
+            Command::Quit => "quit",
+            Command::Cancel => "cancel",
+            Command::SelectAll => "select_all",
+            Command::Undo => "undo",
+            Command::Redo => "redo",
+            Command::UndoBranch => "undo_branch",
+            Command::Copy => "copy",
+            Command::CopyAnsi => "copy_ansi",
+            Command::CopyHtml => "copy_html",
+            Command::Cut => "cut",
+            Command::Paste => "paste",
+            Command::PasteReindent => "paste_reindent",
+            Command::Find => "find",
+            Command::FindReverse => "find_reverse",
+            Command::LineOperations => "line_operations",
+            Command::Format => "format",
+            Command::ConvertLineEndings => "convert_line_endings",
+            Command::ToggleHoverInfo => "toggle_hover_info",
+            Command::DeleteToLineEnd => "delete_to_line_end",
+            Command::DeleteToLineStart => "delete_to_line_start",
+            Command::TransposeChars => "transpose_chars",
+            Command::TransposeWords => "transpose_words",
+            Command::SelectRegister => "select_register",
+            Command::Registers => "registers",
+            Command::GrowSelection => "grow_selection",
+            Command::ShrinkSelection => "shrink_selection",
+            Command::SwapSelectionEnds => "swap_selection_ends",
+            Command::ReloadConfig => "reload_config",
+            Command::InsertUnicode => "insert_unicode",
+            Command::InsertVariable => "insert_variable",
+            Command::InsertDate => "insert_date",
+            Command::Reindent => "reindent",
+            Command::InsertFile => "insert_file",
+            Command::OpenPath => "open_path",
+            Command::Fold => "fold",
+            Command::Unfold => "unfold",
+            Command::NewBuffer => "new_buffer",
+            Command::SwitchBuffer => "switch_buffer",
+            Command::CountOccurrences => "count_occurrences",
+            Command::TransformSelection => "transform_selection",
+            Command::JsonPretty => "json_pretty",
+            Command::JsonMinify => "json_minify",
+            Command::NextLongLine => "next_long_line",
+            Command::NextAnomaly => "next_anomaly",
+            Command::CleanAnomalies => "clean_anomalies",
+            Command::RecentFiles => "recent_files",
+            Command::Outline => "outline",
+            Command::GotoLine => "goto_line",
+            Command::About => "about",
+            Command::KeyHelp => "key_help",
+            Command::DeleteFile => "delete_file",
+            Command::RevertFile => "revert_file",
+            Command::SplitView => "split_view",
+            Command::SwitchPane => "switch_pane",
+            Command::InsertChar(_) => "insert_char",
+            Command::InsertTab => "insert_tab",
+            Command::NewLine => "new_line",
+            Command::DeleteBackward => "delete_backward",
+            Command::DeleteForward => "delete_forward",
+            Command::MoveCursor(..) => "move_cursor",
+            Command::ScrollLine(..) => "scroll_line",
+            Command::Scroll(..) => "scroll",
+            Command::ScrollPage(..) => "scroll_page",
+            Command::MoveLines(_) => "move_lines",
+            Command::DuplicateLines(_) => "duplicate_lines",
+            Command::CopyOrPaste => "copy_or_paste",
+        }
+    }
+
+    /// Whether the view should stay where it is instead of following the cursor.
+    pub fn keeps_view(self) -> bool {
+        matches!(
+            self,
+            Command::SelectAll
+                | Command::ScrollLine(..)
+                | Command::Scroll(..)
+                | Command::ScrollPage(..)
+        )
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Command::ALL
+            .into_iter()
+            .find(|command| command.name() == name)
+    }
+
+    fn default_bindings(self) -> &'static [&'static str] {
+        match self {
+            Command::Save => &["ctrl+s"],
+            Command::SaveAs => &["shift+f12"],
+            Command::Quit => &["ctrl+w"],
+            Command::Cancel => &["esc"],
+            Command::SelectAll => &["ctrl+a"],
+            Command::Undo => &["ctrl+z"],
+            Command::Redo => &["ctrl+y"],
+            Command::UndoBranch => &["alt+z"],
+            Command::Copy => &["ctrl+c"],
+            Command::CopyAnsi => &["alt+c"],
+            Command::CopyHtml => &["alt+shift+c"],
+            Command::Cut => &["ctrl+x"],
+            Command::Paste => &["ctrl+v"],
+            // Terminals tend to keep Ctrl+Shift+V for their own paste
+            Command::PasteReindent => &["ctrl+shift+v", "alt+shift+v"],
+            Command::Find => &["ctrl+f"],
+            Command::FindReverse => &["ctrl+shift+f"],
+            Command::Format => &["alt+shift+f"],
+            Command::DeleteToLineEnd => &["ctrl+k"],
+            Command::DeleteToLineStart => &["ctrl+u"],
+            Command::TransposeChars => &["ctrl+t"],
+            Command::TransposeWords => &["alt+t"],
+            Command::SelectRegister => &["alt+'"],
+            Command::Registers => &["alt+shift+r"],
+            Command::GrowSelection => &["alt+shift+right"],
+            Command::ShrinkSelection => &["alt+shift+left"],
+            // Most terminals send Ctrl+Shift+O as Ctrl+O, so offer Alt+O as well
+            Command::SwapSelectionEnds => &["ctrl+shift+o", "alt+o"],
+            Command::ReloadConfig => &["f5"],
+            // Not Ctrl+Shift+U, which usually arrives as Ctrl+U
+            Command::InsertUnicode => &["alt+u"],
+            Command::InsertVariable => &["alt+v"],
+            Command::InsertDate => &["alt+d"],
+            Command::Reindent => &["alt+i"],
+            Command::InsertFile => &["ctrl+r"],
+            Command::OpenPath => &["alt+enter"],
+            Command::Fold => &["alt+-"],
+            Command::Unfold => &["alt+="],
+            Command::NewBuffer => &["ctrl+n"],
+            Command::SwitchBuffer => &["alt+b"],
+            Command::CountOccurrences => &["alt+n"],
+            Command::TransformSelection => &["alt+shift+t"],
+            Command::LineOperations => &["alt+k"],
+            Command::JsonPretty => &["alt+j"],
+            Command::JsonMinify => &["alt+shift+j"],
+            Command::NextLongLine => &["alt+l"],
+            Command::NextAnomaly => &["alt+a"],
+            Command::RecentFiles => &["alt+r"],
+            Command::Outline => &["alt+shift+o"],
+            Command::GotoLine => &["ctrl+g"],
+            Command::About => &["f1"],
+            Command::KeyHelp => &["shift+f1"],
+            // Without the kitty keyboard protocol Ctrl+\ arrives as Ctrl+4
+            Command::SplitView => &["ctrl+\\", "ctrl+4"],
+            Command::SwitchPane => &["f6"],
+            _ => &[],
+        }
+    }
+}
+
+/// The keys for moving around and editing text, which cannot be rebound.
+///
+/// A key pressed with modifiers that have no meaning for it falls back to the entry
+/// without them, dropping Alt first, then Ctrl, then Shift; so `ctrl+alt+up` acts as
+/// `ctrl+up`, and `ctrl+enter` as `enter`.
+const EDITING_KEYS: [(&str, Command); 40] = [
+    ("up", Command::MoveCursor(Motion::Up, false)),
+    ("shift+up", Command::MoveCursor(Motion::Up, true)),
+    ("ctrl+up", Command::ScrollLine(Direction::Up, false)),
+    ("ctrl+shift+up", Command::ScrollLine(Direction::Up, true)),
+    ("alt+up", Command::MoveLines(Direction::Up)),
+    ("alt+shift+up", Command::DuplicateLines(Direction::Up)),
+    ("down", Command::MoveCursor(Motion::Down, false)),
+    ("shift+down", Command::MoveCursor(Motion::Down, true)),
+    ("ctrl+down", Command::ScrollLine(Direction::Down, false)),
+    (
+        "ctrl+shift+down",
+        Command::ScrollLine(Direction::Down, true),
+    ),
+    ("alt+down", Command::MoveLines(Direction::Down)),
+    ("alt+shift+down", Command::DuplicateLines(Direction::Down)),
+    ("left", Command::MoveCursor(Motion::Left, false)),
+    ("shift+left", Command::MoveCursor(Motion::Left, true)),
+    ("ctrl+left", Command::MoveCursor(Motion::WordLeft, false)),
+    (
+        "ctrl+shift+left",
+        Command::MoveCursor(Motion::WordLeft, true),
+    ),
+    ("right", Command::MoveCursor(Motion::Right, false)),
+    ("shift+right", Command::MoveCursor(Motion::Right, true)),
+    ("ctrl+right", Command::MoveCursor(Motion::WordRight, false)),
+    (
+        "ctrl+shift+right",
+        Command::MoveCursor(Motion::WordRight, true),
+    ),
+    ("pageup", Command::MoveCursor(Motion::PageUp, false)),
+    ("shift+pageup", Command::MoveCursor(Motion::PageUp, true)),
+    ("pagedown", Command::MoveCursor(Motion::PageDown, false)),
+    (
+        "shift+pagedown",
+        Command::MoveCursor(Motion::PageDown, true),
+    ),
+    ("home", Command::MoveCursor(Motion::Home, false)),
+    ("shift+home", Command::MoveCursor(Motion::Home, true)),
+    ("end", Command::MoveCursor(Motion::End, false)),
+    ("shift+end", Command::MoveCursor(Motion::End, true)),
+    ("ctrl+home", Command::MoveCursor(Motion::BufferStart, false)),
+    (
+        "ctrl+shift+home",
+        Command::MoveCursor(Motion::BufferStart, true),
+    ),
+    ("ctrl+end", Command::MoveCursor(Motion::BufferEnd, false)),
+    (
+        "ctrl+shift+end",
+        Command::MoveCursor(Motion::BufferEnd, true),
+    ),
+    (
+        "alt+pageup",
+        Command::MoveCursor(Motion::ParagraphUp, false),
+    ),
+    (
+        "alt+shift+pageup",
+        Command::MoveCursor(Motion::ParagraphUp, true),
+    ),
+    (
+        "alt+pagedown",
+        Command::MoveCursor(Motion::ParagraphDown, false),
+    ),
+    (
+        "alt+shift+pagedown",
+        Command::MoveCursor(Motion::ParagraphDown, true),
+    ),
+    ("enter", Command::NewLine),
+    ("backspace", Command::DeleteBackward),
+    ("delete", Command::DeleteForward),
+    ("tab", Command::InsertTab),
+];
+
+/// Maps key chords to commands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    bindings: HashMap<Chord, Command>,
+    editing: HashMap<Chord, Command>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut bindings = HashMap::new();
+        for command in Command::ALL {
+            for binding in command.default_bindings() {
+                let chord = parse_chord(binding).expect("Invalid default key binding");
+                bindings.insert(chord, command);
+            }
+        }
+        let editing = EDITING_KEYS
+            .iter()
+            .map(|(key, command)| (parse_chord(key).expect("Invalid editing key"), *command))
+            .collect();
+        Keymap { bindings, editing }
+    }
+}
+
+impl Keymap {
+    /// Look up the command bound to a key event.
+    pub fn get(&self, modifiers: KeyModifiers, code: KeyCode) -> Option<Command> {
+        let chord = (modifiers, code, KeyEventState::NONE);
+        self.bindings.get(&normalize(chord)).copied()
+    }
+
+    /// Translate a key event into a command: the configured bindings come first, then
+    /// the editing keys, then typed characters. Keypad keys that are not bound act as
+    /// the keys they stand for.
+    pub fn translate(&self, chord: Chord) -> Option<Command> {
+        if let Some(command) = self.bindings.get(&normalize(chord)) {
+            return Some(*command);
+        }
+        let (modifiers, code, _) = chord;
+        if let Some(command) = self.get(modifiers, code) {
+            return Some(command);
+        }
+        if let KeyCode::Char(c) = code {
+            return (modifiers == KeyModifiers::NONE || modifiers == KeyModifiers::SHIFT)
+                .then_some(Command::InsertChar(c));
+        }
+        let modifiers =
+            modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+        [
+            KeyModifiers::NONE,
+            KeyModifiers::ALT,
+            KeyModifiers::ALT | KeyModifiers::CONTROL,
+            KeyModifiers::ALT | KeyModifiers::CONTROL | KeyModifiers::SHIFT,
+        ]
+        .into_iter()
+        .find_map(|ignored| {
+            let chord = (modifiers - ignored, code, KeyEventState::NONE);
+            self.editing.get(&chord).copied()
+        })
+    }
+
+    /// Every chord the editor responds to with the command it runs, sorted by chord,
+    /// for showing as help. Typed characters are not included.
+    pub fn help(&self) -> Vec<(String, Command)> {
+        let mut keys: Vec<_> = self
+            .bindings
+            .iter()
+            .chain(
+                self.editing
+                    .iter()
+                    .filter(|(chord, _)| !self.bindings.contains_key(chord)),
+            )
+            .map(|(chord, command)| (format_chord(*chord), *command))
+            .collect();
+        keys.sort_by(|a, b| a.0.cmp(&b.0));
+        keys
+    }
+
+    /// The chords bound to a command, formatted for display, e.g. `["ctrl+s"]`.
+    pub fn bindings(&self, command: Command) -> Vec<String> {
+        let mut chords: Vec<String> = self
+            .bindings
+            .iter()
+            .filter(|(_, c)| **c == command)
+            .map(|(chord, _)| format_chord(*chord))
+            .collect();
+        chords.sort();
+        chords
+    }
+
+    /// Replace the bindings of a command with a comma-separated list of chords, e.g.
+    /// `ctrl+q, ctrl+w`. Commands not configured keep their default bindings.
+    ///
+    /// Returns a warning for each chord that was taken away from another command.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<Vec<String>, String> {
+        let command = Command::from_name(name).ok_or_else(|| t!("config.unknown_command", name))?;
+        let chords = value
+            .split(',')
+            .map(str::trim)
+            .filter(|binding| !binding.is_empty())
+            .map(parse_chord)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.bindings.retain(|_, c| *c != command);
+        let mut warnings = Vec::new();
+        for chord in chords {
+            if let Some(previous) = self.bindings.insert(chord, command) {
+                warnings.push(t!(
+                    "config.key_conflict",
+                    format_chord(chord),
+                    previous.name(),
+                    command.name()
+                ));
+            }
+        }
+        Ok(warnings)
+    }
+}
+
+/// How far one step of the mouse wheel scrolls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WheelSpeed {
+    pub lines: usize,
+    /// Lines scrolled with Alt held.
+    pub fast_lines: usize,
+    /// Columns scrolled by a tilt wheel, or with Shift held.
+    pub columns: usize,
+}
+
+impl Default for WheelSpeed {
+    fn default() -> Self {
+        WheelSpeed {
+            lines: 2,
+            fast_lines: 5,
+            columns: 3,
+        }
+    }
+}
+
+/// Translate a mouse wheel or right button event into a command. The left button
+/// places the cursor and selects, which the editor handles itself.
+///
+/// Shift turns the wheel sideways for mice without a tilt wheel, and Ctrl scrolls a
+/// page at a time.
+pub fn mouse_command(event: &MouseEvent, speed: WheelSpeed) -> Option<Command> {
+    let lines = match event.modifiers.contains(KeyModifiers::ALT) {
+        true => speed.fast_lines,
+        false => speed.lines,
+    };
+    let direction = match event.kind {
+        MouseEventKind::ScrollUp => Direction::Up,
+        MouseEventKind::ScrollDown => Direction::Down,
+        MouseEventKind::ScrollLeft => Direction::Left,
+        MouseEventKind::ScrollRight => Direction::Right,
+        MouseEventKind::Down(MouseButton::Right) => return Some(Command::CopyOrPaste),
+        _ => return None,
+    };
+    let sideways = event.modifiers.contains(KeyModifiers::SHIFT);
+    Some(match direction {
+        Direction::Up if sideways => Command::Scroll(Direction::Left, speed.columns),
+        Direction::Down if sideways => Command::Scroll(Direction::Right, speed.columns),
+        Direction::Up | Direction::Down if event.modifiers.contains(KeyModifiers::CONTROL) => {
+            Command::ScrollPage(direction)
+        }
+        Direction::Up | Direction::Down => Command::Scroll(direction, lines),
+        Direction::Left | Direction::Right => Command::Scroll(direction, speed.columns),
+    })
+}
+
+/// Letters are matched case-insensitively when combined with Ctrl or Alt, since
+/// terminals disagree on whether Shift or Caps Lock changes them. Of the key's state
+/// only whether it is on the keypad matters.
+fn normalize((modifiers, code, state): Chord) -> Chord {
+    let state = match code {
+        KeyCode::KeypadBegin => KeyEventState::KEYPAD,
+        _ => state & KeyEventState::KEYPAD,
+    };
+    match code {
+        KeyCode::Char(c) if modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+            (modifiers, KeyCode::Char(c.to_ascii_lowercase()), state)
+        }
+        _ => (modifiers, code, state),
+    }
+}
+
+/// The names of the keypad keys that type a character, which would not read well
+/// after `kp` or next to a `+`.
+const KEYPAD_CHARS: [(&str, char); 7] = [
+    ("plus", '+'),
+    ("minus", '-'),
+    ("multiply", '*'),
+    ("divide", '/'),
+    ("decimal", '.'),
+    ("equal", '='),
+    ("separator", ','),
+];
+
+/// Parse a binding such as `ctrl+shift+s`, `alt+up`, `f5` or `kp5`, the 5 on the keypad.
+pub fn parse_chord(binding: &str) -> Result<Chord, String> {
+    let invalid = || t!("config.invalid_key", binding);
+    let binding = binding.trim().to_ascii_lowercase();
+    let (modifiers, key) = match binding.as_str() {
+        "+" => ("", "+"),
+        b => match b.strip_suffix("++") {
+            Some(modifiers) => (modifiers, "+"),
+            None => b.rsplit_once('+').unwrap_or(("", b)),
+        },
+    };
+
+    let mut result = KeyModifiers::NONE;
+    for modifier in modifiers.split('+').filter(|m| !m.is_empty()) {
+        result |= match modifier {
+            "ctrl" | "control" => KeyModifiers::CONTROL,
+            "alt" | "meta" => KeyModifiers::ALT,
+            "shift" => KeyModifiers::SHIFT,
+            _ => return Err(invalid()),
+        };
+    }
+
+    let (key, state) = match key.strip_prefix("kp") {
+        Some(key) if !key.is_empty() => (key, KeyEventState::KEYPAD),
+        _ => (key, KeyEventState::NONE),
+    };
+    let code = match KEYPAD_CHARS.iter().find(|(name, _)| *name == key) {
+        _ if state.is_empty() => key_code(key),
+        Some((_, c)) => Some(KeyCode::Char(*c)),
+        None if key == "begin" => Some(KeyCode::KeypadBegin),
+        None => key_code(key),
+    };
+    let code = code.ok_or_else(invalid)?;
+
+    Ok(normalize((result, code, state)))
+}
+
+/// The key a name in a binding stands for, e.g. `pageup` or `a`.
+fn key_code(key: &str) -> Option<KeyCode> {
+    let code = match key {
+        "esc" | "escape" => KeyCode::Esc,
+        "enter" | "return" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "backspace" => KeyCode::Backspace,
+        "delete" | "del" => KeyCode::Delete,
+        "insert" | "ins" => KeyCode::Insert,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" | "pgup" => KeyCode::PageUp,
+        "pagedown" | "pgdn" => KeyCode::PageDown,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "space" => KeyCode::Char(' '),
+        f if f.len() > 1 && f.starts_with('f') => match f[1..].parse() {
+            Ok(n @ 1..=24) => KeyCode::F(n),
+            _ => return None,
+        },
+        c => {
+            let mut chars = c.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => KeyCode::Char(c),
+                _ => return None,
+            }
+        }
+    };
+    Some(code)
+}
+
+/// Format a chord the way `parse_chord` reads it.
+pub fn format_chord(chord: Chord) -> String {
+    let (modifiers, code, state) = normalize(chord);
+    let mut result = String::new();
+    for (modifier, name) in [
+        (KeyModifiers::CONTROL, "ctrl+"),
+        (KeyModifiers::ALT, "alt+"),
+        (KeyModifiers::SHIFT, "shift+"),
+    ] {
+        if modifiers.contains(modifier) {
+            result += name;
+        }
+    }
+    if state.contains(KeyEventState::KEYPAD) {
+        result += "kp";
+        let name = KEYPAD_CHARS.iter().find(|(_, c)| code == KeyCode::Char(*c));
+        match (code, name) {
+            (KeyCode::KeypadBegin, _) => return result + "begin",
+            (_, Some((name, _))) => return result + name,
+            _ => {}
+        }
+    }
+    match code {
+        KeyCode::Esc => result += "esc",
+        KeyCode::Enter => result += "enter",
+        KeyCode::Tab => result += "tab",
+        KeyCode::Backspace => result += "backspace",
+        KeyCode::Delete => result += "delete",
+        KeyCode::Insert => result += "insert",
+        KeyCode::Home => result += "home",
+        KeyCode::End => result += "end",
+        KeyCode::PageUp => result += "pageup",
+        KeyCode::PageDown => result += "pagedown",
+        KeyCode::Up => result += "up",
+        KeyCode::Down => result += "down",
+        KeyCode::Left => result += "left",
+        KeyCode::Right => result += "right",
+        KeyCode::Char(' ') => result += "space",
+        KeyCode::Char(c) => result.push(c),
+        KeyCode::F(n) => result += &format!("f{n}"),
+        code => result += &format!("{code:?}").to_ascii_lowercase(),
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_chord() {
+        assert_eq!(
+            parse_chord("ctrl+s"),
+            Ok((
+                KeyModifiers::CONTROL,
+                KeyCode::Char('s'),
+                KeyEventState::NONE
+            ))
+        );
+        assert_eq!(
+            parse_chord("Ctrl+Shift+S"),
+            Ok((
+                KeyModifiers::CONTROL | KeyModifiers::SHIFT,
+                KeyCode::Char('s'),
+                KeyEventState::NONE
+            ))
+        );
+        assert_eq!(
+            parse_chord("shift+f12"),
+            Ok((KeyModifiers::SHIFT, KeyCode::F(12), KeyEventState::NONE))
+        );
+        assert_eq!(
+            parse_chord("alt+up"),
+            Ok((KeyModifiers::ALT, KeyCode::Up, KeyEventState::NONE))
+        );
+        assert_eq!(
+            parse_chord("esc"),
+            Ok((KeyModifiers::NONE, KeyCode::Esc, KeyEventState::NONE))
+        );
+        assert_eq!(
+            parse_chord("ctrl++"),
+            Ok((
+                KeyModifiers::CONTROL,
+                KeyCode::Char('+'),
+                KeyEventState::NONE
+            ))
+        );
+        assert_eq!(
+            parse_chord("ctrl+kpplus"),
+            Ok((
+                KeyModifiers::CONTROL,
+                KeyCode::Char('+'),
+                KeyEventState::KEYPAD
+            ))
+        );
+        assert_eq!(
+            parse_chord("kp5"),
+            Ok((
+                KeyModifiers::NONE,
+                KeyCode::Char('5'),
+                KeyEventState::KEYPAD
+            ))
+        );
+
+        assert!(parse_chord("").is_err());
+        assert!(parse_chord("ctrl+").is_err());
+        assert!(parse_chord("hyper+s").is_err());
+        assert!(parse_chord("ctrl+foo").is_err());
+        assert!(parse_chord("f25").is_err());
+        assert!(parse_chord("kp").is_err());
+        assert!(parse_chord("kpfoo").is_err());
+    }
+
+    #[test]
+    fn test_chord_round_trip() {
+        for binding in [
+            "ctrl+s",
+            "ctrl+alt+shift+delete",
+            "shift+f12",
+            "alt+pagedown",
+            "ctrl+space",
+            "ctrl++",
+            "esc",
+            "q",
+            "f24",
+            "kp5",
+            "shift+kpenter",
+            "ctrl+kpdivide",
+            "kpbegin",
+        ] {
+            let chord = parse_chord(binding).unwrap();
+            assert_eq!(format_chord(chord), binding);
+            assert_eq!(parse_chord(&format_chord(chord)), Ok(chord));
+        }
+    }
+
+    #[test]
+    fn test_keymap() {
+        let mut keymap = Keymap::default();
+        assert_eq!(
+            keymap.get(KeyModifiers::CONTROL, KeyCode::Char('S')),
+            Some(Command::Save)
+        );
+        assert_eq!(keymap.get(KeyModifiers::NONE, KeyCode::Char('s')), None);
+
+        // Rebinding a command replaces its default keys; other commands are untouched
+        assert_eq!(keymap.set("quit", "ctrl+q"), Ok(vec![]));
+        assert_eq!(
+            keymap.get(KeyModifiers::CONTROL, KeyCode::Char('q')),
+            Some(Command::Quit)
+        );
+        assert_eq!(keymap.get(KeyModifiers::CONTROL, KeyCode::Char('w')), None);
+        assert_eq!(
+            keymap.get(KeyModifiers::NONE, KeyCode::Esc),
+            Some(Command::Cancel)
+        );
+        assert_eq!(keymap.bindings(Command::Save), vec!["ctrl+s"]);
+
+        assert!(keymap.set("quit", "ctrl+foo").is_err());
+        assert!(keymap.set("frobnicate", "ctrl+q").is_err());
+    }
+
+    #[test]
+    fn test_translate() {
+        let mut keymap = Keymap::default();
+        keymap.set("save", "ctrl+s, kp5").unwrap();
+        let translate = |binding| keymap.translate(parse_chord(binding).unwrap());
+        assert_eq!(translate("ctrl+s"), Some(Command::Save));
+        assert_eq!(translate("shift+x"), Some(Command::InsertChar('x')));
+        assert_eq!(translate("ctrl+q"), None);
+        assert_eq!(translate("ctrl+alt+s"), None);
+        assert_eq!(
+            translate("alt+shift+up"),
+            Some(Command::DuplicateLines(Direction::Up))
+        );
+
+        // Modifiers a key doesn't use are ignored
+        assert_eq!(translate("ctrl+shift+enter"), Some(Command::NewLine));
+        assert_eq!(
+            translate("ctrl+alt+up"),
+            Some(Command::ScrollLine(Direction::Up, false))
+        );
+        assert_eq!(
+            translate("alt+shift+end"),
+            Some(Command::MoveCursor(Motion::End, true))
+        );
+        assert_eq!(
+            translate("ctrl+alt+shift+home"),
+            Some(Command::MoveCursor(Motion::BufferStart, true))
+        );
+
+        // Keypad keys are told apart when bound, and otherwise act as the usual keys
+        assert_eq!(translate("kp5"), Some(Command::Save));
+        assert_eq!(translate("5"), Some(Command::InsertChar('5')));
+        assert_eq!(translate("kp6"), Some(Command::InsertChar('6')));
+        assert_eq!(translate("kpenter"), Some(Command::NewLine));
+        assert_eq!(translate("kpbegin"), None);
+    }
+
+    #[test]
+    fn test_help() {
+        let mut keymap = Keymap::default();
+        keymap.set("quit", "ctrl+q, enter").unwrap();
+        let help = keymap.help();
+        assert!(help.contains(&("ctrl+q".to_string(), Command::Quit)));
+        assert!(help.contains(&("enter".to_string(), Command::Quit)));
+        assert!(help.contains(&("tab".to_string(), Command::InsertTab)));
+        assert!(!help.contains(&("enter".to_string(), Command::NewLine)));
+        assert!(help.contains(&("ctrl+s".to_string(), Command::Save)));
+        assert!(help.contains(&("alt+u".to_string(), Command::InsertUnicode)));
+
+        // Each chord is listed once: the bound ones, and the editing keys not bound over,
+        // here all but `enter`
+        let bound: usize = (Command::ALL.iter())
+            .map(|&command| keymap.bindings(command).len())
+            .sum();
+        let editing = (EDITING_KEYS.iter())
+            .filter(|(key, _)| {
+                let (modifiers, code, _) = parse_chord(key).unwrap();
+                keymap.get(modifiers, code).is_none()
+            })
+            .count();
+        assert_eq!(editing, EDITING_KEYS.len() - 1);
+        assert_eq!(help.len(), bound + editing);
+    }
+
+    #[test]
+    fn test_mouse_command() {
+        let speed = WheelSpeed {
+            lines: 4,
+            ..WheelSpeed::default()
+        };
+        let wheel = |kind, modifiers| {
+            let event = MouseEvent {
+                kind,
+                column: 0,
+                row: 0,
+                modifiers,
+            };
+            mouse_command(&event, speed)
+        };
+        let (up, down) = (MouseEventKind::ScrollUp, MouseEventKind::ScrollDown);
+        let none = KeyModifiers::NONE;
+        assert_eq!(wheel(up, none), Some(Command::Scroll(Direction::Up, 4)));
+        assert_eq!(
+            wheel(down, KeyModifiers::ALT),
+            Some(Command::Scroll(Direction::Down, 5))
+        );
+        assert_eq!(
+            wheel(MouseEventKind::ScrollRight, none),
+            Some(Command::Scroll(Direction::Right, 3))
+        );
+        // Shift turns the wheel sideways, Ctrl scrolls by pages
+        assert_eq!(
+            wheel(up, KeyModifiers::SHIFT),
+            Some(Command::Scroll(Direction::Left, 3))
+        );
+        assert_eq!(
+            wheel(down, KeyModifiers::CONTROL),
+            Some(Command::ScrollPage(Direction::Down))
+        );
+        assert_eq!(
+            wheel(MouseEventKind::Down(MouseButton::Right), none),
+            Some(Command::CopyOrPaste)
+        );
+        assert_eq!(wheel(MouseEventKind::Moved, none), None);
+    }
+
+    #[test]
+    fn test_keymap_conflict() {
+        let mut keymap = Keymap::default();
+        let warnings = keymap.set("find", "ctrl+s, ctrl+f").unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("save") && warnings[0].contains("find"));
+        assert_eq!(
+            keymap.get(KeyModifiers::CONTROL, KeyCode::Char('s')),
+            Some(Command::Find)
+        );
+        assert!(keymap.bindings(Command::Save).is_empty());
+    }
+}
@@ -1,13 +1,94 @@
+//! A console text editor with syntax highlighting, and the pieces it is built from.
+//!
+//! The editor starts in two steps. `Editor::open` loads the theme and a file, or what
+//! is piped in, or starts a scratch buffer; `Editor::run` then sets up the terminal,
+//! handles input until the editor quits and gives the terminal back. `Editor::init`
+//! does both. In between, the buffer can be changed as if it had been typed:
+//!
+//! ```no_run
+//! use arcaea::{Config, Editor, Position};
+//!
+//! let mut editor = Editor::new(Config::load());
+//! editor.open(&Some("notes.txt".to_string()))?;
+//! editor.insert_text(Position { x: 0, y: 0 }, "Read me first\n");
+//! editor.run()?;
+//! # Ok::<(), arcaea::Error>(())
+//! ```
+//!
+//! An editor with a headless terminal, like `Editor::default()`, never touches the
+//! real one: it is opened the same way, driven with `handle_event`, and what it draws
+//! with `render_to_buffer` can be read back from `terminal.snapshot()`.
+
+pub mod anomaly;
+mod buffer;
+#[cfg(feature = "tui")]
+mod cat;
+#[cfg(feature = "tui")]
+mod codec;
+#[cfg(feature = "tui")]
+mod config;
+#[cfg(feature = "tui")]
+mod date;
+#[cfg(feature = "tui")]
+mod discarded;
+#[cfg(feature = "tui")]
 mod editor;
 mod error;
+#[cfg(feature = "tui")]
+mod follow;
+#[cfg(feature = "tui")]
+mod format;
 mod history;
+pub mod i18n;
+#[cfg(feature = "tui")]
+mod json;
+#[cfg(feature = "tui")]
+pub mod keymap;
+#[cfg(feature = "tui")]
+mod modal;
+mod position;
+#[cfg(feature = "tui")]
+mod recent;
 mod row;
-mod style;
+#[cfg(feature = "tui")]
+mod signal;
+#[cfg(feature = "tui")]
+mod snippet;
+#[cfg(feature = "tui")]
+pub mod style;
 mod syntax;
+#[cfg(feature = "tui")]
 mod terminal;
+#[cfg(feature = "tui")]
+mod trash;
+#[cfg(feature = "tui")]
 mod tui;
+pub mod width;
 
 pub use {
-    editor::Editor, error::Error, history::History, row::Row, syntax::Syntax, terminal::Terminal,
-    tui::Tui,
+    buffer::{Buffer, Stats},
+    error::Error,
+    history::History,
+    position::Position,
+    row::{LineEnding, Row},
+    syntax::{Syntax, TokenType, SYNTAX_DIR},
 };
+#[cfg(feature = "tui")]
+pub use {cat::cat, config::Config, editor::Editor, terminal::Terminal, tui::Tui};
+
+/// The version, followed by the commit it was built from when that is known, e.g.
+/// `0.4.1 (1a2b3c4d 2024-05-01)`.
+pub fn version() -> &'static str {
+    env!("VERSION_INFO")
+}
+
+/// The enabled features and the target the crate was built for, e.g.
+/// `debug, tui; x86_64-unknown-linux-gnu`.
+pub fn build_info() -> &'static str {
+    env!("BUILD_INFO")
+}
+
+/// When the crate was built, in seconds since the Unix epoch.
+pub fn build_timestamp() -> i64 {
+    env!("BUILD_TIMESTAMP").parse().unwrap_or_default()
+}
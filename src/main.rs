@@ -1,71 +1,340 @@
-use arcaea::{Editor, Error};
-use crossterm::style::Stylize;
-
-fn main() -> Result<(), Error> {
-    std::panic::set_hook(Box::new(|panic_info| {
-        let _ = crossterm::execute!(
-            std::io::stdout(),
-            crossterm::event::DisableFocusChange,
-            crossterm::event::DisableBracketedPaste,
-            crossterm::event::DisableMouseCapture,
-            crossterm::terminal::EnableLineWrap,
-            crossterm::terminal::LeaveAlternateScreen,
-            crossterm::cursor::Show,
-        );
-        let _ = crossterm::terminal::disable_raw_mode();
-
-        print!("\n{}: ", "Error".bold().red());
-        if let Some(location) = panic_info.location() {
-            println!("{}:{}:{}", location.file(), location.line(), location.column());
-        } else {
-            println!("at unknown location");
-        }
-        if let Some(s) = panic_info.payload().downcast_ref::<&str>() {
-            println!("panic occurred: {s:?}");
-        } else if let Some(s) = panic_info.payload().downcast_ref::<String>() {
-            println!("panic occurred: {s:?}");
-        } else {
-            println!("panic occurred");
-        }
-    }));
-
-    let mut arguments = std::env::args();
-
-    match (arguments.nth(1), arguments.len()) {
-        (Some(arg), 0) if arg == "-v" || arg == "--version" => {
-            println!("arcaea {}", env!("VERSION_INFO"));
-        }
-        (Some(arg), 0) if arg == "-h" || arg == "--help" => print_help_message(),
-        (Some(arg), 0) if arg.starts_with('-') => return Err(Error::UnrecognizedOption(arg)),
-
-        (filename, 0) => Editor::new().init(&filename)?,
-
-        (_, n_remaining_args) => return Err(Error::TooManyArguments(n_remaining_args + 1)),
-    }
-    Ok(())
-}
-
-/// Prints the help message for the application, including usage instructions and available options.
-fn print_help_message() {
-    println!("A Rust Console Ascii Editor App");
-    println!();
-    println!(
-        "{} {} {}",
-        "Usage:".bold().green(),
-        "arcaea".bold().cyan(),
-        "[filename]".cyan()
-    );
-    println!();
-    println!("{}", "Options:".bold().green());
-    println!(
-        "  {}, {}Print version info and exit",
-        "-v".bold().cyan(),
-        format!("{:<12}", "--version").bold().cyan()
-    );
-    println!(
-        "  {}, {}Print help",
-        "-h".bold().cyan(),
-        format!("{:<12}", "--help").bold().cyan()
-    );
-    println!();
-}
+use std::{
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+};
+
+use arcaea::{
+    anomaly, cat, i18n,
+    style::{set_theme, Theme},
+    width, Config, Editor, Error, Syntax, SYNTAX_DIR,
+};
+use crossterm::style::Stylize;
+
+fn main() -> Result<(), Error> {
+    std::panic::set_hook(Box::new(|panic_info| {
+        let _ = crossterm::execute!(
+            std::io::stdout(),
+            crossterm::event::PopKeyboardEnhancementFlags
+        );
+        let _ = crossterm::execute!(
+            std::io::stdout(),
+            crossterm::event::DisableFocusChange,
+            crossterm::event::DisableBracketedPaste,
+            crossterm::event::DisableMouseCapture,
+            crossterm::terminal::EnableLineWrap,
+            crossterm::terminal::LeaveAlternateScreen,
+            crossterm::cursor::Show,
+        );
+        let _ = crossterm::terminal::disable_raw_mode();
+
+        print!("\n{}: ", "Error".bold().red());
+        if let Some(location) = panic_info.location() {
+            println!(
+                "{}:{}:{}",
+                location.file(),
+                location.line(),
+                location.column()
+            );
+        } else {
+            println!("at unknown location");
+        }
+        if let Some(s) = panic_info.payload().downcast_ref::<&str>() {
+            println!("panic occurred: {s:?}");
+        } else if let Some(s) = panic_info.payload().downcast_ref::<String>() {
+            println!("panic occurred: {s:?}");
+        } else {
+            println!("panic occurred");
+        }
+    }));
+
+    // The warnings about the configuration file are written in the language it asks
+    // for, so that is read first
+    i18n::init_from_env();
+    let mut config = Config::load();
+    if config.language.as_deref().is_some_and(i18n::set_language) {
+        config = Config::load();
+    }
+    let mut mode = Mode::Edit;
+    let mut positional = Vec::new();
+
+    let mut arguments = std::env::args().skip(1);
+    while let Some(arg) = arguments.next() {
+        if !arg.starts_with('-') || arg == "-" {
+            positional.push(arg);
+            continue;
+        }
+        // `--flag=value` is the same as `--flag value`
+        let (flag, mut inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || {
+            inline
+                .take()
+                .or_else(|| arguments.next())
+                .ok_or_else(|| Error::UnrecognizedOption(format!("{flag} (missing value)")))
+        };
+        match flag {
+            "-v" | "--version" => mode = Mode::Version,
+            "-h" | "--help" => mode = Mode::Help,
+            "--cat" => mode = Mode::Cat,
+            "--list-languages" => mode = Mode::ListLanguages,
+            "--check-syntax" => mode = Mode::CheckSyntax,
+            "--follow" => config.follow = true,
+            "--lang" => {
+                let language = value()?;
+                if config.set("language", &language).is_err() {
+                    return Err(Error::UnrecognizedOption(format!(
+                        "--lang={language} (available: {})",
+                        i18n::languages().collect::<Vec<_>>().join(", ")
+                    )));
+                }
+            }
+            "-l" | "--language" => config.syntax = Some(value()?),
+            "--syntax-dir" => config.syntax_dir = PathBuf::from(value()?),
+            "--theme" => config.theme = Some(PathBuf::from(value()?)),
+            "--" => {
+                positional.extend(arguments.by_ref());
+                break;
+            }
+            _ => {
+                return Err(Error::UnrecognizedOption(format!(
+                    "{arg} (valid options: {})",
+                    OPTIONS.join(", ")
+                )))
+            }
+        }
+        if let Some(value) = inline {
+            return Err(Error::UnrecognizedOption(format!(
+                "{flag}={value} ({flag} takes no value)"
+            )));
+        }
+    }
+
+    if let Some(name) = &config.syntax {
+        check_language(name, &config.syntax_dir)?;
+    }
+
+    // `--lang` may have changed it
+    if let Some(language) = &config.language {
+        i18n::set_language(language);
+    }
+    width::set_cjk(config.cjk_width.unwrap_or_else(width::locale_is_cjk));
+    anomaly::set(config.anomaly_chars.clone());
+
+    let n_args = positional.len();
+    let mut positional = positional.into_iter();
+    match (mode, positional.next(), n_args) {
+        (Mode::Version, _, _) => {
+            println!("arcaea {}", arcaea::version());
+            println!("{}", arcaea::build_info());
+        }
+        (Mode::Help, _, _) => print_help_message(),
+        (Mode::ListLanguages, None, _) => list_languages(&config.syntax_dir)?,
+        (Mode::CheckSyntax, dir, 0 | 1) => {
+            let dir = dir.map_or(config.syntax_dir, PathBuf::from);
+            if !check_syntax(&dir)? {
+                std::process::exit(1);
+            }
+        }
+        (Mode::Cat, Some(filename), 1) => {
+            if let Some(path) = &config.theme {
+                match Theme::from_file(path) {
+                    Ok(theme) => set_theme(theme),
+                    Err(err) => eprintln!("arcaea: {:?}", err),
+                }
+            }
+            cat_file(
+                Path::new(&filename),
+                &config.syntax_dir,
+                config.syntax.as_deref(),
+            );
+        }
+        (Mode::Cat, None, _) => {
+            return Err(Error::UnrecognizedOption("--cat (missing FILE)".into()))
+        }
+
+        // Raw mode and the alternate screen would end up in the redirected output
+        (Mode::Edit, _, 0 | 1) if !io::stdout().is_terminal() => return Err(Error::NotATerminal),
+        (Mode::Edit, filename, 0 | 1) => Editor::new(config).init(&filename)?,
+
+        (_, _, n_args) => return Err(Error::TooManyArguments(n_args)),
+    }
+    Ok(())
+}
+
+/// What to do once the command line has been parsed.
+enum Mode {
+    Edit,
+    Version,
+    Help,
+    Cat,
+    ListLanguages,
+    CheckSyntax,
+}
+
+/// Every recognized flag, listed when an unknown one is given.
+const OPTIONS: [&str; 11] = [
+    "-v, --version",
+    "-h, --help",
+    "--lang",
+    "-l, --language",
+    "--syntax-dir",
+    "--theme",
+    "--follow",
+    "--cat",
+    "--list-languages",
+    "--check-syntax",
+    "--",
+];
+
+/// Prints a highlighted file to stdout, exiting with a sysexits-style code on failure.
+fn cat_file(path: &Path, syntax_dir: &Path, language: Option<&str>) {
+    use std::io::ErrorKind;
+    if let Err(err) = cat(path, syntax_dir, language) {
+        let code = match &err {
+            Error::Io(err) if err.kind() == ErrorKind::BrokenPipe => 0,
+            Error::Io(err)
+                if matches!(
+                    err.kind(),
+                    ErrorKind::NotFound | ErrorKind::PermissionDenied
+                ) =>
+            {
+                eprintln!("arcaea: {}: {}", path.display(), err);
+                66 // EX_NOINPUT
+            }
+            err => {
+                eprintln!("arcaea: {}: {:?}", path.display(), err);
+                74 // EX_IOERR
+            }
+        };
+        std::process::exit(code);
+    }
+}
+
+/// Prints every available syntax definition with its file extensions and source file.
+fn list_languages(syntax_dir: &Path) -> Result<(), Error> {
+    for (path, result) in Syntax::definitions(syntax_dir)? {
+        // Broken definitions are reported by --check-syntax
+        if let Ok((syntax, extensions)) = result {
+            println!(
+                "{:<16}{:<24}{}",
+                syntax.name,
+                extensions.join(", "),
+                path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Fails unless a syntax definition in `syntax_dir` is called `name`, ignoring case,
+/// listing the names there are.
+fn check_language(name: &str, syntax_dir: &Path) -> Result<(), Error> {
+    let names = Syntax::definitions(syntax_dir)?
+        .into_iter()
+        .filter_map(|(_, result)| result.ok())
+        .map(|(syntax, _)| syntax.name)
+        .collect::<Vec<_>>();
+    if names.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+        return Ok(());
+    }
+    Err(Error::UnrecognizedOption(format!(
+        "--language={name} (available: {})",
+        names.join(", ")
+    )))
+}
+
+/// Parses every syntax definition in `dir`, reporting each problem on stderr.
+/// Returns `false` if any definition failed to load.
+fn check_syntax(dir: &Path) -> Result<bool, Error> {
+    let definitions = Syntax::definitions(dir)?;
+    let mut failed = 0;
+    for (path, result) in &definitions {
+        match result {
+            Ok(_) => {}
+            Err(Error::FileError(path, line, message)) => {
+                eprintln!("{}:{}: {}", path.display(), line, message);
+                failed += 1;
+            }
+            Err(err) => {
+                eprintln!("{}: {:?}", path.display(), err);
+                failed += 1;
+            }
+        }
+    }
+    println!(
+        "{} definitions checked, {} failed",
+        definitions.len(),
+        failed
+    );
+    Ok(failed == 0)
+}
+
+/// Prints the help message for the application, including usage instructions and available options.
+fn print_help_message() {
+    println!("A Rust Console Ascii Editor App");
+    println!();
+    println!(
+        "{} {} {}",
+        "Usage:".bold().green(),
+        "arcaea".bold().cyan(),
+        "[options] [filename]".cyan()
+    );
+    println!(
+        "A filename of {} edits what is piped in, as in `ls | arcaea -`.",
+        "-".bold().cyan()
+    );
+    println!();
+    println!("{}", "Options:".bold().green());
+    println!(
+        "  {}, {}Print version info and exit",
+        "-v".bold().cyan(),
+        format!("{:<22}", "--version").bold().cyan()
+    );
+    println!(
+        "      {}Set the interface language ({})",
+        format!("{:<22}", "--lang LANG").bold().cyan(),
+        i18n::languages().collect::<Vec<_>>().join(", ")
+    );
+    println!(
+        "  {}, {}Highlight the file as NAME, whatever its extension",
+        "-l".bold().cyan(),
+        format!("{:<22}", "--language NAME").bold().cyan()
+    );
+    println!(
+        "      {}Load syntax definitions from DIR (default: {})",
+        format!("{:<22}", "--syntax-dir DIR").bold().cyan(),
+        SYNTAX_DIR
+    );
+    println!(
+        "      {}Load colors from a theme file",
+        format!("{:<22}", "--theme FILE").bold().cyan()
+    );
+    println!(
+        "      {}Show FILE read-only and follow what is appended to it",
+        format!("{:<22}", "--follow").bold().cyan()
+    );
+    println!(
+        "      {}Print FILE with syntax highlighting and exit",
+        format!("{:<22}", "--cat FILE").bold().cyan()
+    );
+    println!(
+        "      {}List available syntax definitions",
+        format!("{:<22}", "--list-languages").bold().cyan()
+    );
+    println!(
+        "      {}Check the syntax definitions in DIR (default: --syntax-dir)",
+        format!("{:<22}", "--check-syntax [DIR]").bold().cyan()
+    );
+    println!(
+        "  {}, {}Print help",
+        "-h".bold().cyan(),
+        format!("{:<22}", "--help").bold().cyan()
+    );
+    println!(
+        "      {}Treat the remaining arguments as file names",
+        format!("{:<22}", "--").bold().cyan()
+    );
+    println!();
+}
@@ -0,0 +1,230 @@
+use crossterm::event::{KeyCode, KeyModifiers};
+
+use crate::keymap::{Command, Motion};
+
+/// The modes of the vim-like editing enabled by `vim_mode`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    #[default]
+    Normal,
+    Insert,
+    Visual,
+}
+
+impl Mode {
+    /// The indicator shown in the status bar.
+    pub fn label(self) -> &'static str {
+        match self {
+            Mode::Normal => "-- NORMAL --",
+            Mode::Insert => "-- INSERT --",
+            Mode::Visual => "-- VISUAL --",
+        }
+    }
+}
+
+/// Turns keys typed outside insert mode into editor commands.
+///
+/// In normal mode `h` `j` `k` `l`, `w` `b` and `0` `$` move, `i` and `a` go to insert
+/// mode, `x` deletes a character, `dd` cuts the line, `yy` copies it, `p` pastes, `u`
+/// undoes and `v` starts a selection, which `d` or `x` cut and `y` copies. A number
+/// before a motion or `x` repeats it. Esc always returns to normal mode.
+#[derive(Debug, Default)]
+pub struct Modal {
+    pub mode: Mode,
+    /// The repeat count typed so far.
+    count: Option<usize>,
+    /// The operator waiting for its second key, `d` or `y`.
+    pending: Option<char>,
+}
+
+impl Modal {
+    /// Handle a key. Returns the commands to run, which may be none, or `None` if the
+    /// key is left to the keymap: any key in insert mode, and keys with Ctrl or Alt or
+    /// without a meaning here, like the arrows. `at_line_end` tells whether the cursor
+    /// is at the end of its line.
+    pub fn key(
+        &mut self,
+        modifiers: KeyModifiers,
+        code: KeyCode,
+        at_line_end: bool,
+    ) -> Option<Vec<Command>> {
+        if code == KeyCode::Esc {
+            self.mode = Mode::Normal;
+            self.count = None;
+            self.pending = None;
+            return Some(vec![]);
+        }
+        if self.mode == Mode::Insert {
+            return None;
+        }
+        let c = match code {
+            KeyCode::Char(c)
+                if !modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+            {
+                c
+            }
+            // These would edit the text
+            KeyCode::Enter | KeyCode::Tab | KeyCode::Backspace => return Some(vec![]),
+            _ => return None,
+        };
+
+        let count = self.count.take();
+        if let Some(digit) = c.to_digit(10).filter(|&d| d > 0 || count.is_some()) {
+            self.count = Some(count.unwrap_or(0).saturating_mul(10) + digit as usize);
+            return Some(vec![]);
+        }
+        let n = count.unwrap_or(1);
+
+        if let Some(operator) = self.pending.take() {
+            return Some(match (operator, c) {
+                ('d', 'd') => vec![Command::Cut],
+                ('y', 'y') => vec![Command::Copy],
+                _ => vec![],
+            });
+        }
+
+        let visual = self.mode == Mode::Visual;
+        let motion = |motion| vec![Command::MoveCursor(motion, visual); n];
+        let commands = match c {
+            'h' => motion(Motion::Left),
+            'j' => motion(Motion::Down),
+            'k' => motion(Motion::Up),
+            'l' => motion(Motion::Right),
+            'w' => motion(Motion::WordRight),
+            'b' => motion(Motion::WordLeft),
+            '0' => motion(Motion::Home),
+            '$' => motion(Motion::End),
+            'u' => vec![Command::Undo],
+            'p' => {
+                self.mode = Mode::Normal;
+                vec![Command::Paste]
+            }
+            'v' => {
+                self.mode = if visual { Mode::Normal } else { Mode::Visual };
+                vec![]
+            }
+            'd' | 'x' if visual => {
+                self.mode = Mode::Normal;
+                vec![Command::Cut]
+            }
+            'y' if visual => {
+                self.mode = Mode::Normal;
+                // Leave the cursor at the start of the copied text
+                vec![Command::Copy, Command::MoveCursor(Motion::Left, false)]
+            }
+            'i' => {
+                self.mode = Mode::Insert;
+                vec![]
+            }
+            'a' => {
+                self.mode = Mode::Insert;
+                if at_line_end {
+                    vec![]
+                } else {
+                    vec![Command::MoveCursor(Motion::Right, false)]
+                }
+            }
+            'x' => vec![Command::DeleteForward; n],
+            'd' | 'y' => {
+                self.pending = Some(c);
+                vec![]
+            }
+            _ => vec![],
+        };
+        Some(commands)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed keys written as in a vim mapping, e.g. `"3jdd"`, and collect the commands.
+    fn keys(modal: &mut Modal, keys: &str) -> Vec<Command> {
+        keys.chars()
+            .flat_map(|c| {
+                let code = if c == '\x1b' {
+                    KeyCode::Esc
+                } else {
+                    KeyCode::Char(c)
+                };
+                modal
+                    .key(KeyModifiers::NONE, code, false)
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_counts() {
+        let mut modal = Modal::default();
+        let down = Command::MoveCursor(Motion::Down, false);
+        assert_eq!(keys(&mut modal, "j"), vec![down]);
+        assert_eq!(keys(&mut modal, "12j"), vec![down; 12]);
+        // `0` only counts after another digit
+        assert_eq!(keys(&mut modal, "10l").len(), 10);
+        assert_eq!(
+            keys(&mut modal, "0"),
+            vec![Command::MoveCursor(Motion::Home, false)]
+        );
+        // Esc forgets the count
+        assert_eq!(keys(&mut modal, "5\x1bx"), vec![Command::DeleteForward]);
+    }
+
+    #[test]
+    fn test_operators() {
+        let mut modal = Modal::default();
+        assert_eq!(keys(&mut modal, "d"), vec![]);
+        assert_eq!(keys(&mut modal, "d"), vec![Command::Cut]);
+        assert_eq!(keys(&mut modal, "yyp"), vec![Command::Copy, Command::Paste]);
+        // An unknown second key cancels the operator
+        assert_eq!(keys(&mut modal, "dj"), vec![]);
+        assert_eq!(keys(&mut modal, "u"), vec![Command::Undo]);
+    }
+
+    #[test]
+    fn test_modes() {
+        let mut modal = Modal::default();
+        assert_eq!(modal.mode, Mode::Normal);
+        assert_eq!(keys(&mut modal, "i"), vec![]);
+        assert_eq!(modal.mode, Mode::Insert);
+        // Typing is left to the keymap
+        assert_eq!(
+            modal.key(KeyModifiers::NONE, KeyCode::Char('j'), false),
+            None
+        );
+        keys(&mut modal, "\x1b");
+        assert_eq!(modal.mode, Mode::Normal);
+
+        // Appending at the end of the line doesn't move to the next one
+        assert_eq!(
+            modal.key(KeyModifiers::NONE, KeyCode::Char('a'), true),
+            Some(vec![])
+        );
+        assert_eq!(modal.mode, Mode::Insert);
+
+        keys(&mut modal, "\x1bv");
+        assert_eq!(modal.mode, Mode::Visual);
+        assert_eq!(
+            keys(&mut modal, "2ly"),
+            vec![
+                Command::MoveCursor(Motion::Right, true),
+                Command::MoveCursor(Motion::Right, true),
+                Command::Copy,
+                Command::MoveCursor(Motion::Left, false),
+            ]
+        );
+        assert_eq!(modal.mode, Mode::Normal);
+
+        // Other keys still reach the keymap
+        assert_eq!(
+            modal.key(KeyModifiers::CONTROL, KeyCode::Char('s'), false),
+            None
+        );
+        assert_eq!(modal.key(KeyModifiers::NONE, KeyCode::Up, false), None);
+        assert_eq!(
+            modal.key(KeyModifiers::NONE, KeyCode::Enter, false),
+            Some(vec![])
+        );
+    }
+}
@@ -0,0 +1,30 @@
+/// A place in the text: line `y` and the `x`th grapheme on it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Position {
+    pub x: usize,
+    pub y: usize,
+}
+
+impl PartialOrd for Position {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Position {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        match self.y.cmp(&other.y) {
+            std::cmp::Ordering::Equal => self.x.cmp(&other.x),
+            ord => ord,
+        }
+    }
+}
+
+impl<T: Into<usize>> From<(T, T)> for Position {
+    fn from(value: (T, T)) -> Self {
+        Position {
+            x: value.0.into(),
+            y: value.1.into(),
+        }
+    }
+}
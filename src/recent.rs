@@ -0,0 +1,132 @@
+use std::path::{Path, PathBuf};
+
+/// The files opened most recently, newest first, kept in a file between sessions.
+#[derive(Debug, Default)]
+pub struct Recent {
+    /// Where the list is saved; `None` keeps it in memory only.
+    path: Option<PathBuf>,
+    pub files: Vec<PathBuf>,
+}
+
+impl Recent {
+    /// How many files are remembered.
+    const MAX_FILES: usize = 30;
+
+    /// Where the list is kept: `recent` in the `state_dir`.
+    pub fn path() -> Option<PathBuf> {
+        Some(state_dir()?.join("recent"))
+    }
+
+    /// Load the list from its usual place, or start an empty one.
+    pub fn load() -> Self {
+        Self::path().map(Self::from_file).unwrap_or_default()
+    }
+
+    /// Load the list saved at `path`, one file per line. A missing file is an empty list.
+    pub fn from_file(path: PathBuf) -> Self {
+        let files = std::fs::read_to_string(&path)
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(PathBuf::from)
+            .take(Self::MAX_FILES)
+            .collect();
+        Recent {
+            path: Some(path),
+            files,
+        }
+    }
+
+    /// Put `file` first, unless it is in a temporary directory, and save the list.
+    pub fn add(&mut self, file: &Path) {
+        let Ok(file) = std::path::absolute(file) else {
+            return;
+        };
+        if is_temporary(&file) {
+            return;
+        }
+        self.files.retain(|f| *f != file);
+        self.files.insert(0, file);
+        self.files.truncate(Self::MAX_FILES);
+        self.save();
+    }
+
+    /// Forget the file at `index` and save the list.
+    pub fn remove(&mut self, index: usize) {
+        self.files.remove(index);
+        self.save();
+    }
+
+    /// Write the list out. Failing to is not worth bothering anyone about.
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let mut content = String::new();
+        for file in &self.files {
+            content += &file.to_string_lossy();
+            content.push('\n');
+        }
+        if let Some(dir) = path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        let _ = std::fs::write(path, content);
+    }
+}
+
+/// Where the editor keeps what it remembers between sessions: `$XDG_STATE_HOME/arcaea`,
+/// or the platform's usual place for such state.
+pub fn state_dir() -> Option<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+    let dir = if let Some(dir) = var("XDG_STATE_HOME") {
+        PathBuf::from(dir)
+    } else if cfg!(windows) {
+        PathBuf::from(var("LOCALAPPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from(var("HOME")?).join("Library/Application Support")
+    } else {
+        PathBuf::from(var("HOME")?).join(".local/state")
+    };
+    Some(dir.join("arcaea"))
+}
+
+/// Whether `file` is under the system's temporary directory, where files don't last.
+fn is_temporary(file: &Path) -> bool {
+    let temp = std::env::temp_dir();
+    let canonical = temp.canonicalize().unwrap_or_else(|_| temp.clone());
+    file.starts_with(&temp) || file.starts_with(canonical)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = dir.path().join("state").join("recent");
+        let (a, b) = (Path::new("/srv/a.txt"), Path::new("/srv/b.txt"));
+        let mut recent = Recent::from_file(store.clone());
+        assert!(recent.files.is_empty());
+
+        recent.add(a);
+        recent.add(b);
+        recent.add(a);
+        // Files in the temporary directory are left out
+        recent.add(&dir.path().join("c.txt"));
+        assert_eq!(recent.files.len(), 2);
+        assert!(recent.files[0].ends_with(a));
+
+        let mut recent = Recent::from_file(store.clone());
+        assert!(recent.files[0].ends_with(a));
+        assert!(recent.files[1].ends_with(b));
+        recent.remove(0);
+        assert!(Recent::from_file(store.clone()).files[0].ends_with(b));
+
+        for i in 0..40 {
+            recent.add(Path::new(&format!("/srv/{i}.txt")));
+        }
+        assert_eq!(recent.files.len(), Recent::MAX_FILES);
+        assert!(recent.files[0].ends_with("39.txt"));
+    }
+}
@@ -1,22 +1,118 @@
-use std::{fmt, iter::repeat};
+use std::{borrow::Cow, cell::OnceCell, fmt, iter::repeat, ops::Range};
+use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
 use unicode_segmentation::UnicodeSegmentation;
-use unicode_width::UnicodeWidthStr;
 
 use crate::{
+    anomaly,
     syntax::{TokenState, TokenType},
+    width::Width,
     Syntax,
 };
 
 type Cell = (String, usize);
 
-#[derive(Default, Clone, PartialEq, Eq)]
+/// `text` in Unicode normalization form C, where `e` followed by a combining acute
+/// accent becomes `é`. Borrowed if it already is.
+pub fn nfc(text: &str) -> Cow<'_, str> {
+    match is_nfc_quick(text.chars()) {
+        IsNormalized::Yes => Cow::Borrowed(text),
+        _ => Cow::Owned(text.nfc().collect()),
+    }
+}
+
+/// `text` without `prefix`, comparing the lowercase of each character.
+fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    let mut chars = text.chars();
+    for p in prefix.chars() {
+        let c = chars.next()?;
+        if !c.to_lowercase().eq(p.to_lowercase()) {
+            return None;
+        }
+    }
+    Some(chars.as_str())
+}
+
+/// The kinds of characters that word motions stop between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CharClass {
+    Whitespace,
+    /// Letters and digits of any script, and `_`.
+    Word,
+    Punctuation,
+}
+
+impl CharClass {
+    /// The class of a grapheme cluster, decided by its first character.
+    pub fn of(grapheme: &str) -> Self {
+        match grapheme.chars().next() {
+            Some(c) if c.is_whitespace() => CharClass::Whitespace,
+            Some(c) if c.is_alphanumeric() || c == '_' => CharClass::Word,
+            _ => CharClass::Punctuation,
+        }
+    }
+}
+
+/// The line break at the end of a row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::Crlf => "CRLF",
+        }
+    }
+}
+
+/// One line of text, as grapheme clusters with their visual widths.
+///
+/// Edit the rope through the methods below, so that the cached widths stay valid.
+#[derive(Default, Clone)]
 pub struct Row {
     pub rope: Vec<Cell>,
 
+    /// The token type of each cell, as far as it is still known. Edits cut it off
+    /// before the first changed cell, so it is never longer than `rope`, and
+    /// `update_syntax` fills it back in.
     pub syntax: Vec<TokenType>,
     pub final_state: TokenState,
+
+    /// The line break read from the file, kept when saving. `None` for the last line
+    /// and for line breaks typed in the editor, which use the buffer's usual ending.
+    pub ending: Option<LineEnding>,
+
+    /// `widths[i]` is the visual width of the first `i` cells, built on first use.
+    widths: OnceCell<Vec<usize>>,
+    /// The cells with a character of the `anomaly` kind in them, found on first use.
+    anomalies: OnceCell<Vec<usize>>,
+    /// How many cells building `widths` has added up, for tests to see that column
+    /// lookups don't walk the row.
+    #[cfg(test)]
+    pub cells_measured: std::cell::Cell<usize>,
 }
 
+impl PartialEq for Row {
+    fn eq(&self, other: &Self) -> bool {
+        self.rope == other.rope
+            && self.syntax == other.syntax
+            && self.final_state == other.final_state
+            && self.ending == other.ending
+    }
+}
+
+impl Eq for Row {}
+
 impl Row {
     pub fn len(&self) -> usize {
         self.rope.len()
@@ -26,6 +122,358 @@ impl Row {
         self.rope.is_empty()
     }
 
+    /// Whether the row is empty or contains only whitespace.
+    pub fn is_blank(&self) -> bool {
+        self.rope.iter().all(|(g, _)| g.trim().is_empty())
+    }
+
+    /// The index of the first cell that isn't whitespace, or the length of the row.
+    pub fn first_non_blank(&self) -> usize {
+        self.rope
+            .iter()
+            .position(|(g, _)| !g.trim().is_empty())
+            .unwrap_or(self.len())
+    }
+
+    /// Whether the cell at `idx` is code rather than part of a string or comment, as of
+    /// the last highlighting.
+    pub fn is_code(&self, idx: usize) -> bool {
+        !matches!(
+            self.syntax.get(idx),
+            Some(
+                TokenType::String | TokenType::MlString | TokenType::Comment | TokenType::MlComment
+            )
+        )
+    }
+
+    fn class(&self, idx: usize) -> CharClass {
+        CharClass::of(&self.rope[idx].0)
+    }
+
+    /// The start of the word before `idx`: skip whitespace to the left, then the run of
+    /// cells of the same class.
+    pub fn word_start(&self, idx: usize) -> usize {
+        let mut idx = idx.min(self.len());
+        while idx > 0 && self.class(idx - 1) == CharClass::Whitespace {
+            idx -= 1;
+        }
+        if let Some(class) = idx.checked_sub(1).map(|i| self.class(i)) {
+            while idx > 0 && self.class(idx - 1) == class {
+                idx -= 1;
+            }
+        }
+        idx
+    }
+
+    /// The end of the word after `idx`: skip whitespace to the right, then the run of
+    /// cells of the same class.
+    pub fn word_end(&self, idx: usize) -> usize {
+        let mut idx = idx.min(self.len());
+        while idx < self.len() && self.class(idx) == CharClass::Whitespace {
+            idx += 1;
+        }
+        if idx < self.len() {
+            let class = self.class(idx);
+            while idx < self.len() && self.class(idx) == class {
+                idx += 1;
+            }
+        }
+        idx
+    }
+
+    /// Swap the cell before `idx` with the one at `idx`, or the last two cells if `idx`
+    /// is at the end. Returns the index after both, or `None` if there aren't two.
+    pub fn transpose_cells(&mut self, idx: usize) -> Option<usize> {
+        let idx = idx.min(self.len());
+        let second = if idx == self.len() {
+            idx.checked_sub(1)?
+        } else {
+            idx
+        };
+        let first = second.checked_sub(1)?;
+        self.rope.swap(first, second);
+        self.syntax.truncate(first);
+        self.forget_cells();
+        Some(second + 1)
+    }
+
+    /// Swap the word at or before `idx` with the next one, keeping what is between them.
+    /// Returns the index after both, or `None` if there aren't two such words.
+    pub fn transpose_words(&mut self, idx: usize) -> Option<usize> {
+        let len = self.len();
+        let is_word = |i: usize| self.class(i) == CharClass::Word;
+        let mut end = idx.min(len);
+        if end < len && is_word(end) {
+            while end < len && is_word(end) {
+                end += 1;
+            }
+        } else {
+            while end > 0 && !is_word(end - 1) {
+                end -= 1;
+            }
+        }
+        let mut start = end;
+        while start > 0 && is_word(start - 1) {
+            start -= 1;
+        }
+        let mut next_start = end;
+        while next_start < len && !is_word(next_start) {
+            next_start += 1;
+        }
+        let mut next_end = next_start;
+        while next_end < len && is_word(next_end) {
+            next_end += 1;
+        }
+        if start == end || next_start == next_end {
+            return None;
+        }
+
+        let mut cells: Vec<_> = self.rope.drain(start..next_end).collect();
+        let next = cells.split_off(next_start - start);
+        let between = cells.split_off(end - start);
+        let swapped = next.into_iter().chain(between).chain(cells);
+        self.rope.splice(start..start, swapped);
+        self.syntax.truncate(start);
+        self.forget_cells();
+        Some(next_end)
+    }
+
+    /// The run of cells of the same class around `idx`, preferring a word or
+    /// punctuation to whitespace, and the cell after `idx` to the one before it.
+    pub fn word_at(&self, idx: usize) -> Range<usize> {
+        let idx = idx.min(self.len());
+        let is_blank = |i: usize| self.class(i) == CharClass::Whitespace;
+        let pivot = if idx < self.len() && !is_blank(idx) {
+            idx
+        } else if idx > 0 && !is_blank(idx - 1) {
+            idx - 1
+        } else if idx < self.len() {
+            idx
+        } else {
+            return idx..idx;
+        };
+
+        let class = self.class(pivot);
+        let (mut start, mut end) = (pivot, pivot + 1);
+        while start > 0 && self.class(start - 1) == class {
+            start -= 1;
+        }
+        while end < self.len() && self.class(end) == class {
+            end += 1;
+        }
+        start..end
+    }
+
+    /// The cell ranges of the `http://` and `https://` URLs in the row. A URL runs up to
+    /// whitespace, a quote or an angle bracket, without the punctuation ending a
+    /// sentence or a closing parenthesis it didn't open.
+    pub fn urls(&self) -> Vec<Range<usize>> {
+        let cell = |i: usize| self.rope.get(i).map_or("", |(g, _)| g.as_str());
+        let starts_with = |i: usize, prefix: &str| {
+            prefix.chars().enumerate().all(|(k, c)| {
+                let mut chars = cell(i + k).chars();
+                chars.next() == Some(c) && chars.next().is_none()
+            })
+        };
+
+        let mut urls = Vec::new();
+        let mut i = 0;
+        while i < self.len() {
+            let scheme = ["https://", "http://"]
+                .into_iter()
+                .find(|scheme| starts_with(i, scheme));
+            let after_word = i > 0 && cell(i - 1).chars().all(char::is_alphanumeric);
+            let (Some(scheme), false) = (scheme, after_word) else {
+                i += 1;
+                continue;
+            };
+
+            let mut end = i + scheme.len();
+            while end < self.len()
+                && !cell(end)
+                    .chars()
+                    .any(|c| c.is_whitespace() || "\"'<>`".contains(c))
+            {
+                end += 1;
+            }
+            let count = |end: usize, s: &str| (i..end).filter(|&k| cell(k) == s).count();
+            while end > i + scheme.len() {
+                match cell(end - 1) {
+                    "." | "," | ";" | ":" | "!" | "?" | "。" | "，" | "；" | "：" | "！" | "？" => {
+                        end -= 1
+                    }
+                    ")" if count(end, "(") < count(end, ")") => end -= 1,
+                    _ => break,
+                }
+            }
+            if end > i + scheme.len() {
+                urls.push(i..end);
+            }
+            i = end.max(i + 1);
+        }
+        urls
+    }
+
+    /// The cells `needle` covers wherever it is found, in order and without overlaps.
+    /// A match covers whole cells; `ignore_case` compares the lowercase of each character.
+    /// Both sides are compared in normalization form C, so `é` matches `e` followed by a
+    /// combining accent.
+    pub fn find_all(&self, needle: &str, ignore_case: bool) -> Vec<Range<usize>> {
+        let mut found = Vec::new();
+        if needle.is_empty() {
+            return found;
+        }
+        let needle = &nfc(needle);
+        let mut i = 0;
+        while i < self.len() {
+            match self.match_len(i, needle, ignore_case) {
+                Some(len) => {
+                    found.push(i..i + len);
+                    i += len;
+                }
+                None => i += 1,
+            }
+        }
+        found
+    }
+
+    /// How many cells from `idx` on spell out `needle`, if they do.
+    fn match_len(&self, idx: usize, needle: &str, ignore_case: bool) -> Option<usize> {
+        let mut rest = needle;
+        for (len, (g, _)) in self.rope[idx..].iter().enumerate() {
+            if rest.is_empty() {
+                return Some(len);
+            }
+            let g = nfc(g);
+            rest = if ignore_case {
+                strip_prefix_ignore_case(rest, &g)?
+            } else {
+                rest.strip_prefix(&*g)?
+            };
+        }
+        rest.is_empty().then_some(self.len() - idx)
+    }
+
+    /// Insert a string before the cell at `idx`. Returns the number of cells inserted.
+    pub fn insert_str(&mut self, idx: usize, string: &str) -> usize {
+        let cells = Row::from(string).rope;
+        let count = cells.len();
+        self.rope.splice(idx..idx, cells);
+        self.syntax.truncate(idx);
+        self.forget_cells();
+        count
+    }
+
+    /// Insert a single cell, e.g. a tab drawn several columns wide.
+    pub fn insert_cell(&mut self, idx: usize, cell: Cell) {
+        self.rope.insert(idx, cell);
+        self.syntax.truncate(idx);
+        self.forget_cells();
+    }
+
+    /// Remove a range of cells and return them as a new row.
+    pub fn remove_range(&mut self, range: Range<usize>) -> Row {
+        let syntax = self.syntax.get(range.clone()).map(<[_]>::to_vec);
+        self.syntax.truncate(range.start);
+        self.forget_cells();
+        Row {
+            syntax: syntax.unwrap_or_default(),
+            ..Row::from(self.rope.drain(range).collect::<Vec<_>>())
+        }
+    }
+
+    /// Split the row in two at `idx`, returning the cells from `idx` on. The line break
+    /// moves to the returned row, leaving a new one in between.
+    pub fn split_off(&mut self, idx: usize) -> Row {
+        Row {
+            ending: self.ending.take(),
+            ..self.remove_range(idx..self.len())
+        }
+    }
+
+    /// Move the cells of another row to the end of this one, joining the lines. The
+    /// line break of the other row is kept.
+    pub fn append(&mut self, other: Row) {
+        self.syntax.truncate(self.len());
+        self.rope.extend(other.rope);
+        self.ending = other.ending;
+        self.forget_cells();
+    }
+
+    /// Measure the cells again, after the width of ambiguous characters has changed.
+    pub fn remeasure(&mut self) {
+        for (g, w) in &mut self.rope {
+            *w = g.width();
+        }
+        self.forget_cells();
+    }
+
+    /// Drop what is cached about the cells, after they change.
+    fn forget_cells(&mut self) {
+        self.widths.take();
+        self.anomalies.take();
+    }
+
+    /// The indices of the cells with hard to see characters in them, like zero-width
+    /// spaces or bidirectional controls.
+    pub fn anomalies(&self) -> &[usize] {
+        self.anomalies.get_or_init(|| {
+            (self.rope.iter().enumerate())
+                .filter(|(_, (g, _))| g.chars().any(anomaly::is_anomaly))
+                .map(|(i, _)| i)
+                .collect()
+        })
+    }
+
+    fn widths(&self) -> &[usize] {
+        self.widths.get_or_init(|| {
+            #[cfg(test)]
+            self.cells_measured
+                .set(self.cells_measured.get() + self.len());
+            let mut sum = 0;
+            let mut widths = Vec::with_capacity(self.len() + 1);
+            widths.push(0);
+            widths.extend(self.rope.iter().map(|(_, w)| {
+                sum += w;
+                sum
+            }));
+            widths
+        })
+    }
+
+    /// The number of columns the row takes up on screen.
+    pub fn visual_width(&self) -> usize {
+        *self.widths().last().unwrap()
+    }
+
+    /// The column where the cell at `idx` starts. Indices past the end give the width
+    /// of the row.
+    pub fn index_to_x(&self, idx: usize) -> usize {
+        self.widths()[idx.min(self.len())]
+    }
+
+    /// The index of the first cell starting at or after column `x`, or the length of
+    /// the row if `x` is past its end.
+    pub fn x_to_index(&self, x: usize) -> usize {
+        if x >= self.visual_width() {
+            self.len()
+        } else {
+            self.widths().partition_point(|&w| w < x)
+        }
+    }
+
+    /// The index of the cell the byte at `byte` belongs to, counting in UTF-8, or the
+    /// length of the row if `byte` is past its end.
+    pub fn byte_to_index(&self, byte: usize) -> usize {
+        let mut end = 0;
+        (self.rope.iter())
+            .position(|(g, _)| {
+                end += g.len();
+                end > byte
+            })
+            .unwrap_or(self.len())
+    }
+
     #[allow(clippy::inherent_to_string)]
     pub fn to_string(&self) -> String {
         self.rope
@@ -67,6 +515,10 @@ impl Row {
         'syntax_loop: while self.syntax.len() < self.len() {
             let i = self.syntax.len();
             let find_str = |s: &str| str[byte_offset[i]..].starts_with(s);
+            // The number of cells `s` covers when it was found at `i`
+            let cells = |s: &str| {
+                byte_offset[i..].partition_point(|&offset| offset < byte_offset[i] + s.len())
+            };
 
             if *state == TokenState::Normal && syntax.sl_comment_start.iter().any(|s| find_str(s)) {
                 self.syntax
@@ -92,7 +544,7 @@ impl Row {
                     if *state == *mstate {
                         if find_str(end) {
                             // Highlight the remaining symbols of the multi line comment end
-                            self.syntax.extend(repeat(mtype).take(end.len()));
+                            self.syntax.extend(repeat(mtype).take(cells(end)));
                             *state = TokenState::Normal;
                         } else {
                             self.syntax.push(*mtype);
@@ -100,7 +552,7 @@ impl Row {
                         continue 'syntax_loop;
                     } else if *state == TokenState::Normal && find_str(start) {
                         // Highlight the remaining symbols of the multi line comment start
-                        self.syntax.extend(repeat(mtype).take(start.len()));
+                        self.syntax.extend(repeat(mtype).take(cells(start)));
                         *state = mstate.clone();
                         continue 'syntax_loop;
                     }
@@ -145,20 +597,27 @@ impl Row {
                 // The argument is the keyword that is matched at `i`.
                 let s_filter = |kw: &str| {
                     self.rope
-                        .get(i + kw.len())
+                        .get(i + cells(kw))
                         .map_or(true, |c| is_sep(c.0.as_str()))
                 };
-                for (keyword_highlight_type, kws) in &syntax.keywords {
-                    for keyword in kws.iter().filter(|kw| find_str(kw) && s_filter(kw)) {
-                        self.syntax
-                            .extend(repeat(*keyword_highlight_type).take(keyword.len()));
-                    }
+                let keyword = syntax.keywords.iter().find_map(|(token, kws)| {
+                    let keyword = kws
+                        .iter()
+                        .find(|kw| !kw.is_empty() && find_str(kw) && s_filter(kw))?;
+                    Some((*token, keyword))
+                });
+                if let Some((token, keyword)) = keyword {
+                    self.syntax.extend(repeat(token).take(cells(keyword)));
+                    continue;
                 }
             }
 
             self.syntax.push(TokenType::Normal);
         }
 
+        debug_assert_eq!(self.syntax.len(), self.len(), "{self:?}");
+        self.syntax.truncate(self.len());
+
         // String state doesn't propagate to the next row
         self.final_state = if matches!(state, TokenState::String(_)) {
             TokenState::Normal
@@ -186,7 +645,12 @@ impl From<&str> for Row {
         Self {
             syntax: vec![],
             final_state: TokenState::Normal,
+            ending: None,
             rope,
+            widths: OnceCell::new(),
+            anomalies: OnceCell::new(),
+            #[cfg(test)]
+            cells_measured: Default::default(),
         }
     }
 }
@@ -196,7 +660,12 @@ impl From<Vec<Cell>> for Row {
         Self {
             syntax: vec![],
             final_state: TokenState::Normal,
+            ending: None,
             rope,
+            widths: OnceCell::new(),
+            anomalies: OnceCell::new(),
+            #[cfg(test)]
+            cells_measured: Default::default(),
         }
     }
 }
@@ -210,7 +679,11 @@ impl fmt::Debug for Row {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{syntax::TokenState, Error};
+    use crate::{
+        syntax::{TokenState, SYNTAX_DIR},
+        Error,
+    };
+    use std::path::Path;
 
     #[test]
     fn test_row() {
@@ -220,10 +693,246 @@ mod tests {
         assert_eq!(row.is_empty(), false);
     }
 
+    #[test]
+    fn test_char_class() {
+        assert_eq!(CharClass::of(" "), CharClass::Whitespace);
+        assert_eq!(CharClass::of("\t"), CharClass::Whitespace);
+        assert_eq!(CharClass::of("_"), CharClass::Word);
+        assert_eq!(CharClass::of("9"), CharClass::Word);
+        assert_eq!(CharClass::of("中"), CharClass::Word);
+        assert_eq!(CharClass::of("é"), CharClass::Word);
+        assert_eq!(CharClass::of("."), CharClass::Punctuation);
+        assert_eq!(CharClass::of("，"), CharClass::Punctuation);
+        assert_eq!(CharClass::of("🤗"), CharClass::Punctuation);
+    }
+
+    #[test]
+    fn test_word_motion() {
+        let row = Row::from("foo.bar(baz_1, qux)  ");
+        let stops = |mut idx: usize, next: fn(&Row, usize) -> usize| {
+            let mut stops = vec![];
+            while next(&row, idx) != idx {
+                idx = next(&row, idx);
+                stops.push(idx);
+            }
+            stops
+        };
+        assert_eq!(stops(0, Row::word_end), [3, 4, 7, 8, 13, 14, 18, 19, 21]);
+        assert_eq!(stops(21, Row::word_start), [18, 15, 13, 8, 7, 4, 3, 0]);
+
+        let row = Row::from("let 变量=\"中文，ok\";");
+        assert_eq!(row.word_end(0), 3);
+        assert_eq!(row.word_end(3), 6);
+        assert_eq!(row.word_end(6), 8);
+        assert_eq!(row.word_end(8), 10);
+        assert_eq!(row.word_end(10), 11);
+        assert_eq!(row.word_end(11), 13);
+        assert_eq!(row.word_start(13), 11);
+        assert_eq!(row.word_start(6), 4);
+        assert_eq!(row.word_start(99), 13);
+    }
+
+    #[test]
+    fn test_is_blank() {
+        assert!(Row::from("").is_blank());
+        assert!(Row::from(" \t　").is_blank());
+        assert!(!Row::from("  x").is_blank());
+    }
+
+    #[test]
+    fn test_urls() {
+        let row = Row::from("see https://a.io/x_(y), (http://b.io). xhttp://c http://");
+        let urls: Vec<String> = (row.urls().into_iter())
+            .map(|range| row.rope[range].iter().map(|(g, _)| g.as_str()).collect())
+            .collect();
+        assert_eq!(urls, ["https://a.io/x_(y)", "http://b.io"]);
+        assert_eq!(Row::from("链接：https://例子.cn。").urls(), vec![3..16]);
+    }
+
+    #[test]
+    fn test_find_all() {
+        let row = Row::from("aaaa Foo foo 中文字中文");
+        assert_eq!(row.find_all("aa", false), vec![0..2, 2..4]);
+        assert_eq!(row.find_all("foo", false), vec![9..12]);
+        assert_eq!(row.find_all("FOO", true), vec![5..8, 9..12]);
+        assert_eq!(row.find_all("中文", false), vec![13..15, 16..18]);
+        assert_eq!(row.find_all("", false), vec![]);
+        // Half a cell is no match
+        assert_eq!(Row::from("e\u{301}e").find_all("e", false), vec![1..2]);
+        // Composed and decomposed accents match each other
+        let row = Row::from("cafe\u{301} CAFÉ");
+        assert_eq!(row.find_all("café", false), vec![0..4]);
+        assert_eq!(row.find_all("cafe\u{301}", true), vec![0..4, 5..9]);
+    }
+
+    #[test]
+    fn test_transpose() {
+        let transpose = |text: &str, idx, words| {
+            let mut row = Row::from(text);
+            let after = match words {
+                true => row.transpose_words(idx),
+                false => row.transpose_cells(idx),
+            };
+            after.map(|after| (row.to_string(), after))
+        };
+        assert_eq!(transpose("abc", 1, false), Some(("bac".into(), 2)));
+        assert_eq!(transpose("ab中", 3, false), Some(("a中b".into(), 3)));
+        assert_eq!(transpose("a", 1, false), None);
+        assert_eq!(transpose("ab", 0, false), None);
+        let mut row = Row::from("中a");
+        row.transpose_cells(1);
+        assert_eq!((row.visual_width(), row.index_to_x(1)), (3, 1));
+
+        assert_eq!(
+            transpose("foo, bar baz", 1, true),
+            Some(("bar, foo baz".into(), 8))
+        );
+        // Right after a word, and in the punctuation after it
+        assert_eq!(transpose("foo, bar", 3, true), Some(("bar, foo".into(), 8)));
+        assert_eq!(transpose("foo, bar", 4, true), Some(("bar, foo".into(), 8)));
+        assert_eq!(transpose("中文 字", 0, true), Some(("字 中文".into(), 4)));
+        assert_eq!(transpose("foo bar", 7, true), None);
+        assert_eq!(transpose(" ,foo", 0, true), None);
+    }
+
+    #[test]
+    fn test_word_at() {
+        let row = Row::from("foo.bar  中文");
+        assert_eq!(row.word_at(0), 0..3);
+        assert_eq!(row.word_at(3), 3..4);
+        assert_eq!(row.word_at(7), 4..7);
+        assert_eq!(row.word_at(8), 7..9);
+        assert_eq!(row.word_at(10), 9..11);
+        assert_eq!(row.word_at(99), 9..11);
+        assert_eq!(Row::from("  ").word_at(1), 0..2);
+        assert_eq!(Row::from("").word_at(0), 0..0);
+    }
+
+    #[test]
+    fn test_insert_str() {
+        let mut row = Row::from("ab");
+        assert_eq!(row.insert_str(1, "你好🤗"), 3);
+        assert_eq!(row.to_string(), "a你好🤗b");
+        assert_eq!(row.rope[3], ("🤗".to_string(), 2));
+        assert_eq!(row.insert_str(5, "👨‍👩‍👧!"), 2);
+        assert_eq!(row.to_string(), "a你好🤗b👨‍👩‍👧!");
+        assert_eq!(row.len(), 7);
+    }
+
+    #[test]
+    fn test_remove_range() {
+        let mut row = Row::from("中文🤗ok");
+        row.syntax = vec![TokenType::Normal; 5];
+        let removed = row.remove_range(1..3);
+        assert_eq!(removed.to_string(), "文🤗");
+        assert_eq!(removed.syntax.len(), 2);
+        assert_eq!(row.to_string(), "中ok");
+        assert_eq!(row.syntax.len(), 1);
+        assert_eq!(row.rope.iter().map(|g| g.1).sum::<usize>(), 4);
+    }
+
+    #[test]
+    fn test_split_off_append() {
+        let mut row = Row::from("第一🤗second");
+        row.syntax = vec![TokenType::Normal; row.len()];
+        row.ending = Some(LineEnding::Crlf);
+        let mut right = row.split_off(3);
+        assert_eq!(row.to_string(), "第一🤗");
+        assert_eq!(right.to_string(), "second");
+        assert_eq!(row.syntax.len(), 3);
+        // The line break stays at the end
+        assert_eq!(row.ending, None);
+        assert_eq!(right.ending, Some(LineEnding::Crlf));
+
+        assert!(right.split_off(6).is_empty());
+        row.append(right);
+        assert_eq!(row.to_string(), "第一🤗second");
+        assert_eq!(row.syntax.len(), 3);
+    }
+
+    #[test]
+    fn test_widths() {
+        let mut row = Row::from("a中🤗b");
+        assert_eq!(row.visual_width(), 6);
+        assert_eq!(
+            (0..=5).map(|i| row.index_to_x(i)).collect::<Vec<_>>(),
+            [0, 1, 3, 5, 6, 6]
+        );
+        // Columns inside a wide character go to the next cell
+        assert_eq!(
+            (0..=7).map(|x| row.x_to_index(x)).collect::<Vec<_>>(),
+            [0, 1, 2, 2, 3, 3, 4, 4]
+        );
+
+        // The cache follows edits
+        row.insert_str(1, "你好");
+        assert_eq!(row.visual_width(), 10);
+        assert_eq!(row.index_to_x(3), 5);
+        row.remove_range(0..3);
+        assert_eq!(row.visual_width(), 5);
+        assert_eq!(row.x_to_index(2), 1);
+        row.append(Row::from("xyz"));
+        assert_eq!(row.visual_width(), 8);
+        row.split_off(2);
+        assert_eq!(row.visual_width(), 4);
+        assert_eq!(row, Row::from("中🤗"));
+    }
+
+    #[test]
+    fn test_long_line() {
+        let row = Row::from("一二三四五".repeat(10_000).as_str());
+        assert_eq!(row.visual_width(), 100_000);
+        assert_eq!(row.x_to_index(77_777), 38_889);
+        assert_eq!(row.index_to_x(38_889), 77_778);
+    }
+
+    #[test]
+    fn test_anomalies() {
+        let mut row = Row::from("a\u{a0}b\u{200b}c");
+        assert_eq!(row.anomalies(), [1, 3]);
+        row.remove_range(0..2);
+        assert_eq!(row.anomalies(), [1]);
+        // Emoji joined by a zero width joiner are one picture
+        assert!(Row::from("👩\u{200d}💻 ok").anomalies().is_empty());
+    }
+
+    #[test]
+    fn test_syntax_length() -> Result<(), Error> {
+        let syntax = Syntax::get(Path::new(SYNTAX_DIR), "js")?.unwrap();
+        let highlight = |row: &mut Row| {
+            row.update_syntax(&syntax, &mut TokenState::Normal);
+            assert_eq!(row.syntax.len(), row.len());
+        };
+
+        // A keyword at the end of the line, here one in two groups, doesn't spill over
+        let mut row = Row::from("x = eval");
+        highlight(&mut row);
+        assert_eq!(row.syntax[4..], [TokenType::Keyword1; 4]);
+        let mut row = Row::from("return\"中\"");
+        highlight(&mut row);
+        assert_eq!(row.syntax[..6], [TokenType::Keyword3; 6]);
+        assert_eq!(row.syntax[6..], [TokenType::String; 3]);
+
+        // Edits between highlight passes never leave stale types past the text
+        let mut row = Row::from("let x = 42;");
+        highlight(&mut row);
+        row.insert_str(4, "中文");
+        assert_eq!(row.syntax.len(), 4);
+        row.remove_range(0..4);
+        assert!(row.syntax.is_empty());
+        let mut tail = Row::from("return");
+        highlight(&mut tail);
+        row.append(tail);
+        assert!(row.syntax.len() <= row.len());
+        highlight(&mut row);
+        assert_eq!(row.syntax[..2], [TokenType::Normal; 2]);
+        Ok(())
+    }
+
     #[test]
     fn test_update_syntax() -> Result<(), Error> {
         let mut row = Row::from("let x = 42;");
-        let syntax = Syntax::get("js")?.unwrap();
+        let syntax = Syntax::get(Path::new(SYNTAX_DIR), "js")?.unwrap();
         let mut state = TokenState::Normal;
         row.update_syntax(&syntax, &mut state);
         assert_eq!(row.syntax.len(), 11);
@@ -0,0 +1,125 @@
+//! Noticing that the terminal was closed or the editor was told to stop, so that
+//! unsaved changes can be put somewhere safe before exiting.
+//!
+//! The handlers only record what arrived; the event loop checks for it between
+//! events and does the saving. A second SIGHUP while the first is still pending means
+//! the loop is stuck, and the handler exits on the spot.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+use crate::{recent, Buffer};
+
+/// The signal (or Windows console event) that arrived, or 0.
+static RECEIVED: AtomicI32 = AtomicI32::new(0);
+
+/// Start listening for SIGTERM and SIGHUP, or for the console window being closed on
+/// Windows.
+pub fn install() {
+    #[cfg(unix)]
+    unsafe {
+        let handler = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGHUP, handler);
+    }
+    #[cfg(windows)]
+    unsafe {
+        SetConsoleCtrlHandler(Some(handle_console), 1);
+    }
+}
+
+/// The signal that arrived, if one did.
+pub fn received() -> Option<i32> {
+    Some(RECEIVED.load(Ordering::Relaxed)).filter(|&signal| signal != 0)
+}
+
+/// The status to exit with after `signal`, as shells report a process killed by it.
+pub fn exit_code(signal: i32) -> i32 {
+    128 + signal
+}
+
+/// What `Terminal::cleanup` writes, for when there is no time to run it: the keyboard
+/// protocol, focus, paste and mouse reporting off, line wrap on, the main screen and
+/// the cursor back. Raw mode stays, as the settings it replaced are out of reach here.
+#[cfg(unix)]
+const RESET: &[u8] = b"\x1b[<1u\x1b[?1004l\x1b[?2004l\x1b[?1006l\x1b[?1015l\x1b[?1003l\
+\x1b[?1002l\x1b[?1000l\x1b[?7h\x1b[?1049l\x1b[?25h";
+
+#[cfg(unix)]
+extern "C" fn handle(signal: libc::c_int) {
+    if RECEIVED.swap(signal, Ordering::Relaxed) != 0 && signal == libc::SIGHUP {
+        // SAFETY: `write` and `_exit` are async-signal-safe
+        unsafe {
+            libc::write(libc::STDOUT_FILENO, RESET.as_ptr().cast(), RESET.len());
+            libc::_exit(exit_code(signal));
+        }
+    }
+}
+
+#[cfg(windows)]
+extern "system" {
+    fn SetConsoleCtrlHandler(
+        handler: Option<unsafe extern "system" fn(u32) -> i32>,
+        add: i32,
+    ) -> i32;
+}
+
+/// Runs on a thread of its own. Windows ends the process once this returns for the
+/// window being closed, so it waits for the event loop to finish up instead.
+#[cfg(windows)]
+unsafe extern "system" fn handle_console(event: u32) -> i32 {
+    // CTRL_CLOSE_EVENT, CTRL_LOGOFF_EVENT and CTRL_SHUTDOWN_EVENT
+    if !matches!(event, 2 | 5 | 6) {
+        return 0;
+    }
+    RECEIVED.store(event as i32, Ordering::Relaxed);
+    std::thread::sleep(std::time::Duration::from_secs(5));
+    1
+}
+
+/// Where the unsaved changes of `buffer` go when the editor has to exit: a file in
+/// `$XDG_STATE_HOME/arcaea/recovery` named after the buffer's file, with its path
+/// separators turned into `%`, or `Untitled-N`, and this process's id.
+pub fn recovery_path(buffer: &Buffer) -> Option<PathBuf> {
+    let file = match &buffer.filename {
+        Some(filename) => std::path::absolute(filename).unwrap_or_else(|_| filename.into()),
+        None => PathBuf::from(format!("Untitled-{}", buffer.scratch)),
+    };
+    Some(state_path(&recent::state_dir()?, &file))
+}
+
+fn state_path(dir: &Path, file: &Path) -> PathBuf {
+    let name = file.to_string_lossy().replace(['/', '\\', ':'], "%");
+    dir.join("recovery")
+        .join(format!("{name}.{}", std::process::id()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reset() {
+        let reset = String::from_utf8_lossy(RESET);
+        assert!(reset.starts_with("\x1b[<1u\x1b[?1004l"));
+        assert!(reset.ends_with("\x1b[?1049l\x1b[?25h"));
+        assert!(!reset.contains(char::is_whitespace));
+    }
+
+    #[test]
+    fn test_recovery_path() {
+        let path = state_path(Path::new("/state"), Path::new("/home/me/notes.txt"));
+        assert_eq!(
+            path,
+            PathBuf::from(format!(
+                "/state/recovery/%home%me%notes.txt.{}",
+                std::process::id()
+            ))
+        );
+        assert!(state_path(Path::new("/state"), Path::new("Untitled-2"))
+            .ends_with(format!("Untitled-2.{}", std::process::id())));
+    }
+}
@@ -0,0 +1,108 @@
+use std::ops::Range;
+
+/// A snippet body with its placeholders resolved.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snippet {
+    /// The text to insert.
+    pub text: String,
+    /// The byte ranges of the placeholders in `text`, in the order Tab visits them:
+    /// `$1`, `$2`, ... and `$0` last. There is always a final one, at the end of the
+    /// text if the body has no `$0`.
+    pub placeholders: Vec<Range<usize>>,
+}
+
+impl Snippet {
+    /// Parse a snippet body such as `fn ${1:name}(${2}) {\n    $0\n}`.
+    ///
+    /// Placeholders are written `$1`, `${1}` or `${1:default text}`; only the first
+    /// occurrence of a number is kept. `\n`, `\t`, `\$` and `\\` are escapes, and
+    /// `indent` is added after every line break so the snippet lines up with the line
+    /// it is expanded on.
+    pub fn parse(body: &str, indent: &str) -> Self {
+        let mut text = String::new();
+        let mut numbered: Vec<(usize, Range<usize>)> = Vec::new();
+        let mut chars = body.chars().peekable();
+        // The number and start of the `${N:...}` being read
+        let mut open: Option<(usize, usize)> = None;
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('n') => {
+                        text.push('\n');
+                        text.push_str(indent);
+                    }
+                    Some('t') => text.push('\t'),
+                    Some(c @ ('\\' | '$' | '}')) => text.push(c),
+                    Some(c) => {
+                        text.push('\\');
+                        text.push(c);
+                    }
+                    None => text.push('\\'),
+                },
+                '}' if open.is_some() => {
+                    let (number, start) = open.take().unwrap();
+                    numbered.push((number, start..text.len()));
+                }
+                '$' => {
+                    let braced = chars.next_if_eq(&'{').is_some();
+                    let mut digits = String::new();
+                    while let Some(d) = chars.next_if(char::is_ascii_digit) {
+                        digits.push(d);
+                    }
+                    let Ok(number) = digits.parse() else {
+                        text.push('$');
+                        if braced {
+                            text.push('{');
+                        }
+                        continue;
+                    };
+                    if !braced {
+                        numbered.push((number, text.len()..text.len()));
+                    } else if chars.next_if_eq(&':').is_some() && open.is_none() {
+                        open = Some((number, text.len()));
+                    } else {
+                        chars.next_if_eq(&'}');
+                        numbered.push((number, text.len()..text.len()));
+                    }
+                }
+                c => text.push(c),
+            }
+        }
+        // An unclosed default runs to the end
+        if let Some((number, start)) = open {
+            numbered.push((number, start..text.len()));
+        }
+
+        let mut seen = Vec::new();
+        numbered.retain(|(number, _)| {
+            let first = !seen.contains(number);
+            seen.push(*number);
+            first
+        });
+        // `$0` sorts last
+        numbered.sort_by_key(|&(number, _)| number.wrapping_sub(1));
+        let mut placeholders: Vec<_> = numbered.into_iter().map(|(_, range)| range).collect();
+        if !seen.contains(&0) {
+            placeholders.push(text.len()..text.len());
+        }
+        Snippet { text, placeholders }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let snippet = Snippet::parse(r"fn ${1:name}($2) {\n    $0\n}", "  ");
+        assert_eq!(snippet.text, "fn name() {\n      \n  }");
+        assert_eq!(snippet.placeholders, vec![3..7, 8..8, 18..18]);
+
+        // Without `$0`, the last stop is the end of the text
+        let snippet = Snippet::parse(r"${2:b} ${1} \${3} $x", "");
+        assert_eq!(snippet.text, "b  ${3} $x");
+        assert_eq!(snippet.placeholders, vec![2..2, 0..1, 10..10]);
+    }
+}
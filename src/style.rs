@@ -1,6 +1,12 @@
-#![allow(non_upper_case_globals)]
+use std::{path::Path, sync::RwLock};
+
 use crossterm::style::Color;
 
+use crate::{
+    syntax::{process_ini_file, TokenType},
+    Error,
+};
+
 macro_rules! rgb {
     ($r:expr, $g:expr, $b:expr) => {
         Color::Rgb {
@@ -11,27 +17,164 @@ macro_rules! rgb {
     };
 }
 
-pub const background: Color = rgb!(34, 34, 34);
-pub const background_selected: Color = rgb!(38, 79, 120);
-pub const background_primary: Color = rgb!(166, 226, 46);
-pub const background_sidebar: Color = rgb!(51, 51, 51);
-pub const text_primary: Color = rgb!(34, 34, 34);
-pub const text: Color = rgb!(204, 204, 204);
-pub const text_dimmed: Color = rgb!(126, 126, 126);
-pub const text_statusbar: Color = rgb!(255, 255, 255);
-pub const text_sidebar_selected: Color = rgb!(204, 204, 204);
-
-pub const text_alert: Color = rgb!(255, 35, 0);
-pub const text_model: Color = rgb!(231, 231, 231);
-pub const text_model_primary: Color = rgb!(0, 120, 212);
-
-pub const token_normal: Color = rgb!(240, 240, 240);
-pub const token_number: Color = rgb!(181, 206, 168);
-pub const token_match: Color = text;
-pub const token_string: Color = rgb!(206, 145, 120);
-pub const token_ml_string: Color = rgb!(215, 186, 125);
-pub const token_comment: Color = rgb!(106, 153, 85);
-pub const token_ml_comment: Color = rgb!(99, 142, 80);
-pub const token_keyword1: Color = rgb!(86, 156, 214);
-pub const token_keyword2: Color = rgb!(78, 201, 176);
-pub const token_keyword3: Color = rgb!(195, 133, 190);
+/// Declares the `Theme` struct, its defaults, and one accessor function per color
+/// returning it from the active theme.
+macro_rules! theme {
+    ($($name:ident: $default:expr,)*) => {
+        /// The colors of the user interface.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub struct Theme {
+            $(pub $name: Color,)*
+        }
+
+        impl Theme {
+            pub const DEFAULT: Theme = Theme {
+                $($name: $default,)*
+            };
+
+            fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+                match key {
+                    $(stringify!($name) => self.$name = parse_color(value)?,)*
+                    _ => return Err(format!("Invalid key: {key}")),
+                }
+                Ok(())
+            }
+        }
+
+        $(
+            pub fn $name() -> Color {
+                THEME.read().unwrap().$name
+            }
+        )*
+    };
+}
+
+theme! {
+    background: rgb!(34, 34, 34),
+    background_selected: rgb!(38, 79, 120),
+    background_primary: rgb!(166, 226, 46),
+    background_sidebar: rgb!(51, 51, 51),
+    background_sidebar_selected: rgb!(42, 63, 87),
+    background_long_line: rgb!(72, 40, 40),
+    background_match: rgb!(98, 81, 31),
+    background_anomaly: rgb!(150, 60, 150),
+    text_primary: rgb!(34, 34, 34),
+    text: rgb!(204, 204, 204),
+    text_dimmed: rgb!(126, 126, 126),
+    text_statusbar: rgb!(255, 255, 255),
+    text_sidebar_selected: rgb!(204, 204, 204),
+    text_sidebar_anchor: rgb!(86, 156, 214),
+
+    text_alert: rgb!(255, 35, 0),
+    text_model: rgb!(231, 231, 231),
+    text_model_primary: rgb!(0, 120, 212),
+
+    token_normal: rgb!(240, 240, 240),
+    token_number: rgb!(181, 206, 168),
+    token_match: rgb!(204, 204, 204),
+    token_string: rgb!(206, 145, 120),
+    token_ml_string: rgb!(215, 186, 125),
+    token_comment: rgb!(106, 153, 85),
+    token_ml_comment: rgb!(99, 142, 80),
+    token_keyword1: rgb!(86, 156, 214),
+    token_keyword2: rgb!(78, 201, 176),
+    token_keyword3: rgb!(195, 133, 190),
+}
+
+static THEME: RwLock<Theme> = RwLock::new(Theme::DEFAULT);
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::DEFAULT
+    }
+}
+
+impl Theme {
+    /// Load a theme file. Colors it doesn't mention keep their default value.
+    ///
+    /// ```ini
+    /// background=#222222
+    /// token_comment=106, 153, 85
+    /// ```
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let mut theme = Theme::DEFAULT;
+        process_ini_file(path, &mut |key, value, _| theme.set(key, value))?;
+        Ok(theme)
+    }
+}
+
+/// Replace the active theme.
+pub fn set_theme(theme: Theme) {
+    *THEME.write().unwrap() = theme;
+}
+
+/// Parse a color written as `#rrggbb` or `r, g, b`.
+fn parse_color(value: &str) -> Result<Color, String> {
+    let value = value.trim();
+    let invalid = || format!("Invalid color: {value}");
+    let channels = if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() != 6 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        (0..3)
+            .map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid()))
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        value
+            .split(',')
+            .map(|c| c.trim().parse::<u8>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    match channels[..] {
+        [r, g, b] => Ok(rgb!(r, g, b)),
+        _ => Err(invalid()),
+    }
+}
+
+/// The foreground color of a syntax highlighting token.
+pub fn token_color(token: TokenType) -> Color {
+    match token {
+        TokenType::Normal => token_normal(),
+        TokenType::Number => token_number(),
+        TokenType::Match => token_match(),
+        TokenType::String => token_string(),
+        TokenType::MlString => token_ml_string(),
+        TokenType::Comment => token_comment(),
+        TokenType::MlComment => token_ml_comment(),
+        TokenType::Keyword1 => token_keyword1(),
+        TokenType::Keyword2 => token_keyword2(),
+        TokenType::Keyword3 => token_keyword3(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("#2264aA"), Ok(rgb!(0x22, 0x64, 0xaa)));
+        assert_eq!(parse_color(" 1, 2,3 "), Ok(rgb!(1, 2, 3)));
+        assert!(parse_color("#12345").is_err());
+        assert!(parse_color("#12345g").is_err());
+        assert!(parse_color("1, 2").is_err());
+        assert!(parse_color("256, 0, 0").is_err());
+    }
+
+    #[test]
+    fn test_theme_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "; comment\nbackground=#000000\ntoken_number=1, 2, 3").unwrap();
+        let theme = Theme::from_file(file.path()).unwrap();
+        assert_eq!(theme.background, rgb!(0, 0, 0));
+        assert_eq!(theme.token_number, rgb!(1, 2, 3));
+        assert_eq!(theme.text, Theme::DEFAULT.text);
+
+        writeln!(file, "text=#zzzzzz").unwrap();
+        match Theme::from_file(file.path()) {
+            Err(Error::FileError(_, 4, _)) => {}
+            result => panic!("Unexpected result {:?}", result.err()),
+        }
+    }
+}
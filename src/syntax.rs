@@ -1,13 +1,23 @@
+use std::collections::HashMap;
 use std::fmt::Display;
 use std::fs;
-use std::io::{BufRead, BufReader};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use crate::error::Error;
 
+/// The directory syntax definitions are loaded from.
+pub const SYNTAX_DIR: &str = "syntax.d";
+
+/// The definitions in `syntax.d` at build time, as `(file name, content)`, for when
+/// there is no directory to load them from.
+const BUILTIN: &[(&str, &str)] = include!(concat!(env!("OUT_DIR"), "/syntax_d.rs"));
+
+/// A syntax definition file together with the result of parsing it.
+pub type Definition = (PathBuf, Result<(Syntax, Vec<String>), Error>);
+
 /// The "Highlight State" of the row
-#[derive(Clone, Default, PartialEq, Eq)]
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
 pub enum TokenState {
     /// Normal state.
     #[default]
@@ -47,6 +57,8 @@ pub struct Syntax {
     pub name: String,
     /// Whether to highlight numbers.
     pub highlight_numbers: bool,
+    /// Whether reindenting follows the nesting of brackets.
+    pub indent_brackets: bool,
     /// Quotes for single-line strings.
     pub sl_string_quotes: Vec<String>,
     /// The tokens that starts a single-line comment, e.g. "//".
@@ -59,25 +71,55 @@ pub struct Syntax {
     /// Keywords to highlight and there corresponding `HLType` (typically
     /// `HLType::Keyword1` or `HLType::Keyword2`)
     pub keywords: Vec<(TokenType, Vec<String>)>,
+    /// Words that start a definition worth listing in the outline, e.g. "fn" or "class",
+    /// or "#" for a Markdown heading.
+    pub structure_keywords: Vec<String>,
+    /// Snippet bodies by trigger word, from the `snippet.<trigger>` keys.
+    pub snippets: HashMap<String, String>,
+    /// The file the definition was loaded from.
+    pub path: Option<PathBuf>,
 }
 
 /// Process an INI file.
 ///
-/// The `kv_fn` function will be called for each key-value pair in the file.
-/// Typically, this function will update a configuration instance.
+/// The `kv_fn` function will be called for each key-value pair in the file, with the
+/// number of the line it is on. Typically, this function will update a configuration
+/// instance. Keys below a `[section]` header are passed as `section.key`.
+#[cfg(feature = "tui")]
 pub fn process_ini_file<F>(path: &Path, kv_fn: &mut F) -> Result<(), Error>
 where
-    F: FnMut(&str, &str) -> Result<(), String>,
+    F: FnMut(&str, &str, usize) -> Result<(), String>,
+{
+    let content =
+        fs::read_to_string(path).map_err(|e| Error::FileError(path.into(), 0, e.to_string()))?;
+    process_ini_lines(path, content.lines(), kv_fn)
+}
+
+/// Like `process_ini_file`, for lines already read. `path` is only used in errors.
+fn process_ini_lines<'a, F>(
+    path: &Path,
+    lines: impl Iterator<Item = &'a str>,
+    kv_fn: &mut F,
+) -> Result<(), Error>
+where
+    F: FnMut(&str, &str, usize) -> Result<(), String>,
 {
-    let file = fs::File::open(path).map_err(|e| Error::FileError(path.into(), 0, e.to_string()))?;
-    for (i, line) in BufReader::new(file).lines().enumerate() {
-        let (i, line) = (i + 1, line?);
+    let mut section = String::new();
+    for (i, line) in lines.enumerate() {
+        let i = i + 1;
+        if let Some(name) = line
+            .trim()
+            .strip_prefix('[')
+            .and_then(|l| l.strip_suffix(']'))
+        {
+            section = format!("{}.", name.trim());
+            continue;
+        }
         let mut parts = line.trim_start().splitn(2, '=');
         match (parts.next(), parts.next()) {
             (Some(comment_line), _) if comment_line.starts_with(&['#', ';'][..]) => (),
-            (Some(k), Some(v)) => {
-                kv_fn(k.trim_end(), v).map_err(|r| Error::FileError(path.into(), i, r))?
-            }
+            (Some(k), Some(v)) => kv_fn(&(section.clone() + k.trim_end()), v, i)
+                .map_err(|r| Error::FileError(path.into(), i, r))?,
             (Some(""), None) | (None, _) => (), // Empty line
             (Some(_), None) => {
                 return Err(Error::FileError(path.into(), i, String::from("No '='")))
@@ -89,9 +131,7 @@ where
 
 /// Trim a value (right-hand side of a key=value INI line) and parses it.
 pub fn pv<T: FromStr<Err = E>, E: Display>(value: &str) -> Result<T, String> {
-    value
-        .parse()
-        .map_err(|e| format!("Parser error: {e}"))
+    value.parse().map_err(|e| format!("Parser error: {e}"))
 }
 
 /// Split a comma-separated list of values (right-hand side of a
@@ -102,9 +142,9 @@ pub fn pvs<T: FromStr<Err = E>, E: Display>(value: &str) -> Result<Vec<T>, Strin
 
 impl Syntax {
     /// Return the syntax configuration corresponding to the given file
-    /// extension, if a matching INI file is found in a config directory.
-    pub fn get(ext: &str) -> Result<Option<Self>, Error> {
-        match fs::read_dir("syntax.d") {
+    /// extension, if a matching INI file is found in `dir`.
+    pub fn get(dir: &Path, ext: &str) -> Result<Option<Self>, Error> {
+        match fs::read_dir(dir) {
             Ok(dir_entries) => {
                 for dir_entry in dir_entries {
                     let (sc, extensions) = Self::from_file(&dir_entry?.path())?;
@@ -119,14 +159,61 @@ impl Syntax {
         Ok(None)
     }
 
+    /// The definition in `dir` with the given name, ignoring case.
+    pub fn by_name(dir: &Path, name: &str) -> Result<Option<Self>, Error> {
+        Ok(Self::definitions(dir)?
+            .into_iter()
+            .filter_map(|(_, result)| result.ok())
+            .map(|(syntax, _)| syntax)
+            .find(|syntax| syntax.name.eq_ignore_ascii_case(name)))
+    }
+
+    /// Load every syntax definition (`*.ini`) in a directory, sorted by file name.
+    /// Each file keeps its own result, so one broken definition doesn't hide the others.
+    pub fn definitions(dir: &Path) -> Result<Vec<Definition>, Error> {
+        let mut paths = fs::read_dir(dir)
+            .map_err(|e| Error::FileError(dir.into(), 0, e.to_string()))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.retain(|path| path.extension().is_some_and(|ext| ext == "ini"));
+        paths.sort();
+        Ok(paths
+            .into_iter()
+            .map(|path| {
+                let result = Self::from_file(&path);
+                (path, result)
+            })
+            .collect())
+    }
+
+    /// The definition built in for the given file extension, from `syntax.d` at build
+    /// time.
+    pub fn builtin(ext: &str) -> Option<Self> {
+        BUILTIN.iter().find_map(|(name, content)| {
+            let path = Path::new(SYNTAX_DIR).join(name);
+            let (sc, extensions) = Self::parse(&path, content).ok()?;
+            extensions.iter().any(|e| e == ext).then_some(sc)
+        })
+    }
+
     /// Load a `SyntaxConf` from file.
     pub fn from_file(path: &Path) -> Result<(Self, Vec<String>), Error> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| Error::FileError(path.into(), 0, e.to_string()))?;
+        let (mut sc, extensions) = Self::parse(path, &content)?;
+        sc.path = Some(path.to_path_buf());
+        Ok((sc, extensions))
+    }
+
+    /// Parse the content of a definition file. `path` is only used in errors.
+    fn parse(path: &Path, content: &str) -> Result<(Self, Vec<String>), Error> {
         let (mut sc, mut extensions) = (Self::default(), Vec::new());
-        process_ini_file(path, &mut |key, val| {
+        process_ini_lines(path, content.lines(), &mut |key, val, _| {
             match key {
                 "name" => sc.name = pv(val)?,
                 "extensions" => extensions.extend(val.split(", ").map(String::from)),
                 "highlight_numbers" => sc.highlight_numbers = pv(val)?,
+                "indent_brackets" => sc.indent_brackets = pv(val)?,
                 "singleline_string_quotes" => sc.sl_string_quotes = pvs(val)?,
                 "singleline_comment_start" => sc.sl_comment_start = pvs(val)?,
                 "multiline_comment_delims" => {
@@ -139,6 +226,11 @@ impl Syntax {
                 "keywords_1" => sc.keywords.push((TokenType::Keyword1, pvs(val)?)),
                 "keywords_2" => sc.keywords.push((TokenType::Keyword2, pvs(val)?)),
                 "keywords_3" => sc.keywords.push((TokenType::Keyword3, pvs(val)?)),
+                "structure_keywords" => sc.structure_keywords = pvs(val)?,
+                _ if key.starts_with("snippet.") => {
+                    sc.snippets
+                        .insert(key["snippet.".len()..].to_string(), val.to_string());
+                }
                 _ => return Err(format!("Invalid key: {key}")),
             }
             Ok(())
@@ -171,6 +263,44 @@ mod tests {
         assert_eq!(file_count, syntax_names.len());
     }
 
+    #[test]
+    fn builtin() {
+        assert_eq!(BUILTIN.len(), fs::read_dir("syntax.d").unwrap().count());
+        let rust = Syntax::builtin("rs").unwrap();
+        assert_eq!(rust.name, "Rust");
+        assert_eq!(rust.path, None);
+        assert!(Syntax::builtin("no such extension").is_none());
+    }
+
+    #[test]
+    fn definitions() {
+        let tmp_dir = TempDir::new().expect("Could not create temporary directory");
+        fs::write(tmp_dir.path().join("b.ini"), "name=B\nextensions=b\n").unwrap();
+        fs::write(tmp_dir.path().join("a.ini"), "name=A\nextension=a\n").unwrap();
+        fs::write(tmp_dir.path().join("notes.txt"), "not a definition").unwrap();
+
+        let definitions = Syntax::definitions(tmp_dir.path()).unwrap();
+        assert_eq!(definitions.len(), 2);
+        match &definitions[0] {
+            (path, Err(Error::FileError(_, 2, message))) if path.ends_with("a.ini") => {
+                assert_eq!(message, "Invalid key: extension")
+            }
+            (_, result) => panic!("Unexpected result {:?}", result.as_ref().err()),
+        }
+        let (path, result) = &definitions[1];
+        assert!(path.ends_with("b.ini"));
+        let (syntax, extensions) = result.as_ref().unwrap();
+        assert_eq!(syntax.name, "B");
+        assert_eq!(extensions, &["b"]);
+
+        assert!(Syntax::definitions(&tmp_dir.path().join("missing")).is_err());
+
+        let by_name = |name| Syntax::by_name(tmp_dir.path(), name).unwrap();
+        assert_eq!(by_name("b").unwrap().name, "B");
+        // Broken definitions can't be chosen
+        assert!(by_name("A").is_none());
+    }
+
     #[test]
     fn conf_from_invalid_path() {
         let tmp_dir = TempDir::new().expect("Could not create temporary directory");
@@ -3,11 +3,13 @@ use crossterm::{
     style::{self, ContentStyle, Print, StyledContent, Stylize},
     terminal,
 };
-use std::io::{stdout, Stdout};
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{self, stdout, Write},
+};
 use unicode_segmentation::UnicodeSegmentation;
-use unicode_width::UnicodeWidthStr;
 
-use crate::{editor::Position, Error};
+use crate::{width::Width, Error, Position};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Pixel {
@@ -17,6 +19,16 @@ pub struct Pixel {
     content: String,
 }
 
+// The attributes can't be hashed, and are left out
+impl Hash for Pixel {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.content.hash(state);
+        self.style.foreground_color.hash(state);
+        self.style.background_color.hash(state);
+        self.style.underline_color.hash(state);
+    }
+}
+
 impl Default for Pixel {
     fn default() -> Self {
         Pixel {
@@ -27,7 +39,8 @@ impl Default for Pixel {
 }
 
 pub struct Terminal {
-    stdout: Stdout,
+    /// Where escape sequences go: stdout, or nowhere for a headless terminal.
+    out: Box<dyn Write>,
 
     pub height: usize,
     pub width: usize,
@@ -36,45 +49,90 @@ pub struct Terminal {
 
     buffer: Vec<Vec<Pixel>>,
     last_buffer: Vec<Vec<Pixel>>,
+    /// Whether the screen must be cleared before the next render.
+    clear: bool,
+    /// Whether a frame that is mostly the last one moved up or down is drawn by
+    /// scrolling the screen, rather than painting every row that changed.
+    scroll: bool,
+    /// Whether this draws to the real terminal, rather than being headless.
+    real: bool,
+    /// Whether the real terminal is set up for the editor, between `init` and
+    /// `cleanup`.
+    active: bool,
 }
 
 impl Default for Terminal {
     fn default() -> Self {
-        Terminal::new()
+        Terminal::headless(80, 24)
     }
 }
 
 impl Terminal {
+    /// A terminal drawing to stdout. It takes the size of the real one in `init`.
     pub fn new() -> Self {
-        let (width, height) = terminal::size().expect("Failed to get terminal size");
         Terminal {
-            stdout: stdout(),
-            height: height.into(),
-            width: width.into(),
+            out: Box::new(stdout()),
+            real: true,
+            ..Terminal::headless(0, 0)
+        }
+    }
+
+    /// A terminal of the given size that draws nothing. What would be on screen can
+    /// be read with `snapshot`.
+    pub fn headless(width: usize, height: usize) -> Self {
+        Terminal {
+            out: Box::new(io::sink()),
+            height,
+            width,
 
             cursor: None,
 
-            buffer: vec![vec![Pixel::default(); width.into()]; height.into()],
-            last_buffer: vec![vec![Pixel::default(); width.into()]; height.into()],
+            buffer: vec![vec![Pixel::default(); width]; height],
+            last_buffer: vec![vec![Pixel::default(); width]; height],
+            clear: true,
+            scroll: true,
+            real: false,
+            active: false,
         }
     }
 
-    pub fn init(&mut self) -> Result<(), Error> {
+    /// Take the size of the real terminal and set it up for the editor: raw mode, the
+    /// alternate screen and the input modes. Does nothing if it already is, or if the
+    /// terminal is headless.
+    pub fn init(&mut self, mouse_capture: bool) -> Result<(), Error> {
+        if !self.real || self.active {
+            return Ok(());
+        }
+        let (width, height) = terminal::size().map_err(Error::TerminalSize)?;
+        self.update_window_size(height, width);
         terminal::enable_raw_mode()?;
         execute!(
-            self.stdout,
+            self.out,
             terminal::EnterAlternateScreen,
             terminal::DisableLineWrap,
-            event::EnableMouseCapture,
             event::EnableBracketedPaste,
             event::EnableFocusChange,
         )?;
+        if mouse_capture {
+            execute!(self.out, event::EnableMouseCapture)?;
+        }
+        // Keypad keys are only told apart with the kitty keyboard protocol. Terminals
+        // without it ignore the request; crossterm refuses it on Windows.
+        let flags = event::KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES;
+        let _ = execute!(self.out, event::PushKeyboardEnhancementFlags(flags));
+        self.active = true;
         Ok(())
     }
 
+    /// Give the real terminal back as `init` found it.
     pub fn cleanup(&mut self) -> Result<(), Error> {
+        if !self.active {
+            return Ok(());
+        }
+        self.active = false;
+        let _ = execute!(self.out, event::PopKeyboardEnhancementFlags);
         execute!(
-            self.stdout,
+            self.out,
             event::DisableFocusChange,
             event::DisableBracketedPaste,
             event::DisableMouseCapture,
@@ -91,7 +149,46 @@ impl Terminal {
         self.width = width as usize;
 
         self.buffer = vec![vec![Pixel::default(); self.width]; self.height];
+        self.invalidate();
+    }
+
+    /// Forget what is on the screen, so that the next render clears it and draws
+    /// everything again.
+    pub fn invalidate(&mut self) {
+        self.last_buffer = vec![vec![Pixel::default(); self.width]; self.height];
+        self.clear = true;
+    }
+
+    /// The text on each line of the buffer, without trailing spaces.
+    pub fn snapshot(&self) -> Vec<String> {
+        self.buffer
+            .iter()
+            .map(|row| {
+                let line = row
+                    .iter()
+                    .map(|pixel| pixel.content.as_str())
+                    .collect::<String>();
+                line.trim_end().to_string()
+            })
+            .collect()
+    }
+
+    /// The style of the cell at `pos`.
+    pub fn style_at(&self, pos: Position) -> ContentStyle {
+        self.buffer[pos.y][pos.x].style
+    }
+
+    /// Restore raw mode and clear the screen, so that the next render repaints
+    /// everything. Used after another program may have written to the terminal.
+    pub fn repair(&mut self) -> Result<(), Error> {
+        terminal::enable_raw_mode()?;
+        execute!(
+            self.out,
+            style::ResetColor,
+            terminal::Clear(terminal::ClearType::All)
+        )?;
         self.last_buffer = vec![vec![Pixel::default(); self.width]; self.height];
+        Ok(())
     }
 
     pub fn clear_buffer(&mut self) {
@@ -102,23 +199,41 @@ impl Terminal {
         }
     }
 
+    /// Blank every cell in `style`.
+    pub fn fill(&mut self, style: ContentStyle) {
+        for pixel in self.buffer.iter_mut().flatten() {
+            pixel.content.clear();
+            pixel.content.push(' ');
+            pixel.style = style;
+        }
+    }
+
     pub fn begin_render(&mut self) -> Result<(), Error> {
-        execute!(self.stdout, terminal::BeginSynchronizedUpdate)?;
+        execute!(self.out, terminal::BeginSynchronizedUpdate)?;
         Ok(())
     }
 
     pub fn end_render(&mut self) -> Result<(), Error> {
         let mut current_style = ContentStyle::default();
+        let cleared = self.clear;
+        if self.clear {
+            queue!(self.out, terminal::Clear(terminal::ClearType::All))?;
+            self.clear = false;
+        }
         queue!(
-            self.stdout,
+            self.out,
             cursor::Hide,
             style::ResetColor,
             style::SetAttribute(style::Attribute::Reset),
         )?;
+        // Rows scrolled in take the current colors, which are now the default ones
+        if self.scroll && !cleared && cfg!(not(feature = "debug")) {
+            self.scroll_screen()?;
+        }
 
         for (y, row) in self.buffer.iter().enumerate() {
             let mut cursor_x = 0;
-            queue!(self.stdout, cursor::MoveTo(0, y as u16))?;
+            queue!(self.out, cursor::MoveTo(0, y as u16))?;
             for (x, pixel) in row.iter().enumerate() {
                 if pixel.content.is_empty() {
                     continue;
@@ -129,17 +244,17 @@ impl Terminal {
                 {
                     if pixel != last_pixel {
                         if x != cursor_x {
-                            queue!(self.stdout, cursor::MoveTo(x as u16, y as u16))?;
+                            queue!(self.out, cursor::MoveTo(x as u16, y as u16))?;
                             cursor_x = x;
                         }
                         if pixel.style != current_style {
                             if pixel.style.attributes != current_style.attributes {
-                                queue!(self.stdout, style::SetAttribute(style::Attribute::Reset))?;
+                                queue!(self.out, style::SetAttribute(style::Attribute::Reset))?;
                             }
-                            queue!(self.stdout, style::SetStyle(pixel.style))?;
+                            queue!(self.out, style::SetStyle(pixel.style))?;
                             current_style = pixel.style;
                         }
-                        queue!(self.stdout, Print(pixel.content.clone()))?;
+                        queue!(self.out, Print(pixel.content.clone()))?;
                         cursor_x += pixel.content.width();
                     }
                 }
@@ -149,7 +264,7 @@ impl Terminal {
                     if pixel != last_pixel {
                         let mut ch = ".";
                         if x != cursor_x {
-                            queue!(self.stdout, cursor::MoveTo(x as u16, y as u16))?;
+                            queue!(self.out, cursor::MoveTo(x as u16, y as u16))?;
                             ch = "@";
                             cursor_x = x;
                         }
@@ -160,42 +275,64 @@ impl Terminal {
                             }
                             current_style = pixel.style;
                         }
-                        queue!(self.stdout, Print(ch))?;
+                        queue!(self.out, Print(ch))?;
                         cursor_x += 1;
                     } else {
-                        queue!(self.stdout, Print(" "))?;
+                        queue!(self.out, Print(" "))?;
                     }
                 }
             }
         }
 
         if let Some(Position { x, y }) = self.cursor {
-            queue!(
-                self.stdout,
-                cursor::Show,
-                cursor::MoveTo(x as u16, y as u16)
-            )?;
+            queue!(self.out, cursor::Show, cursor::MoveTo(x as u16, y as u16))?;
         }
 
-        execute!(self.stdout, terminal::EndSynchronizedUpdate)?;
+        execute!(self.out, terminal::EndSynchronizedUpdate)?;
 
         self.last_buffer = self.buffer.clone();
 
         Ok(())
     }
 
+    /// Move what is on the screen the way the new frame moved from the last one, if it
+    /// did, within a scroll region, so that only the rows that came into view are left
+    /// to paint.
+    fn scroll_screen(&mut self) -> Result<(), Error> {
+        let Some((top, bottom, shift)) = find_shift(&self.last_buffer, &self.buffer) else {
+            return Ok(());
+        };
+        queue!(self.out, Print(format!("\x1b[{};{}r", top + 1, bottom)))?;
+        let rows = &mut self.last_buffer[top..bottom];
+        let distance = shift.unsigned_abs();
+        if shift > 0 {
+            queue!(self.out, terminal::ScrollUp(distance as u16))?;
+            rows.rotate_left(distance);
+            let len = rows.len();
+            rows[len - distance..].fill(vec![Pixel::default(); self.width]);
+        } else {
+            queue!(self.out, terminal::ScrollDown(distance as u16))?;
+            rows.rotate_right(distance);
+            rows[..distance].fill(vec![Pixel::default(); self.width]);
+        }
+        // Resetting the region moves the cursor, which every row moves anyway
+        queue!(self.out, Print("\x1b[r"))?;
+        Ok(())
+    }
+
     pub fn write(&mut self, mut pos: Position, content: StyledContent<String>) {
         for ch in content.content().graphemes(true) {
             let width = ch.width();
             if pos.x + width > self.width || pos.y >= self.height {
                 break;
             }
-            for i in 0..width {
-                let pixel = &mut self.buffer[pos.y][pos.x + i];
-                pixel.content = String::new();
+            for pixel in &mut self.buffer[pos.y][pos.x..pos.x + width] {
+                pixel.content.clear();
                 pixel.style = *content.style();
             }
-            self.buffer[pos.y][pos.x].content = ch.to_string();
+            let first = &mut self.buffer[pos.y][pos.x].content;
+            first.clear();
+            first.push_str(ch);
             pos.x += width;
         }
     }
@@ -214,13 +351,34 @@ impl Terminal {
         self.buffer[pos.y][pos.x].content = ch.to_string();
     }
 
+    /// Write graphemes in one style from `pos` on, each taking up the given number of
+    /// columns, as `write_char` would one at a time.
+    pub fn write_cells(&mut self, mut pos: Position, cells: &[(&str, usize)], style: ContentStyle) {
+        let Some(row) = self.buffer.get_mut(pos.y) else {
+            return;
+        };
+        for &(ch, columns) in cells {
+            let width = ch.width();
+            if pos.x + width <= self.width {
+                for pixel in &mut row[pos.x..pos.x + width] {
+                    pixel.content.clear();
+                    pixel.style = style;
+                }
+                let first = &mut row[pos.x].content;
+                first.clear();
+                first.push_str(ch);
+            }
+            pos.x += columns;
+        }
+    }
+
     pub fn dimmed(&mut self) -> Result<(), Error> {
         for row in &mut self.buffer {
             for pixel in row {
                 pixel.style = pixel
                     .style
-                    .with(crate::style::text_dimmed)
-                    .on(crate::style::background);
+                    .with(crate::style::text_dimmed())
+                    .on(crate::style::background());
             }
         }
         self.cursor = None;
@@ -228,3 +386,172 @@ impl Terminal {
         Ok(())
     }
 }
+
+/// The rows `top..bottom` to scroll, and how far: up if positive, down if negative,
+/// to turn the screen showing `old` into one showing as much of `new` as possible. Rows
+/// scrolled in are blank. `None` if scrolling leaves no fewer rows to paint.
+fn find_shift(old: &[Vec<Pixel>], new: &[Vec<Pixel>]) -> Option<(usize, usize, isize)> {
+    let hash = |row: &Vec<Pixel>| {
+        let mut hasher = DefaultHasher::new();
+        row.hash(&mut hasher);
+        hasher.finish()
+    };
+    let height = new.len();
+    if old.len() != height || height < 4 {
+        return None;
+    }
+    let blank = hash(&vec![Pixel::default(); new[0].len()]);
+    let (old, new): (Vec<_>, Vec<_>) = (
+        old.iter().map(hash).collect(),
+        new.iter().map(hash).collect(),
+    );
+    let cost = |screen: &dyn Fn(usize) -> u64| (0..height).filter(|&y| screen(y) != new[y]).count();
+
+    let mut best = (cost(&|y| old[y]), None);
+    for distance in 1..=height / 2 {
+        for up in [true, false] {
+            // The rows that show an old row `distance` rows below them, or above
+            let moved = |y: usize| match up {
+                true => new[y] == old[y + distance],
+                false => new[y + distance] == old[y],
+            };
+            let mut matches = (0..height - distance).filter(|&y| moved(y));
+            let Some(first) = matches.next() else {
+                continue;
+            };
+            let (top, bottom) = (first, matches.next_back().unwrap_or(first) + distance + 1);
+            let screen = |y: usize| match up {
+                _ if !(top..bottom).contains(&y) => old[y],
+                true if y + distance < bottom => old[y + distance],
+                false if y >= top + distance => old[y - distance],
+                _ => blank,
+            };
+            let cost = cost(&screen);
+            if cost < best.0 {
+                let shift = distance as isize;
+                best = (cost, Some((top, bottom, if up { shift } else { -shift })));
+            }
+        }
+    }
+    best.1
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+
+    /// Output kept where the test can count it.
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn draw(terminal: &mut Terminal, lines: &[String]) {
+        terminal.clear_buffer();
+        for (y, line) in lines.iter().enumerate() {
+            terminal.write(pos(0, y), line.clone().stylize());
+        }
+        terminal.end_render().unwrap();
+    }
+
+    fn pos(x: usize, y: usize) -> Position {
+        Position { x, y }
+    }
+
+    /// Lines `first..` of a file, below a title and above a status bar.
+    fn frame(first: usize) -> Vec<String> {
+        let mut lines = vec!["title".to_string()];
+        lines.extend((first..first + 20).map(|i| format!("{i:>4} {}", "word ".repeat(i % 7))));
+        lines.push("status".to_string());
+        lines
+    }
+
+    #[test]
+    fn test_scroll() {
+        let bytes = |scroll: bool, frames: &[usize]| {
+            let output = Output::default();
+            let mut terminal = Terminal {
+                out: Box::new(output.clone()),
+                scroll,
+                ..Terminal::headless(40, 22)
+            };
+            draw(&mut terminal, &frame(0));
+            let before = output.0.borrow().len();
+            for &first in frames {
+                draw(&mut terminal, &frame(first));
+            }
+            let written = output.0.borrow()[before..].to_vec();
+            (written.len(), String::from_utf8(written).unwrap())
+        };
+
+        // One line down, then back up
+        let (scrolled, out) = bytes(true, &[1, 0]);
+        let (repainted, _) = bytes(false, &[1, 0]);
+        assert!(scrolled * 2 < repainted, "{scrolled} vs {repainted}");
+        assert!(out.contains("\x1b[2;21r\x1b[1S\x1b[r"));
+        assert!(out.contains("\x1b[2;21r\x1b[1T\x1b[r"));
+
+        // A jump to elsewhere in the file is painted as usual
+        let (_, out) = bytes(true, &[100]);
+        assert!(!out.contains("\x1b[r"));
+    }
+
+    #[test]
+    fn test_write_cells() {
+        let cells = [
+            ("a", 1),
+            ("中", 2),
+            ("e\u{301}", 1),
+            ("\u{200b}", 0),
+            ("b", 1),
+        ];
+        let style = ContentStyle::new().red().on_blue();
+        for x in [0, 3, 6] {
+            let mut one_by_one = Terminal::headless(8, 2);
+            let mut column = x;
+            for (ch, width) in cells {
+                one_by_one.write_char(pos(column, 1), StyledContent::new(style, ch));
+                column += width;
+            }
+            let mut together = Terminal::headless(8, 2);
+            together.write_cells(pos(x, 1), &cells, style);
+            assert_eq!(together.buffer, one_by_one.buffer, "{x}");
+        }
+    }
+
+    #[test]
+    fn test_find_shift() {
+        let rows = |lines: &[&str]| -> Vec<Vec<Pixel>> {
+            lines
+                .iter()
+                .map(|line| {
+                    line.chars()
+                        .map(|c| Pixel {
+                            content: c.to_string(),
+                            ..Pixel::default()
+                        })
+                        .collect()
+                })
+                .collect()
+        };
+        let old = rows(&["t", "a", "b", "c", "d", "e", "s"]);
+        assert_eq!(find_shift(&old, &old), None);
+        let new = rows(&["t", "c", "d", "e", "f", "g", "s"]);
+        assert_eq!(find_shift(&old, &new), Some((1, 6, 2)));
+        let new = rows(&["t", "z", "a", "b", "c", "d", "s"]);
+        assert_eq!(find_shift(&old, &new), Some((1, 6, -1)));
+        // Nothing moved, only changed
+        let new = rows(&["t", "v", "w", "x", "y", "z", "s"]);
+        assert_eq!(find_shift(&old, &new), None);
+    }
+}
@@ -0,0 +1,139 @@
+//! Moving files to the trash as freedesktop.org desktops do, so that they can be put
+//! back from the file manager.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::date::DateTime;
+
+/// The trash in the user's home: `$XDG_DATA_HOME/Trash`. `None` where there is no such
+/// thing, as on Windows and macOS.
+pub fn dir() -> Option<PathBuf> {
+    if cfg!(any(windows, target_os = "macos")) {
+        return None;
+    }
+    let var = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+    let data = match var("XDG_DATA_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(var("HOME")?).join(".local/share"),
+    };
+    Some(data.join("Trash"))
+}
+
+/// Move `path` into the trash at `trash`, next to a note of where it came from and
+/// when. Fails if the trash is on another file system.
+pub fn move_to(trash: &Path, path: &Path) -> io::Result<()> {
+    // A symlink is trashed itself, not what it points to
+    let path = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => {
+            let parent = Some(parent).filter(|parent| !parent.as_os_str().is_empty());
+            fs::canonicalize(parent.unwrap_or(Path::new(".")))?.join(name)
+        }
+        _ => fs::canonicalize(path)?,
+    };
+    fs::symlink_metadata(&path)?;
+    let (files, info) = (trash.join("files"), trash.join("info"));
+    fs::create_dir_all(&files)?;
+    fs::create_dir_all(&info)?;
+
+    // Files of the same name get a number, and the note claims the name
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut i = 1;
+    let (note, target) = loop {
+        let unique = match i {
+            1 => name.to_string(),
+            _ => format!("{name}.{i}"),
+        };
+        i += 1;
+        let (note, target) = (info.join(format!("{unique}.trashinfo")), files.join(unique));
+        match fs::File::options().write(true).create_new(true).open(&note) {
+            Ok(_) if target.symlink_metadata().is_ok() => fs::remove_file(&note)?,
+            Ok(_) => break (note, target),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(err) => return Err(err),
+        }
+    };
+
+    let text = format!(
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        encode(&path.to_string_lossy()),
+        DateTime::now().format("%Y-%m-%dT%H:%M:%S")
+    );
+    let result = fs::write(&note, text).and_then(|()| fs::rename(&path, &target));
+    if result.is_err() {
+        let _ = fs::remove_file(&note);
+    }
+    result
+}
+
+/// Percent-encode a path as the trash notes have it, leaving `/` alone.
+fn encode(path: &str) -> String {
+    let mut encoded = String::new();
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded += &format!("%{byte:02X}"),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_move_to() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash = dir.path().join("Trash");
+        for text in ["first", "second"] {
+            let path = dir.path().join("a file.txt");
+            fs::write(&path, text).unwrap();
+            move_to(&trash, &path).unwrap();
+            assert!(!path.exists());
+        }
+        assert_eq!(
+            fs::read_to_string(trash.join("files/a file.txt")).unwrap(),
+            "first"
+        );
+        assert_eq!(
+            fs::read_to_string(trash.join("files/a file.txt.2")).unwrap(),
+            "second"
+        );
+        let note = fs::read_to_string(trash.join("info/a file.txt.2.trashinfo")).unwrap();
+        let path = fs::canonicalize(dir.path()).unwrap().join("a%20file.txt");
+        assert!(note.starts_with(&format!("[Trash Info]\nPath={}\n", path.display())));
+        assert!(note.contains("\nDeletionDate=2"));
+
+        assert!(move_to(&trash, &dir.path().join("missing")).is_err());
+        assert_eq!(fs::read_dir(trash.join("info")).unwrap().count(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_move_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash = dir.path().join("Trash");
+        let (file, link) = (dir.path().join("file.txt"), dir.path().join("link.txt"));
+        fs::write(&file, "text").unwrap();
+        std::os::unix::fs::symlink(&file, &link).unwrap();
+
+        // The link goes to the trash, and the file it points to stays
+        move_to(&trash, &link).unwrap();
+        assert!(link.symlink_metadata().is_err());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "text");
+        let trashed = trash.join("files/link.txt");
+        assert!(trashed.symlink_metadata().unwrap().file_type().is_symlink());
+        let note = fs::read_to_string(trash.join("info/link.txt.trashinfo")).unwrap();
+        assert!(note.contains("/link.txt\n"));
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode("/tmp/a b/中.txt"), "/tmp/a%20b/%E4%B8%AD.txt");
+    }
+}
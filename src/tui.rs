@@ -1,10 +1,10 @@
 use crossterm::{
-    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers, MouseButton, MouseEventKind},
+    event::{Event, KeyCode, KeyEventKind, KeyModifiers, MouseButton, MouseEventKind},
     style::{Color, Stylize},
 };
-use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+use std::path::PathBuf;
 
-use crate::{editor::Position, style, Editor, Error, Row, Terminal};
+use crate::{history::Branch, style, t, width::Width, Editor, Error, Position, Row, Terminal};
 
 #[derive(Default)]
 pub struct Input {
@@ -17,6 +17,9 @@ pub struct Input {
     pub buffer: Row,
 
     dragging: bool,
+
+    /// What Tab does to the text, e.g. `complete_path`; `None` if nothing.
+    pub complete: Option<fn(&str) -> Option<String>>,
 }
 
 impl Input {
@@ -36,29 +39,14 @@ impl Input {
 
                 KeyCode::Left => {
                     if event.modifiers.contains(KeyModifiers::CONTROL) {
-                        // Move to the beginning of the word
-                        while self.cursor > 0 && self.buffer.rope[self.cursor - 1].0 == " " {
-                            self.cursor -= 1;
-                        }
-                        while self.cursor > 0 && self.buffer.rope[self.cursor - 1].0 != " " {
-                            self.cursor -= 1;
-                        }
+                        self.cursor = self.buffer.word_start(self.cursor);
                     } else if self.cursor > 0 {
                         self.cursor -= 1;
                     }
                 }
                 KeyCode::Right => {
                     if event.modifiers.contains(KeyModifiers::CONTROL) {
-                        while self.cursor < self.buffer.len()
-                            && self.buffer.rope[self.cursor].0 == " "
-                        {
-                            self.cursor += 1;
-                        }
-                        while self.cursor < self.buffer.len()
-                            && self.buffer.rope[self.cursor].0 != " "
-                        {
-                            self.cursor += 1;
-                        }
+                        self.cursor = self.buffer.word_end(self.cursor);
                     } else if self.cursor < self.buffer.len() {
                         self.cursor += 1;
                     }
@@ -73,21 +61,24 @@ impl Input {
                 KeyCode::Char(char) => {
                     self.cursor = self.cursor.min(self.buffer.len());
 
-                    self.buffer
-                        .rope
-                        .insert(self.cursor, (char.to_string(), char.width().unwrap_or(0)));
-                    self.cursor += 1;
+                    self.cursor += self.buffer.insert_str(self.cursor, &char.to_string());
                 }
 
                 KeyCode::Backspace => {
                     if self.cursor > 0 {
                         self.cursor -= 1;
-                        self.buffer.rope.remove(self.cursor);
+                        self.buffer.remove_range(self.cursor..self.cursor + 1);
                     }
                 }
                 KeyCode::Delete => {
                     if self.cursor < self.buffer.len() {
-                        self.buffer.rope.remove(self.cursor);
+                        self.buffer.remove_range(self.cursor..self.cursor + 1);
+                    }
+                }
+                KeyCode::Tab => {
+                    if let Some(text) = self.complete.and_then(|f| f(&self.buffer.to_string())) {
+                        self.buffer = Row::from(text.as_str());
+                        self.cursor = self.buffer.len();
                     }
                 }
                 _ => {}
@@ -108,19 +99,7 @@ impl Input {
                         }
 
                         let x = (x + self.offset).saturating_sub(self.viewbox.x);
-                        let visual_width = self.buffer.rope.iter().map(|g| g.1).sum::<usize>();
-                        if x > visual_width {
-                            self.cursor = self.buffer.len();
-                        } else {
-                            let mut width = 0;
-                            for (i, cell) in self.buffer.rope.iter().enumerate() {
-                                if width >= x {
-                                    self.cursor = i;
-                                    break;
-                                }
-                                width += cell.1;
-                            }
-                        }
+                        self.cursor = self.buffer.x_to_index(x);
                     }
                 }
 
@@ -144,8 +123,8 @@ impl Input {
         term.write(
             self.viewbox,
             " ".repeat(self.max_width)
-                .with(style::text_model)
-                .on(style::background)
+                .with(style::text_model())
+                .on(style::background())
                 .underlined(),
         );
 
@@ -159,14 +138,14 @@ impl Input {
                 term.write_char(
                     (dx as usize + self.viewbox.x - 1, self.viewbox.y).into(),
                     g.as_str()
-                        .with(style::text_model)
-                        .on(style::background)
+                        .with(style::text_model())
+                        .on(style::background())
                         .underlined(),
                 );
             }
         }
 
-        let visual_width: usize = self.buffer.rope.iter().take(self.cursor).map(|g| g.1).sum();
+        let visual_width = self.buffer.index_to_x(self.cursor);
         term.cursor = Some(
             (
                 (self.viewbox.x + visual_width).saturating_sub(self.offset),
@@ -233,9 +212,13 @@ impl Button {
             (x, y),
             (self.width + 2, 1),
             self.color,
-            style::background,
+            style::background(),
         );
-        let text = self.text.to_string().with(self.color).on(style::background);
+        let text = self
+            .text
+            .to_string()
+            .with(self.color)
+            .on(style::background());
         if !self.hover {
             term.write((x + 2, y + 1).into(), text);
         } else {
@@ -243,7 +226,7 @@ impl Button {
             if let Some(ref hint) = self.hint {
                 term.write(
                     (x + 2, y + 2).into(),
-                    hint.to_string().with(self.color).on(style::background),
+                    hint.to_string().with(self.color).on(style::background()),
                 );
             }
         }
@@ -262,19 +245,51 @@ pub struct Confirm {
     yes: Button,
     no: Button,
     cancel: Option<Button>,
+    /// Where the mouse was last seen, to find the button under it again after a resize.
+    mouse: Option<(usize, usize)>,
 }
 
 impl Confirm {
     pub fn new(title: String, yes: String, no: String, cancel: Option<String>) -> Self {
-        let yes = Button::new(yes, style::text_model_primary, Some("Yes".to_string()));
-        let no = Button::new(no, style::text_model, Some("No".to_string()));
-        let cancel = cancel.map(|s| Button::new(s, style::text_model, Some("Esc".to_string())));
+        let yes = Button::new(yes, style::text_model_primary(), Some("Yes".to_string()));
+        let no = Button::new(no, style::text_model(), Some("No".to_string()));
+        let cancel = cancel.map(|s| Button::new(s, style::text_model(), Some("Esc".to_string())));
 
         Self {
             title,
             yes,
             no,
             cancel,
+            mouse: None,
+        }
+    }
+
+    /// The width of the buttons with the gaps between them.
+    fn buttons_width(&self) -> usize {
+        let cancel_width = self.cancel.as_ref().map_or(0, |s| s.width + 5);
+        self.yes.width + 5 + self.no.width + 5 + cancel_width
+    }
+
+    /// The position and size of the dialog in the terminal as it is now.
+    fn geometry(&self, term: &Terminal) -> ((usize, usize), (usize, usize)) {
+        let (w, h) = (
+            (self.title.width().max(self.buttons_width()) + 16).min(term.width - 5),
+            6,
+        );
+        (((term.width - w) / 2, (term.height - 2 - h) / 2), (w, h))
+    }
+
+    /// Highlight the button under the mouse.
+    fn hover(&mut self, term: &Terminal) {
+        let ((x, y), (w, h)) = self.geometry(term);
+        let mouse = self.mouse.unwrap_or((usize::MAX, usize::MAX));
+        let mut offset = (x + w - self.buttons_width(), y + h - 2);
+        self.yes.intersect(offset, mouse);
+        offset.0 += self.yes.width + 5;
+        self.no.intersect(offset, mouse);
+        offset.0 += self.no.width + 5;
+        if let Some(ref mut cancel_button) = self.cancel {
+            cancel_button.intersect(offset, mouse);
         }
     }
 
@@ -285,8 +300,8 @@ impl Confirm {
         }
 
         loop {
-            if event::poll(std::time::Duration::from_millis(25))? {
-                match event::read()? {
+            if editor.poll_event()? {
+                match editor.read_event()? {
                     Event::Key(event) if event.kind != KeyEventKind::Release => match event.code {
                         KeyCode::Char('y' | 'Y') | KeyCode::Enter => {
                             return Ok(Some(true));
@@ -302,36 +317,8 @@ impl Confirm {
                     },
 
                     Event::Mouse(event) => {
-                        self.yes.hover = false;
-                        self.no.hover = false;
-                        if let Some(ref mut button) = self.cancel {
-                            button.hover = false;
-                        }
-
-                        let mouse = (event.column as usize, event.row as usize);
-
-                        let title_width = self.title.width();
-                        let cancel_width = self.cancel.as_ref().map_or(0, |s| s.width + 5);
-                        let buttons_offset = self.yes.width + 5 + self.no.width + 5 + cancel_width;
-
-                        let (w, h) = (
-                            (title_width.max(buttons_offset) + 16).min(editor.terminal.width - 5),
-                            6,
-                        );
-                        let (x, y) = (
-                            (editor.terminal.width - w) / 2,
-                            (editor.terminal.height - 2 - h) / 2,
-                        );
-
-                        let mut offset = (x + w - buttons_offset, y + h - 2);
-
-                        self.yes.intersect(offset, mouse);
-                        offset.0 += self.yes.width + 5;
-                        self.no.intersect(offset, mouse);
-                        offset.0 += self.no.width + 5;
-                        if let Some(ref mut cancel_button) = self.cancel {
-                            cancel_button.intersect(offset, mouse);
-                        }
+                        self.mouse = Some((event.column as usize, event.row as usize));
+                        self.hover(&editor.terminal);
 
                         if let MouseEventKind::Down(_) = event.kind {
                             if self.yes.hover {
@@ -348,6 +335,8 @@ impl Confirm {
 
                     Event::Resize(width, height) => {
                         editor.terminal.update_window_size(height, width);
+                        editor.on_resize();
+                        self.hover(&editor.terminal);
                     }
 
                     _ => {}
@@ -366,37 +355,35 @@ impl Confirm {
     pub fn render(&self, term: &mut Terminal) -> Result<(), Error> {
         term.dimmed()?;
 
-        let title_width = self.title.width();
-        let cancel_width = self.cancel.as_ref().map_or(0, |s| s.width + 5);
-        let buttons_offset = self.yes.width + 5 + self.no.width + 5 + cancel_width;
-
-        let (w, h) = (
-            (title_width.max(buttons_offset) + 16).min(term.width - 5),
-            6,
-        );
-        let (x, y) = ((term.width - w) / 2, (term.height - 2 - h) / 2);
+        let ((x, y), (w, h)) = self.geometry(term);
 
         term.begin_render()?;
 
-        draw_rounded_rect(term, (x, y), (w, h), style::text_model, style::background);
+        draw_rounded_rect(
+            term,
+            (x, y),
+            (w, h),
+            style::text_model(),
+            style::background(),
+        );
 
         term.write(
             (x + 3, y).into(),
             " CONFIRM "
                 .to_string()
                 .bold()
-                .with(style::text_primary)
-                .on(style::text_model),
+                .with(style::text_primary())
+                .on(style::text_model()),
         );
         term.write(
             (x + 3, y + 2).into(),
             self.title
                 .to_string()
-                .with(style::text_model)
-                .on(style::background),
+                .with(style::text_model())
+                .on(style::background()),
         );
 
-        let mut offset = (x + w - buttons_offset, y + h - 2);
+        let mut offset = (x + w - self.buttons_width(), y + h - 2);
         self.yes.render(term, offset)?;
         offset.0 += self.yes.width + 5;
         self.no.render(term, offset)?;
@@ -416,12 +403,16 @@ struct Prompt {
     input: Input,
     yes: Button,
     no: Button,
+    /// Why the entered text was rejected, shown under the input.
+    error: Option<String>,
+    /// Where the mouse was last seen, to find the button under it again after a resize.
+    mouse: Option<(usize, usize)>,
 }
 
 impl Prompt {
     pub fn new(title: String, yes: String, no: String) -> Self {
-        let yes = Button::new(yes, style::text_model_primary, Some("Yes".to_string()));
-        let no = Button::new(no, style::text_model, Some("No".to_string()));
+        let yes = Button::new(yes, style::text_model_primary(), Some("Yes".to_string()));
+        let no = Button::new(no, style::text_model(), Some("No".to_string()));
         let mut input = Input::new();
         input.max_width = 256;
         Self {
@@ -429,52 +420,87 @@ impl Prompt {
             input,
             yes,
             no,
+            error: None,
+            mouse: None,
+        }
+    }
+
+    /// The position and size of the dialog in the terminal as it is now.
+    fn geometry(&self, term: &Terminal) -> ((usize, usize), (usize, usize)) {
+        let (w, h) = ((self.title.width() + 16).min(term.width - 5), 8);
+        (((term.width - w) / 2, (term.height - 2 - h) / 2), (w, h))
+    }
+
+    /// Highlight the button under the mouse. Submitting needs some text.
+    fn hover(&mut self, term: &Terminal) {
+        let ((x, y), (w, h)) = self.geometry(term);
+        let mouse = self.mouse.unwrap_or((usize::MAX, usize::MAX));
+        let buttons_offset = self.yes.width + self.no.width + 10;
+        let mut offset = (x + w - buttons_offset, y + h - 2);
+        self.yes.hover = false;
+        if !self.input.buffer.is_empty() {
+            self.yes.intersect(offset, mouse);
+        }
+        offset.0 += self.yes.width + 5;
+        self.no.intersect(offset, mouse);
+    }
+
+    /// Accept the entered text if `parse` does, otherwise show its error.
+    fn submit<T>(&mut self, parse: &impl Fn(&str) -> Result<T, String>) -> Option<T> {
+        match parse(&self.input.buffer.to_string()) {
+            Ok(value) => Some(value),
+            Err(message) => {
+                self.error = Some(message);
+                None
+            }
         }
     }
 
-    pub fn event_loop(&mut self, editor: &mut Editor) -> Result<Option<String>, Error> {
+    /// Ask for a line of text; `parse` turns it into the result or explains what is
+    /// wrong with it, in which case the dialog stays open.
+    pub fn event_loop<T>(
+        &mut self,
+        editor: &mut Editor,
+        parse: impl Fn(&str) -> Result<T, String>,
+    ) -> Result<Option<T>, Error> {
         if editor.check_minimum_window_size() {
             editor.render_to_buffer();
             self.render(&mut editor.terminal)?;
         }
 
         loop {
-            if event::poll(std::time::Duration::from_millis(25))? {
-                let event = event::read()?;
+            if editor.poll_event()? {
+                let event = editor.read_event()?;
+                let text = self.input.buffer.to_string();
                 match self.input.handle_event(&event)? {
                     Some(true) => {
                         if !self.input.buffer.is_empty() {
-                            return Ok(Some(self.input.buffer.to_string()));
+                            if let Some(value) = self.submit(&parse) {
+                                return Ok(Some(value));
+                            }
                         }
                     }
                     Some(false) => {
                         return Ok(None);
                     }
                     None => {
+                        if self.input.buffer.to_string() != text {
+                            self.error = None;
+                        }
+                        if let Event::Resize(width, height) = event {
+                            editor.terminal.update_window_size(height, width);
+                            editor.on_resize();
+                            self.hover(&editor.terminal);
+                        }
                         if let Event::Mouse(event) = event {
-                            self.yes.hover = false;
-                            self.no.hover = false;
-
-                            let mouse = (event.column as usize, event.row as usize);
-
-                            let (w, h) =
-                                ((self.title.width() + 16).min(editor.terminal.width - 5), 8);
-                            let (x, y) = (
-                                (editor.terminal.width - w) / 2,
-                                (editor.terminal.height - 2 - h) / 2,
-                            );
-
-                            let buttons_offset = self.yes.width + self.no.width + 10;
-                            let mut offset = (x + w - buttons_offset, y + h - 2);
-                            if !self.input.buffer.is_empty() {
-                                self.yes.intersect(offset, mouse);
-                            }
-                            offset.0 += self.yes.width + 5;
-                            self.no.intersect(offset, mouse);
+                            self.mouse = Some((event.column as usize, event.row as usize));
+                            self.hover(&editor.terminal);
 
                             if let MouseEventKind::Down(_) = event.kind {
                                 if self.yes.hover {
-                                    return Ok(Some(self.input.buffer.to_string()));
+                                    if let Some(value) = self.submit(&parse) {
+                                        return Ok(Some(value));
+                                    }
                                 } else if self.no.hover {
                                     return Ok(None);
                                 }
@@ -496,33 +522,48 @@ impl Prompt {
     pub fn render(&mut self, term: &mut Terminal) -> Result<(), Error> {
         term.dimmed()?;
 
-        let (w, h) = ((self.title.width() + 16).min(term.width - 5), 8);
-        let (x, y) = ((term.width - w) / 2, (term.height - 2 - h) / 2);
+        let ((x, y), (w, h)) = self.geometry(term);
 
         term.begin_render()?;
 
-        draw_rounded_rect(term, (x, y), (w, h), style::text_model, style::background);
+        draw_rounded_rect(
+            term,
+            (x, y),
+            (w, h),
+            style::text_model(),
+            style::background(),
+        );
 
         term.write(
             (x + 3, y).into(),
             " PROMPT "
                 .to_string()
                 .bold()
-                .with(style::text_primary)
-                .on(style::text_model),
+                .with(style::text_primary())
+                .on(style::text_model()),
         );
         term.write(
             (x + 3, y + 2).into(),
             self.title
                 .to_string()
-                .with(style::text_model)
-                .on(style::background),
+                .with(style::text_model())
+                .on(style::background()),
         );
 
         self.input.viewbox = (x + 3, y + 4).into();
         self.input.max_width = w - 4;
         self.input.render(term);
 
+        if let Some(error) = &self.error {
+            term.write(
+                (x + 3, y + 5).into(),
+                error
+                    .to_string()
+                    .with(style::text_alert())
+                    .on(style::background()),
+            );
+        }
+
         let buttons_offset = self.yes.width + self.no.width + 10;
         let mut offset = (x + w - buttons_offset, y + h - 2);
         self.yes.render(term, offset)?;
@@ -536,21 +577,51 @@ impl Prompt {
 }
 
 struct Alert {
+    /// Shown on the top border, like ` ALERT `.
+    label: &'static str,
     title: String,
+    /// One or more lines, drawn as a block in the middle.
     message: String,
     yes: Button,
+    /// Where the mouse was last seen, to find the button under it again after a resize.
+    mouse: Option<(usize, usize)>,
 }
 
 impl Alert {
     pub fn new(title: String, message: String, yes: String) -> Self {
-        let yes = Button::new(yes, style::text_model_primary, Some("Fuck".to_string()));
+        let yes = Button::new(yes, style::text_model_primary(), Some("Fuck".to_string()));
         Self {
+            label: " ALERT ",
             title,
             message,
             yes,
+            mouse: None,
         }
     }
 
+    fn message_width(&self) -> usize {
+        self.message.lines().map(str::width).max().unwrap_or(0)
+    }
+
+    /// The position and size of the dialog in the terminal as it is now.
+    fn geometry(&self, term: &Terminal) -> ((usize, usize), (usize, usize)) {
+        let content_width = self.message_width().max(self.title.width());
+        let lines = self.message.lines().count().max(1);
+        let (w, h) = (
+            (content_width + 12).min(term.width - 5),
+            (7 + lines).min(term.height - 2),
+        );
+        (((term.width - w) / 2, (term.height - 2 - h) / 2), (w, h))
+    }
+
+    /// Highlight the button if the mouse is over it.
+    fn hover(&mut self, term: &Terminal) {
+        let ((x, y), (w, h)) = self.geometry(term);
+        let mouse = self.mouse.unwrap_or((usize::MAX, usize::MAX));
+        self.yes
+            .intersect((x + (w - self.yes.width) / 2 - 2, y + h - 2), mouse);
+    }
+
     pub fn event_loop(&mut self, editor: &mut Editor) -> Result<(), Error> {
         if editor.check_minimum_window_size() {
             editor.render_to_buffer();
@@ -558,8 +629,8 @@ impl Alert {
         }
 
         loop {
-            if event::poll(std::time::Duration::from_millis(25))? {
-                match event::read()? {
+            if editor.poll_event()? {
+                match editor.read_event()? {
                     Event::Key(event) if event.kind != KeyEventKind::Release => match event.code {
                         KeyCode::Char('y' | 'Y') | KeyCode::Enter | KeyCode::Esc => {
                             return Ok(());
@@ -569,24 +640,8 @@ impl Alert {
                     },
 
                     Event::Mouse(event) => {
-                        self.yes.hover = false;
-
-                        let mouse = (event.column as usize, event.row as usize);
-
-                        let title_width = self.title.width();
-                        let message_width = self.message.width();
-
-                        let (w, h) = (
-                            (message_width.max(title_width) + 12).min(editor.terminal.width - 5),
-                            8,
-                        );
-                        let (x, y) = (
-                            (editor.terminal.width - w) / 2,
-                            (editor.terminal.height - 2 - h) / 2,
-                        );
-
-                        self.yes
-                            .intersect((x + (w - self.yes.width) / 2 - 2, y + h - 2), mouse);
+                        self.mouse = Some((event.column as usize, event.row as usize));
+                        self.hover(&editor.terminal);
 
                         if let MouseEventKind::Down(_) = event.kind {
                             if self.yes.hover {
@@ -597,6 +652,8 @@ impl Alert {
 
                     Event::Resize(width, height) => {
                         editor.terminal.update_window_size(height, width);
+                        editor.on_resize();
+                        self.hover(&editor.terminal);
                     }
 
                     _ => {}
@@ -615,42 +672,263 @@ impl Alert {
     pub fn render(&self, term: &mut Terminal) -> Result<(), Error> {
         term.dimmed()?;
 
-        let title_width = self.title.width();
-        let message_width = self.message.width();
-
-        let (w, h) = ((message_width.max(title_width) + 12).min(term.width - 5), 8);
-        let (x, y) = ((term.width - w) / 2, (term.height - 2 - h) / 2);
+        let (title_width, message_width) = (self.title.width(), self.message_width());
+        let ((x, y), (w, h)) = self.geometry(term);
 
         term.begin_render()?;
 
-        draw_rounded_rect(term, (x, y), (w, h), style::text_model, style::background);
+        draw_rounded_rect(
+            term,
+            (x, y),
+            (w, h),
+            style::text_model(),
+            style::background(),
+        );
 
         term.write(
-            (x + w / 2 - 3, y).into(),
-            " ALERT "
+            (x + (w - self.label.width()) / 2, y).into(),
+            self.label
                 .to_string()
                 .bold()
-                .with(style::text_primary)
-                .on(style::text_model),
+                .with(style::text_primary())
+                .on(style::text_model()),
         );
         term.write(
             (x + (w - title_width) / 2 + 1, y + 2).into(),
             self.title
                 .to_string()
                 .bold()
-                .with(style::text_alert)
-                .on(style::background),
+                .with(style::text_alert())
+                .on(style::background()),
+        );
+        for (i, line) in self.message.lines().enumerate() {
+            term.write(
+                (x + w.saturating_sub(message_width) / 2 + 1, y + 4 + i).into(),
+                line.to_string()
+                    .with(style::text_model())
+                    .on(style::background()),
+            );
+        }
+
+        self.yes
+            .render(term, (x + (w - self.yes.width) / 2 - 1, y + h - 2))?;
+
+        term.end_render()?;
+
+        Ok(())
+    }
+}
+
+/// A list dialog that lets the user pick one of several items.
+pub struct Select {
+    title: String,
+    items: Vec<String>,
+    /// Which items are shown dimmed, if any.
+    dimmed: Vec<bool>,
+    /// The text typed to narrow down the items, and the items that contain it.
+    filter: String,
+    shown: Vec<usize>,
+    /// Which of the shown items is selected, and the first one in view.
+    selected: usize,
+    offset: usize,
+}
+
+impl Select {
+    const MAX_ROWS: usize = 10;
+
+    pub fn new(title: String, items: Vec<String>) -> Self {
+        Self {
+            title,
+            shown: (0..items.len()).collect(),
+            items,
+            dimmed: Vec::new(),
+            filter: String::new(),
+            selected: 0,
+            offset: 0,
+        }
+    }
+
+    /// Show only the items containing `filter`, ignoring case, and select the first.
+    fn set_filter(&mut self, filter: String) {
+        let needle = filter.to_lowercase();
+        self.shown = (0..self.items.len())
+            .filter(|&i| self.items[i].to_lowercase().contains(&needle))
+            .collect();
+        self.filter = filter;
+        self.selected = 0;
+        self.offset = 0;
+    }
+
+    /// Returns the position and size of the dialog, and the number of visible items.
+    fn geometry(&self, term: &Terminal) -> ((usize, usize), (usize, usize), usize) {
+        let content_width = self
+            .items
+            .iter()
+            .map(|item| item.width())
+            .chain([self.title.width()])
+            .max()
+            .unwrap_or(0);
+        let rows = self
+            .items
+            .len()
+            .clamp(1, Self::MAX_ROWS)
+            .min((term.height - 2).saturating_sub(8).max(1));
+
+        let (w, h) = ((content_width + 12).min(term.width - 5), rows + 4);
+        let (x, y) = (
+            (term.width - w) / 2,
+            (term.height - 2).saturating_sub(h) / 2,
+        );
+        ((x, y), (w, h), rows)
+    }
+
+    fn select(&mut self, index: usize, rows: usize) {
+        if self.shown.is_empty() {
+            return;
+        }
+        self.selected = index.min(self.shown.len() - 1);
+        self.offset = self
+            .offset
+            .clamp((self.selected + 1).saturating_sub(rows), self.selected);
+    }
+
+    pub fn event_loop(&mut self, editor: &mut Editor) -> Result<Option<usize>, Error> {
+        if editor.check_minimum_window_size() {
+            editor.render_to_buffer();
+            self.render(&mut editor.terminal)?;
+        }
+
+        loop {
+            if editor.poll_event()? {
+                let (_, _, rows) = self.geometry(&editor.terminal);
+                match editor.read_event()? {
+                    Event::Key(event) if event.kind != KeyEventKind::Release => match event.code {
+                        KeyCode::Enter if !self.shown.is_empty() => {
+                            return Ok(Some(self.shown[self.selected]));
+                        }
+                        KeyCode::Esc => {
+                            return Ok(None);
+                        }
+                        KeyCode::Up => self.select(self.selected.saturating_sub(1), rows),
+                        KeyCode::Down => self.select(self.selected + 1, rows),
+                        KeyCode::PageUp => self.select(self.selected.saturating_sub(rows), rows),
+                        KeyCode::PageDown => self.select(self.selected + rows, rows),
+                        KeyCode::Home => self.select(0, rows),
+                        KeyCode::End => self.select(usize::MAX, rows),
+                        KeyCode::Backspace => {
+                            let mut filter = self.filter.clone();
+                            filter.pop();
+                            self.set_filter(filter);
+                        }
+                        KeyCode::Char(c)
+                            if !(event.modifiers)
+                                .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+                        {
+                            self.set_filter(format!("{}{c}", self.filter));
+                        }
+
+                        _ => {}
+                    },
+
+                    Event::Mouse(event) => {
+                        let ((x, y), (w, _), rows) = self.geometry(&editor.terminal);
+                        let (mx, my) = (event.column as usize, event.row as usize);
+
+                        match event.kind {
+                            MouseEventKind::ScrollUp => {
+                                self.select(self.selected.saturating_sub(1), rows)
+                            }
+                            MouseEventKind::ScrollDown => self.select(self.selected + 1, rows),
+                            _ => {
+                                if mx > x && mx <= x + w && my >= y + 4 && my < y + 4 + rows {
+                                    let index = self.offset + my - y - 4;
+                                    if index < self.shown.len() {
+                                        self.select(index, rows);
+                                        if let MouseEventKind::Down(_) = event.kind {
+                                            return Ok(Some(self.shown[index]));
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+
+                    Event::Resize(width, height) => {
+                        editor.terminal.update_window_size(height, width);
+                        editor.on_resize();
+                        // Keep the selected item in view in the new number of rows
+                        let (_, _, rows) = self.geometry(&editor.terminal);
+                        self.select(self.selected, rows);
+                    }
+
+                    _ => {}
+                }
+
+                if !editor.check_minimum_window_size() {
+                    continue;
+                }
+
+                editor.render_to_buffer();
+                self.render(&mut editor.terminal)?;
+            }
+        }
+    }
+
+    pub fn render(&self, term: &mut Terminal) -> Result<(), Error> {
+        term.dimmed()?;
+
+        let ((x, y), (w, h), rows) = self.geometry(term);
+
+        term.begin_render()?;
+
+        draw_rounded_rect(
+            term,
+            (x, y),
+            (w, h),
+            style::text_model(),
+            style::background(),
+        );
+
+        term.write(
+            (x + 3, y).into(),
+            " SELECT "
+                .to_string()
+                .bold()
+                .with(style::text_primary())
+                .on(style::text_model()),
         );
         term.write(
-            (x + (w - message_width) / 2 + 1, y + 4).into(),
-            self.message
+            (x + 3, y + 2).into(),
+            self.title
                 .to_string()
-                .with(style::text_model)
-                .on(style::background),
+                .with(style::text_model())
+                .on(style::background()),
         );
+        if !self.filter.is_empty() {
+            term.write(
+                (x + 5 + self.title.width(), y + 2).into(),
+                t!("dialog.filter", &self.filter)
+                    .with(style::text_dimmed())
+                    .on(style::background()),
+            );
+        }
 
-        self.yes
-            .render(term, (x + (w - self.yes.width) / 2 - 1, y + h - 2))?;
+        for (i, &index) in self.shown.iter().enumerate().skip(self.offset).take(rows) {
+            let width = w.saturating_sub(4);
+            let line = format!(" {:<width$}", self.items[index]);
+            let line = line.with(match self.dimmed.get(index) {
+                Some(true) => style::text_dimmed(),
+                _ => style::text_model(),
+            });
+            term.write(
+                (x + 2, y + 4 + i - self.offset).into(),
+                if i == self.selected {
+                    line.on(style::background_selected())
+                } else {
+                    line.on(style::background())
+                },
+            );
+        }
 
         term.end_render()?;
 
@@ -661,46 +939,356 @@ impl Alert {
 pub struct Tui {}
 
 impl Tui {
-    pub fn confirm_exit(editor: &mut Editor) -> Result<Option<bool>, Error> {
-        if !editor.dirty {
+    /// Run a dialog, then draw the editor again in full, so that nothing the dialog
+    /// left on the screen is seen while the next input is handled.
+    fn show<T>(
+        editor: &mut Editor,
+        dialog: impl FnOnce(&mut Editor) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let result = dialog(editor)?;
+        editor.redraw()?;
+        Ok(result)
+    }
+
+    /// Ask whether to save the buffers named in `unsaved`. Returns `Some(false)` right
+    /// away if there are none.
+    pub fn confirm_exit(editor: &mut Editor, unsaved: Vec<String>) -> Result<Option<bool>, Error> {
+        if unsaved.is_empty() {
             return Ok(Some(false));
         }
 
-        Confirm::new(
-            format!(
-                "是否要保存对 {} 的更改？",
-                editor.filename.clone().unwrap_or("Untitled".to_string())
-            ),
-            "保存".to_string(),
-            "不保存".to_string(),
-            Some("取消".to_string()),
-        )
-        .event_loop(editor)
+        Self::show(editor, |editor| {
+            Confirm::new(
+                t!(
+                    "dialog.save_changes",
+                    unsaved.join(t!("dialog.list_separator"))
+                ),
+                t!("button.save").to_string(),
+                t!("button.dont_save").to_string(),
+                Some(t!("button.cancel").to_string()),
+            )
+            .event_loop(editor)
+        })
     }
 
     pub fn prompt_filename(editor: &mut Editor) -> Result<Option<String>, Error> {
-        Prompt::new(
-            "请输入文件名: ".to_string(),
-            "保存".to_string(),
-            "取消".to_string(),
+        Self::prompt_path(
+            editor,
+            t!("dialog.enter_filename").to_string(),
+            t!("button.save").to_string(),
+            |text| Ok(text.to_string()),
         )
-        .event_loop(editor)
+    }
+
+    /// Like `prompt`, with Tab completing file names.
+    pub fn prompt_path<T>(
+        editor: &mut Editor,
+        title: String,
+        yes: String,
+        parse: impl Fn(&str) -> Result<T, String>,
+    ) -> Result<Option<T>, Error> {
+        let mut prompt = Prompt::new(title, yes, t!("button.cancel").to_string());
+        prompt.input.complete = Some(complete_path);
+        Self::show(editor, |editor| prompt.event_loop(editor, parse))
+    }
+
+    /// Ask for a line of text until `parse` accepts it, showing its errors.
+    pub fn prompt<T>(
+        editor: &mut Editor,
+        title: String,
+        yes: String,
+        parse: impl Fn(&str) -> Result<T, String>,
+    ) -> Result<Option<T>, Error> {
+        Self::show(editor, |editor| {
+            Prompt::new(title, yes, t!("button.cancel").to_string()).event_loop(editor, parse)
+        })
+    }
+
+    /// Ask whether to save over a file that is open in another buffer, which this one
+    /// then replaces.
+    pub fn confirm_overwrite_open(
+        editor: &mut Editor,
+        filename: &String,
+    ) -> Result<Option<bool>, Error> {
+        Self::show(editor, |editor| {
+            Confirm::new(
+                t!("dialog.overwrite_open", filename),
+                t!("button.overwrite").to_string(),
+                t!("button.cancel").to_string(),
+                None,
+            )
+            .event_loop(editor)
+        })
     }
 
     pub fn confirm_overwrite(
         editor: &mut Editor,
         filename: &String,
     ) -> Result<Option<bool>, Error> {
-        Confirm::new(
-            format!("文件 {} 已存在，是否覆盖？", filename),
-            "覆盖".to_string(),
-            "取消".to_string(),
-            None,
-        )
-        .event_loop(editor)
+        Self::show(editor, |editor| {
+            Confirm::new(
+                t!("dialog.overwrite", filename),
+                t!("button.overwrite").to_string(),
+                t!("button.cancel").to_string(),
+                None,
+            )
+            .event_loop(editor)
+        })
+    }
+
+    /// Ask whether to load a file of `size` megabytes whole, or only preview its first
+    /// `preview` megabytes. Returns `Some(false)` for the preview.
+    pub fn confirm_large_file(
+        editor: &mut Editor,
+        filename: &str,
+        size: u64,
+        preview: u64,
+    ) -> Result<Option<bool>, Error> {
+        Self::show(editor, |editor| {
+            Confirm::new(
+                t!("dialog.large_file", filename, size, preview),
+                t!("button.open_whole").to_string(),
+                t!("button.preview", preview),
+                Some(t!("button.cancel").to_string()),
+            )
+            .event_loop(editor)
+        })
+    }
+
+    /// Ask whether to move a file to the trash, or if `permanently`, to delete it for
+    /// good.
+    pub fn confirm_delete(
+        editor: &mut Editor,
+        filename: &str,
+        permanently: bool,
+    ) -> Result<Option<bool>, Error> {
+        let message = match permanently {
+            true => t!("dialog.delete_permanently", filename),
+            false => t!("dialog.delete", filename),
+        };
+        Self::show(editor, |editor| {
+            Confirm::new(
+                message,
+                t!("button.delete").to_string(),
+                t!("button.cancel").to_string(),
+                None,
+            )
+            .event_loop(editor)
+        })
+    }
+
+    /// Ask whether to clean up `count` hard to see characters.
+    pub fn confirm_clean_anomalies(
+        editor: &mut Editor,
+        count: usize,
+    ) -> Result<Option<bool>, Error> {
+        Self::show(editor, |editor| {
+            Confirm::new(
+                t!("dialog.clean_anomalies", count),
+                t!("button.replace").to_string(),
+                t!("button.cancel").to_string(),
+                None,
+            )
+            .event_loop(editor)
+        })
+    }
+
+    /// Ask whether to throw away the changes to a file and read it again.
+    pub fn confirm_revert(editor: &mut Editor, filename: &str) -> Result<Option<bool>, Error> {
+        Self::show(editor, |editor| {
+            Confirm::new(
+                t!("dialog.revert", filename),
+                t!("button.revert").to_string(),
+                t!("button.cancel").to_string(),
+                None,
+            )
+            .event_loop(editor)
+        })
+    }
+
+    pub fn confirm_binary(editor: &mut Editor, filename: &str) -> Result<Option<bool>, Error> {
+        Self::show(editor, |editor| {
+            Confirm::new(
+                t!("dialog.binary_file", filename),
+                t!("button.insert").to_string(),
+                t!("button.cancel").to_string(),
+                None,
+            )
+            .event_loop(editor)
+        })
     }
 
     pub fn alert(editor: &mut Editor, title: String, message: String) -> Result<(), Error> {
-        Alert::new(title, message, "好吧".to_string()).event_loop(editor)
+        Self::show(editor, |editor| {
+            Alert::new(title, message, t!("button.ok").to_string()).event_loop(editor)
+        })
+    }
+
+    /// Show lines of information about the editor, under `title`.
+    pub fn about(editor: &mut Editor, title: String, lines: Vec<String>) -> Result<(), Error> {
+        Self::show(editor, |editor| {
+            let mut alert = Alert::new(title, lines.join("\n"), t!("button.ok").to_string());
+            alert.label = " ABOUT ";
+            alert.event_loop(editor)
+        })
+    }
+
+    pub fn select(
+        editor: &mut Editor,
+        title: String,
+        items: Vec<String>,
+    ) -> Result<Option<usize>, Error> {
+        Self::show(editor, |editor| {
+            Select::new(title, items).event_loop(editor)
+        })
+    }
+
+    /// Pick one of the recently opened files. Those that no longer exist are dimmed.
+    /// Pick one of `files`, or the entry after them for the `discarded` scratch buffers
+    /// if there are any.
+    pub fn select_recent(
+        editor: &mut Editor,
+        files: &[PathBuf],
+        discarded: usize,
+    ) -> Result<Option<usize>, Error> {
+        let mut items: Vec<_> = files
+            .iter()
+            .map(|file| file.display().to_string())
+            .collect();
+        let mut dimmed: Vec<_> = files.iter().map(|file| !file.is_file()).collect();
+        if discarded > 0 {
+            items.push(t!("recent.discarded", discarded));
+            dimmed.push(false);
+        }
+        let mut select = Select::new(t!("dialog.recent_files").to_string(), items);
+        select.dimmed = dimmed;
+        Self::show(editor, |editor| select.event_loop(editor))
+    }
+
+    pub fn select_register(
+        editor: &mut Editor,
+        registers: &[(char, String)],
+    ) -> Result<Option<usize>, Error> {
+        let items = registers
+            .iter()
+            .map(|(name, text)| {
+                let lines = text.lines().count();
+                let first = text.lines().next().unwrap_or_default().trim();
+                let preview: String = first.chars().take(40).collect();
+                match lines {
+                    0 | 1 => format!("{name}  {preview}"),
+                    _ => format!("{name}  {preview}  (+{})", lines - 1),
+                }
+            })
+            .collect();
+        Tui::select(editor, t!("dialog.registers").to_string(), items)
+    }
+
+    pub fn select_undo_branch(
+        editor: &mut Editor,
+        branches: &[Branch],
+    ) -> Result<Option<usize>, Error> {
+        let items = branches
+            .iter()
+            .map(|branch| {
+                let id = if branch.active {
+                    "history.branch_active"
+                } else {
+                    "history.branch"
+                };
+                t!(
+                    id,
+                    format!("{:>4}", branch.depth),
+                    format_elapsed(branch.time.elapsed())
+                )
+            })
+            .collect();
+        Tui::select(editor, t!("dialog.undo_branch").to_string(), items)
+    }
+}
+
+/// Formats a duration as a short relative time, e.g. "3 minutes ago".
+fn format_elapsed(elapsed: std::time::Duration) -> String {
+    match elapsed.as_secs() {
+        0..=4 => t!("time.just_now").to_string(),
+        s @ 5..=59 => t!("time.seconds_ago", s),
+        s @ 60..=3599 => t!("time.minutes_ago", s / 60),
+        s => t!("time.hours_ago", s / 3600),
+    }
+}
+
+/// Complete the file name at the end of `text` as far as all the files it could be
+/// agree, with a `/` after a directory. Hidden files only count once a `.` is typed.
+/// Returns `None` if nothing can be added.
+pub fn complete_path(text: &str) -> Option<String> {
+    let split = text
+        .rfind(['/', std::path::MAIN_SEPARATOR])
+        .map_or(0, |i| i + 1);
+    let (dir, prefix) = text.split_at(split);
+    let entries = std::fs::read_dir(if dir.is_empty() { "." } else { dir }).ok()?;
+    let mut names = entries.filter_map(|entry| {
+        let entry = entry.ok()?;
+        let mut name = entry.file_name().into_string().ok()?;
+        if !name.starts_with(prefix) || name.starts_with('.') && !prefix.starts_with('.') {
+            return None;
+        }
+        if entry.path().is_dir() {
+            name.push('/');
+        }
+        Some(name)
+    });
+
+    let first = names.next()?;
+    let common = names.fold(first, |mut common, name| {
+        let len = (common.chars().zip(name.chars()))
+            .take_while(|(a, b)| a == b)
+            .map(|(a, _)| a.len_utf8())
+            .sum();
+        common.truncate(len);
+        common
+    });
+    (common.len() > prefix.len()).then(|| format!("{dir}{common}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hover_after_resize() {
+        let mut confirm = Confirm::new("Save?".into(), "Save".into(), "Don't".into(), None);
+        let mut term = Terminal::headless(80, 24);
+        let ((x, y), (w, h)) = confirm.geometry(&term);
+        let yes = (x + w - confirm.buttons_width() + 1, y + h - 1);
+        confirm.mouse = Some(yes);
+        confirm.hover(&term);
+        assert!(confirm.yes.hover);
+
+        // The dialog moves with the window while the mouse stays put
+        term.update_window_size(20, 60);
+        confirm.hover(&term);
+        assert!(!confirm.yes.hover);
+        let ((x, y), (w, h)) = confirm.geometry(&term);
+        confirm.mouse = Some((x + w - confirm.buttons_width() + 1, y + h - 1));
+        confirm.hover(&term);
+        assert!(confirm.yes.hover && !confirm.no.hover);
+    }
+
+    #[test]
+    fn test_complete_path() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["alpha.txt", "beta", ".hidden"] {
+            std::fs::write(dir.path().join(file), "").unwrap();
+        }
+        std::fs::create_dir(dir.path().join("alpine")).unwrap();
+        let dir = format!("{}/", dir.path().display());
+
+        let complete = |text: &str| complete_path(&format!("{dir}{text}"));
+        assert_eq!(complete("al"), Some(format!("{dir}alp")));
+        assert_eq!(complete("alpi"), Some(format!("{dir}alpine/")));
+        assert_eq!(complete("b"), Some(format!("{dir}beta")));
+        assert_eq!(complete("."), Some(format!("{dir}.hidden")));
+        assert_eq!(complete("alp"), None);
+        assert_eq!(complete("x"), None);
     }
 }
@@ -0,0 +1,82 @@
+//! Display widths of text.
+//!
+//! Terminals set up for Chinese, Japanese or Korean draw the East Asian "ambiguous"
+//! characters (`·`, `§`, some box drawing) two columns wide, others one. Every width
+//! the editor computes goes through [`Width`], so rows, the screen buffer and dialogs
+//! all agree on which it is. The choice is made for the thread the editor runs on.
+
+use std::cell::Cell;
+
+use unicode_width::UnicodeWidthStr;
+
+thread_local! {
+    static CJK: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Count ambiguous characters as two columns wide if `cjk`, otherwise as one, on the
+/// current thread.
+pub fn set_cjk(cjk: bool) {
+    CJK.set(cjk);
+}
+
+/// Whether ambiguous characters count as two columns wide on the current thread.
+pub fn cjk() -> bool {
+    CJK.get()
+}
+
+/// Whether the locale environment variables name Chinese, Japanese or Korean.
+pub fn locale_is_cjk() -> bool {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .is_some_and(|locale| is_cjk(&locale))
+}
+
+fn is_cjk(locale: &str) -> bool {
+    let language = locale
+        .split(['_', '-', '.', '@'])
+        .next()
+        .unwrap_or_default();
+    ["zh", "ja", "ko"].contains(&language.to_ascii_lowercase().as_str())
+}
+
+/// The number of columns text takes up on screen.
+pub trait Width {
+    fn width(&self) -> usize;
+}
+
+impl Width for str {
+    fn width(&self) -> usize {
+        if cjk() {
+            self.width_cjk()
+        } else {
+            UnicodeWidthStr::width(self)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locale() {
+        assert!(is_cjk("zh_CN.UTF-8"));
+        assert!(is_cjk("ja_JP"));
+        assert!(is_cjk("ko"));
+        assert!(!is_cjk("en_US.UTF-8"));
+        assert!(!is_cjk("C"));
+        assert!(!is_cjk("zu_ZA"));
+    }
+
+    #[test]
+    fn test_ambiguous() {
+        assert_eq!("±§".width(), 2);
+        set_cjk(true);
+        assert_eq!("±§".width(), 4);
+        assert_eq!("a中".width(), 3);
+        set_cjk(false);
+        assert_eq!("±§".width(), 2);
+    }
+}